@@ -0,0 +1,57 @@
+use std::env;
+use std::fs;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use dantelion_formats::bhd5::{BHD5, BHD5Format};
+use dantelion_formats::bnd4::BND4;
+use dantelion_formats::dcx::DCX;
+use dantelion_formats::fixtures;
+use dantelion_formats::oodle::OodleContext;
+
+// Path to a KRAK DCX to bench Oodle decompression with. There's no Oodle compressor to generate one, so the KRAK
+// bench only runs when this is set and the Oodle DLL can be found.
+const KRAK_SAMPLE_ENV_VAR: &str = "DANTELION_BENCH_KRAK";
+
+fn bhd5(c: &mut Criterion) {
+    let archive = fixtures::bhd5_archive(BHD5Format::EldenRing, 2000, 16).unwrap();
+    let mut group = c.benchmark_group("bhd5");
+    group.throughput(Throughput::Bytes(archive.bhd.len() as u64));
+    group.bench_function("decrypt_and_parse", |b| {
+        b.iter(|| BHD5::from_encrypted_bytes(black_box(&archive.bhd), archive.public_key.as_bytes()).unwrap())
+    });
+    group.finish();
+}
+
+fn bnd4(c: &mut Criterion) {
+    let bytes = fixtures::bnd4_bytes(500, 4 * 1024).unwrap();
+    let mut group = c.benchmark_group("bnd4");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_function("parse", |b| b.iter(|| BND4::from_bytes(black_box(&bytes)).unwrap()));
+    group.finish();
+}
+
+fn dcx(c: &mut Criterion) {
+    let size = 8 * 1024 * 1024;
+    let dflt = DCX::from_bytes(&fixtures::dflt_dcx_bytes(size).unwrap()).unwrap();
+    let edge = DCX::from_bytes(&fixtures::edge_dcx_bytes(size).unwrap()).unwrap();
+    let mut out = Vec::new();
+
+    let mut group = c.benchmark_group("dcx");
+    group.throughput(Throughput::Bytes(size as u64));
+    group.bench_function("dflt_decompress", |b| b.iter(|| dflt.decompress_into(black_box(&mut out)).unwrap()));
+    group.bench_function("edge_decompress", |b| b.iter(|| edge.decompress_into(black_box(&mut out)).unwrap()));
+
+    if let Some(krak) = env::var(KRAK_SAMPLE_ENV_VAR).ok().and_then(|path| fs::read(path).ok()) {
+        let krak = DCX::from_bytes(&krak).unwrap();
+        if let Ok(oodle) = OodleContext::new() {
+            group.throughput(Throughput::Bytes(krak.header.uncompressed_size as u64));
+            group.bench_function("krak_decompress", |b| {
+                b.iter(|| krak.decompress_into_with(&oodle, black_box(&mut out)).unwrap())
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bhd5, bnd4, dcx);
+criterion_main!(benches);
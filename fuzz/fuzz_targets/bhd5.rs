@@ -0,0 +1,9 @@
+#![no_main]
+
+use dantelion_formats::bhd5::BHD5;
+use libfuzzer_sys::fuzz_target;
+
+// Decrypted BHD5s. The RSA layer is OpenSSL's problem.
+fuzz_target!(|data: &[u8]| {
+    let _ = BHD5::from_bytes(data);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use dantelion_formats::bnd3::BND3;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = BND3::from_bytes(data);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use dantelion_formats::bnd4::BND4;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = BND4::from_bytes(data);
+});
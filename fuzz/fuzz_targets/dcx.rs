@@ -0,0 +1,12 @@
+#![no_main]
+
+use dantelion_formats::dcx::DCX;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(dcx) = DCX::from_bytes(data) else { return };
+    // KRAK needs the Oodle DLL, which isn't something to fuzz.
+    if dcx.header.format != "KRAK" {
+        let _ = dcx.decompress();
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use dantelion_formats::open_bytes;
+use dantelion_formats::dcx::DCX;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Same as the dcx target, KRAK isn't fuzzed.
+    if data.starts_with(b"DCX\0") && DCX::from_bytes(data).map_or(true, |dcx| dcx.header.format == "KRAK") {
+        return;
+    }
+    let _ = open_bytes(data);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use dantelion_formats::tpf::TPF;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = TPF::from_bytes(data);
+});
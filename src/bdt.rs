@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use crate::error::DantelionFormatsError;
+
+/// Writes a BDT, or any other big file written front to back, to disk. The file can be preallocated to the size it's
+/// expected to end up, so the filesystem can lay it out in one piece up front instead of growing it write by write,
+/// and with `sparse`, long runs of zeros are skipped over instead of written, leaving holes on filesystems that
+/// support them. Call `finish`, which cuts the file to what was written and syncs it.
+#[derive(Debug)]
+pub struct BdtWriter {
+    file: File,
+    position: u64,
+    sparse: bool,
+}
+
+impl BdtWriter {
+    // Shorter zero runs are written, holes are made a filesystem block at a time anyway.
+    const MIN_HOLE: usize = 0x1000;
+
+    /// Creates `path`, preallocated to `expected_len` bytes when it's not 0. It's fine to write less or more.
+    pub fn create(path: &Path, expected_len: u64) -> Result<BdtWriter, DantelionFormatsError> {
+        let file = File::create(path)?;
+        if expected_len > 0 {
+            preallocate(&file, expected_len)?;
+        }
+
+        Ok(BdtWriter {
+            file,
+            position: 0,
+            sparse: false,
+        })
+    }
+
+    /// Skips zero runs of at least 4KB instead of writing them. Holes are only made where the file hasn't been
+    /// preallocated, and on Windows not at all, since that needs the file marked sparse first, so there the zeros
+    /// just end up written by the filesystem.
+    pub fn sparse(mut self, sparse: bool) -> BdtWriter {
+        self.sparse = sparse;
+        self
+    }
+
+    /// Zero padding, e.g. between entries, skipped when sparse.
+    pub fn write_zeros(&mut self, len: u64) -> Result<(), DantelionFormatsError> {
+        if self.sparse && len >= BdtWriter::MIN_HOLE as u64 {
+            self.skip(len)?;
+        } else {
+            std::io::copy(&mut std::io::repeat(0).take(len), &mut self.file)?;
+            self.position += len;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the length to what was written, which drops any preallocated space past it and fills in a trailing
+    /// hole, then syncs. Returns the length.
+    pub fn finish(self) -> Result<u64, DantelionFormatsError> {
+        self.file.set_len(self.position)?;
+        self.file.sync_all()?;
+
+        Ok(self.position)
+    }
+
+    fn skip(&mut self, len: u64) -> std::io::Result<()> {
+        self.position += len;
+        self.file.seek(SeekFrom::Start(self.position))?;
+
+        Ok(())
+    }
+}
+
+impl Write for BdtWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if !self.sparse {
+            let written = self.file.write(buf)?;
+            self.position += written as u64;
+            return Ok(written);
+        }
+
+        // Whole 4KB blocks of zeros, counted from the start of `buf`, are skipped. Anything else is written up to the
+        // next such block.
+        let zero_block = |block: &[u8]| block.len() == BdtWriter::MIN_HOLE && block.iter().all(|&b| b == 0);
+        let zeros = buf.chunks(BdtWriter::MIN_HOLE).take_while(|block| zero_block(block)).count() * BdtWriter::MIN_HOLE;
+        if zeros > 0 {
+            self.skip(zeros as u64)?;
+            return Ok(zeros);
+        }
+
+        let data = buf.chunks(BdtWriter::MIN_HOLE).take_while(|block| !zero_block(block)).map(<[u8]>::len).sum();
+        let written = self.file.write(&buf[..data])?;
+        self.position += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// Asks the filesystem for the space up front. Elsewhere than Linux, growing the file with `set_len` is the closest
+// std has, which NTFS allocates for but most Unix filesystems leave as a hole.
+#[cfg(target_os = "linux")]
+fn preallocate(file: &File, len: u64) -> Result<(), DantelionFormatsError> {
+    use std::os::fd::AsRawFd;
+
+    let len = libc::off_t::try_from(len).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Preallocation too big"))?;
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len) } {
+        0 => Ok(()),
+        // Filesystems without fallocate get the size at least
+        libc::EOPNOTSUPP | libc::EINVAL => Ok(file.set_len(len as u64)?),
+        errno => Err(std::io::Error::from_raw_os_error(errno).into()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn preallocate(file: &File, len: u64) -> Result<(), DantelionFormatsError> {
+    Ok(file.set_len(len)?)
+}
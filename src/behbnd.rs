@@ -0,0 +1,135 @@
+use std::io::Cursor;
+use binary_interpreter::binary_reader::BinaryReader;
+use byteorder::{BE, LE, ReadBytesExt};
+use crate::bnd4::BND4;
+
+/// What an entry in a behavior binder is, going by its folder and extension.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BehaviorEntryType {
+    // Behaviors\c0000.hkx
+    Behavior,
+    // Characters\c0000.hkx
+    Character,
+    // CharacterData\c0000.hkx
+    CharacterData,
+    // Any other Havok file
+    Havok,
+    // .nmb Morpheme network
+    Morpheme,
+    Other,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum HavokFormat {
+    // Binary packfile, used up to DS3
+    Packfile,
+    // Tagfile, used from Sekiro on
+    Tagfile,
+}
+
+/// Version info from the start of a Havok file, enough to pick a parser without reading the whole thing.
+#[derive(Clone, Debug)]
+pub struct HavokInfo {
+    pub format: HavokFormat,
+    // e.g. "hk_2010.2.0-r1" for packfiles or "20180100" for tagfiles
+    pub version: String,
+    // Packfiles only
+    pub pointer_size: Option<u8>,
+    pub little_endian: Option<bool>,
+}
+
+#[derive(Debug)]
+pub struct BehaviorEntry<'a> {
+    pub id: Option<i32>,
+    pub name: Option<&'a str>,
+    pub entry_type: BehaviorEntryType,
+    pub havok: Option<HavokInfo>,
+}
+
+const PACKFILE_MAGIC: [u32; 2] = [0x57E0E057, 0x10C0C010];
+const PACKFILE_VERSION_OFFSET: u64 = 0x28;
+const PACKFILE_VERSION_SIZE: usize = 16;
+
+/// Types every entry in a behbnd and reads the Havok version of the ones that have one.
+pub fn entries(bnd4: &BND4) -> Vec<BehaviorEntry<'_>> {
+    bnd4.files.iter().map(|file| {
+        let data = file.data.as_deref().unwrap_or(&[]);
+        let havok = havok_info(data);
+        BehaviorEntry {
+            id: file.id,
+            name: file.name.as_deref(),
+            entry_type: entry_type(file.name.as_deref().unwrap_or(""), havok.is_some()),
+            havok,
+        }
+    }).collect()
+}
+
+pub fn entry_type(name: &str, is_havok: bool) -> BehaviorEntryType {
+    let name = name.to_lowercase().replace('/', "\\");
+    if name.ends_with(".nmb") {
+        return BehaviorEntryType::Morpheme;
+    }
+    if !is_havok && !name.ends_with(".hkx") {
+        return BehaviorEntryType::Other;
+    }
+
+    if name.contains("\\behaviors\\") {
+        BehaviorEntryType::Behavior
+    } else if name.contains("\\characters\\") {
+        BehaviorEntryType::Character
+    } else if name.contains("\\characterdata\\") {
+        BehaviorEntryType::CharacterData
+    } else {
+        BehaviorEntryType::Havok
+    }
+}
+
+/// Reads the format and version from a Havok packfile or tagfile header. None if `data` is neither.
+pub fn havok_info(data: &[u8]) -> Option<HavokInfo> {
+    read_packfile_info(data).or_else(|| read_tagfile_info(data))
+}
+
+fn read_packfile_info(data: &[u8]) -> Option<HavokInfo> {
+    let mut c = Cursor::new(data);
+    // The magic is the same either way around, so read it as little endian and trust the layout rules after it.
+    if [c.read_u32::<LE>().ok()?, c.read_u32::<LE>().ok()?] != PACKFILE_MAGIC {
+        return None;
+    }
+
+    // Skip the user tag and file version
+    c.set_position(0x10);
+    let pointer_size = c.read_u8().ok()?;
+    let little_endian = c.read_u8().ok()? != 0;
+    c.set_position(PACKFILE_VERSION_OFFSET);
+    let version = c.read_fixed_cstr(PACKFILE_VERSION_SIZE).ok()?;
+
+    Some(HavokInfo {
+        format: HavokFormat::Packfile,
+        version: version.trim_end_matches('\0').to_string(),
+        pointer_size: Some(pointer_size),
+        little_endian: Some(little_endian),
+    })
+}
+
+// Tagfiles are a tree of sections, each a big endian u32 of flags and size followed by a 4 character tag. The
+// SDK version is in the SDKV section right inside the root TAG0 section.
+fn read_tagfile_info(data: &[u8]) -> Option<HavokInfo> {
+    let mut c = Cursor::new(data);
+    c.read_u32::<BE>().ok()?;
+    if c.read_fixed_cstr(4).ok()? != "TAG0" {
+        return None;
+    }
+
+    let sdkv_size = (c.read_u32::<BE>().ok()? & 0x3FFFFFFF) as usize;
+    if c.read_fixed_cstr(4).ok()? != "SDKV" || sdkv_size < 8 {
+        return None;
+    }
+    let version = c.read_fixed_cstr(sdkv_size - 8).ok()?;
+
+    Some(HavokInfo {
+        format: HavokFormat::Tagfile,
+        version: version.trim_end_matches('\0').to_string(),
+        pointer_size: None,
+        little_endian: None,
+    })
+}
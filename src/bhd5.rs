@@ -1,12 +1,32 @@
-use std::io::Cursor;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::{Cursor, Error, ErrorKind, Read};
+#[cfg(feature = "crypto")]
+use std::io::Write;
+use std::iter::Flatten;
+use std::ops::RangeInclusive;
+use std::{slice, vec};
 use std::fs;
+#[cfg(feature = "crypto")]
+use std::path::Path;
+#[cfg(feature = "crypto")]
+use crate::bdt::BdtWriter;
+#[cfg(feature = "crypto")]
 use crate::{crypto_util};
+#[cfg(feature = "crypto")]
+use crate::config::Config;
 use crate::error::DantelionFormatsError;
-use crate::util::Validate;
-use byteorder::{LE, ReadBytesExt};
+use crate::source::DataSource;
+use crate::util;
+use crate::util::{ensure, Validate};
+use byteorder::{ByteOrder, LE, ReadBytesExt, WriteBytesExt};
+#[cfg(feature = "crypto")]
+use openssl::symm::Mode;
+use serde::{Deserialize, Serialize};
 use binary_interpreter::binary_reader::BinaryReader;
 //Idk how necessary this is. Might need it for DS1, idk.
-pub(crate) enum GameType {
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum GameType {
     DemonSouls,
     DarkSouls,
     DarkSoulsII,
@@ -17,22 +37,80 @@ pub(crate) enum GameType {
     EldenRing,
 }
 
-#[derive(PartialEq, Eq, Clone, Copy)]
-pub(crate) enum BHD5Format {
+impl GameType {
+    /// The game's executable, for checking whether it's running. None for Demon's Souls, which only runs in an
+    /// emulator.
+    pub fn exe_name(&self) -> Option<&'static str> {
+        match self {
+            GameType::DemonSouls => None,
+            GameType::DarkSouls => Some("DARKSOULS.exe"),
+            GameType::DarkSoulsII | GameType::DarkSoulsIISotFS => Some("DarkSoulsII.exe"),
+            GameType::DarkSoulsRemastered => Some("DarkSoulsRemastered.exe"),
+            GameType::DarkSoulsIII => Some("DarkSoulsIII.exe"),
+            GameType::Sekiro => Some("sekiro.exe"),
+            GameType::EldenRing => Some("eldenring.exe"),
+        }
+    }
+
+    /// The store name, for matching installs that are only known by name, like EGS and GOG ones.
+    pub fn title(&self) -> &'static str {
+        match self {
+            GameType::DemonSouls => "Demon's Souls",
+            GameType::DarkSouls => "Dark Souls: Prepare to Die Edition",
+            GameType::DarkSoulsII => "Dark Souls II",
+            GameType::DarkSoulsIISotFS => "Dark Souls II: Scholar of the First Sin",
+            GameType::DarkSoulsRemastered => "Dark Souls: Remastered",
+            GameType::DarkSoulsIII => "Dark Souls III",
+            GameType::Sekiro => "Sekiro: Shadows Die Twice",
+            GameType::EldenRing => "Elden Ring",
+        }
+    }
+
+    /// None for Demon's Souls, which isn't on Steam.
+    pub fn steam_app_id(&self) -> Option<u32> {
+        match self {
+            GameType::DemonSouls => None,
+            GameType::DarkSouls => Some(211420),
+            GameType::DarkSoulsII => Some(236430),
+            GameType::DarkSoulsIISotFS => Some(335300),
+            GameType::DarkSoulsRemastered => Some(570940),
+            GameType::DarkSoulsIII => Some(374320),
+            GameType::Sekiro => Some(814380),
+            GameType::EldenRing => Some(1245620),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BHD5Format {
     DarkSoulsII,
     DarkSoulsIII,
     EldenRing,
 }
 
+impl BHD5Format {
+    /// Every game whose archives use this format.
+    pub fn games(&self) -> &'static [GameType] {
+        match self {
+            BHD5Format::DarkSoulsII => &[GameType::DarkSoulsII, GameType::DarkSoulsIISotFS],
+            BHD5Format::DarkSoulsIII => &[GameType::DarkSoulsIII, GameType::Sekiro],
+            BHD5Format::EldenRing => &[GameType::EldenRing],
+        }
+    }
+}
+
+#[derive(Debug)]
 #[repr(C)]
-pub(crate) struct BHD5 {
+pub struct BHD5 {
     pub format: BHD5Format,
     pub bhd5_header: BHD5Header,
     pub buckets: Vec<BHD5Bucket>,
 }
 
+#[derive(Clone, Debug)]
 #[repr(C)]
-pub(crate) struct BHD5Header {
+#[non_exhaustive]
+pub struct BHD5Header {
     pub magic: String,
     pub unk04: u8,
     pub unk05: u8,
@@ -46,15 +124,17 @@ pub(crate) struct BHD5Header {
     pub salt: Vec<u8>
 }
 
+#[derive(Debug)]
 #[repr(C)]
-pub(crate) struct BHD5Bucket {
+pub struct BHD5Bucket {
     pub file_header_count: u32,
     pub file_headers_offset: u32,
     pub file_headers: Vec<FileHeader>,
 }
 
+#[derive(Debug)]
 #[repr(C)]
-pub(crate) struct FileHeader {
+pub struct FileHeader {
     pub file_path_hash: u64,
     pub padded_file_size: u32,
     pub file_size: u64,
@@ -65,36 +145,475 @@ pub(crate) struct FileHeader {
     pub aes_key: Option<AESKey>,
 }
 
+#[derive(PartialEq, Eq, Debug)]
 #[repr(C)]
-pub(crate) struct SaltedHash {
+pub struct SaltedHash {
     pub hash: Vec<u8>,
     pub range_count: u32,
     pub ranges: Vec<Range>,
 }
 
+#[derive(PartialEq, Eq, Debug)]
 #[repr(C)]
-pub(crate) struct AESKey {
+pub struct AESKey {
     pub key: Vec<u8>,
     pub range_count: u32,
     pub ranges: Vec<Range>,
 }
 
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 #[repr(C)]
-pub(crate) struct Range {
+pub struct Range {
     pub begin: u64,
     pub end: u64,
 }
 
+/// A BHD5 held as arrays, one per field, instead of a `FileHeader` per file with its own hash, key and range Vecs.
+/// It's a fraction of the memory, for tools that keep every archive's metadata resident, and parsing Data0's ~100k
+/// headers this way is a handful of allocations rather than a few hundred thousand. Files are indexed in bucket order,
+/// and `file_header` builds a regular `FileHeader` when one is needed, e.g. to read the file. The salted hash and AES
+/// key offsets aren't kept, `BHD5::to_bytes` lays those out again anyway.
+#[derive(Debug)]
+pub struct CompactBHD5 {
+    pub format: BHD5Format,
+    pub bhd5_header: BHD5Header,
+    // Each bucket's span of the file arrays
+    pub buckets: Vec<Span>,
+    pub file_path_hashes: Vec<u64>,
+    pub padded_file_sizes: Vec<u32>,
+    pub file_sizes: Vec<u64>,
+    pub file_offsets: Vec<u64>,
+    // Indices into `entries`, `CompactBHD5::NONE` for files without one
+    pub salted_hashes: Vec<u32>,
+    pub aes_keys: Vec<u32>,
+    // Each salted hash and AES key's span of `bytes` and `ranges`
+    pub entries: Vec<(Span, Span)>,
+    pub bytes: Vec<u8>,
+    pub ranges: Vec<Range>,
+}
+
+/// `len` items from `start` in one of `CompactBHD5`'s tables.
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct Span {
+    pub start: u32,
+    pub len: u32,
+}
+
+#[cfg(feature = "crypto")]
+/// An encrypted BHD5 and its BDT, along with the keys needed to read it and to patch it into the game.
+pub struct BHD5Archive {
+    pub bhd: Vec<u8>,
+    pub bdt: Vec<u8>,
+    // PKCS#1 PEM. This is the key the game needs to be patched with.
+    pub public_key: String,
+    // PEM. Keep this around to be able to re-encrypt the archive later.
+    pub private_key: String,
+}
+
+// The private key is left out so archives can be logged.
+#[cfg(feature = "crypto")]
+impl fmt::Debug for BHD5Archive {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BHD5Archive")
+            .field("bhd", &util::DataLen(self.bhd.len()))
+            .field("bdt", &util::DataLen(self.bdt.len()))
+            .field("public_key", &self.public_key)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "crypto")]
+impl BHD5Archive {
+    pub fn write(&self, bhd_path: &str, bdt_path: &str) -> Result<(), DantelionFormatsError> {
+        fs::write(bhd_path, &self.bhd)?;
+        let mut bdt = BdtWriter::create(Path::new(bdt_path), self.bdt.len() as u64)?;
+        bdt.write_all(&self.bdt)?;
+        bdt.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "crypto")]
+/// Builds a new, self-consistent BHD5/BDT pair for total conversions that ship their own Data archives. Every
+/// file is AES encrypted with its own key, and the BHD5 with a freshly generated RSA key.
+#[derive(Debug)]
+pub struct BHD5ArchiveBuilder {
+    format: BHD5Format,
+    salt: Option<String>,
+    load_factor: f64,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+#[cfg(feature = "crypto")]
+impl BHD5ArchiveBuilder {
+    pub(crate) const BDT_HEADER: &'static [u8; 16] = b"BDF307D7R6\0\0\0\0\0\0";
+    pub(crate) const BDT_ALIGNMENT: usize = 0x10;
+
+    pub fn new(format: BHD5Format) -> BHD5ArchiveBuilder {
+        BHD5ArchiveBuilder {
+            format,
+            salt: None,
+            load_factor: BHD5::DEFAULT_LOAD_FACTOR,
+            files: vec![],
+        }
+    }
+
+    /// Uses `salt` instead of a random one. It must keep the format's prefix, or the format won't be detected
+    /// when reading the archive back.
+    pub fn salt(mut self, salt: &str) -> BHD5ArchiveBuilder {
+        self.salt = Some(salt.to_string());
+        self
+    }
+
+    /// Average files per bucket, see `BHD5::with_load_factor`.
+    pub fn load_factor(mut self, load_factor: f64) -> BHD5ArchiveBuilder {
+        self.load_factor = load_factor;
+        self
+    }
+
+    /// Adds a file under its virtual path, e.g. "/parts/am_m_1600.partsbnd.dcx".
+    pub fn add_file(mut self, path: &str, data: Vec<u8>) -> BHD5ArchiveBuilder {
+        self.files.push((path.to_string(), data));
+        self
+    }
+
+    pub fn build(self) -> Result<BHD5Archive, DantelionFormatsError> {
+        let (public_key, private_key) = crypto_util::generate_bhd5_key_pair()?;
+        self.build_with_keys(public_key, private_key)
+    }
+
+    /// Builds with an existing key pair, so an archive can be rebuilt without patching the game again.
+    pub fn build_with_keys(self, public_key: String, private_key: String) -> Result<BHD5Archive, DantelionFormatsError> {
+        let salt = match self.salt {
+            Some(salt) => salt,
+            None => crypto_util::generate_salt(BHD5::salt_prefix(self.format))?,
+        };
+
+        let mut bdt = BHD5ArchiveBuilder::BDT_HEADER.to_vec();
+        let mut file_headers = Vec::with_capacity(self.files.len());
+        for (path, data) in self.files {
+            util::pad_to(&mut bdt, BHD5ArchiveBuilder::BDT_ALIGNMENT);
+            let (file_header, encrypted) = BHD5::encrypt_file(&path, data, self.format, bdt.len() as u64)?;
+            file_headers.push(file_header);
+            bdt.extend(encrypted);
+        }
+
+        let bhd5 = BHD5::with_load_factor(self.format, salt, file_headers, self.load_factor)?;
+        let bhd = crypto_util::encrypt_bhd5_file(&bhd5.to_bytes()?, private_key.as_bytes())?;
+
+        Ok(BHD5Archive {
+            bhd,
+            bdt,
+            public_key,
+            private_key,
+        })
+    }
+}
+
 impl BHD5 {
     const MAGIC_SIZE: usize = 4;
     const SALTED_HASH_SIZE: usize = 32;
     const AES_KEY_SIZE: usize = 16;
+    const FILE_SIZE_OFFSET: usize = 0xC;
+    const BUCKETS_OFFSET_OFFSET: usize = 0x14;
+    /// Files per bucket the games' own archives are built with.
+    pub const DEFAULT_LOAD_FACTOR: f64 = 7.0;
+    /// The range `with_load_factor` accepts. Below it the bucket table dwarfs the files, above it every lookup is a
+    /// linear scan.
+    pub const LOAD_FACTOR_RANGE: RangeInclusive<f64> = 0.1..=1000.0;
 
+    #[cfg(feature = "crypto")]
+    /// Decrypts a BHD5 with the given PKCS#1 PEM public key and parses it. For archives made with
+    /// `BHD5ArchiveBuilder`, or games that `from_path` doesn't have keys for. Already decrypted BHD5s are parsed as is.
+    pub fn from_encrypted_bytes(file: &[u8], public_key: &[u8]) -> Result<BHD5, DantelionFormatsError> {
+        if BHD5::is_decrypted(file) {
+            return BHD5::from_bytes(file);
+        }
+
+        let decrypted = crypto_util::decrypt_bhd5_file(file, public_key)?;
+        BHD5::from_bytes(&decrypted)
+    }
+
+    #[cfg(feature = "crypto")]
+    /// Same as `from_encrypted_bytes`, for a BHD5 in any `DataSource`.
+    pub fn from_encrypted_source(source: &(impl DataSource + ?Sized), public_key: &[u8]) -> Result<BHD5, DantelionFormatsError> {
+        BHD5::from_encrypted_bytes(&source.read_all()?, public_key)
+    }
+
+    /// Builds an unencrypted BHD5, putting each file in the bucket its path hash selects.
+    pub fn new(format: BHD5Format, salt: String, file_headers: Vec<FileHeader>) -> BHD5 {
+        BHD5::with_load_factor(format, salt, file_headers, BHD5::DEFAULT_LOAD_FACTOR)
+            .expect("the default load factor fits any file count a Vec can hold")
+    }
+
+    /// Same as `new`, aiming for `load_factor` files per bucket. Lower is faster to look up, at the cost of a bigger
+    /// header. Fails if `load_factor` is outside `LOAD_FACTOR_RANGE`.
+    pub fn with_load_factor(format: BHD5Format, salt: String, file_headers: Vec<FileHeader>, load_factor: f64) -> Result<BHD5, DantelionFormatsError> {
+        let bucket_count = BHD5::bucket_count_for(file_headers.len(), load_factor)?;
+        let mut buckets: Vec<BHD5Bucket> = (0..bucket_count).map(|_| BHD5Bucket {
+            file_header_count: 0,
+            file_headers_offset: 0,
+            file_headers: vec![],
+        }).collect();
+        for file_header in file_headers {
+            let bucket = &mut buckets[(file_header.file_path_hash % bucket_count as u64) as usize];
+            bucket.file_header_count += 1;
+            bucket.file_headers.push(file_header);
+        }
+
+        let salt = salt.into_bytes();
+        Ok(BHD5 {
+            format,
+            bhd5_header: BHD5Header {
+                magic: "BHD5".to_string(),
+                unk04: u8::MAX,
+                unk05: 0,
+                unk06: 0,
+                unk07: 0,
+                unk08: 1,
+                file_size: 0,
+                bucket_count,
+                buckets_offset: 0,
+                salt_len: salt.len() as u32,
+                salt,
+            },
+            buckets,
+        })
+    }
+
+    #[cfg(feature = "crypto")]
+    /// Encrypts `data` with a new AES key for storing at `file_offset` in a BDT, returning its header and the
+    /// bytes to write.
+    pub(crate) fn encrypt_file(path: &str, data: Vec<u8>, format: BHD5Format, file_offset: u64) -> Result<(FileHeader, Vec<u8>), DantelionFormatsError> {
+        let mut padded = data;
+        let file_size = padded.len() as u64;
+        util::pad_to(&mut padded, BHD5::AES_KEY_SIZE);
+
+        let padded_file_size = padded.len();
+        let key = crypto_util::generate_aes_key()?;
+        let ranges = vec![Range { begin: 0, end: padded_file_size as u64 }];
+        crypto_util::crypt_aes_ranges(&mut padded, &key, &[(0, padded_file_size as i64)], Mode::Encrypt)?;
+
+        let file_header = FileHeader {
+            file_path_hash: BHD5::hash_path(path, format),
+            padded_file_size: util::checked_cast(padded_file_size, "Padded file size")?,
+            file_size,
+            file_offset,
+            salted_hash_offset: 0,
+            aes_key_offset: 0,
+            salted_hash: None,
+            aes_key: Some(AESKey {
+                key: key.to_vec(),
+                range_count: ranges.len() as u32,
+                ranges,
+            }),
+        };
+
+        Ok((file_header, padded))
+    }
+
+    /// Converts to another format, e.g. a DS3 header to ER with 64-bit hashes. Path hashes can't be converted
+    /// directly, so `paths` has to contain the path of every file in the archive. The BDT stays the same.
+    pub fn convert(self, format: BHD5Format, paths: &[&str]) -> Result<BHD5, DantelionFormatsError> {
+        let new_hashes: HashMap<u64, u64> = paths.iter()
+            .map(|path| (BHD5::hash_path(path, self.format), BHD5::hash_path(path, format)))
+            .collect();
+
+        let mut file_headers = vec![];
+        for bucket in self.buckets {
+            for mut file_header in bucket.file_headers {
+                file_header.file_path_hash = *new_hashes.get(&file_header.file_path_hash).ok_or_else(|| {
+                    DantelionFormatsError::IoError(Error::new(
+                        ErrorKind::NotFound,
+                        format!("No path given for file hash {:#x}", file_header.file_path_hash),
+                    ))
+                })?;
+                file_headers.push(file_header);
+            }
+        }
+
+        // The salt prefix is how the format is detected, so it has to change with it.
+        let salt = String::from_utf8(self.bhd5_header.salt)?;
+        let salt = ["GR_", "FDP_", "NTC_", "DS2_"].iter()
+            .find_map(|prefix| salt.strip_prefix(prefix))
+            .unwrap_or(&salt);
+
+        Ok(BHD5::new(format, format!("{}{}", BHD5::salt_prefix(format), salt), file_headers))
+    }
+
+    /// Every file header, bucket by bucket.
+    pub fn iter(&self) -> Flatten<slice::Iter<'_, BHD5Bucket>> {
+        self.buckets.iter().flatten()
+    }
+
+    /// Headers can be edited in place, but changing `file_path_hash` needs a `BHD5::new` to rebucket.
+    pub fn iter_mut(&mut self) -> Flatten<slice::IterMut<'_, BHD5Bucket>> {
+        self.buckets.iter_mut().flatten()
+    }
+
+    /// The first prime at or above `file_count / load_factor`, the way FromSoftware's tools size their bucket tables.
+    /// Fails if `load_factor` is outside `LOAD_FACTOR_RANGE` or the count doesn't fit the header's `u32`.
+    pub fn bucket_count_for(file_count: usize, load_factor: f64) -> Result<u32, DantelionFormatsError> {
+        ensure!(BHD5::LOAD_FACTOR_RANGE.contains(&load_factor), "BHD5 load factor {} is outside {:?}", load_factor, BHD5::LOAD_FACTOR_RANGE);
+        // Saturating, so counts past u64 still fail the check below.
+        let minimum = (file_count as f64 / load_factor).ceil() as u64;
+        ensure!(minimum <= u32::MAX as u64, "{} files need more BHD5 buckets than fit in a u32", file_count);
+        (minimum as u32..=u32::MAX).find(|&n| util::is_prime(n)).ok_or_else(|| DantelionFormatsError::IoError(
+            Error::new(ErrorKind::InvalidData, format!("{} files need more BHD5 buckets than fit in a u32", file_count))))
+    }
+
+    /// The path hash this format uses for `file_path_hash`.
+    pub fn hash_path(path: &str, format: BHD5Format) -> u64 {
+        match format {
+            BHD5Format::EldenRing => util::path_hash_64(path),
+            _ => util::path_hash(path) as u64,
+        }
+    }
+
+    fn salt_prefix(format: BHD5Format) -> &'static str {
+        match format {
+            BHD5Format::EldenRing => "GR_",
+            BHD5Format::DarkSoulsIII => "FDP_",
+            BHD5Format::DarkSoulsII => "DS2_",
+        }
+    }
+
+    /// Serializes the BHD5 unencrypted. Counts and offsets are recalculated.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        let header = &self.bhd5_header;
+        let mut bytes = vec![];
+        util::write_fixed_str(&mut bytes, &header.magic, BHD5::MAGIC_SIZE)?;
+        bytes.write_u8(header.unk04)?;
+        bytes.write_u8(header.unk05)?;
+        bytes.write_u8(header.unk06)?;
+        bytes.write_u8(header.unk07)?;
+        bytes.write_u32::<LE>(header.unk08)?;
+        bytes.write_u32::<LE>(0)?; // file_size
+        bytes.write_u32::<LE>(util::checked_cast(self.buckets.len(), "Bucket count")?)?;
+        bytes.write_u32::<LE>(0)?; // buckets_offset
+        bytes.write_u32::<LE>(util::checked_cast(header.salt.len(), "Salt length")?)?;
+        bytes.extend_from_slice(&header.salt);
+
+        let buckets_offset = util::checked_cast(bytes.len(), "Buckets offset")?;
+        LE::write_u32(&mut bytes[BHD5::BUCKETS_OFFSET_OFFSET..BHD5::BUCKETS_OFFSET_OFFSET + 4], buckets_offset);
+        let mut bucket_positions = Vec::with_capacity(self.buckets.len());
+        for bucket in &self.buckets {
+            bytes.write_u32::<LE>(util::checked_cast(bucket.file_headers.len(), "File header count")?)?;
+            bucket_positions.push(bytes.len());
+            bytes.write_u32::<LE>(0)?;
+        }
+
+        let mut hash_and_key_positions = vec![];
+        for (bucket, position) in self.buckets.iter().zip(bucket_positions) {
+            let offset = util::checked_cast(bytes.len(), "File headers offset")?;
+            LE::write_u32(&mut bytes[position..position + 4], offset);
+            for file_header in &bucket.file_headers {
+                let positions = BHD5::write_file_header(&mut bytes, file_header, self.format)?;
+                hash_and_key_positions.push((positions, file_header));
+            }
+        }
+
+        for ((salted_hash_position, aes_key_position), file_header) in hash_and_key_positions {
+            if let Some(salted_hash) = &file_header.salted_hash {
+                let offset = bytes.len() as u64;
+                LE::write_u64(&mut bytes[salted_hash_position..salted_hash_position + 8], offset);
+                bytes.extend_from_slice(&salted_hash.hash);
+                BHD5::write_ranges(&mut bytes, &salted_hash.ranges)?;
+            }
+            if let Some(aes_key) = &file_header.aes_key {
+                let offset = bytes.len() as u64;
+                LE::write_u64(&mut bytes[aes_key_position..aes_key_position + 8], offset);
+                bytes.extend_from_slice(&aes_key.key);
+                BHD5::write_ranges(&mut bytes, &aes_key.ranges)?;
+            }
+        }
+
+        let file_size = util::checked_cast(bytes.len(), "BHD5 size")?;
+        LE::write_u32(&mut bytes[BHD5::FILE_SIZE_OFFSET..BHD5::FILE_SIZE_OFFSET + 4], file_size);
+
+        Ok(bytes)
+    }
+
+    // Returns the positions of the salted hash and AES key offsets, to be filled in once those are written.
+    fn write_file_header(bytes: &mut Vec<u8>, file_header: &FileHeader, format: BHD5Format) -> Result<(usize, usize), DantelionFormatsError> {
+        let positions;
+        if format == BHD5Format::EldenRing {
+            bytes.write_u64::<LE>(file_header.file_path_hash)?;
+            bytes.write_u32::<LE>(file_header.padded_file_size)?;
+            // ER stores the size in 32 bits, even though the offset is 64.
+            bytes.write_u32::<LE>(util::checked_cast(file_header.file_size, "File size")?)?;
+            bytes.write_u64::<LE>(file_header.file_offset)?;
+            positions = (bytes.len(), bytes.len() + 8);
+            bytes.write_u64::<LE>(0)?;
+            bytes.write_u64::<LE>(0)?;
+        } else {
+            bytes.write_u32::<LE>(util::checked_cast(file_header.file_path_hash, "32-bit path hash")?)?;
+            bytes.write_u32::<LE>(file_header.padded_file_size)?;
+            bytes.write_u64::<LE>(file_header.file_offset)?;
+            positions = (bytes.len(), bytes.len() + 8);
+            bytes.write_u64::<LE>(0)?;
+            bytes.write_u64::<LE>(0)?;
+            if format == BHD5Format::DarkSoulsIII {
+                bytes.write_u64::<LE>(file_header.file_size)?;
+            }
+        }
+
+        Ok(positions)
+    }
+
+    fn write_ranges(bytes: &mut Vec<u8>, ranges: &[Range]) -> Result<(), DantelionFormatsError> {
+        bytes.write_u32::<LE>(util::checked_cast(ranges.len(), "Range count")?)?;
+        for range in ranges {
+            bytes.write_u64::<LE>(range.begin)?;
+            bytes.write_u64::<LE>(range.end)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads a BHD5, decrypting it with the game's key unless it's already decrypted, like the ones in some dumps.
+    /// Keys come from `Config::key_store`, so archives without a built in key can be read by adding theirs to the
+    /// config. Encrypted BHD5s need the crypto feature.
     pub fn from_path(path: &str) -> Result<BHD5, DantelionFormatsError> {
+        let mut buffer = Vec::new();
+        BHD5::from_path_with_buffer(path, &mut buffer)
+    }
+
+    /// Same as `from_path`, but decrypts into `buffer` so the scratch space can be reused when parsing
+    /// several archives.
+    pub fn from_path_with_buffer(path: &str, buffer: &mut Vec<u8>) -> Result<BHD5, DantelionFormatsError> {
+        BHD5::parse_path(path, buffer, BHD5::from_bytes)
+    }
+
+    // Decrypts the file at `path` if it needs it, then hands it to `parse`.
+    fn parse_path<T>(path: &str, buffer: &mut Vec<u8>, parse: fn(&[u8]) -> Result<T, DantelionFormatsError>) -> Result<T, DantelionFormatsError> {
         let file = fs::read(path)?;
-        let key = crypto_util::get_elden_ring_bhd5_key(path)?;
-        let decrypted = crypto_util::decrypt_bhd5_file(file.as_slice(), key)?;
-        BHD5::from_bytes(&decrypted)
+        if BHD5::is_decrypted(&file) {
+            return parse(&file);
+        }
+
+        #[cfg(not(feature = "crypto"))]
+        {
+            let _ = buffer;
+            Err(DantelionFormatsError::IoError(Error::new(ErrorKind::Unsupported, "Encrypted BHD5s need the crypto feature")))
+        }
+        #[cfg(feature = "crypto")]
+        {
+            Config::load()?.key_store().decrypt_into(GameType::EldenRing, path, &file, None, buffer)?;
+            parse(buffer)
+        }
+    }
+
+    /// Whether `file` is a plain BHD5 rather than an RSA encrypted one. Encrypted data starting with the magic is
+    /// possible, but at odds of one in four billion.
+    pub fn is_decrypted(file: &[u8]) -> bool {
+        file.starts_with(b"BHD5")
+    }
+
+    /// For an already decrypted BHD5 in any `DataSource`.
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<BHD5, DantelionFormatsError> {
+        BHD5::from_bytes(&source.read_all()?)
     }
 
     pub fn from_bytes(file: &[u8]) -> Result<BHD5, DantelionFormatsError> {
@@ -102,7 +621,7 @@ impl BHD5 {
         let header = BHD5::read_bhd5_header(&mut c)?;
         let format = BHD5::get_bhd5_format(&header.salt);
 
-        let mut buckets: Vec<BHD5Bucket> = Vec::with_capacity(header.bucket_count as usize);
+        let mut buckets: Vec<BHD5Bucket> = Vec::with_capacity(util::capacity_for(&c, header.bucket_count as u64));
 
         for _ in 0..header.bucket_count {
             let file_header_count = c.read_u32::<LE>()?;
@@ -133,7 +652,7 @@ impl BHD5 {
         let bucket_count=  c.read_u32::<LE>()?;
         let buckets_offset=  c.read_u32::<LE>()?;
         let salt_len=  c.read_u32::<LE>()?;
-        let salt=  c.read_bytes(salt_len as usize)?;
+        let salt=  util::read_len(c, salt_len as u64)?;
         let header = BHD5Header {
             magic,
             unk04,
@@ -148,54 +667,69 @@ impl BHD5 {
             salt,
         };
 
-        header.validate();
+        header.validate()?;
 
         Ok(header)
     }
 
     fn get_bhd5_format(salt: &[u8]) -> BHD5Format {
-        if &salt[..3] == b"GR_" {
+        if salt.starts_with(b"GR_") {
             return BHD5Format::EldenRing;
-        } else if &salt[..4] == b"FDP_" || &salt[..4] == b"NTC_" {
+        } else if salt.starts_with(b"FDP_") || salt.starts_with(b"NTC_") {
             return BHD5Format::DarkSoulsIII;
         }
         BHD5Format::DarkSoulsII
     }
 
     fn read_file_headers(c: &mut Cursor<&[u8]>, file_header_count: u64, file_headers_offset: u64, format: BHD5Format) -> Result<Vec<FileHeader>, DantelionFormatsError> {
-        let mut headers: Vec<FileHeader> = Vec::with_capacity(file_header_count as usize);
         let start = c.position();
         c.set_position(file_headers_offset);
+        let mut headers: Vec<FileHeader> = Vec::with_capacity(util::checked_count(c, file_header_count, BHD5::file_header_size(format), "File header count")?);
         for _ in 0..file_header_count {
-            if format == BHD5Format::EldenRing {
-                let file_path_hash = c.read_u64::<LE>()?;
-                let padded_file_size = c.read_u32::<LE>()?;
-                let file_size = c.read_u32::<LE>()? as u64; //Read a 32 bit file size, but store it in a 64 bit field
-                let file_offset = c.read_u64::<LE>()?;
-                let salted_hash_offset = c.read_u64::<LE>()?;
-                let aes_key_offset = c.read_u64::<LE>()?;
-                let salted_hash = if salted_hash_offset == 0 { None } else { Some(BHD5::read_salted_hash(c, salted_hash_offset)?) };
-                let aes_key = if aes_key_offset == 0 { None } else { Some(BHD5::read_aes_key(c, aes_key_offset)?) };
-                headers.push(FileHeader { file_path_hash, padded_file_size, file_size, file_offset, salted_hash_offset, aes_key_offset, salted_hash, aes_key })
-            } else {
-                let file_path_hash = c.read_u32::<LE>()? as u64; //Read a 32 bit hash, but store it in a 64 bit field
-                let padded_file_size = c.read_u32::<LE>()?;
-                let file_offset = c.read_u64::<LE>()?;
-                let salted_hash_offset = c.read_u64::<LE>()?;
-                let aes_key_offset = c.read_u64::<LE>()?;
-                let salted_hash = if salted_hash_offset == 0 { None } else { Some(BHD5::read_salted_hash(c, salted_hash_offset)?) };
-                let aes_key = if aes_key_offset == 0 { None } else { Some(BHD5::read_aes_key(c, aes_key_offset)?) };
-                let mut file_size = 0;
-                if format == BHD5Format::DarkSoulsIII {
-                    file_size = c.read_u64::<LE>()?;
-                }
-                headers.push(FileHeader { file_path_hash, padded_file_size, file_size, file_offset, salted_hash_offset, aes_key_offset, salted_hash, aes_key })
+            let mut file_header = BHD5::read_file_header(c, format)?;
+            if file_header.salted_hash_offset != 0 {
+                file_header.salted_hash = Some(BHD5::read_salted_hash(c, file_header.salted_hash_offset)?);
+            }
+            if file_header.aes_key_offset != 0 {
+                file_header.aes_key = Some(BHD5::read_aes_key(c, file_header.aes_key_offset)?);
             }
+            headers.push(file_header);
         }
         c.set_position(start);
         return Ok(headers);
     }
 
+    // The size of what `read_file_header` reads.
+    fn file_header_size(format: BHD5Format) -> u64 {
+        match format {
+            BHD5Format::DarkSoulsII => 0x20,
+            BHD5Format::DarkSoulsIII | BHD5Format::EldenRing => 0x28,
+        }
+    }
+
+    // The fixed size part of a file header, its salted hash and AES key are left for the caller to read.
+    fn read_file_header(c: &mut Cursor<&[u8]>, format: BHD5Format) -> Result<FileHeader, DantelionFormatsError> {
+        let file_path_hash = if format == BHD5Format::EldenRing {
+            c.read_u64::<LE>()?
+        } else {
+            c.read_u32::<LE>()? as u64 //Read a 32 bit hash, but store it in a 64 bit field
+        };
+        let padded_file_size = c.read_u32::<LE>()?;
+        let mut file_size = if format == BHD5Format::EldenRing {
+            c.read_u32::<LE>()? as u64 //Read a 32 bit file size, but store it in a 64 bit field
+        } else {
+            0
+        };
+        let file_offset = c.read_u64::<LE>()?;
+        let salted_hash_offset = c.read_u64::<LE>()?;
+        let aes_key_offset = c.read_u64::<LE>()?;
+        if format == BHD5Format::DarkSoulsIII {
+            file_size = c.read_u64::<LE>()?;
+        }
+
+        Ok(FileHeader { file_path_hash, padded_file_size, file_size, file_offset, salted_hash_offset, aes_key_offset, salted_hash: None, aes_key: None })
+    }
+
     fn read_salted_hash(c: &mut Cursor<&[u8]>, salted_hash_offset: u64) -> Result<SaltedHash, DantelionFormatsError> {
         let start = c.position();
         c.set_position(salted_hash_offset);
@@ -230,7 +764,7 @@ impl BHD5 {
     }
 
     fn read_ranges(c: &mut Cursor<&[u8]>, range_count: u32) -> Result<Vec<Range>, DantelionFormatsError> {
-        let mut ranges: Vec<Range> = Vec::with_capacity(range_count as usize);
+        let mut ranges: Vec<Range> = Vec::with_capacity(util::checked_count(c, range_count as u64, 0x10, "Range count")?);
         for _ in 0..range_count {
             let begin = c.read_u64::<LE>()?;
             let end = c.read_u64::<LE>()?;
@@ -242,14 +776,307 @@ impl BHD5 {
 
 
 
+impl FileHeader {
+    /// Reads this file's data out of the BDT and decrypts its encrypted ranges. Offsets are u64, so on 32-bit targets
+    /// files past 4GB in the BDT can't be read from memory, use `read_data_from` with a `FileSource` instead.
+    pub fn read_data(&self, bdt: &[u8]) -> Result<Vec<u8>, DantelionFormatsError> {
+        self.decrypt_data(bdt.read_at(self.file_offset, util::checked_cast(self.padded_file_size, "Padded file size")?)?)
+    }
+
+    /// Same as `read_data`, but only reads this file's bytes, so the BDT doesn't have to be in memory, or even local.
+    pub fn read_data_from(&self, bdt: &(impl DataSource + ?Sized)) -> Result<Vec<u8>, DantelionFormatsError> {
+        self.decrypt_data(self.read_encrypted_from(bdt)?)
+    }
+
+    /// Reads and decrypts only the first `len` bytes of this file, e.g. to sniff its magic for a preview. Whole AES
+    /// blocks are read, so encrypted ranges still decrypt correctly, and the result is cut to `len`.
+    pub fn read_prefix_from(&self, bdt: &(impl DataSource + ?Sized), len: usize) -> Result<Vec<u8>, DantelionFormatsError> {
+        let padded_file_size: usize = util::checked_cast(self.padded_file_size, "Padded file size")?;
+        let block_len = len.div_ceil(BHD5::AES_KEY_SIZE).saturating_mul(BHD5::AES_KEY_SIZE);
+        let mut data = self.decrypt_data(bdt.read_at(self.file_offset, block_len.min(padded_file_size))?)?;
+        data.truncate(len);
+
+        Ok(data)
+    }
+
+    // The two halves of `read_data_from`, for callers that time them separately.
+    pub(crate) fn read_encrypted_from(&self, bdt: &(impl DataSource + ?Sized)) -> Result<Vec<u8>, DantelionFormatsError> {
+        bdt.read_at(self.file_offset, util::checked_cast(self.padded_file_size, "Padded file size")?)
+    }
+
+    pub(crate) fn decrypt_data(&self, mut data: Vec<u8>) -> Result<Vec<u8>, DantelionFormatsError> {
+        #[cfg(not(feature = "crypto"))]
+        if self.aes_key.is_some() {
+            return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::Unsupported, "Encrypted BDT entries need the crypto feature")));
+        }
+        #[cfg(feature = "crypto")]
+        if let Some(aes_key) = &self.aes_key {
+            // Unused ranges are stored as -1, so the wrap to i64 is intended.
+            let ranges: Vec<(i64, i64)> = aes_key.ranges.iter().map(|range| (range.begin as i64, range.end as i64)).collect();
+            crypto_util::crypt_aes_ranges(&mut data, &aes_key.key, &ranges, Mode::Decrypt)?;
+        }
+        if self.file_size != 0 {
+            data.truncate(util::checked_cast(self.file_size, "File size")?);
+        }
+
+        Ok(data)
+    }
+}
+
+impl CompactBHD5 {
+    pub const NONE: u32 = u32::MAX;
+
+    /// Same as `BHD5::from_path`, decrypting it if it needs to.
+    pub fn from_path(path: &str) -> Result<CompactBHD5, DantelionFormatsError> {
+        BHD5::parse_path(path, &mut Vec::new(), CompactBHD5::from_bytes)
+    }
+
+    /// For an already decrypted BHD5 in any `DataSource`.
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<CompactBHD5, DantelionFormatsError> {
+        CompactBHD5::from_bytes(&source.read_all()?)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<CompactBHD5, DantelionFormatsError> {
+        let mut c = Cursor::new(file);
+        let header = BHD5::read_bhd5_header(&mut c)?;
+        let format = BHD5::get_bhd5_format(&header.salt);
+
+        // The bucket table is small, reading it first lets every array be sized up front. Buckets are 8 bytes, so a bad
+        // count can't reserve more than the file could hold either.
+        let mut bucket_table = Vec::with_capacity((header.bucket_count as usize).min(file.len() / 8));
+        for _ in 0..header.bucket_count {
+            bucket_table.push((c.read_u32::<LE>()?, c.read_u32::<LE>()?));
+        }
+        let file_count: u64 = bucket_table.iter().map(|&(count, _)| count as u64).sum();
+        // Headers are at least 0x18 bytes, so a bad count can't reserve more than the file could hold.
+        let file_count = util::checked_cast::<usize, _>(file_count, "File header count")?.min(file.len() / 0x18);
+        let mut bhd5 = CompactBHD5 {
+            format,
+            bhd5_header: header,
+            buckets: Vec::with_capacity(bucket_table.len()),
+            file_path_hashes: Vec::with_capacity(file_count),
+            padded_file_sizes: Vec::with_capacity(file_count),
+            file_sizes: Vec::with_capacity(file_count),
+            file_offsets: Vec::with_capacity(file_count),
+            salted_hashes: Vec::with_capacity(file_count),
+            aes_keys: Vec::with_capacity(file_count),
+            entries: vec![],
+            bytes: vec![],
+            ranges: vec![],
+        };
+
+        for (file_header_count, file_headers_offset) in bucket_table {
+            let start = util::checked_cast(bhd5.len(), "File count")?;
+            c.set_position(file_headers_offset as u64);
+            for _ in 0..file_header_count {
+                let file_header = BHD5::read_file_header(&mut c, format)?;
+                let salted_hash = bhd5.read_entry(&mut c, file_header.salted_hash_offset, BHD5::SALTED_HASH_SIZE)?;
+                let aes_key = bhd5.read_entry(&mut c, file_header.aes_key_offset, BHD5::AES_KEY_SIZE)?;
+                bhd5.file_path_hashes.push(file_header.file_path_hash);
+                bhd5.padded_file_sizes.push(file_header.padded_file_size);
+                bhd5.file_sizes.push(file_header.file_size);
+                bhd5.file_offsets.push(file_header.file_offset);
+                bhd5.salted_hashes.push(salted_hash);
+                bhd5.aes_keys.push(aes_key);
+            }
+            bhd5.buckets.push(Span { start, len: file_header_count });
+        }
+        // These grew as they went, and are kept around.
+        bhd5.entries.shrink_to_fit();
+        bhd5.bytes.shrink_to_fit();
+        bhd5.ranges.shrink_to_fit();
+
+        Ok(bhd5)
+    }
+
+    // Reads a salted hash or AES key onto the end of the tables, restoring the cursor.
+    fn read_entry(&mut self, c: &mut Cursor<&[u8]>, offset: u64, size: usize) -> Result<u32, DantelionFormatsError> {
+        if offset == 0 {
+            return Ok(CompactBHD5::NONE);
+        }
+        let position = c.position();
+        c.set_position(offset);
+
+        let index = util::checked_cast(self.entries.len(), "Entry count")?;
+        let bytes = Span { start: util::checked_cast(self.bytes.len(), "Key bytes length")?, len: size as u32 };
+        let end = self.bytes.len() + size;
+        self.bytes.resize(end, 0);
+        c.read_exact(&mut self.bytes[end - size..])?;
+        let range_count = c.read_u32::<LE>()?;
+        let ranges = Span { start: util::checked_cast(self.ranges.len(), "Range count")?, len: range_count };
+        for _ in 0..range_count {
+            let begin = c.read_u64::<LE>()?;
+            let end = c.read_u64::<LE>()?;
+            self.ranges.push(Range { begin, end });
+        }
+        self.entries.push((bytes, ranges));
+        c.set_position(position);
+
+        Ok(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.file_path_hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.file_path_hashes.is_empty()
+    }
+
+    /// The indices of the files in bucket `index`.
+    pub fn bucket(&self, index: usize) -> std::ops::Range<usize> {
+        let bucket = self.buckets[index];
+        bucket.start as usize..bucket.start as usize + bucket.len as usize
+    }
+
+    /// The index of `path`, looked up in its bucket.
+    pub fn find(&self, path: &str) -> Option<usize> {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        let hash = BHD5::hash_path(path, self.format);
+        self.bucket((hash % self.buckets.len() as u64) as usize).find(|&index| self.file_path_hashes[index] == hash)
+    }
+
+    /// The salted hash of file `index` and the ranges it covers.
+    pub fn salted_hash(&self, index: usize) -> Option<(&[u8], &[Range])> {
+        self.entry(self.salted_hashes[index])
+    }
+
+    /// The AES key of file `index` and the ranges it encrypts.
+    pub fn aes_key(&self, index: usize) -> Option<(&[u8], &[Range])> {
+        self.entry(self.aes_keys[index])
+    }
+
+    fn entry(&self, entry: u32) -> Option<(&[u8], &[Range])> {
+        let (bytes, ranges) = *self.entries.get(entry as usize)?;
+        Some((
+            &self.bytes[bytes.start as usize..bytes.start as usize + bytes.len as usize],
+            &self.ranges[ranges.start as usize..ranges.start as usize + ranges.len as usize],
+        ))
+    }
+
+    /// File `index` with its own hash, key and ranges, e.g. to read its data with `FileHeader::read_data_from`.
+    pub fn file_header(&self, index: usize) -> FileHeader {
+        FileHeader {
+            file_path_hash: self.file_path_hashes[index],
+            padded_file_size: self.padded_file_sizes[index],
+            file_size: self.file_sizes[index],
+            file_offset: self.file_offsets[index],
+            salted_hash_offset: 0,
+            aes_key_offset: 0,
+            salted_hash: self.salted_hash(index).map(|(hash, ranges)| SaltedHash {
+                hash: hash.to_vec(),
+                range_count: ranges.len() as u32,
+                ranges: ranges.to_vec(),
+            }),
+            aes_key: self.aes_key(index).map(|(key, ranges)| AESKey {
+                key: key.to_vec(),
+                range_count: ranges.len() as u32,
+                ranges: ranges.to_vec(),
+            }),
+        }
+    }
+
+    /// Back to a regular `BHD5`, e.g. to edit and write it.
+    pub fn to_bhd5(&self) -> BHD5 {
+        let buckets = self.buckets.iter().enumerate().map(|(index, bucket)| BHD5Bucket {
+            file_header_count: bucket.len,
+            file_headers_offset: 0,
+            file_headers: self.bucket(index).map(|file| self.file_header(file)).collect(),
+        }).collect();
+
+        BHD5 {
+            format: self.format,
+            bhd5_header: self.bhd5_header.clone(),
+            buckets,
+        }
+    }
+}
+
+impl IntoIterator for BHD5 {
+    type Item = FileHeader;
+    type IntoIter = Flatten<vec::IntoIter<BHD5Bucket>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.buckets.into_iter().flatten()
+    }
+}
+
+impl<'a> IntoIterator for &'a BHD5 {
+    type Item = &'a FileHeader;
+    type IntoIter = Flatten<slice::Iter<'a, BHD5Bucket>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut BHD5 {
+    type Item = &'a mut FileHeader;
+    type IntoIter = Flatten<slice::IterMut<'a, BHD5Bucket>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl IntoIterator for BHD5Bucket {
+    type Item = FileHeader;
+    type IntoIter = vec::IntoIter<FileHeader>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.file_headers.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a BHD5Bucket {
+    type Item = &'a FileHeader;
+    type IntoIter = slice::Iter<'a, FileHeader>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.file_headers.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut BHD5Bucket {
+    type Item = &'a mut FileHeader;
+    type IntoIter = slice::IterMut<'a, FileHeader>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.file_headers.iter_mut()
+    }
+}
+
+/// A one line summary, e.g. "BHD5 EldenRing — 4096 files in 587 buckets".
+impl Display for BHD5 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "BHD5 {:?} — {} files in {} buckets", self.format, self.iter().count(), self.buckets.len())
+    }
+}
+
+impl BHD5Header {
+    // Stable accessors, see `BND4Header`.
+
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+
+    pub fn bucket_count(&self) -> u32 {
+        self.bucket_count
+    }
+}
+
 impl Validate for BHD5Header {
-    fn validate(&self) {
-        assert_eq!(self.magic, "BHD5");
-        assert_eq!(self.unk04, u8::MAX, "header.unk04: {}", self.unk04);
-        assert!(self.unk05 == 0 || self.unk05 == 1, "header.unk05: {}", self.unk05);
-        assert_eq!(self.unk06, 0, "header.unk06: {}", self.unk06);
-        assert_eq!(self.unk07, 0, "header.unk07: {}", self.unk07);
-        assert_eq!(self.unk08, 1, "header.unk08: {}", self.unk08);
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.magic == "BHD5", "Magic was {}", self.magic);
+        ensure!(self.unk04 == u8::MAX, "header.unk04: {}", self.unk04);
+        ensure!(self.unk05 == 0 || self.unk05 == 1, "header.unk05: {}", self.unk05);
+        ensure!(self.unk06 == 0, "header.unk06: {}", self.unk06);
+        ensure!(self.unk07 == 0, "header.unk07: {}", self.unk07);
+        ensure!(self.unk08 == 1, "header.unk08: {}", self.unk08);
+
+        Ok(())
     }
 }
 
@@ -0,0 +1,145 @@
+use std::collections::BTreeSet;
+use std::fmt::{self, Display, Formatter};
+
+/// The kinds of binders whose entry ids follow a convention the games rely on.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BinderType {
+    Chrbnd,
+    Partsbnd,
+    Objbnd,
+    Anibnd,
+    // Ids aren't fixed, see `behbnd` for typing the entries.
+    Behbnd,
+    // No convention, ids are handed out in order from 0.
+    Generic,
+}
+
+impl BinderType {
+    /// Guesses the binder type from a file name like "c0000.chrbnd.dcx".
+    pub fn from_file_name(name: &str) -> BinderType {
+        let name = name.to_lowercase();
+        if name.contains(".chrbnd") {
+            BinderType::Chrbnd
+        } else if name.contains(".partsbnd") {
+            BinderType::Partsbnd
+        } else if name.contains(".objbnd") {
+            BinderType::Objbnd
+        } else if name.contains(".anibnd") {
+            BinderType::Anibnd
+        } else if name.contains(".behbnd") {
+            BinderType::Behbnd
+        } else {
+            BinderType::Generic
+        }
+    }
+
+    // (name suffix, first id). Checked in order, so more specific suffixes come first.
+    fn id_rules(&self) -> &'static [(&'static str, i32)] {
+        match self {
+            BinderType::Chrbnd => &[
+                (".tpf", 100),
+                (".flver", 200),
+                (".hkxpwv", 300),
+                (".clm2", 400),
+                ("_c.hkx", 500),
+                (".hkx", 700),
+            ],
+            BinderType::Partsbnd | BinderType::Objbnd => &[
+                (".tpf", 100),
+                (".flver", 200),
+                (".clm2", 400),
+                ("_c.hkx", 500),
+            ],
+            BinderType::Anibnd => &[
+                ("skeleton.hkx", 1000000),
+                (".tae", 3000000),
+            ],
+            BinderType::Behbnd | BinderType::Generic => &[],
+        }
+    }
+}
+
+/// A binder version string decoded as the date stamp it is, e.g. "07D7R6" is 2007-04-07, revision R6. Versions
+/// compare by date, then revision, so tools can tell which of two binders is newer.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub struct BinderVersion {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    // The letter and number after the day. `BND4Version::Timestamp` writes these as the hour and minute.
+    pub revision: char,
+    pub revision_number: u32,
+}
+
+impl BinderVersion {
+    /// Decodes a version string: two digit year, month as a letter from A, day, revision letter and number. Returns
+    /// `None` for strings that aren't date stamps.
+    pub fn parse(version: &str) -> Option<BinderVersion> {
+        let version = version.trim_end_matches('\0');
+        let year = version.get(..2)?.parse::<u16>().ok()?;
+        let mut rest = version.get(2..)?.chars();
+        let month = rest.next().filter(|month| ('A'..='L').contains(month))? as u8 - b'A' + 1;
+        let rest = rest.as_str();
+
+        let day_len = rest.find(|c: char| !c.is_ascii_digit())?;
+        let day = rest[..day_len].parse::<u8>().ok().filter(|day| (1..=31).contains(day))?;
+        let mut rest = rest[day_len..].chars();
+        let revision = rest.next().filter(char::is_ascii_uppercase)?;
+        let revision_number = rest.as_str().parse::<u32>().ok()?;
+
+        Some(BinderVersion {
+            year: 2000 + year,
+            month,
+            day,
+            revision,
+            revision_number,
+        })
+    }
+}
+
+/// Writes the version string back out, e.g. "07D7R6".
+impl Display for BinderVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}{}{}{}{}", self.year % 100, (b'A' + self.month - 1) as char, self.day, self.revision, self.revision_number)
+    }
+}
+
+/// Hands out entry ids following the conventions of a binder type. The first file with a given extension gets
+/// the base id, and later ones the next free id after it, e.g. c0000.flver = 200 and c0000_1.flver = 201.
+#[derive(Debug)]
+pub struct IdAllocator {
+    binder_type: BinderType,
+    used: BTreeSet<i32>,
+}
+
+impl IdAllocator {
+    pub fn new(binder_type: BinderType) -> IdAllocator {
+        IdAllocator {
+            binder_type,
+            used: BTreeSet::new(),
+        }
+    }
+
+    /// Marks an id as taken, for files added with an explicit id.
+    pub fn reserve(&mut self, id: i32) {
+        self.used.insert(id);
+    }
+
+    /// The base id for a file name, without taking into account ids that are already used.
+    pub fn base_id(&self, name: &str) -> i32 {
+        let name = name.to_lowercase();
+        self.binder_type.id_rules().iter()
+            .find(|(suffix, _)| name.ends_with(suffix))
+            .map_or(0, |(_, id)| *id)
+    }
+
+    pub fn allocate(&mut self, name: &str) -> i32 {
+        let mut id = self.base_id(name);
+        while self.used.contains(&id) {
+            id += 1;
+        }
+        self.used.insert(id);
+
+        id
+    }
+}
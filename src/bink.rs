@@ -0,0 +1,106 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::Cursor;
+use binary_interpreter::binary_reader::BinaryReader;
+use byteorder::{LE, ReadBytesExt};
+use crate::error::DantelionFormatsError;
+use crate::source::{DataSource, FileSource};
+use crate::util::{ensure, Validate};
+
+/// The header of a Bink or Bink 2 movie. Only metadata, the frames aren't decoded.
+#[derive(Debug)]
+#[repr(C)]
+#[non_exhaustive]
+pub struct BinkHeader {
+    // "BIK" or "KB2"
+    pub magic: String,
+    pub revision: u8,
+    // Of the whole file. Stored as the size minus 8.
+    pub file_size: u32,
+    pub frame_count: u32,
+    pub largest_frame_size: u32,
+    pub unk10: u32,
+    pub width: u32,
+    pub height: u32,
+    pub fps_numerator: u32,
+    pub fps_denominator: u32,
+    pub flags: u32,
+    pub audio_track_count: u32,
+}
+
+impl BinkHeader {
+    const MAGIC_SIZE: usize = 3;
+    const HEADER_SIZE: u64 = 0x2C;
+
+    pub(crate) fn is(bytes: &[u8]) -> bool {
+        bytes.starts_with(b"BIK") || bytes.starts_with(b"KB2")
+    }
+
+    /// Reads just the header, so movies don't have to be loaded whole.
+    pub fn from_path(path: &str) -> Result<BinkHeader, DantelionFormatsError> {
+        BinkHeader::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<BinkHeader, DantelionFormatsError> {
+        let header = source.read_at(0, BinkHeader::HEADER_SIZE.min(source.size()?) as usize)?;
+
+        BinkHeader::from_bytes(&header)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<BinkHeader, DantelionFormatsError> {
+        let mut c = Cursor::new(file);
+        let header = BinkHeader {
+            magic: c.read_fixed_cstr(BinkHeader::MAGIC_SIZE)?,
+            revision: c.read_u8()?,
+            file_size: c.read_u32::<LE>()?.saturating_add(8),
+            frame_count: c.read_u32::<LE>()?,
+            largest_frame_size: c.read_u32::<LE>()?,
+            unk10: c.read_u32::<LE>()?,
+            width: c.read_u32::<LE>()?,
+            height: c.read_u32::<LE>()?,
+            fps_numerator: c.read_u32::<LE>()?,
+            fps_denominator: c.read_u32::<LE>()?,
+            flags: c.read_u32::<LE>()?,
+            audio_track_count: c.read_u32::<LE>()?,
+        };
+
+        header.validate()?;
+
+        Ok(header)
+    }
+
+    pub fn is_bink2(&self) -> bool {
+        self.magic == "KB2"
+    }
+
+    pub fn fps(&self) -> f64 {
+        if self.fps_denominator == 0 {
+            return 0.0;
+        }
+
+        self.fps_numerator as f64 / self.fps_denominator as f64
+    }
+
+    pub fn duration_seconds(&self) -> f64 {
+        let fps = self.fps();
+        if fps == 0.0 {
+            return 0.0;
+        }
+
+        self.frame_count as f64 / fps
+    }
+}
+
+/// A one line summary, e.g. "KB2 1920x1080 — 1800 frames at 30.00 fps".
+impl Display for BinkHeader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}x{} — {} frames at {:.2} fps", self.magic, self.width, self.height, self.frame_count, self.fps())
+    }
+}
+
+impl Validate for BinkHeader {
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.magic == "BIK" || self.magic == "KB2", "Magic was {}", self.magic);
+
+        Ok(())
+    }
+}
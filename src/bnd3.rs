@@ -0,0 +1,541 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::ops::{Index, IndexMut};
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind};
+use std::{slice, vec};
+use binary_interpreter::binary_reader::{BinaryPeeker, BinaryReader};
+use byteorder::{BE, LE, ByteOrder, ReadBytesExt, WriteBytesExt};
+use crate::binder::BinderVersion;
+use crate::bnd4::{BND4, BND4Builder, BND4FileRef, BND4Version};
+use crate::dcx::{DCX, DcxInfo};
+use crate::error::DantelionFormatsError;
+use crate::flver::FLVER;
+use crate::kind::FileKind;
+use crate::mqb::MQB;
+use crate::parsed_file::{self, strip_dcx, ParsedFile};
+use crate::sound::{BNK, FSB5};
+use crate::source::{DataSource, FileSource};
+use crate::tpf::TPF;
+use crate::util;
+use crate::util::{DataLen, NameMatcher, ensure, Validate};
+
+/// The binder used by Demon's Souls, DS1 and DSR, and for some files in later games.
+#[derive(Debug)]
+#[repr(C)]
+pub struct BND3 {
+    pub header: BND3Header,
+    pub files: Vec<BND3File>,
+    // How the binder was compressed when it was read, and will be again by `to_bytes`. None writes it uncompressed.
+    pub dcx: Option<DcxInfo>,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+#[non_exhaustive]
+pub struct BND3Header {
+    pub magic: String,
+    pub version: String,
+    pub raw_format: u8,
+    pub big_endian: bool,
+    pub bit_big_endian: bool,
+    pub unk0f: u8,
+    pub file_count: u32,
+    // Not including the padding before the data
+    pub file_headers_end: u32,
+    pub unk18: u32,
+    pub unk1c: u32,
+}
+
+#[repr(C)]
+#[non_exhaustive]
+pub struct BND3File {
+    pub raw_flags: u8,
+    pub unk01: u8,
+    pub unk02: u8,
+    pub unk03: u8,
+    pub compressed_size: u32,
+    pub data_offset: u64,
+    pub id: Option<i32>,
+    pub name_offset: Option<u32>,
+    pub uncompressed_size: Option<u32>,
+    pub name: Option<String>,
+    pub data: Option<Vec<u8>>,
+}
+
+impl BND3 {
+    const MAGIC_SIZE: usize = 4;
+    const VERSION_SIZE: usize = 8;
+    const FORMAT_OFFSET: u64 = 0xC;
+    const BIG_ENDIAN_OFFSET: u64 = 0xD;
+    const BIT_BIG_ENDIAN_OFFSET: u64 = 0xE;
+    const FILE_HEADERS_END_OFFSET: usize = 0x14;
+    const DATA_ALIGNMENT: usize = 0x10;
+    // Not supported by BND4 in this crate, so dropped when converting.
+    const LONG_OFFSETS: u8 = 0b00010000;
+
+    pub fn from_path(path: &str) -> Result<BND3, DantelionFormatsError> {
+        BND3::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<BND3, DantelionFormatsError> {
+        let file = source.read_all()?;
+
+        BND3::from_bytes(&file)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<BND3, DantelionFormatsError> {
+        let mut decompressed = vec![];
+        let dcx = DCX::decompress_layers_into(file, &mut decompressed)?;
+        let bytes = if dcx.is_some() { &decompressed[..] } else { file };
+        let mut c = Cursor::new(bytes);
+
+        // The BigEndian format flag forces big endian even when the header byte says otherwise.
+        let bit_big_endian = c.peek_u8(BND3::BIT_BIG_ENDIAN_OFFSET)? != 0;
+        let raw_format = c.peek_u8(BND3::FORMAT_OFFSET)?;
+        let format = if bit_big_endian { raw_format } else { util::reverse_bits(raw_format) };
+        let be = c.peek_u8(BND3::BIG_ENDIAN_OFFSET)? != 0 || format & 0b00000001 != 0;
+
+        let header = if be { BND3::read_bnd3_header::<BE>(&mut c)? } else { BND3::read_bnd3_header::<LE>(&mut c)? };
+        let files = if be { BND3::read_bnd3_files::<BE>(&mut c, &header)? } else { BND3::read_bnd3_files::<LE>(&mut c, &header)? };
+
+        Ok(BND3 {
+            header,
+            files,
+            dcx,
+        })
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, BND3File> {
+        self.files.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> slice::IterMut<'_, BND3File> {
+        self.files.iter_mut()
+    }
+
+    pub fn file_index<'a>(&self, file: impl Into<BND4FileRef<'a>>) -> Option<usize> {
+        match file.into() {
+            BND4FileRef::Id(id) => self.files.iter().position(|file| file.id == Some(id)),
+            BND4FileRef::Name(name) => self.files.iter().position(|file| file.name.as_deref().is_some_and(|file_name| NameMatcher::new().matches(file_name, name))),
+        }
+    }
+
+    pub fn get<'a>(&self, file: impl Into<BND4FileRef<'a>>) -> Option<&BND3File> {
+        self.file_index(file).map(|index| &self.files[index])
+    }
+
+    pub fn get_mut<'a>(&mut self, file: impl Into<BND4FileRef<'a>>) -> Option<&mut BND3File> {
+        self.file_index(file).map(|index| &mut self.files[index])
+    }
+
+    pub fn to_path(&self, path: &str) -> Result<(), DantelionFormatsError> {
+        Ok(fs::write(path, self.to_bytes()?)?)
+    }
+
+    /// Serializes the binder. Counts, offsets and sizes are recalculated from `files`. Names are written as
+    /// Shift-JIS, so names that can't be encoded are an error. Binders read from a DCX are compressed again, see `dcx`.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        let bytes = if self.header.big_endian || self.header.format() & 0b00000001 != 0 {
+            self.write_bnd3::<BE>()?
+        } else {
+            self.write_bnd3::<LE>()?
+        };
+
+        match &self.dcx {
+            Some(dcx) => dcx.compress(&bytes),
+            None => Ok(bytes),
+        }
+    }
+
+    /// Converts to a BND4 with the same version, flags, ids and names.
+    pub fn to_bnd4(&self) -> BND4 {
+        let format = self.header.format() & !BND3::LONG_OFFSETS;
+        let big_endian = self.header.big_endian || format & 0b00000001 != 0;
+        let mut builder = BND4Builder::new()
+            .version(BND4Version::Custom(self.header.version.trim_end_matches('\0').to_string()))
+            .big_endian(big_endian)
+            .unicode(true)
+            .format(format);
+
+        for (i, file) in self.files.iter().enumerate() {
+            let data = file.data.clone().unwrap_or_default();
+            builder = builder.add_file(file.id.unwrap_or(i as i32), file.name.as_deref().unwrap_or(""), data);
+        }
+
+        let mut bnd4 = builder.build();
+        for (file, bnd3_file) in bnd4.files.iter_mut().zip(&self.files) {
+            let flags = if self.header.bit_big_endian { bnd3_file.raw_flags } else { util::reverse_bits(bnd3_file.raw_flags) };
+            file.raw_flags = if big_endian { flags } else { util::reverse_bits(flags) };
+            file.uncompressed_size = bnd3_file.uncompressed_size.map(|size| size as u64);
+            if bnd3_file.id.is_none() {
+                file.id = None;
+            }
+            if bnd3_file.name.is_none() {
+                file.name = None;
+            }
+        }
+        bnd4.dcx = self.dcx.clone();
+
+        bnd4
+    }
+
+    /// Converts a BND4 back to a BND3. Fails if a file is too large for BND3's 32-bit sizes or a name can't be
+    /// encoded as Shift-JIS.
+    pub fn from_bnd4(bnd4: &BND4) -> Result<BND3, DantelionFormatsError> {
+        let big_endian = bnd4.header.big_endian;
+        let mut files = Vec::with_capacity(bnd4.files.len());
+        for file in &bnd4.files {
+            let flags = if big_endian { file.raw_flags } else { util::reverse_bits(file.raw_flags) };
+            let size = file.data.as_ref().map_or(0, |data| data.len());
+            let compressed_size = u32::try_from(size).map_err(|_| too_large(file.name.as_deref()))?;
+            let uncompressed_size = file.uncompressed_size
+                .map(|size| u32::try_from(size).map_err(|_| too_large(file.name.as_deref())))
+                .transpose()?;
+            if let Some(name) = &file.name {
+                util::write_shift_jis(&mut vec![], name)?;
+            }
+
+            files.push(BND3File {
+                raw_flags: if big_endian { flags } else { util::reverse_bits(flags) },
+                unk01: 0,
+                unk02: 0,
+                unk03: 0,
+                compressed_size,
+                data_offset: 0,
+                id: file.id,
+                name_offset: file.name.as_ref().map(|_| 0),
+                uncompressed_size,
+                name: file.name.clone(),
+                data: file.data.clone(),
+            });
+        }
+
+        let format = bnd4.header.format();
+        Ok(BND3 {
+            header: BND3Header {
+                magic: "BND3".to_string(),
+                version: bnd4.header.version.clone(),
+                raw_format: if big_endian { format } else { util::reverse_bits(format) },
+                big_endian,
+                bit_big_endian: big_endian,
+                unk0f: 0,
+                file_count: files.len() as u32,
+                file_headers_end: 0,
+                unk18: 0,
+                unk1c: 0,
+            },
+            files,
+            dcx: bnd4.dcx.clone(),
+        })
+    }
+
+    fn write_bnd3<T: ByteOrder>(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        let header = &self.header;
+        let format = header.format();
+        let mut bytes = vec![];
+
+        util::write_fixed_str(&mut bytes, &header.magic, BND3::MAGIC_SIZE)?;
+        util::write_fixed_str(&mut bytes, &header.version, BND3::VERSION_SIZE)?;
+        bytes.write_u8(header.raw_format)?;
+        bytes.write_u8(header.big_endian as u8)?;
+        bytes.write_u8(header.bit_big_endian as u8)?;
+        bytes.write_u8(header.unk0f)?;
+        bytes.write_u32::<T>(self.files.len() as u32)?;
+        bytes.write_u32::<T>(0)?; // file_headers_end
+        bytes.write_u32::<T>(header.unk18)?;
+        bytes.write_u32::<T>(header.unk1c)?;
+
+        let mut data_offset_positions = Vec::with_capacity(self.files.len());
+        let mut name_offset_positions = Vec::with_capacity(self.files.len());
+        for file in &self.files {
+            let size = file.data.as_ref().map_or(0, |data| data.len() as u32);
+            bytes.write_u8(file.raw_flags)?;
+            bytes.write_u8(file.unk01)?;
+            bytes.write_u8(file.unk02)?;
+            bytes.write_u8(file.unk03)?;
+            bytes.write_u32::<T>(size)?;
+            data_offset_positions.push(bytes.len());
+            if format & BND3::LONG_OFFSETS != 0 {
+                bytes.write_u64::<T>(0)?;
+            } else {
+                bytes.write_u32::<T>(0)?;
+            }
+            if format & 0b00000010 != 0 {
+                bytes.write_i32::<T>(file.id.unwrap_or(-1))?;
+            }
+            if format & 0b00000100 != 0 || format & 0b00001000 != 0 {
+                name_offset_positions.push(bytes.len());
+                bytes.write_u32::<T>(0)?;
+            }
+            if format & 0b00100000 != 0 {
+                bytes.write_u32::<T>(file.uncompressed_size.unwrap_or(size))?;
+            }
+        }
+
+        for (file, position) in self.files.iter().zip(name_offset_positions) {
+            let offset = bytes.len() as u32;
+            T::write_u32(&mut bytes[position..position + 4], offset);
+            util::write_shift_jis(&mut bytes, file.name.as_deref().unwrap_or(""))?;
+        }
+
+        let headers_end = bytes.len() as u32;
+        T::write_u32(&mut bytes[BND3::FILE_HEADERS_END_OFFSET..BND3::FILE_HEADERS_END_OFFSET + 4], headers_end);
+
+        for (file, position) in self.files.iter().zip(data_offset_positions) {
+            let data = file.data.as_deref().unwrap_or(&[]);
+            if !data.is_empty() {
+                util::pad_to(&mut bytes, BND3::DATA_ALIGNMENT);
+            }
+            let offset = bytes.len() as u64;
+            if format & BND3::LONG_OFFSETS != 0 {
+                T::write_u64(&mut bytes[position..position + 8], offset);
+            } else {
+                T::write_u32(&mut bytes[position..position + 4], offset as u32);
+            }
+            bytes.extend_from_slice(data);
+        }
+
+        Ok(bytes)
+    }
+
+    fn read_bnd3_header<T: ByteOrder>(c: &mut Cursor<&[u8]>) -> Result<BND3Header, DantelionFormatsError> {
+        let header = BND3Header {
+            magic: c.read_fixed_cstr(BND3::MAGIC_SIZE)?,
+            version: c.read_fixed_cstr(BND3::VERSION_SIZE)?,
+            raw_format: c.read_u8()?,
+            big_endian: c.read_u8()? != 0,
+            bit_big_endian: c.read_u8()? != 0,
+            unk0f: c.read_u8()?,
+            file_count: c.read_u32::<T>()?,
+            file_headers_end: c.read_u32::<T>()?,
+            unk18: c.read_u32::<T>()?,
+            unk1c: c.read_u32::<T>()?,
+        };
+
+        header.validate()?;
+
+        Ok(header)
+    }
+
+    fn read_bnd3_files<T: ByteOrder>(c: &mut Cursor<&[u8]>, header: &BND3Header) -> Result<Vec<BND3File>, DantelionFormatsError> {
+        let format = header.format();
+        let mut files: Vec<BND3File> = Vec::with_capacity(util::capacity_for(c, header.file_count as u64));
+        for _ in 0..header.file_count {
+            let raw_flags = c.read_u8()?;
+            let unk01 = c.read_u8()?;
+            let unk02 = c.read_u8()?;
+            let unk03 = c.read_u8()?;
+            let compressed_size = c.read_u32::<T>()?;
+            let data_offset = if format & BND3::LONG_OFFSETS != 0 { c.read_u64::<T>()? } else { c.read_u32::<T>()? as u64 };
+            let id = if format & 0b00000010 != 0 { Some(c.read_i32::<T>()?) } else { None };
+            let name_offset = if format & 0b00000100 != 0 || format & 0b00001000 != 0 { Some(c.read_u32::<T>()?) } else { None };
+            let uncompressed_size = if format & 0b00100000 != 0 { Some(c.read_u32::<T>()?) } else { None };
+
+            let name = match name_offset {
+                None => None,
+                Some(offset) => Some(util::peek_shift_jis(c, offset as u64)?),
+            };
+
+            let start = c.position();
+            c.set_position(data_offset);
+            let data = Some(util::read_len(c, compressed_size as u64)?);
+            c.set_position(start);
+
+            let file = BND3File {
+                raw_flags,
+                unk01,
+                unk02,
+                unk03,
+                compressed_size,
+                data_offset,
+                id,
+                name_offset,
+                uncompressed_size,
+                name,
+                data,
+            };
+
+            file.validate()?;
+            files.push(file);
+        }
+
+        Ok(files)
+    }
+}
+
+impl BND3Header {
+    /// The format flags with the bit order normalized. Same bits as `BND4Header::format`.
+    pub fn format(&self) -> u8 {
+        if self.bit_big_endian { self.raw_format } else { util::reverse_bits(self.raw_format) }
+    }
+
+    // Stable accessors, see `BND4Header`.
+
+    pub fn version(&self) -> &str {
+        self.version.trim_end_matches('\0')
+    }
+
+    pub fn binder_version(&self) -> Option<BinderVersion> {
+        BinderVersion::parse(&self.version)
+    }
+
+    pub fn file_count(&self) -> u32 {
+        self.file_count
+    }
+
+    pub fn big_endian(&self) -> bool {
+        self.big_endian
+    }
+}
+
+impl IntoIterator for BND3 {
+    type Item = BND3File;
+    type IntoIter = vec::IntoIter<BND3File>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a BND3 {
+    type Item = &'a BND3File;
+    type IntoIter = slice::Iter<'a, BND3File>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut BND3 {
+    type Item = &'a mut BND3File;
+    type IntoIter = slice::IterMut<'a, BND3File>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.iter_mut()
+    }
+}
+
+impl BND3File {
+    /// Parses the data based on its magic, decompressing it first if it's a DCX, see `open_bytes`.
+    pub fn open(&self) -> Result<ParsedFile, DantelionFormatsError> {
+        parsed_file::open_entry(self.name.as_deref(), self.data.as_deref())
+    }
+
+    /// What the data is, looking through any DCX compression. `Unknown` for files without data.
+    pub fn kind(&self) -> Result<FileKind, DantelionFormatsError> {
+        let Some(data) = self.data.as_deref() else { return Ok(FileKind::Unknown) };
+        Ok(FileKind::sniff(&strip_dcx(data)?))
+    }
+
+    pub fn as_bnd3(&self) -> Result<BND3, DantelionFormatsError> {
+        self.open()?.into_bnd3()
+    }
+
+    pub fn as_bnd4(&self) -> Result<BND4, DantelionFormatsError> {
+        self.open()?.into_bnd4()
+    }
+
+    pub fn as_tpf(&self) -> Result<TPF, DantelionFormatsError> {
+        self.open()?.into_tpf()
+    }
+
+    pub fn as_flver(&self) -> Result<FLVER, DantelionFormatsError> {
+        self.open()?.into_flver()
+    }
+
+    pub fn as_mqb(&self) -> Result<MQB, DantelionFormatsError> {
+        self.open()?.into_mqb()
+    }
+
+    pub fn as_fsb5(&self) -> Result<FSB5, DantelionFormatsError> {
+        self.open()?.into_fsb5()
+    }
+
+    pub fn as_bnk(&self) -> Result<BNK, DantelionFormatsError> {
+        self.open()?.into_bnk()
+    }
+}
+
+impl Index<usize> for BND3 {
+    type Output = BND3File;
+
+    fn index(&self, index: usize) -> &BND3File {
+        &self.files[index]
+    }
+}
+
+impl IndexMut<usize> for BND3 {
+    fn index_mut(&mut self, index: usize) -> &mut BND3File {
+        &mut self.files[index]
+    }
+}
+
+/// Looks a file up by name like `get`, panicking if there isn't one.
+impl Index<&str> for BND3 {
+    type Output = BND3File;
+
+    fn index(&self, name: &str) -> &BND3File {
+        self.get(name).unwrap_or_else(|| panic!("No file named {} in the binder", name))
+    }
+}
+
+impl IndexMut<&str> for BND3 {
+    fn index_mut(&mut self, name: &str) -> &mut BND3File {
+        self.get_mut(name).unwrap_or_else(|| panic!("No file named {} in the binder", name))
+    }
+}
+
+/// A one line summary, e.g. "BND3 v07D7R6 — 12 files, big endian".
+impl Display for BND3 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "BND3 v{} — {} files", self.header.version(), self.files.len())?;
+        if self.header.big_endian {
+            write!(f, ", big endian")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Debug for BND3File {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BND3File")
+            .field("raw_flags", &self.raw_flags)
+            .field("unk01", &self.unk01)
+            .field("unk02", &self.unk02)
+            .field("unk03", &self.unk03)
+            .field("compressed_size", &self.compressed_size)
+            .field("data_offset", &self.data_offset)
+            .field("id", &self.id)
+            .field("name_offset", &self.name_offset)
+            .field("uncompressed_size", &self.uncompressed_size)
+            .field("name", &self.name)
+            .field("data", &self.data.as_ref().map(|data| DataLen(data.len())))
+            .finish()
+    }
+}
+
+fn too_large(name: Option<&str>) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("{} is too large for a BND3", name.unwrap_or("File"))))
+}
+
+impl Validate for BND3Header {
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.magic == "BND3", "Magic was {}", self.magic);
+        ensure!(self.unk0f == 0, "unk0F was {}", self.unk0f);
+        ensure!(self.unk18 == 0 || self.unk18 == 0x80000000, "unk18 was {}", self.unk18);
+        ensure!(self.unk1c == 0, "unk1C was {}", self.unk1c);
+
+        Ok(())
+    }
+}
+
+impl Validate for BND3File {
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.unk01 == 0, "unk01 was {}", self.unk01);
+        ensure!(self.unk02 == 0, "unk02 was {}", self.unk02);
+        ensure!(self.unk03 == 0, "unk03 was {}", self.unk03);
+
+        Ok(())
+    }
+}
@@ -1,20 +1,39 @@
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::ops::{Index, IndexMut};
 use std::fs;
-use std::io::{Cursor};
+use std::io::{Cursor, Error, ErrorKind};
+use std::{slice, vec};
 use binary_interpreter::binary_reader::{BinaryPeeker, BinaryReader};
-use byteorder::{BE, LE, ByteOrder, ReadBytesExt};
-use crate::dcx::DCX;
+use byteorder::{BE, LE, ByteOrder, ReadBytesExt, WriteBytesExt};
+use crate::bhd5::GameType;
+use crate::binder::{BinderType, BinderVersion, IdAllocator};
+use crate::bnd3::BND3;
+use crate::dcx::{DCX, DcxInfo};
 use crate::error::DantelionFormatsError;
+use crate::flver::FLVER;
+use crate::kind::FileKind;
+use crate::mqb::MQB;
+use crate::parsed_file::{self, strip_dcx, ParsedFile};
+use crate::sound::{BNK, FSB5};
+use crate::source::{DataSource, FileSource};
+use crate::tpf::TPF;
 use crate::util;
-use crate::util::Validate;
+use crate::util::{DataLen, NameMatcher, ensure, Validate};
 
+#[derive(Debug)]
 #[repr(C)]
 pub struct BND4 {
     pub header: BND4Header,
     pub files: Vec<File>,
     pub buckets: Option<BND4BucketHeader>,
+    // How the binder was compressed when it was read, and will be again on write, see `BND4WriteOptions::keep_dcx`
+    pub dcx: Option<DcxInfo>,
 }
 
+#[derive(Debug)]
 #[repr(C)]
+#[non_exhaustive]
 pub struct BND4Header {
     pub magic: String,
     pub unk04: u8,
@@ -40,6 +59,7 @@ pub struct BND4Header {
 }
 
 #[repr(C)]
+#[non_exhaustive]
 pub struct File {
     pub raw_flags: u8,
     pub unk01: u8,
@@ -54,9 +74,52 @@ pub struct File {
     pub zero: Option<u32>,
     pub name: Option<String>,
     pub data: Option<Vec<u8>>,
+    // Position in the binder when it was read or added, so the order can be restored after sorting
+    pub original_index: usize,
 }
 
+/// A file in a BND4 or BND3, looked up by id or by name. Names match either the full path or just the file name,
+/// ignoring case.
+#[derive(Clone, Copy, Debug)]
+pub enum BND4FileRef<'a> {
+    Id(i32),
+    Name(&'a str),
+}
+
+impl From<i32> for BND4FileRef<'_> {
+    fn from(id: i32) -> Self {
+        BND4FileRef::Id(id)
+    }
+}
+
+impl<'a> From<&'a str> for BND4FileRef<'a> {
+    fn from(name: &'a str) -> Self {
+        BND4FileRef::Name(name)
+    }
+}
+
+/// An id or name more than one file in a binder has, see `BND4::duplicates`. `indices` are the files', in order.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum BND4Duplicate {
+    Id { id: i32, indices: Vec<usize> },
+    // The first file's name, as written
+    Name { name: String, indices: Vec<usize> },
+}
+
+/// Entry orders for `BND4::sort_files`. Sorts are stable, so ties keep their current order.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BND4FileOrder {
+    // Files without an id go last.
+    Id,
+    // Case insensitive, files without a name go last.
+    Name,
+    // The order the files were read or added in.
+    Original,
+}
+
+#[derive(Debug)]
 #[repr(C)]
+#[non_exhaustive]
 pub struct BND4BucketHeader {
     pub hashes_offset: u64,
     pub bucket_count: u32,
@@ -68,37 +131,281 @@ pub struct BND4BucketHeader {
     pub hashes: Vec<BND4Hash>,
 }
 
+#[derive(Debug)]
 #[repr(C)]
 pub struct BND4Bucket {
     pub count: u32,
     pub index: u32,
 }
 
+#[derive(Debug)]
 #[repr(C)]
 pub struct BND4Hash {
     pub hash: u32,
     pub index: u32,
 }
 
+/// Padding used when writing a BND4. Use `for_game` to get the layout a game's own binders use, and the setters
+/// to change it.
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub struct BND4WriteOptions {
+    // Alignment of each file's data. Empty files are not padded.
+    pub data_alignment: usize,
+    // Alignment of the hash table, when the binder has one.
+    pub hash_table_alignment: usize,
+    // Pad the end of the last file's data to `data_alignment`.
+    pub pad_end: bool,
+    // Overrides the binder's own byte order, e.g. to write a PC binder for a console. None keeps it.
+    pub big_endian: Option<bool>,
+    // Compresses the output the way the binder was when it was read. Set `BND4::dcx` to change how.
+    pub keep_dcx: bool,
+}
+
+impl BND4WriteOptions {
+    pub fn for_game(game: GameType) -> BND4WriteOptions {
+        match game {
+            GameType::DarkSoulsII | GameType::DarkSoulsIISotFS => BND4WriteOptions {
+                data_alignment: 0x10,
+                hash_table_alignment: 0x8,
+                pad_end: true,
+                big_endian: None,
+                keep_dcx: true,
+            },
+            _ => BND4WriteOptions {
+                data_alignment: 0x10,
+                hash_table_alignment: 0x8,
+                pad_end: false,
+                big_endian: None,
+                keep_dcx: true,
+            },
+        }
+    }
+}
+
+impl BND4WriteOptions {
+    pub fn data_alignment(mut self, data_alignment: usize) -> BND4WriteOptions {
+        self.data_alignment = data_alignment;
+        self
+    }
+
+    pub fn hash_table_alignment(mut self, hash_table_alignment: usize) -> BND4WriteOptions {
+        self.hash_table_alignment = hash_table_alignment;
+        self
+    }
+
+    pub fn pad_end(mut self, pad_end: bool) -> BND4WriteOptions {
+        self.pad_end = pad_end;
+        self
+    }
+
+    /// Writes the binder uncompressed even if it was read from a DCX.
+    pub fn keep_dcx(mut self, keep_dcx: bool) -> BND4WriteOptions {
+        self.keep_dcx = keep_dcx;
+        self
+    }
+
+    /// Writes big endian, with the format and file flags in their big endian bit order, or little endian.
+    pub fn big_endian(mut self, big_endian: bool) -> BND4WriteOptions {
+        self.big_endian = Some(big_endian);
+        self
+    }
+}
+
+impl Default for BND4WriteOptions {
+    fn default() -> Self {
+        BND4WriteOptions::for_game(GameType::EldenRing)
+    }
+}
+
+/// The version string in the BND4 header. The games don't check it, but tools and some loaders expect the
+/// date-style string the games' own binders carry.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum BND4Version {
+    /// "07D7R6", found in the binders of every game that uses BND4.
+    Standard,
+    /// A date stamp in the same format: two digit year, month as a letter, day, hour as a letter, minute. Months
+    /// outside 1-12 and hours past 23 are clamped, so the letters stay in range.
+    Timestamp { year: u16, month: u8, day: u8, hour: u8, minute: u8 },
+    Custom(String),
+}
+
+impl BND4Version {
+    /// Every BND4 game shipped with the same stamp so far, but callers should go through this in case a
+    /// game turns up that doesn't.
+    pub fn for_game(_game: GameType) -> BND4Version {
+        BND4Version::Standard
+    }
+
+    pub fn to_version_string(&self) -> String {
+        match self {
+            BND4Version::Standard => "07D7R6".to_string(),
+            BND4Version::Timestamp { year, month, day, hour, minute } => format!(
+                "{:02}{}{}{}{}",
+                year % 100,
+                (b'A' + (*month).clamp(1, 12) - 1) as char,
+                day,
+                (b'A' + (*hour).min(23)) as char,
+                minute
+            ),
+            BND4Version::Custom(version) => version.clone(),
+        }
+    }
+}
+
+/// Builds a BND4 from scratch with the header flags the games use, so callers only have to supply files.
+#[derive(Debug)]
+pub struct BND4Builder {
+    version: BND4Version,
+    big_endian: bool,
+    unicode: bool,
+    format: u8,
+    extended: bool,
+    ids: IdAllocator,
+    files: Vec<File>,
+}
+
+impl BND4Builder {
+    // IDs, Names1, Names2 and Compression.
+    const DEFAULT_FORMAT: u8 = 0b00101110;
+
+    pub fn new() -> BND4Builder {
+        BND4Builder {
+            version: BND4Version::Standard,
+            big_endian: false,
+            unicode: true,
+            format: BND4Builder::DEFAULT_FORMAT,
+            extended: true,
+            ids: IdAllocator::new(BinderType::Generic),
+            files: vec![],
+        }
+    }
+
+    /// Starts a builder with the version string that `game` uses.
+    pub fn for_game(game: GameType) -> BND4Builder {
+        BND4Builder::new().version(BND4Version::for_game(game))
+    }
+
+    pub fn version(mut self, version: BND4Version) -> BND4Builder {
+        self.version = version;
+        self
+    }
+
+    pub fn big_endian(mut self, big_endian: bool) -> BND4Builder {
+        self.big_endian = big_endian;
+        self
+    }
+
+    pub fn unicode(mut self, unicode: bool) -> BND4Builder {
+        self.unicode = unicode;
+        self
+    }
+
+    /// Format flags in normalized bit order, as returned by `BND4Header::format`.
+    pub fn format(mut self, format: u8) -> BND4Builder {
+        self.format = format;
+        self
+    }
+
+    /// Whether to write the name hash table.
+    pub fn extended(mut self, extended: bool) -> BND4Builder {
+        self.extended = extended;
+        self
+    }
+
+    /// Sets the id conventions used by `add_file_auto_id`.
+    pub fn binder_type(mut self, binder_type: BinderType) -> BND4Builder {
+        self.ids = IdAllocator::new(binder_type);
+        for id in self.files.iter().filter_map(|file| file.id) {
+            self.ids.reserve(id);
+        }
+        self
+    }
+
+    /// Adds a file with the id the binder type's convention gives its extension, e.g. 200 for the first
+    /// FLVER in a chrbnd.
+    pub fn add_file_auto_id(mut self, name: &str, data: Vec<u8>) -> BND4Builder {
+        let file_name = name.rsplit(['\\', '/']).next().unwrap_or(name);
+        let id = self.ids.allocate(file_name);
+        self.add_file(id, name, data)
+    }
+
+    pub fn add_file(mut self, id: i32, name: &str, data: Vec<u8>) -> BND4Builder {
+        self.ids.reserve(id);
+        let original_index = self.files.len();
+        self.files.push(File::new(id, name, data, original_index, self.big_endian));
+        self
+    }
+
+    pub fn build(self) -> BND4 {
+        let raw_format = if self.big_endian { self.format } else { util::reverse_bits(self.format) };
+        let header = BND4Header {
+            magic: "BND4".to_string(),
+            unk04: 0,
+            unk05: 0,
+            unk06: 0,
+            unk07: 0,
+            unk08: 0,
+            big_endian: self.big_endian,
+            unk0a: !self.big_endian as u8,
+            unk0b: 0,
+            file_count: self.files.len() as u32,
+            header_size: BND4::HEADER_SIZE,
+            version: self.version.to_version_string(),
+            file_header_size: BND4::file_header_size(self.format),
+            file_headers_end: 0,
+            unicode: self.unicode,
+            raw_format,
+            extended: if self.extended { 4 } else { 0 },
+            unk33: 0,
+            unk34: 0,
+            buckets_offset: 0,
+        };
+
+        BND4 {
+            header,
+            files: self.files,
+            buckets: None,
+            dcx: None,
+        }
+    }
+}
+
+impl Default for BND4Builder {
+    fn default() -> Self {
+        BND4Builder::new()
+    }
+}
+
 impl BND4 {
     const MAGIC_SIZE: usize = 4;
     const VERSION_SIZE: usize = 8;
     const ENDIANNESS_OFFSET: u64 = 9;
+    const HEADER_SIZE: u64 = 0x40;
+    const FILE_HEADERS_END_OFFSET: usize = 0x28;
+    const BUCKETS_OFFSET_OFFSET: usize = 0x38;
 
     pub fn from_path(path: &str) -> Result<BND4, DantelionFormatsError> {
-        let file = fs::read(path)?;
+        BND4::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<BND4, DantelionFormatsError> {
+        let file = source.read_all()?;
 
         BND4::from_bytes(&file)
     }
 
     pub fn from_bytes(file: &[u8]) -> Result<BND4, DantelionFormatsError> {
-        let bytes = if DCX::is(file) {
-            let dcx = DCX::from_bytes(file)?;
-            dcx.decompress()?
-        } else {
-            file.to_vec()
-        };
-        let mut c = Cursor::new(&bytes[..]);
+        let mut buffer = Vec::new();
+        BND4::from_bytes_with_buffer(file, &mut buffer)
+    }
+
+    /// Same as `from_bytes`, but DCX compressed input is decompressed into `buffer`, so it can be reused
+    /// across many binders.
+    pub fn from_bytes_with_buffer(file: &[u8], buffer: &mut Vec<u8>) -> Result<BND4, DantelionFormatsError> {
+        let dcx = DCX::decompress_layers_into(file, buffer)?;
+        let bytes = if dcx.is_some() { &buffer[..] } else { file };
+        let mut c = Cursor::new(bytes);
 
         let be = c.peek_u8(BND4::ENDIANNESS_OFFSET)? != 0;
         let header = if be { BND4::read_bnd4_header::<BE>(&mut c)? } else { BND4::read_bnd4_header::<LE>(&mut c)? };
@@ -113,9 +420,293 @@ impl BND4 {
             header,
             files,
             buckets,
+            dcx,
         })
     }
 
+    pub fn iter(&self) -> slice::Iter<'_, File> {
+        self.files.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> slice::IterMut<'_, File> {
+        self.files.iter_mut()
+    }
+
+    pub fn file_index<'a>(&self, file: impl Into<BND4FileRef<'a>>) -> Option<usize> {
+        match file.into() {
+            BND4FileRef::Id(id) => self.files.iter().position(|file| file.id == Some(id)),
+            BND4FileRef::Name(name) => self.files.iter().position(|file| file.name.as_deref().is_some_and(|file_name| NameMatcher::new().matches(file_name, name))),
+        }
+    }
+
+    /// The hash table's hash for a file name, see `util::path_hash`.
+    pub fn name_hash(name: &str) -> u32 {
+        util::path_hash(name)
+    }
+
+    /// Indices of files whose hash table entry doesn't match their name, or that have no entry. Empty if the binder
+    /// has no hash table.
+    pub fn mismatched_hashes(&self) -> Vec<usize> {
+        let Some(buckets) = &self.buckets else { return vec![] };
+        (0..self.files.len())
+            .filter(|&index| {
+                let expected = BND4::name_hash(self.files[index].name.as_deref().unwrap_or(""));
+                !buckets.hashes.iter().any(|hash| hash.index == index as u32 && hash.hash == expected)
+            })
+            .collect()
+    }
+
+    /// Ids and names shared by more than one file, ids first. Lookups, the game's included, only ever find the first
+    /// of them, so the rest are silently ignored. Names are compared ignoring case and slash direction.
+    pub fn duplicates(&self) -> Vec<BND4Duplicate> {
+        // Indices into `ids` and `names`, which keep the order each was first seen in
+        let (mut id_positions, mut name_positions) = (HashMap::new(), HashMap::new());
+        let mut ids: Vec<(i32, Vec<usize>)> = vec![];
+        let mut names: Vec<(String, Vec<usize>)> = vec![];
+        for (index, file) in self.files.iter().enumerate() {
+            if let Some(id) = file.id {
+                let position = *id_positions.entry(id).or_insert_with(|| {
+                    ids.push((id, vec![]));
+                    ids.len() - 1
+                });
+                ids[position].1.push(index);
+            }
+            if let Some(name) = &file.name {
+                let position = *name_positions.entry(NameMatcher::new().normalize(name)).or_insert_with(|| {
+                    names.push((name.clone(), vec![]));
+                    names.len() - 1
+                });
+                names[position].1.push(index);
+            }
+        }
+
+        let ids = ids.into_iter()
+            .filter(|(_, indices)| indices.len() > 1)
+            .map(|(id, indices)| BND4Duplicate::Id { id, indices });
+        let names = names.into_iter()
+            .filter(|(_, indices)| indices.len() > 1)
+            .map(|(name, indices)| BND4Duplicate::Name { name, indices });
+
+        ids.chain(names).collect()
+    }
+
+    pub fn get<'a>(&self, file: impl Into<BND4FileRef<'a>>) -> Option<&File> {
+        self.file_index(file).map(|index| &self.files[index])
+    }
+
+    pub fn get_mut<'a>(&mut self, file: impl Into<BND4FileRef<'a>>) -> Option<&mut File> {
+        self.file_index(file).map(|index| &mut self.files[index])
+    }
+
+    /// Swaps out a file's data, keeping its id, name and flags. Sizes and offsets are recalculated on write.
+    pub fn replace_file<'a>(&mut self, file: impl Into<BND4FileRef<'a>>, data: Vec<u8>) -> Result<(), DantelionFormatsError> {
+        let file = file.into();
+        let index = self.file_index(file).ok_or_else(|| file_not_found(file))?;
+        let file = &mut self.files[index];
+        file.compressed_size = data.len() as u64;
+        if file.uncompressed_size.is_some() {
+            file.uncompressed_size = Some(data.len() as u64);
+        }
+        file.data = Some(data);
+
+        Ok(())
+    }
+
+    /// Adds a file to the end with the same default flags `BND4Builder` uses.
+    pub fn add_file(&mut self, id: i32, name: &str, data: Vec<u8>) {
+        let original_index = self.files.iter().map(|file| file.original_index + 1).max().unwrap_or(0);
+        self.files.push(File::new(id, name, data, original_index, self.header.big_endian));
+        self.header.file_count = self.files.len() as u32;
+    }
+
+    pub fn remove_file<'a>(&mut self, file: impl Into<BND4FileRef<'a>>) -> Result<File, DantelionFormatsError> {
+        let file = file.into();
+        let index = self.file_index(file).ok_or_else(|| file_not_found(file))?;
+        let removed = self.files.remove(index);
+        self.header.file_count = self.files.len() as u32;
+
+        Ok(removed)
+    }
+
+    /// Reorders the files. Some games care about entry order, and the writer keeps whatever order `files` is in.
+    pub fn sort_files(&mut self, order: BND4FileOrder) {
+        match order {
+            BND4FileOrder::Id => self.files.sort_by_key(|file| (file.id.is_none(), file.id)),
+            BND4FileOrder::Name => self.files.sort_by_cached_key(|file| (file.name.is_none(), file.name.as_ref().map(|name| name.to_lowercase()))),
+            BND4FileOrder::Original => self.files.sort_by_key(|file| file.original_index),
+        }
+    }
+
+    pub fn to_path(&self, path: &str, options: &BND4WriteOptions) -> Result<(), DantelionFormatsError> {
+        Ok(fs::write(path, self.to_bytes(options)?)?)
+    }
+
+    /// Serializes the binder. Counts, sizes, offsets and the hash table are recalculated from `files`, so only
+    /// the flags in the header need to be correct. Binders read from a DCX are compressed again, see `dcx`.
+    pub fn to_bytes(&self, options: &BND4WriteOptions) -> Result<Vec<u8>, DantelionFormatsError> {
+        let bytes = if options.big_endian.unwrap_or(self.header.big_endian) {
+            self.write_bnd4::<BE>(options)?
+        } else {
+            self.write_bnd4::<LE>(options)?
+        };
+
+        match &self.dcx {
+            Some(dcx) if options.keep_dcx => dcx.compress(&bytes),
+            _ => Ok(bytes),
+        }
+    }
+
+    fn write_bnd4<T: ByteOrder>(&self, options: &BND4WriteOptions) -> Result<Vec<u8>, DantelionFormatsError> {
+        let header = &self.header;
+        let format = header.format();
+        let big_endian = options.big_endian.unwrap_or(header.big_endian);
+        // Flag bytes are stored bit reversed in little endian binders, so they flip along with the byte order.
+        let swap = big_endian != header.big_endian;
+        let flip = |raw: u8| if swap { util::reverse_bits(raw) } else { raw };
+        let mut bytes = vec![];
+
+        util::write_fixed_str(&mut bytes, &header.magic, BND4::MAGIC_SIZE)?;
+        bytes.write_u8(header.unk04)?;
+        bytes.write_u8(header.unk05)?;
+        bytes.write_u8(header.unk06)?;
+        bytes.write_u8(header.unk07)?;
+        bytes.write_u8(header.unk08)?;
+        bytes.write_u8(big_endian as u8)?;
+        bytes.write_u8(if swap { !big_endian as u8 } else { header.unk0a })?;
+        bytes.write_u8(header.unk0b)?;
+        bytes.write_u32::<T>(self.files.len() as u32)?;
+        bytes.write_u64::<T>(BND4::HEADER_SIZE)?;
+        util::write_fixed_str(&mut bytes, &header.version, BND4::VERSION_SIZE)?;
+        bytes.write_u64::<T>(BND4::file_header_size(format))?;
+        bytes.write_u64::<T>(0)?; // file_headers_end
+        bytes.write_u8(header.unicode as u8)?;
+        bytes.write_u8(flip(header.raw_format))?;
+        bytes.write_u8(header.extended)?;
+        bytes.write_u8(header.unk33)?;
+        bytes.write_u32::<T>(header.unk34)?;
+        bytes.write_u64::<T>(0)?; // buckets_offset
+
+        let mut data_offset_positions = Vec::with_capacity(self.files.len());
+        let mut name_offset_positions = Vec::with_capacity(self.files.len());
+        for file in &self.files {
+            let size = file.data.as_ref().map_or(0, |data| data.len() as u64);
+            bytes.write_u8(flip(file.raw_flags))?;
+            bytes.write_u8(file.unk01)?;
+            bytes.write_u8(file.unk02)?;
+            bytes.write_u8(file.unk03)?;
+            bytes.write_i32::<T>(file.unk04)?;
+            bytes.write_u64::<T>(size)?;
+            if format & 0b00100000 != 0 {
+                bytes.write_u64::<T>(file.uncompressed_size.unwrap_or(size))?;
+            }
+            data_offset_positions.push(bytes.len());
+            bytes.write_u32::<T>(0)?;
+            if format & 0b00000010 != 0 {
+                bytes.write_i32::<T>(file.id.unwrap_or(-1))?;
+            }
+            if format & 0b00000100 != 0 || format & 0b00001000 != 0 {
+                name_offset_positions.push(bytes.len());
+                bytes.write_u32::<T>(0)?;
+            }
+            if format == 0b00000100 {
+                bytes.write_i32::<T>(file.id.unwrap_or(-1))?;
+                bytes.write_u32::<T>(0)?;
+            }
+        }
+
+        for (file, position) in self.files.iter().zip(name_offset_positions) {
+            let offset = bytes.len() as u32;
+            T::write_u32(&mut bytes[position..position + 4], offset);
+            BND4::write_file_name::<T>(&mut bytes, file.name.as_deref().unwrap_or(""), header.unicode)?;
+        }
+
+        if header.extended == 4 {
+            util::pad_to(&mut bytes, options.hash_table_alignment);
+            let offset = bytes.len() as u64;
+            T::write_u64(&mut bytes[BND4::BUCKETS_OFFSET_OFFSET..BND4::BUCKETS_OFFSET_OFFSET + 8], offset);
+            self.write_bnd4_hash_table::<T>(&mut bytes)?;
+        }
+
+        let headers_end = bytes.len() as u64;
+        T::write_u64(&mut bytes[BND4::FILE_HEADERS_END_OFFSET..BND4::FILE_HEADERS_END_OFFSET + 8], headers_end);
+
+        for (file, position) in self.files.iter().zip(data_offset_positions) {
+            let data = file.data.as_deref().unwrap_or(&[]);
+            if !data.is_empty() {
+                util::pad_to(&mut bytes, options.data_alignment);
+            }
+            let offset = bytes.len() as u32;
+            T::write_u32(&mut bytes[position..position + 4], offset);
+            bytes.extend_from_slice(data);
+        }
+
+        if options.pad_end {
+            util::pad_to(&mut bytes, options.data_alignment);
+        }
+
+        Ok(bytes)
+    }
+
+    fn file_header_size(format: u8) -> u64 {
+        let mut size = 0x14;
+        if format & 0b00100000 != 0 { size += 8; }
+        if format & 0b00000010 != 0 { size += 4; }
+        if format & 0b00000100 != 0 || format & 0b00001000 != 0 { size += 4; }
+        if format == 0b00000100 { size += 8; }
+        size
+    }
+
+    fn write_file_name<T: ByteOrder>(bytes: &mut Vec<u8>, name: &str, unicode: bool) -> Result<(), DantelionFormatsError> {
+        if unicode {
+            for c in name.encode_utf16() {
+                bytes.write_u16::<T>(c)?;
+            }
+            bytes.write_u16::<T>(0)?;
+        } else {
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.write_u8(0)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_bnd4_hash_table<T: ByteOrder>(&self, bytes: &mut Vec<u8>) -> Result<(), DantelionFormatsError> {
+        let bucket_count = (self.files.len() as u32 / 7..).find(|&n| util::is_prime(n)).unwrap();
+
+        let mut buckets: Vec<Vec<BND4Hash>> = (0..bucket_count).map(|_| vec![]).collect();
+        for (index, file) in self.files.iter().enumerate() {
+            let hash = BND4::name_hash(file.name.as_deref().unwrap_or(""));
+            buckets[(hash % bucket_count) as usize].push(BND4Hash { hash, index: index as u32 });
+        }
+        for bucket in buckets.iter_mut() {
+            bucket.sort_by_key(|hash| hash.hash);
+        }
+
+        let hashes_offset_position = bytes.len();
+        bytes.write_u64::<T>(0)?;
+        bytes.write_u32::<T>(bucket_count)?;
+        bytes.write_u8(0x10)?;
+        bytes.write_u8(8)?;
+        bytes.write_u8(8)?;
+        bytes.write_u8(0)?;
+
+        let mut index = 0;
+        for bucket in &buckets {
+            bytes.write_u32::<T>(bucket.len() as u32)?;
+            bytes.write_u32::<T>(index)?;
+            index += bucket.len() as u32;
+        }
+
+        let hashes_offset = bytes.len() as u64;
+        T::write_u64(&mut bytes[hashes_offset_position..hashes_offset_position + 8], hashes_offset);
+        for hash in buckets.iter().flatten() {
+            bytes.write_u32::<T>(hash.hash)?;
+            bytes.write_u32::<T>(hash.index)?;
+        }
+
+        Ok(())
+    }
+
     fn read_bnd4_header<T: ByteOrder>(c: &mut Cursor<&[u8]>) -> Result<BND4Header, DantelionFormatsError> {
 
         let header = BND4Header {
@@ -141,7 +732,7 @@ impl BND4 {
             buckets_offset: c.read_u64::<T>()?,
         };
 
-        header.validate();
+        header.validate()?;
 
         Ok(header)
 
@@ -175,7 +766,7 @@ impl BND4 {
 
     fn read_bnd4_hashes<T: ByteOrder>(c: &mut Cursor<&[u8]>, header: &BND4Header, hashes_offset: u64) -> Result<Vec<BND4Hash>, DantelionFormatsError> {
         c.set_position(hashes_offset);
-        let mut hashes = Vec::with_capacity(header.file_count as usize);
+        let mut hashes = Vec::with_capacity(util::capacity_for(c, header.file_count as u64));
         for _ in 0..header.file_count {
             hashes.push(BND4Hash {
                 hash: c.read_u32::<T>()?,
@@ -187,7 +778,7 @@ impl BND4 {
     }
 
     fn read_bnd4_buckets<T: ByteOrder>(c: &mut Cursor<&[u8]>, count: usize) -> Result<Vec<BND4Bucket>, DantelionFormatsError> {
-        let mut buckets = Vec::with_capacity(count);
+        let mut buckets = Vec::with_capacity(util::capacity_for(c, count as u64));
         for _ in 0..count {
             buckets.push(BND4Bucket {
                 count: c.read_u32::<T>()?,
@@ -199,9 +790,9 @@ impl BND4 {
     }
 
     fn read_bnd4_files<T: ByteOrder>(c: &mut Cursor<&[u8]>, header: &BND4Header) -> Result<Vec<File>, DantelionFormatsError> {
-        let format = if header.big_endian { header.raw_format } else { util::reverse_bits(header.raw_format) };
-        let mut files: Vec<File> = Vec::with_capacity(header.file_count as usize);
-        for _ in 0..header.file_count {
+        let format = header.format();
+        let mut files: Vec<File> = Vec::with_capacity(util::capacity_for(c, header.file_count as u64));
+        for original_index in 0..header.file_count as usize {
             let raw_flags = c.read_u8()?;
             let unk01 = c.read_u8()?;
             let unk02 = c.read_u8()?;
@@ -220,10 +811,14 @@ impl BND4 {
 
             let name = match name_offset {
                 None => None,
-                Some(offset) => Some(BND4::get_file_name(c, offset as u64, header)?)
+                Some(offset) => Some(BND4::get_file_name::<T>(c, offset as u64, header)?)
             };
 
-            let data: Option<Vec<u8>> = Some(vec![]);
+            let start = c.position();
+            c.set_position(data_offset as u64);
+            let data = Some(util::read_len(c, compressed_size)?);
+            c.set_position(start);
+
             let file = File {
                 raw_flags,
                 unk01,
@@ -238,18 +833,19 @@ impl BND4 {
                 zero,
                 name,
                 data,
+                original_index,
             };
 
-            file.validate();
+            file.validate()?;
             files.push(file);
         }
 
         Ok(files)
     }
 
-    fn get_file_name(c: &mut Cursor<&[u8]>, offset: u64, header: &BND4Header) -> Result<String, DantelionFormatsError> {
+    fn get_file_name<T: ByteOrder>(c: &mut Cursor<&[u8]>, offset: u64, header: &BND4Header) -> Result<String, DantelionFormatsError> {
         let name= if header.unicode {
-            c.peek_wcstr(offset)?
+            util::peek_utf16::<T>(c, offset)?
         } else {
             c.peek_cstr(offset)?
         };
@@ -260,31 +856,255 @@ impl BND4 {
 
 
 
+impl File {
+    const DEFAULT_FLAGS: u8 = 0b00000010;
+
+    pub fn id(&self) -> Option<i32> {
+        self.id
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn data(&self) -> Option<&[u8]> {
+        self.data.as_deref()
+    }
+
+    pub fn compressed_size(&self) -> u64 {
+        self.compressed_size
+    }
+
+    pub fn uncompressed_size(&self) -> Option<u64> {
+        self.uncompressed_size
+    }
+
+    /// Parses the data based on its magic, decompressing it first if it's a DCX, see `open_bytes`.
+    pub fn open(&self) -> Result<ParsedFile, DantelionFormatsError> {
+        parsed_file::open_entry(self.name.as_deref(), self.data.as_deref())
+    }
+
+    /// What the data is, looking through any DCX compression. `Unknown` for files without data.
+    pub fn kind(&self) -> Result<FileKind, DantelionFormatsError> {
+        let Some(data) = self.data.as_deref() else { return Ok(FileKind::Unknown) };
+        Ok(FileKind::sniff(&strip_dcx(data)?))
+    }
+
+    pub fn as_bnd3(&self) -> Result<BND3, DantelionFormatsError> {
+        self.open()?.into_bnd3()
+    }
+
+    pub fn as_bnd4(&self) -> Result<BND4, DantelionFormatsError> {
+        self.open()?.into_bnd4()
+    }
+
+    pub fn as_tpf(&self) -> Result<TPF, DantelionFormatsError> {
+        self.open()?.into_tpf()
+    }
+
+    pub fn as_flver(&self) -> Result<FLVER, DantelionFormatsError> {
+        self.open()?.into_flver()
+    }
+
+    pub fn as_mqb(&self) -> Result<MQB, DantelionFormatsError> {
+        self.open()?.into_mqb()
+    }
+
+    pub fn as_fsb5(&self) -> Result<FSB5, DantelionFormatsError> {
+        self.open()?.into_fsb5()
+    }
+
+    pub fn as_bnk(&self) -> Result<BNK, DantelionFormatsError> {
+        self.open()?.into_bnk()
+    }
+
+    fn new(id: i32, name: &str, data: Vec<u8>, original_index: usize, big_endian: bool) -> File {
+        File {
+            raw_flags: if big_endian { File::DEFAULT_FLAGS } else { util::reverse_bits(File::DEFAULT_FLAGS) },
+            unk01: 0,
+            unk02: 0,
+            unk03: 0,
+            unk04: -1,
+            compressed_size: data.len() as u64,
+            uncompressed_size: Some(data.len() as u64),
+            data_offset: 0,
+            id: Some(id),
+            name_offset: Some(0),
+            zero: None,
+            name: Some(name.to_string()),
+            data: Some(data),
+            original_index,
+        }
+    }
+}
+
+impl IntoIterator for BND4 {
+    type Item = File;
+    type IntoIter = vec::IntoIter<File>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a BND4 {
+    type Item = &'a File;
+    type IntoIter = slice::Iter<'a, File>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut BND4 {
+    type Item = &'a mut File;
+    type IntoIter = slice::IterMut<'a, File>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.iter_mut()
+    }
+}
+
+impl Index<usize> for BND4 {
+    type Output = File;
+
+    fn index(&self, index: usize) -> &File {
+        &self.files[index]
+    }
+}
+
+impl IndexMut<usize> for BND4 {
+    fn index_mut(&mut self, index: usize) -> &mut File {
+        &mut self.files[index]
+    }
+}
+
+/// Looks a file up by name like `get`, panicking if there isn't one.
+impl Index<&str> for BND4 {
+    type Output = File;
+
+    fn index(&self, name: &str) -> &File {
+        self.get(name).unwrap_or_else(|| panic!("No file named {} in the binder", name))
+    }
+}
+
+impl IndexMut<&str> for BND4 {
+    fn index_mut(&mut self, name: &str) -> &mut File {
+        self.get_mut(name).unwrap_or_else(|| panic!("No file named {} in the binder", name))
+    }
+}
+
+/// A one line summary, e.g. "BND4 v07D7R6 — 214 files, unicode, buckets".
+impl Display for BND4 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "BND4 v{} — {} files", self.header.version(), self.files.len())?;
+        if self.header.big_endian {
+            write!(f, ", big endian")?;
+        }
+        if self.header.unicode {
+            write!(f, ", unicode")?;
+        }
+        if self.buckets.is_some() {
+            write!(f, ", buckets")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Debug for File {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("File")
+            .field("raw_flags", &self.raw_flags)
+            .field("unk01", &self.unk01)
+            .field("unk02", &self.unk02)
+            .field("unk03", &self.unk03)
+            .field("unk04", &self.unk04)
+            .field("compressed_size", &self.compressed_size)
+            .field("uncompressed_size", &self.uncompressed_size)
+            .field("data_offset", &self.data_offset)
+            .field("id", &self.id)
+            .field("name_offset", &self.name_offset)
+            .field("zero", &self.zero)
+            .field("name", &self.name)
+            .field("data", &self.data.as_ref().map(|data| DataLen(data.len())))
+            .field("original_index", &self.original_index)
+            .finish()
+    }
+}
+
+fn file_not_found(file: BND4FileRef) -> DantelionFormatsError {
+    let message = match file {
+        BND4FileRef::Id(id) => format!("No file with id {} in BND4", id),
+        BND4FileRef::Name(name) => format!("No file named {} in BND4", name),
+    };
+    DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, message))
+}
+
+impl BND4Header {
+    /// The format flags with the bit order normalized, so the same masks work for both endiannesses.
+    pub fn format(&self) -> u8 {
+        if self.big_endian { self.raw_format } else { util::reverse_bits(self.raw_format) }
+    }
+
+    // Accessors for the fields whose meaning is known. These keep their names when the unknown fields around them
+    // get identified and renamed, so prefer them over the fields.
+
+    /// The version string without the null padding, e.g. "07D7R6".
+    pub fn version(&self) -> &str {
+        self.version.trim_end_matches('\0')
+    }
+
+    /// The version decoded as a date stamp, or `None` for custom versions.
+    pub fn binder_version(&self) -> Option<BinderVersion> {
+        BinderVersion::parse(&self.version)
+    }
+
+    pub fn file_count(&self) -> u32 {
+        self.file_count
+    }
+
+    pub fn big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    pub fn unicode(&self) -> bool {
+        self.unicode
+    }
+
+    pub fn extended(&self) -> u8 {
+        self.extended
+    }
+}
+
 impl Validate for BND4Header {
-    fn validate(&self) {
-        assert_eq!(self.magic, "BND4", "Magic was {}", self.magic);
-        assert!(self.unk04 == 0 || self.unk04 == 1, "unk04 was {}", self.unk04);
-        assert!(self.unk05 == 0 || self.unk05 == 1, "unk05 was {}", self.unk05);
-        assert_eq!(self.unk06, 0, "unk06 was {}", self.unk06);
-        assert_eq!(self.unk07, 0, "unk07 was {}", self.unk07);
-        assert_eq!(self.unk08, 0, "unk08 was {}", self.unk08);
-        assert!(self.unk0a == 0 || self.unk0a == 1, "unk0A was {}", self.unk0a);
-        assert_eq!(self.unk0b, 0, "unk0B was {}", self.unk0b);
-        assert_eq!(self.header_size, 0x40, "self_size was {}", self.header_size);
-        assert!(self.unicode == false || self.unicode == true, "unicode was {}", self.unicode);
-        assert!(self.extended == 0 || self.extended == 4, "extended was {}", self.extended);
-        assert_eq!(self.unk33, 0, "unk33 was {}", self.unk33);
-        assert_eq!(self.unk34, 0, "unk34 was {}", self.unk34);
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.magic == "BND4", "Magic was {}", self.magic);
+        ensure!(self.unk04 == 0 || self.unk04 == 1, "unk04 was {}", self.unk04);
+        ensure!(self.unk05 == 0 || self.unk05 == 1, "unk05 was {}", self.unk05);
+        ensure!(self.unk06 == 0, "unk06 was {}", self.unk06);
+        ensure!(self.unk07 == 0, "unk07 was {}", self.unk07);
+        ensure!(self.unk08 == 0, "unk08 was {}", self.unk08);
+        ensure!(self.unk0a == 0 || self.unk0a == 1, "unk0A was {}", self.unk0a);
+        ensure!(self.unk0b == 0, "unk0B was {}", self.unk0b);
+        ensure!(self.header_size == 0x40, "self_size was {}", self.header_size);
+        ensure!(self.extended == 0 || self.extended == 4, "extended was {}", self.extended);
+        ensure!(self.unk33 == 0, "unk33 was {}", self.unk33);
+        ensure!(self.unk34 == 0, "unk34 was {}", self.unk34);
+
+        Ok(())
     }
 }
 
 
 impl Validate for File {
-    fn validate(&self) {
-        assert_eq!(self.unk01, 0, "unk01 was {}", self.unk01);
-        assert_eq!(self.unk02, 0, "unk02 was {}", self.unk02);
-        assert_eq!(self.unk03, 0, "unk03 was {}", self.unk03);
-        assert_eq!(self.unk04, -1, "unk04 was {}", self.unk04);
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.unk01 == 0, "unk01 was {}", self.unk01);
+        ensure!(self.unk02 == 0, "unk02 was {}", self.unk02);
+        ensure!(self.unk03 == 0, "unk03 was {}", self.unk03);
+        ensure!(self.unk04 == -1, "unk04 was {}", self.unk04);
+
+        Ok(())
     }
 }
 
@@ -0,0 +1,128 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use crate::bhd5::{BHD5, BHD5Format};
+use crate::error::DantelionFormatsError;
+
+/// Recovers names for BHD5 hashes that aren't in any dictionary, by hashing candidate paths in parallel. Searches
+/// use rayon's global pool, which takes every core, unless given a pool of their own.
+#[derive(Debug)]
+pub struct HashBruteForcer {
+    pub format: BHD5Format,
+    pub targets: HashSet<u64>,
+    pool: Option<Arc<ThreadPool>>,
+}
+
+enum PatternPart {
+    Literal(String),
+    // Zero padded to `width`
+    Range { start: u64, end: u64, width: usize },
+    Word,
+}
+
+impl HashBruteForcer {
+    pub fn new(format: BHD5Format, targets: impl IntoIterator<Item = u64>) -> HashBruteForcer {
+        HashBruteForcer {
+            format,
+            targets: targets.into_iter().collect(),
+            pool: None,
+        }
+    }
+
+    /// Runs searches on `pool`, e.g. one shared with the rest of the application.
+    pub fn with_thread_pool(mut self, pool: Arc<ThreadPool>) -> HashBruteForcer {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Runs searches on a pool of their own with `threads` threads.
+    pub fn with_threads(self, threads: usize) -> Result<HashBruteForcer, DantelionFormatsError> {
+        let pool = ThreadPoolBuilder::new().num_threads(threads).build()
+            .map_err(|e| Error::other(e.to_string()))?;
+        Ok(self.with_thread_pool(Arc::new(pool)))
+    }
+
+    /// Hashes every candidate path as is.
+    pub fn search_wordlist(&self, candidates: &[String]) -> HashMap<u64, String> {
+        self.install(|| candidates.par_iter()
+            .filter_map(|path| self.check(path))
+            .collect())
+    }
+
+    /// Hashes every path `pattern` expands to. `{0000-9999}` is a zero padded number range, padded to the width of
+    /// the start, and `{word}` is each of `words`, e.g. "/parts/{word}_m_{0000-9999}.partsbnd.dcx".
+    pub fn search_pattern(&self, pattern: &str, words: &[String]) -> Result<HashMap<u64, String>, DantelionFormatsError> {
+        let parts = HashBruteForcer::parse_pattern(pattern)?;
+        let radixes: Vec<u64> = parts.iter().map(|part| match part {
+            PatternPart::Literal(_) => 1,
+            PatternPart::Range { start, end, .. } => end - start + 1,
+            PatternPart::Word => words.len() as u64,
+        }).collect();
+        let total = radixes.iter().try_fold(1u64, |total, &radix| total.checked_mul(radix))
+            .ok_or_else(|| invalid_pattern("Pattern has too many combinations"))?;
+
+        Ok(self.install(|| (0..total).into_par_iter()
+            .filter_map(|mut index| {
+                // Each index is a mixed radix number, one digit per part.
+                let mut path = String::new();
+                for (part, radix) in parts.iter().zip(&radixes) {
+                    let digit = index % radix;
+                    index /= radix;
+                    match part {
+                        PatternPart::Literal(literal) => path.push_str(literal),
+                        PatternPart::Range { start, width, .. } => path.push_str(&format!("{:0width$}", start + digit, width = width)),
+                        PatternPart::Word => path.push_str(&words[digit as usize]),
+                    }
+                }
+                self.check(&path)
+            })
+            .collect()))
+    }
+
+    fn install<R: Send>(&self, search: impl FnOnce() -> R + Send) -> R {
+        match &self.pool {
+            Some(pool) => pool.install(search),
+            None => search(),
+        }
+    }
+
+    fn check(&self, path: &str) -> Option<(u64, String)> {
+        let hash = BHD5::hash_path(path, self.format);
+        if self.targets.contains(&hash) { Some((hash, path.to_string())) } else { None }
+    }
+
+    fn parse_pattern(pattern: &str) -> Result<Vec<PatternPart>, DantelionFormatsError> {
+        let mut parts = vec![];
+        let mut rest = pattern;
+        while let Some(open) = rest.find('{') {
+            let close = rest[open..].find('}').ok_or_else(|| invalid_pattern("Unclosed { in pattern"))? + open;
+            if open > 0 {
+                parts.push(PatternPart::Literal(rest[..open].to_string()));
+            }
+
+            let placeholder = &rest[open + 1..close];
+            if placeholder == "word" {
+                parts.push(PatternPart::Word);
+            } else {
+                let (start, end) = placeholder.split_once('-').ok_or_else(|| invalid_pattern("Unknown placeholder in pattern"))?;
+                let range = (start.parse::<u64>(), end.parse::<u64>());
+                match range {
+                    (Ok(start_value), Ok(end_value)) if start_value <= end_value => parts.push(PatternPart::Range { start: start_value, end: end_value, width: start.len() }),
+                    _ => return Err(invalid_pattern("Invalid range in pattern")),
+                }
+            }
+            rest = &rest[close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(PatternPart::Literal(rest.to_string()));
+        }
+
+        Ok(parts)
+    }
+}
+
+fn invalid_pattern(message: &str) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidInput, message))
+}
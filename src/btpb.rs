@@ -0,0 +1,229 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind, Read};
+use binary_interpreter::binary_reader::BinaryPeeker;
+use byteorder::{ByteOrder, LE, ReadBytesExt, WriteBytesExt};
+use crate::dcx::DCX;
+use crate::error::DantelionFormatsError;
+use crate::source::{DataSource, FileSource};
+use crate::util;
+use crate::util::{DataLen, ensure, Validate};
+
+/// Baked light probes from a map's gi binder (".btpb"), in named groups. The header and groups are read, and each
+/// probe is kept as its raw record, `probe_size` bytes long, so a lighting pipeline can swap probes out and write the
+/// file back without knowing what's in them. Fields nobody has named yet are kept as they are.
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+pub struct BTPB {
+    pub header: BTPBHeader,
+    pub groups: Vec<BTPBGroup>,
+}
+
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+#[non_exhaustive]
+pub struct BTPBHeader {
+    pub version: u32,
+    // Size of each group record and of each probe
+    pub group_size: u32,
+    pub probe_size: u32,
+    pub unk18: [u8; 0x20],
+}
+
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+pub struct BTPBGroup {
+    pub name: String,
+    pub unk0c: u32,
+    pub probes: Vec<BTPBProbe>,
+    // The rest of the group record
+    pub unk18: Vec<u8>,
+}
+
+#[derive(PartialEq, Clone)]
+#[repr(C)]
+pub struct BTPBProbe {
+    pub data: Vec<u8>,
+}
+
+impl BTPB {
+    const HEADER_SIZE: usize = 0x48;
+    // The fields read out of a group record, everything after them is `unk18`
+    const GROUP_FIELDS_SIZE: usize = 0x18;
+    const ALIGNMENT: usize = 0x10;
+
+    pub fn from_path(path: &str) -> Result<BTPB, DantelionFormatsError> {
+        BTPB::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<BTPB, DantelionFormatsError> {
+        let file = source.read_all()?;
+
+        BTPB::from_bytes(&file)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<BTPB, DantelionFormatsError> {
+        let bytes = if DCX::is(file) {
+            DCX::from_bytes(file)?.decompress()?
+        } else {
+            file.to_vec()
+        };
+        let mut c = Cursor::new(&bytes[..]);
+
+        let version = c.read_u32::<LE>()?;
+        let group_count = c.read_u32::<LE>()?;
+        let data_length = c.read_u32::<LE>()?;
+        let zero = c.read_u32::<LE>()?;
+        let group_size = c.read_u32::<LE>()?;
+        let probe_size = c.read_u32::<LE>()?;
+        let mut unk18 = [0; 0x20];
+        c.read_exact(&mut unk18)?;
+        let groups_offset = c.read_u64::<LE>()?;
+        let data_offset = c.read_u64::<LE>()?;
+        let header = BTPBHeader { version, group_size, probe_size, unk18 };
+        header.validate()?;
+        ensure!(zero == 0, "unk0c was {}", zero);
+
+        let data_end = data_offset.checked_add(data_length as u64).filter(|&end| end <= bytes.len() as u64)
+            .ok_or_else(|| invalid(format!("Probe data at {:#X} runs past the end of the file", data_offset)))?;
+        c.set_position(groups_offset);
+        let mut groups = Vec::with_capacity(util::capacity_for(&c, group_count as u64));
+        for index in 0..group_count {
+            let name_offset = c.read_u64::<LE>()?;
+            let probe_count = c.read_u32::<LE>()?;
+            let unk0c = c.read_u32::<LE>()?;
+            let probes_offset = c.read_u64::<LE>()?;
+            let unk18 = util::read_len(&mut c, (group_size as usize - BTPB::GROUP_FIELDS_SIZE) as u64)?;
+
+            let start = data_offset + probes_offset;
+            let end = (probe_count as u64).checked_mul(probe_size as u64).and_then(|size| start.checked_add(size))
+                .filter(|&end| end <= data_end)
+                .ok_or_else(|| invalid(format!("Group {}'s {} probes run past the probe data", index, probe_count)))?;
+            let probes = bytes[start as usize..end as usize].chunks_exact(probe_size as usize)
+                .map(|probe| BTPBProbe { data: probe.to_vec() })
+                .collect();
+
+            groups.push(BTPBGroup {
+                name: c.peek_wcstr(name_offset)?,
+                unk0c,
+                probes,
+                unk18,
+            });
+        }
+
+        Ok(BTPB {
+            header,
+            groups,
+        })
+    }
+
+    pub fn to_path(&self, path: &str) -> Result<(), DantelionFormatsError> {
+        Ok(fs::write(path, self.to_bytes()?)?)
+    }
+
+    /// Serializes the BTPB. The groups follow the header, then their names, then every group's probes in order.
+    /// Offsets are recalculated. Fails if a probe or group record isn't the size the header says.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        let header = &self.header;
+        header.validate()?;
+        let probe_size = header.probe_size as usize;
+        for (index, group) in self.groups.iter().enumerate() {
+            if group.unk18.len() + BTPB::GROUP_FIELDS_SIZE != header.group_size as usize {
+                return Err(invalid(format!("Group {} is {} bytes, but groups are {}", index, group.unk18.len() + BTPB::GROUP_FIELDS_SIZE, header.group_size)));
+            }
+            if let Some(probe) = group.probes.iter().position(|probe| probe.data.len() != probe_size) {
+                return Err(invalid(format!("Probe {} in group {} is {} bytes, but probes are {}", probe, index, group.probes[probe].data.len(), probe_size)));
+            }
+        }
+
+        let mut bytes = vec![];
+        bytes.write_u32::<LE>(header.version)?;
+        bytes.write_u32::<LE>(self.groups.len() as u32)?;
+        let data_length: usize = self.groups.iter().map(|group| group.probes.len() * probe_size).sum();
+        bytes.write_u32::<LE>(data_length as u32)?;
+        bytes.write_u32::<LE>(0)?;
+        bytes.write_u32::<LE>(header.group_size)?;
+        bytes.write_u32::<LE>(header.probe_size)?;
+        bytes.extend_from_slice(&header.unk18);
+        bytes.write_u64::<LE>(BTPB::HEADER_SIZE as u64)?;
+        let data_offset_position = bytes.len();
+        bytes.write_u64::<LE>(0)?;
+
+        let mut name_positions = vec![];
+        let mut probes_offset = 0;
+        for group in &self.groups {
+            name_positions.push(bytes.len());
+            bytes.write_u64::<LE>(0)?;
+            bytes.write_u32::<LE>(group.probes.len() as u32)?;
+            bytes.write_u32::<LE>(group.unk0c)?;
+            bytes.write_u64::<LE>(probes_offset as u64)?;
+            bytes.extend_from_slice(&group.unk18);
+            probes_offset += group.probes.len() * probe_size;
+        }
+
+        for (group, position) in self.groups.iter().zip(name_positions) {
+            let offset = bytes.len() as u64;
+            LE::write_u64(&mut bytes[position..position + 8], offset);
+            for c in group.name.encode_utf16() {
+                bytes.write_u16::<LE>(c)?;
+            }
+            bytes.write_u16::<LE>(0)?;
+        }
+
+        util::pad_to(&mut bytes, BTPB::ALIGNMENT);
+        let data_offset = bytes.len() as u64;
+        LE::write_u64(&mut bytes[data_offset_position..data_offset_position + 8], data_offset);
+        for probe in self.groups.iter().flat_map(|group| &group.probes) {
+            bytes.extend_from_slice(&probe.data);
+        }
+
+        Ok(bytes)
+    }
+
+    /// The group called `name`.
+    pub fn group(&self, name: &str) -> Option<&BTPBGroup> {
+        self.groups.iter().find(|group| group.name == name)
+    }
+}
+
+fn invalid(message: String) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, message))
+}
+
+/// A one line summary, e.g. "BTPB v3 — 4 groups, 1200 probes".
+impl Display for BTPB {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let probes: usize = self.groups.iter().map(|group| group.probes.len()).sum();
+        write!(f, "BTPB v{} — {} groups, {} probes", self.header.version, self.groups.len(), probes)
+    }
+}
+
+impl Debug for BTPBProbe {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BTPBProbe")
+            .field("data", &DataLen(self.data.len()))
+            .finish()
+    }
+}
+
+impl BTPBHeader {
+    // Stable accessors, see `BND4Header`.
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn probe_size(&self) -> u32 {
+        self.probe_size
+    }
+}
+
+impl Validate for BTPBHeader {
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(matches!(self.version, 2 | 3), "version was {}", self.version);
+        ensure!(self.group_size as usize >= BTPB::GROUP_FIELDS_SIZE, "group_size was {}", self.group_size);
+        ensure!(self.probe_size != 0, "probe_size was {}", self.probe_size);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use std::fs;
+use std::fs::File;
+use std::io::Error;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::bhd5::FileHeader;
+use crate::error::DantelionFormatsError;
+use crate::parsed_file::strip_dcx;
+use crate::source::FileSource;
+use crate::unpack::entry_size;
+use crate::util;
+
+/// An on-disk cache of decrypted and decompressed archive entries, keyed by archive, hash and size. Repeated reads
+/// of the same entry skip the decryption and Oodle work. An archive's entries are dropped when its BDT is modified,
+/// and the least recently used entries are evicted once the cache is over `max_size`.
+#[derive(Debug)]
+pub struct ExtractionCache {
+    pub dir: PathBuf,
+    pub max_size: u64,
+    // The total size of the cached entries, kept up to date by `insert` so it doesn't have to list the cache every
+    // time. `None` until the cache is first listed, or after an archive's entries were dropped.
+    size: Mutex<Option<u64>>,
+}
+
+impl ExtractionCache {
+    pub const DEFAULT_MAX_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+    // Holds the BDT modified time the archive's entries were cached from.
+    const MTIME_FILE_NAME: &'static str = "mtime";
+
+    pub fn new(dir: &str) -> Result<ExtractionCache, DantelionFormatsError> {
+        fs::create_dir_all(dir)?;
+
+        Ok(ExtractionCache {
+            dir: PathBuf::from(dir),
+            max_size: ExtractionCache::DEFAULT_MAX_SIZE,
+            size: Mutex::new(None),
+        })
+    }
+
+    pub fn with_max_size(mut self, max_size: u64) -> ExtractionCache {
+        self.max_size = max_size;
+        self
+    }
+
+    /// The entry's decompressed bytes, from the cache if it's there, otherwise read from the BDT and cached. Entries
+    /// are keyed by their `file_size`, or by their padded size in archives that leave `file_size` at 0.
+    pub fn read(&self, bdt_path: &str, file_header: &FileHeader) -> Result<Vec<u8>, DantelionFormatsError> {
+        let size = entry_size(file_header);
+        if let Some(data) = self.get(bdt_path, file_header.file_path_hash, size)? {
+            return Ok(data);
+        }
+
+        let data = strip_dcx(&file_header.read_data_from(&FileSource::open(bdt_path)?)?)?;
+        self.insert(bdt_path, file_header.file_path_hash, size, &data)?;
+
+        Ok(data)
+    }
+
+    pub fn get(&self, bdt_path: &str, hash: u64, size: u64) -> Result<Option<Vec<u8>>, DantelionFormatsError> {
+        let path = self.archive_dir(bdt_path)?.join(ExtractionCache::entry_name(hash, size));
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        // Marks the entry as recently used for eviction.
+        File::options().write(true).open(&path)?.set_modified(SystemTime::now())?;
+        Ok(Some(fs::read(&path)?))
+    }
+
+    pub fn insert(&self, bdt_path: &str, hash: u64, size: u64, data: &[u8]) -> Result<(), DantelionFormatsError> {
+        let path = self.archive_dir(bdt_path)?.join(ExtractionCache::entry_name(hash, size));
+        // Written under a temp name so a crash can't leave a partial entry that reads as a hit.
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, data)?;
+        let replaced = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+        fs::rename(&temp, &path)?;
+
+        let mut size = self.lock_size()?;
+        let total = match *size {
+            Some(total) => total - replaced.min(total) + data.len() as u64,
+            None => self.entries()?.iter().map(|(_, size, _)| size).sum(),
+        };
+        *size = Some(total);
+        if total > self.max_size {
+            *size = Some(self.evict()?);
+        }
+
+        Ok(())
+    }
+
+    /// The total size of every cached entry.
+    pub fn size(&self) -> Result<u64, DantelionFormatsError> {
+        let mut size = self.lock_size()?;
+        if let Some(total) = *size {
+            return Ok(total);
+        }
+
+        let total = self.entries()?.iter().map(|(_, size, _)| size).sum();
+        *size = Some(total);
+        Ok(total)
+    }
+
+    pub fn clear(&self) -> Result<(), DantelionFormatsError> {
+        let mut size = self.lock_size()?;
+        fs::remove_dir_all(&self.dir)?;
+        fs::create_dir_all(&self.dir)?;
+        *size = Some(0);
+
+        Ok(())
+    }
+
+    fn lock_size(&self) -> Result<MutexGuard<'_, Option<u64>>, DantelionFormatsError> {
+        Ok(self.size.lock().map_err(|_| Error::other("Cache size lock poisoned"))?)
+    }
+
+    // Each archive gets a folder named after a hash of its path. The folder is emptied if the BDT changed since.
+    fn archive_dir(&self, bdt_path: &str) -> Result<PathBuf, DantelionFormatsError> {
+        let absolute = fs::canonicalize(bdt_path)?;
+        let dir = self.dir.join(format!("{:016x}", util::path_hash_64(&absolute.to_string_lossy())));
+        let mtime = fs::metadata(&absolute)?.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string();
+
+        let mtime_path = dir.join(ExtractionCache::MTIME_FILE_NAME);
+        if fs::read_to_string(&mtime_path).ok().as_deref() != Some(mtime.as_str()) {
+            // Whatever was cached from the old BDT no longer counts towards the total.
+            *self.lock_size()? = None;
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir)?;
+            fs::write(&mtime_path, mtime)?;
+        }
+
+        Ok(dir)
+    }
+
+    fn entry_name(hash: u64, size: u64) -> String {
+        format!("{:016x}_{:x}", hash, size)
+    }
+
+    fn entries(&self) -> Result<Vec<(PathBuf, u64, SystemTime)>, DantelionFormatsError> {
+        let mut entries = vec![];
+        for archive_dir in fs::read_dir(&self.dir)? {
+            let archive_dir = archive_dir?.path();
+            if !archive_dir.is_dir() {
+                continue;
+            }
+
+            for entry in fs::read_dir(&archive_dir)? {
+                let entry = entry?;
+                if entry.file_name() == ExtractionCache::MTIME_FILE_NAME {
+                    continue;
+                }
+                let metadata = entry.metadata()?;
+                entries.push((entry.path(), metadata.len(), metadata.modified()?));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    // Lists the cache and removes the least recently used entries until it fits. Returns the new total size.
+    fn evict(&self) -> Result<u64, DantelionFormatsError> {
+        let mut entries = self.entries()?;
+        let mut size: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        if size <= self.max_size {
+            return Ok(size);
+        }
+
+        entries.sort_by_key(|(_, _, modified)| *modified);
+        for (path, entry_size, _) in entries {
+            if size <= self.max_size {
+                break;
+            }
+            fs::remove_file(&path)?;
+            size -= entry_size;
+        }
+
+        Ok(size)
+    }
+}
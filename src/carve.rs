@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::bnd4::BND4;
+use crate::dcx::DCX;
+use crate::error::DantelionFormatsError;
+use crate::kind::FileKind;
+
+const DCX_HEADER_SIZE: usize = 0x4C;
+
+/// A BND4 or DCX found in a memory dump, at `dump[offset..offset + len]`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Carved {
+    pub offset: usize,
+    pub len: usize,
+    pub kind: FileKind,
+}
+
+impl Carved {
+    pub fn bytes<'a>(&self, dump: &'a [u8]) -> &'a [u8] {
+        &dump[self.offset..self.offset + self.len]
+    }
+}
+
+/// Finds every BND4 and DCX in `dump`, e.g. a game's memory saved by a debugger, which holds the files it has loaded
+/// already decrypted. Each magic is handed to its parser, and the sizes in the header checked against the bytes that are
+/// left, so random bytes that happen to spell a magic are skipped. DCXs are also decompressed when they can be. Scanning resumes after the end of each find, so binders inside binders are
+/// left to be read from the outer one.
+pub fn carve(dump: &[u8]) -> Vec<Carved> {
+    let mut found = vec![];
+    let mut offset = 0;
+    while offset + 4 <= dump.len() {
+        let bytes = &dump[offset..];
+        let carved = match &bytes[..4] {
+            b"BND4" => bnd4_len(bytes).map(|len| (len, FileKind::BND4)),
+            b"DCX\0" => dcx_len(bytes).map(|len| (len, FileKind::DCX)),
+            _ => None,
+        };
+
+        match carved {
+            Some((len, kind)) => {
+                found.push(Carved { offset, len, kind });
+                offset += len;
+            }
+            None => offset += 1,
+        }
+    }
+
+    found
+}
+
+/// Writes everything `carve` finds to `out_dir`, named by offset, e.g. "0001F400.bnd4", and returns the paths.
+pub fn carve_to_dir(dump: &[u8], out_dir: &Path) -> Result<Vec<PathBuf>, DantelionFormatsError> {
+    fs::create_dir_all(out_dir)?;
+    let mut paths = vec![];
+    for carved in carve(dump) {
+        let extension = if carved.kind == FileKind::BND4 { "bnd4" } else { "dcx" };
+        let path = out_dir.join(format!("{:08X}.{}", carved.offset, extension));
+        fs::write(&path, carved.bytes(dump))?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+fn bnd4_len(bytes: &[u8]) -> Option<usize> {
+    let bnd4 = BND4::from_bytes(bytes).ok()?;
+
+    // Where the binder ends: its last file's data, or the end of its headers and hash table if that's further.
+    let header = &bnd4.header;
+    let mut end = header.header_size.max(header.file_headers_end);
+    for file in &bnd4.files {
+        end = end.max(file.data_offset as u64 + file.compressed_size);
+    }
+    if let Some(buckets) = &bnd4.buckets {
+        end = end.max(header.buckets_offset + 0x10 + buckets.bucket_count as u64 * 8)
+            .max(buckets.hashes_offset + header.file_count as u64 * 8);
+    }
+
+    let end = usize::try_from(end).ok()?;
+    (end <= bytes.len()).then_some(end)
+}
+
+fn dcx_len(bytes: &[u8]) -> Option<usize> {
+    let dcx = DCX::from_bytes(bytes).ok()?;
+    if dcx.can_decompress() && dcx.decompress().is_err() {
+        return None;
+    }
+
+    // EDGE blocks are padded past the compressed size, and the content read covers them, padding included.
+    let len = match &dcx.header.egdt {
+        Some(egdt) => DCX_HEADER_SIZE + 0x24 + egdt.blocks.len() * 0x10 + dcx.content.len(),
+        None => DCX_HEADER_SIZE + dcx.header.compressed_size as usize,
+    };
+
+    (len <= bytes.len()).then_some(len)
+}
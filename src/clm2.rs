@@ -0,0 +1,190 @@
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind};
+use binary_interpreter::binary_reader::BinaryReader;
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
+use crate::dcx::DCX;
+use crate::error::DantelionFormatsError;
+use crate::flver::FLVER;
+use crate::source::{DataSource, FileSource};
+use crate::util;
+use crate::util::{ensure, Validate};
+
+/// Cloth mapping stored next to a FLVER in its binder (".clm2"). Each mesh ties one of the FLVER's meshes and a bone
+/// to the cloth simulation in the binder's HKX, with one entry per simulated vertex. Use `check` to make sure the
+/// indices fit the FLVER it's paired with.
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+pub struct CLM2 {
+    pub header: CLM2Header,
+    pub meshes: Vec<ClothMesh>,
+}
+
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+#[non_exhaustive]
+pub struct CLM2Header {
+    pub magic: String,
+    pub version: u32,
+}
+
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+pub struct ClothMesh {
+    // Indices into `FLVER::meshes` and `FLVER::bones`
+    pub mesh_index: i32,
+    pub bone_index: i32,
+    pub vertices: Vec<ClothVertex>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(C)]
+pub struct ClothVertex {
+    pub position: [f32; 3],
+    // Index into the FLVER mesh's vertices
+    pub vertex_index: i32,
+    pub weight: f32,
+}
+
+impl CLM2 {
+    const MAGIC_SIZE: usize = 4;
+    const HEADER_SIZE: usize = 0x10;
+    const MESH_SIZE: usize = 0x10;
+    const VERTEX_SIZE: usize = 0x14;
+
+    pub(crate) fn is(bytes: &[u8]) -> bool {
+        bytes.starts_with(b"CLM2")
+    }
+
+    pub fn from_path(path: &str) -> Result<CLM2, DantelionFormatsError> {
+        CLM2::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<CLM2, DantelionFormatsError> {
+        let file = source.read_all()?;
+
+        CLM2::from_bytes(&file)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<CLM2, DantelionFormatsError> {
+        let bytes = if DCX::is(file) {
+            DCX::from_bytes(file)?.decompress()?
+        } else {
+            file.to_vec()
+        };
+        let mut c = Cursor::new(&bytes[..]);
+
+        let header = CLM2Header {
+            magic: c.read_fixed_cstr(CLM2::MAGIC_SIZE)?,
+            version: c.read_u32::<LE>()?,
+        };
+        header.validate()?;
+        let mesh_count = c.read_u32::<LE>()?;
+        let meshes_offset = c.read_u32::<LE>()?;
+
+        c.set_position(meshes_offset as u64);
+        let mut meshes = Vec::with_capacity(util::capacity_for(&c, mesh_count as u64));
+        for _ in 0..mesh_count {
+            let mesh_index = c.read_i32::<LE>()?;
+            let bone_index = c.read_i32::<LE>()?;
+            let vertex_count = c.read_u32::<LE>()?;
+            let vertices_offset = c.read_u32::<LE>()?;
+
+            let start = c.position();
+            c.set_position(vertices_offset as u64);
+            let mut vertices = Vec::with_capacity(util::capacity_for(&c, vertex_count as u64));
+            for _ in 0..vertex_count {
+                vertices.push(ClothVertex {
+                    position: [c.read_f32::<LE>()?, c.read_f32::<LE>()?, c.read_f32::<LE>()?],
+                    vertex_index: c.read_i32::<LE>()?,
+                    weight: c.read_f32::<LE>()?,
+                });
+            }
+            c.set_position(start);
+
+            meshes.push(ClothMesh { mesh_index, bone_index, vertices });
+        }
+
+        Ok(CLM2 {
+            header,
+            meshes,
+        })
+    }
+
+    pub fn to_path(&self, path: &str) -> Result<(), DantelionFormatsError> {
+        Ok(fs::write(path, self.to_bytes()?)?)
+    }
+
+    /// Serializes the CLM2. The meshes follow the header, then every mesh's vertices in order.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        let mut bytes = vec![];
+        util::write_fixed_str(&mut bytes, &self.header.magic, CLM2::MAGIC_SIZE)?;
+        bytes.write_u32::<LE>(self.header.version)?;
+        bytes.write_u32::<LE>(self.meshes.len() as u32)?;
+        bytes.write_u32::<LE>(CLM2::HEADER_SIZE as u32)?;
+
+        let mut vertices_offset = CLM2::HEADER_SIZE + self.meshes.len() * CLM2::MESH_SIZE;
+        for mesh in &self.meshes {
+            bytes.write_i32::<LE>(mesh.mesh_index)?;
+            bytes.write_i32::<LE>(mesh.bone_index)?;
+            bytes.write_u32::<LE>(mesh.vertices.len() as u32)?;
+            bytes.write_u32::<LE>(vertices_offset as u32)?;
+            vertices_offset += mesh.vertices.len() * CLM2::VERTEX_SIZE;
+        }
+        for vertex in self.meshes.iter().flat_map(|mesh| &mesh.vertices) {
+            for value in vertex.position {
+                bytes.write_f32::<LE>(value)?;
+            }
+            bytes.write_i32::<LE>(vertex.vertex_index)?;
+            bytes.write_f32::<LE>(vertex.weight)?;
+        }
+
+        Ok(bytes)
+    }
+
+    /// Checks that every mesh, bone and vertex index points at something in `flver`, the model this cloth belongs to.
+    pub fn check(&self, flver: &FLVER) -> Result<(), DantelionFormatsError> {
+        for (index, cloth) in self.meshes.iter().enumerate() {
+            let mesh = usize::try_from(cloth.mesh_index).ok().and_then(|mesh_index| flver.meshes.get(mesh_index))
+                .ok_or_else(|| invalid(format!("Cloth mesh {} uses mesh {}, but the FLVER has {}", index, cloth.mesh_index, flver.meshes.len())))?;
+            if usize::try_from(cloth.bone_index).map_or(true, |bone_index| bone_index >= flver.bones.len()) {
+                return Err(invalid(format!("Cloth mesh {} uses bone {}, but the FLVER has {}", index, cloth.bone_index, flver.bones.len())));
+            }
+
+            let vertex_count = mesh.vertex_buffers.first().map_or(0, |buffer| buffer.vertex_count);
+            if let Some(vertex) = cloth.vertices.iter().find(|vertex| u32::try_from(vertex.vertex_index).map_or(true, |vertex_index| vertex_index >= vertex_count)) {
+                return Err(invalid(format!("Cloth mesh {} uses vertex {}, but mesh {} has {}", index, vertex.vertex_index, cloth.mesh_index, vertex_count)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A one line summary, e.g. "CLM2 — 2 meshes, 400 vertices".
+impl Display for CLM2 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let vertices: usize = self.meshes.iter().map(|mesh| mesh.vertices.len()).sum();
+        write!(f, "CLM2 — {} meshes, {} vertices", self.meshes.len(), vertices)
+    }
+}
+
+fn invalid(message: String) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, message))
+}
+
+impl CLM2Header {
+    // Stable accessors, see `BND4Header`.
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+}
+
+impl Validate for CLM2Header {
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.magic == "CLM2", "Magic was {}", self.magic);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::bhd5::GameType;
+#[cfg(feature = "crypto")]
+use crate::keys::KeyStore;
+use crate::error::DantelionFormatsError;
+#[cfg(feature = "steam-discovery")]
+use crate::discovery::{self, GameLocator};
+#[cfg(feature = "oodle")]
+use crate::{oodle, util};
+
+pub const CONFIG_PATH_ENV_VAR: &str = "DANTELION_CONFIG";
+pub const CONFIG_FILE_NAME: &str = "dantelion.json";
+
+/// Overrides for everything that's normally auto-discovered. Anything set here is used as is, so headless setups
+/// and installs Steam doesn't know about work without the registry.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct Config {
+    pub steam_path: Option<PathBuf>,
+    // The DLL itself or the folder it's in.
+    pub oodle_path: Option<PathBuf>,
+    // The folder with the exe and data archives, same as `GameLocator::game_dir`.
+    pub game_dirs: HashMap<GameType, PathBuf>,
+    // Defaults to `GameLocator::default_epic_manifests_path`.
+    pub epic_manifests_path: Option<PathBuf>,
+    // Extra BHD5 public keys in PEM, by archive path relative to the game folder, e.g. "sd/sd_dlc02". Checked before
+    // the built in ones.
+    pub bhd5_keys: HashMap<String, String>,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config::default()
+    }
+
+    /// Reads `DANTELION_CONFIG` if set, otherwise `dantelion.json` in the working dir. No file means no overrides.
+    pub fn load() -> Result<Config, DantelionFormatsError> {
+        if let Ok(path) = env::var(CONFIG_PATH_ENV_VAR) {
+            return Config::from_path(&path);
+        }
+
+        if Path::new(CONFIG_FILE_NAME).exists() {
+            return Config::from_path(CONFIG_FILE_NAME);
+        }
+
+        Ok(Config::default())
+    }
+
+    pub fn from_path(path: &str) -> Result<Config, DantelionFormatsError> {
+        let file = fs::read_to_string(path)?;
+
+        Ok(serde_json::from_str(&file)?)
+    }
+
+    pub fn to_json(&self) -> Result<String, DantelionFormatsError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn with_steam_path(mut self, steam_path: &str) -> Config {
+        self.steam_path = Some(PathBuf::from(steam_path));
+        self
+    }
+
+    pub fn with_oodle_path(mut self, oodle_path: &str) -> Config {
+        self.oodle_path = Some(PathBuf::from(oodle_path));
+        self
+    }
+
+    pub fn with_game_dir(mut self, game: GameType, game_dir: &str) -> Config {
+        self.game_dirs.insert(game, PathBuf::from(game_dir));
+        self
+    }
+
+    /// Adds a BHD5 public key, see `bhd5_keys`.
+    pub fn with_bhd5_key(mut self, archive: &str, public_key: &str) -> Config {
+        self.bhd5_keys.insert(archive.to_string(), public_key.to_string());
+        self
+    }
+
+    #[cfg(feature = "crypto")]
+    /// The ER keys readers decrypt with: `bhd5_keys` first, then the built in ones, so a configured key that doesn't
+    /// decrypt an archive falls back to the built in one.
+    pub fn key_store(&self) -> KeyStore {
+        self.bhd5_keys.iter()
+            .fold(KeyStore::new(), |store, (archive, public_key)| store.add(GameType::EldenRing, archive, None, public_key))
+            .extend(KeyStore::elden_ring())
+    }
+
+    #[cfg(feature = "steam-discovery")]
+    /// A locator using these overrides. The Steam registry keys are only read if no Steam path is set.
+    pub fn locator(&self) -> GameLocator {
+        GameLocator {
+            steam_path: self.steam_path.clone().or_else(|| discovery::get_steam_install_path().map(PathBuf::from)),
+            game_dirs: self.game_dirs.clone(),
+            epic_manifests_path: Some(self.epic_manifests_path.clone().unwrap_or_else(GameLocator::default_epic_manifests_path)),
+            search_gog: true,
+        }
+    }
+
+    #[cfg(feature = "oodle")]
+    /// The configured DLL if there is one, otherwise the usual search.
+    pub fn oodle_path(&self) -> Option<String> {
+        if let Some(path) = &self.oodle_path {
+            let dll = if path.is_dir() { path.join(util::OODLE_DLL_NAME) } else { path.clone() };
+            if dll.exists() {
+                return Some(dll.to_string_lossy().to_string());
+            }
+        }
+
+        oodle::find_oodle_path(&self.oodle_game_dirs())
+    }
+
+    #[cfg(feature = "oodle")]
+    // ER and Sekiro ship the DLL. Without steam-discovery only their configured `game_dirs` are searched.
+    fn oodle_game_dirs(&self) -> Vec<PathBuf> {
+        let games = [GameType::EldenRing, GameType::Sekiro];
+        #[cfg(feature = "steam-discovery")]
+        let game_dirs = {
+            let locator = self.locator();
+            games.iter().filter_map(|&game| locator.game_dir(game)).collect()
+        };
+        #[cfg(not(feature = "steam-discovery"))]
+        let game_dirs = games.iter().filter_map(|game| self.game_dirs.get(game).cloned()).collect();
+
+        game_dirs
+    }
+}
@@ -1,62 +1,270 @@
-use std::io::{Error, ErrorKind};
+use std::io::{Error, ErrorKind, Read, Write};
 use std::path::Path;
+use openssl::rand::rand_bytes;
 use openssl::symm::*;
 use openssl::rsa::{Padding, Rsa};
+use crate::bnd4::BND4;
+use crate::dcx::DCX;
 use crate::error::DantelionFormatsError;
+use crate::stats::Stats;
 
-pub(crate) fn decrypt_regulation(file: &[u8], key: &[u8]) -> Result<Vec<u8>, DantelionFormatsError> {
+/// Decrypts a regulation.bin. The result is a DCX, e.g. an ER regulation decrypted with `ER_REGULATION_KEY`.
+pub fn decrypt_regulation(file: &[u8], key: &[u8]) -> Result<Vec<u8>, DantelionFormatsError> {
+    let mut out = Vec::new();
+    decrypt_regulation_into(file, key, &mut out)?;
+    Ok(out)
+}
+
+pub fn decrypt_regulation_into(file: &[u8], key: &[u8], out: &mut Vec<u8>) -> Result<(), DantelionFormatsError> {
     let iv = &file[..16];
     let cipher = Cipher::aes_256_cbc();
     let mut crypter = Crypter::new(cipher, Mode::Decrypt, key, Some(iv))?;
     crypter.pad(false);
     let encypted = &file[16..];
-    let mut out = vec![0; file.len() + cipher.block_size()];
-    let count = crypter.update(encypted, &mut out)?;
+    out.clear();
+    out.resize(file.len() + cipher.block_size(), 0);
+    let count = crypter.update(encypted, out)?;
     let rest = crypter.finalize(&mut out[count..])?;
     out.truncate(count + rest);
-    Ok(out)
+    Ok(())
+}
+
+/// Encrypts a DCX into a regulation.bin with a random IV, the reverse of `decrypt_regulation`. The data is zero padded
+/// to the AES block size, which the DCX header's sizes make harmless.
+pub fn encrypt_regulation(dcx: &[u8], key: &[u8]) -> Result<Vec<u8>, DantelionFormatsError> {
+    let cipher = Cipher::aes_256_cbc();
+    let mut iv = [0; 16];
+    rand_bytes(&mut iv)?;
+    let mut crypter = Crypter::new(cipher, Mode::Encrypt, key, Some(&iv))?;
+    crypter.pad(false);
+
+    let mut padded = dcx.to_vec();
+    padded.resize(dcx.len().div_ceil(cipher.block_size()) * cipher.block_size(), 0);
+    let mut out = vec![0; padded.len() + cipher.block_size()];
+    let count = crypter.update(&padded, &mut out)?;
+    let rest = crypter.finalize(&mut out[count..])?;
+    out.truncate(count + rest);
+
+    Ok([iv.as_slice(), &out].concat())
+}
+
+/// Decrypts, decompresses and parses a regulation.bin into its BND4 of params.
+pub fn read_regulation(file: &[u8], key: &[u8]) -> Result<(BND4, Stats), DantelionFormatsError> {
+    let mut stats = Stats {
+        files: 1,
+        bytes_read: file.len() as u64,
+        ..Stats::default()
+    };
+    let dcx = Stats::time(&mut stats.decrypt_time, || decrypt_regulation(file, key))?;
+    let bnd4 = Stats::time(&mut stats.decompress_time, || DCX::decompress_bytes(&dcx))?;
+    let bnd4 = BND4::from_bytes(&bnd4)?;
+    stats.count_format("BND4");
+
+    Ok((bnd4, stats))
+}
+
+/// Decrypts a BHD5 with its PKCS#1 PEM public key. See `BHD5::from_encrypted_bytes` to also parse it.
+pub fn decrypt_bhd5_file(file: &[u8], key: &[u8]) -> Result<Vec<u8>, DantelionFormatsError> {
+    let mut decrypted_data = Vec::new();
+    decrypt_bhd5_file_into(file, key, &mut decrypted_data)?;
+    Ok(decrypted_data)
 }
 
-pub(crate) fn decrypt_bhd5_file(file: &[u8], key: &[u8]) -> Result<Vec<u8>, DantelionFormatsError> {
+pub fn decrypt_bhd5_file_into(file: &[u8], key: &[u8], decrypted_data: &mut Vec<u8>) -> Result<(), DantelionFormatsError> {
+    decrypted_data.clear();
+    decrypted_data.reserve(file.len());
+    let mut reader = file;
+    decrypt_bhd5_stream(&mut reader, key, decrypted_data)?;
 
-    // Read the private key from a PEM file
+    Ok(())
+}
+
+/// Same as `decrypt_bhd5_file`, one key sized block at a time from `reader` to `writer`, so very large headers
+/// don't have to be in memory twice. Returns how many decrypted bytes were written. Input that ends partway through
+/// a block is a `MisalignedRsaData` error, after the whole blocks before it have been written.
+pub fn decrypt_bhd5_stream(reader: &mut impl Read, key: &[u8], writer: &mut impl Write) -> Result<u64, DantelionFormatsError> {
     let public_key = Rsa::public_key_from_pem_pkcs1(key)?;
 
-    // Decrypt the data using the private key
     let key_size = public_key.size() as usize;
-    let mut len = 0;
-    let mut decrypted_data: Vec<u8> = vec!();
-
-    while len < file.len() {
-        let mut decrypted_block = vec![0; key_size];
-        let next_block: usize = len + key_size;
-        let block_data = &file[len..next_block];
-        len += public_key.public_decrypt(block_data, &mut decrypted_block, Padding::NONE)?;
-        decrypted_data.extend_from_slice(&decrypted_block[1..]);
+    let mut block = vec![0; key_size];
+    let mut decrypted_block = vec![0; key_size];
+    let mut read = 0u64;
+    let mut written = 0u64;
+    loop {
+        let filled = read_block(reader, &mut block)?;
+        read += filled as u64;
+        if filled == 0 {
+            break;
+        }
+        if filled < key_size {
+            return Err(DantelionFormatsError::MisalignedRsaData { len: read, key_size });
+        }
+
+        public_key.public_decrypt(&block, &mut decrypted_block, Padding::NONE)?;
+        // Each block starts with a zero byte, see `encrypt_bhd5_file`.
+        writer.write_all(&decrypted_block[1..])?;
+        written += key_size as u64 - 1;
+    }
+
+    Ok(written)
+}
+
+// Fills `block` unless the reader ends first, returning how much was read.
+fn read_block(reader: &mut impl Read, block: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < block.len() {
+        match reader.read(&mut block[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
     }
 
-    // Trim the decrypted data to the actual length
-    decrypted_data.truncate(len as usize);
+    Ok(filled)
+}
+
+/// Counterpart to `decrypt_bhd5_file`. Each block of the key size holds a leading zero byte and `key_size - 1`
+/// bytes of data, with the last block padded with zeros.
+pub fn encrypt_bhd5_file(file: &[u8], private_key: &[u8]) -> Result<Vec<u8>, DantelionFormatsError> {
+    let private_key = Rsa::private_key_from_pem(private_key)?;
+    let key_size = private_key.size() as usize;
+    let mut encrypted_data = Vec::with_capacity((file.len() / (key_size - 1) + 1) * key_size);
+    let mut block = vec![0; key_size];
+    let mut encrypted_block = vec![0; key_size];
 
-    return Ok(decrypted_data);
+    for chunk in file.chunks(key_size - 1) {
+        block.fill(0);
+        block[1..1 + chunk.len()].copy_from_slice(chunk);
+        let len = private_key.private_encrypt(&block, &mut encrypted_block, Padding::NONE)?;
+        encrypted_data.extend_from_slice(&encrypted_block[..len]);
+    }
+
+    Ok(encrypted_data)
 }
 
-pub(crate) fn get_elden_ring_bhd5_key(path: &str) -> Result<&[u8], DantelionFormatsError> {
-    let file_name = Path::new(path)
-        .file_stem().unwrap().to_str().unwrap();
-    for key in ELDEN_RING_KEYS {
-        if key.0 == file_name {
-            return Ok(key.1.as_bytes());
+/// Generates a new RSA key pair for encrypting a BHD5. Returns the PKCS#1 PEM encoded (public, private) keys. The
+/// public key is the one that has to be patched into the game.
+pub fn generate_bhd5_key_pair() -> Result<(String, String), DantelionFormatsError> {
+    let rsa = Rsa::generate(2048)?;
+    let public_key = String::from_utf8(rsa.public_key_to_pem_pkcs1()?)?;
+    let private_key = String::from_utf8(rsa.private_key_to_pem()?)?;
+
+    Ok((public_key, private_key))
+}
+
+pub(crate) fn generate_aes_key() -> Result<[u8; 16], DantelionFormatsError> {
+    let mut key = [0; 16];
+    rand_bytes(&mut key)?;
+    Ok(key)
+}
+
+/// A random salt with the prefix `BHD5::from_bytes` uses to detect the format.
+pub(crate) fn generate_salt(prefix: &str) -> Result<String, DantelionFormatsError> {
+    let mut bytes = [0; 8];
+    rand_bytes(&mut bytes)?;
+    let random: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!("{}{}", prefix, random))
+}
+
+/// AES-128-ECB encrypts or decrypts the given ranges of `data` in place, the way BDT entries are encrypted.
+/// Ranges with a negative begin or end are skipped, like the game does.
+pub(crate) fn crypt_aes_ranges(data: &mut [u8], key: &[u8], ranges: &[(i64, i64)], mode: Mode) -> Result<(), DantelionFormatsError> {
+    let cipher = Cipher::aes_128_ecb();
+    for &(begin, end) in ranges {
+        if begin < 0 || end < 0 || begin >= end {
+            continue;
+        }
+        // Ranges past the end of the data, or past what a 32-bit usize can hold, stop at the end of the data.
+        let end = usize::try_from(end).unwrap_or(usize::MAX).min(data.len());
+        let begin = usize::try_from(begin).unwrap_or(usize::MAX);
+        if begin >= end {
+            continue;
         }
+
+        let mut crypter = Crypter::new(cipher, mode, key, None)?;
+        crypter.pad(false);
+        let mut out = vec![0; end - begin + cipher.block_size()];
+        let count = crypter.update(&data[begin..end], &mut out)?;
+        let rest = crypter.finalize(&mut out[count..])?;
+        data[begin..begin + count + rest].copy_from_slice(&out[..count + rest]);
     }
 
-    Err(DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, format!("Could not find key for {}", file_name))))
+    Ok(())
+}
+
+/// The public key for one of ER's archives, picked by its path relative to the game folder, e.g. ".../Game/Data0.bhd"
+/// or ".../Game/sd/sd.bhd". Keys for other archives, like the DLC's, can be added with `Config::bhd5_keys`.
+pub fn get_elden_ring_bhd5_key(path: &str) -> Result<&'static [u8], DantelionFormatsError> {
+    find_bhd5_key(path, ELDEN_RING_KEYS)
+        .ok_or_else(|| DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, format!("Could not find key for {}", path))))
+}
+
+/// Picks the key whose archive name matches the end of `path`, see `bhd5_key_matches`. Relative paths that don't
+/// match as given are tried again made absolute, so "sd.bhd" run from the sd folder still finds `sd\sd`.
+pub fn find_bhd5_key<'a>(path: &str, keys: impl IntoIterator<Item = (&'a str, &'a str)>) -> Option<&'a [u8]> {
+    let keys: Vec<_> = keys.into_iter().collect();
+    let find = |path: &str| keys.iter().find(|(name, _)| bhd5_key_matches(path, name)).map(|(_, key)| key.as_bytes());
+    find(path).or_else(|| find(&std::path::absolute(path).ok()?.to_string_lossy()))
+}
+
+/// Whether a key table name, e.g. "Data0" or `sd\sd`, names the archive at `path`. The name is matched against
+/// whole trailing path components, ignoring case, slash direction and the extension.
+pub fn bhd5_key_matches(path: &str, name: &str) -> bool {
+    let normalize = |s: &str| s.to_lowercase().replace('\\', "/");
+    let path = normalize(path);
+    let path = Path::new(&path).with_extension("");
+    let path = path.to_string_lossy();
+    let name = normalize(name);
+
+    path.strip_suffix(name.as_str()).is_some_and(|rest| rest.is_empty() || rest.ends_with('/'))
 }
 
-pub(crate) const ER_REGULATION_KEY: [u8; 0x20] = [0x99, 0xBF, 0xFC, 0x36, 0x6A, 0x6B, 0xC8, 0xC6, 0xF5,
+pub const ER_REGULATION_KEY: [u8; 0x20] = [0x99, 0xBF, 0xFC, 0x36, 0x6A, 0x6B, 0xC8, 0xC6, 0xF5,
     0x82, 0x7D, 0x09, 0x36, 0x02, 0xD6, 0x76, 0xC4, 0x28, 0x92, 0xA0, 0x1C, 0x20, 0x7F, 0xB0, 0x24,
     0xD3, 0xAF, 0x4E, 0x49, 0x3F, 0xEF, 0x99];
 
+// AES-128 key for the USER_DATA entries in DS3 saves
+pub const DS3_SAVE_KEY: [u8; 0x10] = [0xFD, 0x46, 0x4D, 0x69, 0x5E, 0x69, 0xA3, 0x9A, 0x10, 0xE3, 0x19, 0xA7, 0xAC, 0xE8,
+    0xB7, 0xFA];
+
+/// Decrypts a DS3 save entry, after its checksum: a 16 byte IV and AES-128-CBC data. The data's padding is kept.
+pub fn decrypt_save_entry(entry: &[u8], key: &[u8]) -> Result<Vec<u8>, DantelionFormatsError> {
+    if entry.len() < 16 || !entry.len().is_multiple_of(16) {
+        return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("0x{:X} bytes is not an IV and whole AES blocks", entry.len()))));
+    }
+
+    let cipher = Cipher::aes_128_cbc();
+    let mut crypter = Crypter::new(cipher, Mode::Decrypt, key, Some(&entry[..16]))?;
+    crypter.pad(false);
+    let mut out = vec![0; entry.len() + cipher.block_size()];
+    let count = crypter.update(&entry[16..], &mut out)?;
+    let rest = crypter.finalize(&mut out[count..])?;
+    out.truncate(count + rest);
+
+    Ok(out)
+}
+
+/// Encrypts data for a DS3 save entry with a random IV, returning the IV and encrypted data. The data has to be whole
+/// AES blocks, as it is when it came from `decrypt_save_entry`.
+pub fn encrypt_save_entry(data: &[u8], key: &[u8]) -> Result<Vec<u8>, DantelionFormatsError> {
+    if !data.len().is_multiple_of(16) {
+        return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidInput, format!("0x{:X} bytes is not whole AES blocks", data.len()))));
+    }
+
+    let cipher = Cipher::aes_128_cbc();
+    let mut iv = [0; 16];
+    rand_bytes(&mut iv)?;
+    let mut crypter = Crypter::new(cipher, Mode::Encrypt, key, Some(&iv))?;
+    crypter.pad(false);
+    let mut out = vec![0; data.len() + cipher.block_size()];
+    let count = crypter.update(data, &mut out)?;
+    let rest = crypter.finalize(&mut out[count..])?;
+    out.truncate(count + rest);
+
+    Ok([iv.as_slice(), &out].concat())
+}
+
 pub(crate) static ELDEN_RING_KEYS: [(&str, &str); 5] = [
     ("Data0",
      "-----BEGIN RSA PUBLIC KEY-----
@@ -1,11 +1,24 @@
+use std::fmt::{self, Debug, Display, Formatter};
 use std::fs;
-use std::io::{Cursor};
+use std::io::{Cursor, Error, ErrorKind, Write};
 use binary_interpreter::binary_reader::BinaryReader;
-use byteorder::{BE, ReadBytesExt};
-use miniz_oxide::inflate::decompress_to_vec;
-use crate::{oodle};
+use byteorder::{BE, ReadBytesExt, WriteBytesExt};
+use miniz_oxide::deflate::{compress_to_vec, compress_to_vec_zlib};
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::{DataFormat, MZFlush, MZStatus};
+#[cfg(not(feature = "libdeflate"))]
+use miniz_oxide::inflate::{decompress_slice_iter_to_slice, DecompressError};
+#[cfg(feature = "oodle")]
+use crate::oodle::{self, OodleContext};
 use crate::error::DantelionFormatsError;
-use crate::util::Validate;
+use crate::source::{DataSource, FileSource};
+use crate::spill::{Payload, SpillWriter};
+use crate::util;
+use crate::util::{DataLen, ensure, Validate};
+
+// Stands in for the Oodle context when the oodle feature is off, so KRAK files fail with `OodleUnavailable`.
+#[cfg(not(feature = "oodle"))]
+enum OodleContext {}
 
 #[repr(C)]
 pub struct DCX {
@@ -13,7 +26,9 @@ pub struct DCX {
     pub content: Vec<u8>,
 }
 
+#[derive(Debug)]
 #[repr(C)]
+#[non_exhaustive]
 pub struct DCXHeader {
     pub magic: String,
     pub unk04: u32,
@@ -41,8 +56,37 @@ pub struct DCXHeader {
     // From before "DCA" to dca end
     pub egdt: Option<EGDTHeader>
 }
-#[derive(Clone)]
+/// How a container was DCX compressed, kept by the containers that are read from DCX so they can be written back the
+/// same way.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct DcxInfo {
+    // "DFLT", "EDGE" or "KRAK"
+    pub format: String,
+    pub compression_level: u8,
+    // Usually 1. Files compressed more than once are assumed to use the same format for every layer.
+    pub layers: u32,
+}
+
+impl DcxInfo {
+    /// Compresses `data` into `layers` layers of `format`. DFLT and EDGE are recompressed with this crate's settings,
+    /// so the bytes can differ from the original even when the format matches. KRAK can't be written.
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, DantelionFormatsError> {
+        let mut bytes = data.to_vec();
+        for _ in 0..self.layers.max(1) {
+            bytes = match self.format.as_str() {
+                "DFLT" => DCX::compress_dflt(&bytes),
+                "EDGE" => DCX::compress_edge(&bytes),
+                format => return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::Unsupported, format!("Can't write {} DCX", format)))),
+            }.to_bytes()?;
+        }
+
+        Ok(bytes)
+    }
+}
+
+#[derive(Clone, Debug)]
 #[repr(C)]
+#[non_exhaustive]
 pub struct EGDTHeader {
     pub egdt: String,
     pub unk50: u32,
@@ -56,8 +100,9 @@ pub struct EGDTHeader {
     pub blocks: Vec<Block>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 #[repr(C)]
+#[non_exhaustive]
 pub struct Block {
     pub unk00: u32,
     pub data_offset: u32,
@@ -72,9 +117,13 @@ impl DCX {
     const FORMAT_SIZE: usize = 4;
     const DCA_SIZE: usize = 4;
     const EGDT_SIZE: usize = 4;
+    const EDGE_BLOCK_SIZE: usize = 0x10000;
+    const COMPRESSION_LEVEL: u8 = 9;
+    // Deflate's best case is a 258 byte match from about two bits of input.
+    const MAX_DEFLATE_RATIO: usize = 1032;
 
     pub(crate) fn is(bytes: &[u8]) -> bool {
-        &bytes[..4] == b"DCX\0"
+        bytes.starts_with(b"DCX\0")
     }
 
     pub fn decompress_bytes(bytes: &[u8]) -> Result<Vec<u8>, DantelionFormatsError> {
@@ -82,20 +131,378 @@ impl DCX {
         dcx.decompress()
     }
 
+    /// Same as `decompress_bytes`, but decompresses into `out` so the allocation can be reused across calls.
+    pub fn decompress_bytes_into(bytes: &[u8], out: &mut Vec<u8>) -> Result<(), DantelionFormatsError> {
+        let dcx = DCX::from_bytes(bytes)?;
+        dcx.decompress_into(out)
+    }
+
+    /// Decompresses every DCX layer of `bytes` into `out`. None, with `out` untouched, if `bytes` isn't DCX.
+    pub(crate) fn decompress_layers_into(bytes: &[u8], out: &mut Vec<u8>) -> Result<Option<DcxInfo>, DantelionFormatsError> {
+        if !DCX::is(bytes) {
+            return Ok(None);
+        }
+
+        let dcx = DCX::from_bytes(bytes)?;
+        let mut info = dcx.info();
+        dcx.decompress_into(out)?;
+        while DCX::is(out) {
+            let inner = DCX::from_bytes(out)?.decompress()?;
+            *out = inner;
+            info.layers += 1;
+        }
+
+        Ok(Some(info))
+    }
+
+    pub fn info(&self) -> DcxInfo {
+        DcxInfo {
+            format: self.header.format.clone(),
+            compression_level: self.header.compression_level(),
+            layers: 1,
+        }
+    }
+
     pub fn decompress(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        let mut out = Vec::new();
+        self.decompress_into(&mut out)?;
+        Ok(out)
+    }
+
+    /// Decompresses into `out`, replacing its contents. The buffer's capacity is kept, so passing the same
+    /// buffer in a loop avoids an allocation per file.
+    pub fn decompress_into(&self, out: &mut Vec<u8>) -> Result<(), DantelionFormatsError> {
+        self.decompress_content(None, out)
+    }
+
+    /// Same as `decompress`, but once the output passes `threshold` bytes it goes to a temporary file instead of
+    /// memory. DFLT and EDGE content is inflated a chunk at a time, so never needs more than `threshold` bytes of
+    /// memory for the output.
+    ///
+    /// `threshold` is not a memory cap for KRAK content. Oodle needs the whole output buffer, so KRAK files are
+    /// decoded in memory, `uncompressed_size` bytes at once, and only spilled afterwards.
+    pub fn decompress_spilling(&self, threshold: usize) -> Result<Payload, DantelionFormatsError> {
+        let payload = if self.header.format == "KRAK" {
+            Payload::from_vec(self.decompress()?, threshold)?
+        } else if let Some(egdt) = &self.header.egdt {
+            let mut writer = SpillWriter::new(threshold);
+            self.inflate_edge_to(egdt, &mut writer)?;
+            writer.finish()?
+        } else {
+            // Same zlib then raw deflate fallback as `inflate_into`, with a fresh writer for the retry.
+            let zlib = DCX::has_zlib_header(&self.content).then(|| {
+                let mut writer = SpillWriter::new(threshold);
+                DCX::inflate_stream(&self.content, DataFormat::Zlib, &mut writer)?;
+                writer.finish()
+            });
+            match zlib {
+                Some(Ok(payload)) => payload,
+                _ => {
+                    let mut writer = SpillWriter::new(threshold);
+                    DCX::inflate_stream(&self.content, DataFormat::Raw, &mut writer)?;
+                    writer.finish()?
+                }
+            }
+        };
+
+        let expected = self.header.uncompressed_size as usize;
+        if payload.len() != expected as u64 {
+            return Err(DantelionFormatsError::DecompressedSizeMismatch { expected, actual: payload.len() as usize });
+        }
+
+        Ok(payload)
+    }
+
+    /// False for KRAK files when no Oodle DLL can be found, or the config saying where it is can't be read, so tools can
+    /// tell the user before trying.
+    #[cfg(feature = "oodle")]
+    pub fn can_decompress(&self) -> bool {
+        self.header.format != "KRAK" || matches!(oodle::get_oodle_path(), Ok(Some(_)))
+    }
+
+    /// Without the oodle feature KRAK files can't be decompressed at all.
+    #[cfg(not(feature = "oodle"))]
+    pub fn can_decompress(&self) -> bool {
+        self.header.format != "KRAK"
+    }
+
+    fn oodle_unavailable(&self) -> DantelionFormatsError {
+        DantelionFormatsError::OodleUnavailable {
+            required_dll: util::OODLE_DLL_NAME,
+            compression_level: self.header.compression_level(),
+        }
+    }
+
+    #[cfg(feature = "oodle")]
+    /// Same as `decompress_into`, but uses an already loaded Oodle for KRAK files instead of searching for it.
+    pub fn decompress_into_with(&self, oodle: &OodleContext, out: &mut Vec<u8>) -> Result<(), DantelionFormatsError> {
+        self.decompress_content(Some(oodle), out)
+    }
+
+    fn decompress_content(&self, oodle: Option<&OodleContext>, out: &mut Vec<u8>) -> Result<(), DantelionFormatsError> {
         if self.header.format == "KRAK" {
-            unsafe {
-                    return Ok(oodle::decompress(&self.content[..], self.header.uncompressed_size as usize)?)
+            self.decompress_krak_into(oodle, out)?;
+        } else if let Some(egdt) = &self.header.egdt {
+            self.inflate_edge_into(egdt, out)?;
+        } else {
+            self.inflate_into(out)?;
+        }
+
+        let expected = self.header.uncompressed_size as usize;
+        if out.len() != expected {
+            return Err(DantelionFormatsError::DecompressedSizeMismatch { expected, actual: out.len() });
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "oodle")]
+    fn decompress_krak_into(&self, oodle: Option<&OodleContext>, out: &mut Vec<u8>) -> Result<(), DantelionFormatsError> {
+        let loaded;
+        let oodle = match oodle {
+            Some(oodle) => oodle,
+            None => {
+                loaded = OodleContext::new().map_err(|_| self.oodle_unavailable())?;
+                &loaded
+            }
+        };
+        unsafe {
+            oodle.decompress_into(&self.content[..], self.header.uncompressed_size as usize, out)?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "oodle"))]
+    fn decompress_krak_into(&self, _oodle: Option<&OodleContext>, _out: &mut Vec<u8>) -> Result<(), DantelionFormatsError> {
+        Err(self.oodle_unavailable())
+    }
+
+    fn inflate_into(&self, out: &mut Vec<u8>) -> Result<(), DantelionFormatsError> {
+        // The header's size is only trusted as far as deflate could expand the content, so a corrupt one can't
+        // reserve gigabytes. The byte past it is how output longer than the header says gets noticed.
+        let expected = self.header.uncompressed_size as usize;
+        let capacity = expected.min(self.content.len().saturating_mul(DCX::MAX_DEFLATE_RATIO)) + 1;
+
+        // Most DFLT content is zlib wrapped, but some files are raw deflate, so fall back to inflating the
+        // whole thing if the header check or the zlib inflate fails.
+        let mut attempts = vec![(&self.content[..], DataFormat::Raw)];
+        if DCX::has_zlib_header(&self.content) {
+            attempts.insert(0, (&self.content[2..], DataFormat::Zlib));
+        }
+        let mut first_error = None;
+        for &(data, _) in &attempts {
+            out.clear();
+            out.resize(capacity, 0);
+            match DCX::inflate(data, out) {
+                Ok(len) => {
+                    out.truncate(len);
+                    return Ok(());
+                }
+                Err(e) => { first_error.get_or_insert(e); }
+            }
+        }
+
+        // Either the content is bad or there's more of it than fits. Streaming tells which, and gives the real size
+        // for `DecompressedSizeMismatch`.
+        for (_, format) in attempts {
+            out.clear();
+            if DCX::inflate_stream(&self.content, format, out).is_ok() {
+                return Ok(());
+            }
+        }
+
+        Err(first_error.expect("There is always at least one attempt"))
+    }
+
+    fn inflate_edge_into(&self, egdt: &EGDTHeader, out: &mut Vec<u8>) -> Result<(), DantelionFormatsError> {
+        out.clear();
+        out.reserve(self.header.uncompressed_size as usize);
+        self.inflate_edge_to(egdt, out)
+    }
+
+    // Blocks are inflated independently, so only one block's output is held at a time.
+    fn inflate_edge_to(&self, egdt: &EGDTHeader, out: &mut impl Write) -> Result<(), DantelionFormatsError> {
+        let mut block_out = vec![0; DCX::EDGE_BLOCK_SIZE];
+        let mut remaining = self.header.uncompressed_size as usize;
+        for (i, block) in egdt.blocks.iter().enumerate() {
+            let size = if i + 1 == egdt.blocks.len() { egdt.last_block_uncompressed_size as usize } else { DCX::EDGE_BLOCK_SIZE };
+            // The block table comes from the file, so a block past the end of the content is truncated input.
+            let start = block.data_offset as usize;
+            let data = start.checked_add(block.data_length as usize)
+                .and_then(|end| self.content.get(start..end))
+                .ok_or_else(|| DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("EDGE block {} at {:#X} runs past the end of the data", i, start))))?;
+            let end = size.min(remaining).min(block_out.len());
+            let len = if block.is_compressed() {
+                DCX::inflate(data, &mut block_out[..end])?
+            } else {
+                let len = data.len().min(end);
+                block_out[..len].copy_from_slice(&data[..len]);
+                len
+            };
+            out.write_all(&block_out[..len])?;
+            remaining -= len;
+        }
+        Ok(())
+    }
+
+    fn inflate_stream(data: &[u8], format: DataFormat, out: &mut impl Write) -> Result<(), DantelionFormatsError> {
+        let mut state = InflateState::new_boxed(format);
+        let mut chunk = vec![0; DCX::EDGE_BLOCK_SIZE];
+        let mut input = data;
+        loop {
+            let result = inflate(&mut state, input, &mut chunk, MZFlush::None);
+            input = &input[result.bytes_consumed..];
+            out.write_all(&chunk[..result.bytes_written])?;
+            match result.status {
+                Ok(MZStatus::StreamEnd) => return Ok(()),
+                Ok(_) if result.bytes_consumed != 0 || result.bytes_written != 0 => {}
+                status => return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("Could not inflate DCX content: {:?}", status)))),
             }
         }
+    }
+
+    fn has_zlib_header(data: &[u8]) -> bool {
+        if data.len() < 2 {
+            return false;
+        }
+
+        let cmf = data[0];
+        let flg = data[1];
+        // Compression method 8 (deflate), window size <= 32K, no preset dictionary and a valid check value.
+        cmf & 0x0F == 8 && cmf >> 4 <= 7 && flg & 0x20 == 0 && (((cmf as u16) << 8) | flg as u16).is_multiple_of(31)
+    }
+
+    #[cfg(not(feature = "libdeflate"))]
+    fn inflate(data: &[u8], out: &mut [u8]) -> Result<usize, DantelionFormatsError> {
+        Ok(decompress_slice_iter_to_slice(out, std::iter::once(data), false, true)
+            .map_err(|status| DecompressError { status, output: vec![] })?)
+    }
 
-        assert_eq!(self.content[0], 0x78);
-        assert!(self.content[1] == 0x01 || self.content[1] == 0x05E || self.content[1] == 0x9C || self.content[1] == 0xDA);
-        Ok(decompress_to_vec(&self.content[2..])?)
+    #[cfg(feature = "libdeflate")]
+    fn inflate(data: &[u8], out: &mut [u8]) -> Result<usize, DantelionFormatsError> {
+        let mut decompressor = libdeflater::Decompressor::new();
+        Ok(decompressor.deflate_decompress(data, out)?)
+    }
+
+    /// Zlib compresses `data` into a DFLT DCX, using the header layout from DS3.
+    pub fn compress_dflt(data: &[u8]) -> DCX {
+        let content = compress_to_vec_zlib(data, DCX::COMPRESSION_LEVEL);
+
+        DCX {
+            header: DCXHeader::new("DFLT", 0x2C, data.len() as u32, content.len() as u32, 0, 0x00010100, None),
+            content,
+        }
+    }
+
+    /// Splits `data` into 64KB blocks and deflates each one into an EDGE DCX, as used on PS3. Blocks that
+    /// don't get smaller are stored uncompressed.
+    pub fn compress_edge(data: &[u8]) -> DCX {
+        let mut blocks = vec![];
+        let mut content = vec![];
+        let mut compressed_size = 0;
+        for chunk in data.chunks(DCX::EDGE_BLOCK_SIZE) {
+            let compressed = compress_to_vec(chunk, DCX::COMPRESSION_LEVEL);
+            let (bytes, is_compressed) = if compressed.len() < chunk.len() { (compressed, 1) } else { (chunk.to_vec(), 0) };
+            blocks.push(Block {
+                unk00: 0,
+                data_offset: content.len() as u32,
+                data_length: bytes.len() as u32,
+                unk0c: is_compressed,
+            });
+            compressed_size += bytes.len();
+            content.extend(bytes);
+            util::pad_to(&mut content, 0x10);
+        }
+
+        let block_count = blocks.len() as u32;
+        let last_block_uncompressed_size = match blocks.len() {
+            0 => 0,
+            count => data.len() - (count - 1) * DCX::EDGE_BLOCK_SIZE,
+        };
+        let egdt = EGDTHeader {
+            egdt: "EgdT".to_string(),
+            unk50: 0x10100,
+            unk54: 0x24,
+            unk58: 0x10,
+            unk5c: 0x10000,
+            last_block_uncompressed_size: last_block_uncompressed_size as u32,
+            egdt_size: 0x24 + block_count * 0x10,
+            block_count,
+            unk6c: 0x100000,
+            blocks,
+        };
+
+        DCX {
+            header: DCXHeader::new("EDGE", 0x50 + block_count * 0x10, data.len() as u32, compressed_size as u32, 0x10000, 0x00100100, Some(egdt)),
+            content,
+        }
+    }
+
+    pub fn to_path(&self, path: &str) -> Result<(), DantelionFormatsError> {
+        Ok(fs::write(path, self.to_bytes()?)?)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        let mut bytes = vec![];
+        self.write_dcx_header(&mut bytes)?;
+        bytes.extend_from_slice(&self.content);
+
+        Ok(bytes)
+    }
+
+    fn write_dcx_header(&self, bytes: &mut Vec<u8>) -> Result<(), DantelionFormatsError> {
+        let header = &self.header;
+        util::write_fixed_str(bytes, &header.magic, DCX::MAGIC_SIZE)?;
+        bytes.write_u32::<BE>(header.unk04)?;
+        bytes.write_u32::<BE>(header.dcs_offset)?;
+        bytes.write_u32::<BE>(header.dcp_offset)?;
+        bytes.write_u32::<BE>(header.unk10)?;
+        bytes.write_u32::<BE>(header.unk14)?;
+        util::write_fixed_str(bytes, &header.dcs, DCX::DCS_SIZE)?;
+        bytes.write_u32::<BE>(header.uncompressed_size)?;
+        bytes.write_u32::<BE>(header.compressed_size)?;
+        util::write_fixed_str(bytes, &header.dcp, DCX::DCP_SIZE)?;
+        util::write_fixed_str(bytes, &header.format, DCX::FORMAT_SIZE)?;
+        bytes.write_u32::<BE>(header.unk2c)?;
+        bytes.write_u8(header.unk30)?;
+        bytes.write_u8(header.unk31)?;
+        bytes.write_u8(header.unk32)?;
+        bytes.write_u8(header.unk33)?;
+        bytes.write_u32::<BE>(header.unk34)?;
+        bytes.write_u32::<BE>(header.unk38)?;
+        bytes.write_u32::<BE>(header.unk3c)?;
+        bytes.write_u32::<BE>(header.unk40)?;
+        util::write_fixed_str(bytes, &header.dca, DCX::DCA_SIZE)?;
+        bytes.write_u32::<BE>(header.dca_size)?;
+
+        if let Some(egdt) = &header.egdt {
+            util::write_fixed_str(bytes, &egdt.egdt, DCX::EGDT_SIZE)?;
+            bytes.write_u32::<BE>(egdt.unk50)?;
+            bytes.write_u32::<BE>(egdt.unk54)?;
+            bytes.write_u32::<BE>(egdt.unk58)?;
+            bytes.write_u32::<BE>(egdt.unk5c)?;
+            bytes.write_u32::<BE>(egdt.last_block_uncompressed_size)?;
+            bytes.write_u32::<BE>(egdt.egdt_size)?;
+            bytes.write_u32::<BE>(egdt.block_count)?;
+            bytes.write_u32::<BE>(egdt.unk6c)?;
+            for block in &egdt.blocks {
+                bytes.write_u32::<BE>(block.unk00)?;
+                bytes.write_u32::<BE>(block.data_offset)?;
+                bytes.write_u32::<BE>(block.data_length)?;
+                bytes.write_u32::<BE>(block.unk0c)?;
+            }
+        }
+
+        Ok(())
     }
 
     pub fn from_path(path: &str) -> Result<DCX, DantelionFormatsError> {
-        let file = fs::read(path)?;
+        DCX::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<DCX, DantelionFormatsError> {
+        let file = source.read_all()?;
 
         DCX::from_bytes(&file)
     }
@@ -147,7 +554,7 @@ impl DCX {
             header.egdt = Some(DCX::read_egdt_header(c)?);
         }
 
-        header.validate();
+        header.validate()?;
 
         Ok(header)
     }
@@ -181,20 +588,23 @@ impl DCX {
     }
 
     fn read_content(c: &mut Cursor<&[u8]>, header: &DCXHeader) -> Result<Vec<u8>, DantelionFormatsError> {
-        // Will have to look at a file.
-        // if header.format == "EDGE" {
-        //     let start = br.pos;
-        //     for block in header.blocks.unwrap() {
-        //         br.pos = start + block.data_offset;
-        //
-        //     }
-        // }
+        // EDGE blocks are padded, so compressed_size doesn't cover all of the block data. Keep everything up to
+        // the end of the last block, including its padding when it's there, so the block offsets stay valid.
+        if let Some(egdt) = &header.egdt {
+            let end = egdt.blocks.iter()
+                .map(|block| block.data_offset as usize + block.data_length as usize)
+                .max()
+                .unwrap_or(0);
+            let remaining = c.get_ref().len().saturating_sub(c.position() as usize);
+            let padded_end = ((end + 0xF) & !0xF).min(remaining).max(end);
+            return util::read_len(c, padded_end as u64);
+        }
 
-        Ok(c.read_bytes(header.compressed_size as usize)?)
+        util::read_len(c, header.compressed_size as u64)
     }
 
     fn read_blocks(c: &mut Cursor<&[u8]>, count: u32) -> Result<Vec<Block>, DantelionFormatsError> {
-        let mut blocks = Vec::with_capacity(count as usize);
+        let mut blocks = Vec::with_capacity(util::capacity_for(c, count as u64));
         for _ in 0..count {
             let block = Block {
                 unk00: c.read_u32::<BE>()?,
@@ -213,39 +623,130 @@ impl DCX {
 
 
 
+impl DCXHeader {
+    fn new(format: &str, unk14: u32, uncompressed_size: u32, compressed_size: u32, unk34: u32, unk40: u32, egdt: Option<EGDTHeader>) -> DCXHeader {
+        let dca_size = match &egdt {
+            None => 8,
+            Some(egdt) => 8 + egdt.egdt_size,
+        };
+
+        DCXHeader {
+            magic: "DCX\0".to_string(),
+            unk04: 0x10000,
+            dcs_offset: 0x18,
+            dcp_offset: 0x24,
+            unk10: 0x24,
+            unk14,
+            dcs: "DCS\0".to_string(),
+            uncompressed_size,
+            compressed_size,
+            dcp: "DCP\0".to_string(),
+            format: format.to_string(),
+            unk2c: 0x20,
+            unk30: 9,
+            unk31: 0,
+            unk32: 0,
+            unk33: 0,
+            unk34,
+            unk38: 0,
+            unk3c: 0,
+            unk40,
+            dca: "DCA\0".to_string(),
+            dca_size,
+            egdt,
+        }
+    }
+
+    // Stable accessors, see `BND4Header`.
+
+    /// "DFLT", "EDGE" or "KRAK".
+    pub fn format(&self) -> &str {
+        &self.format
+    }
+
+    pub fn uncompressed_size(&self) -> u32 {
+        self.uncompressed_size
+    }
+
+    pub fn compressed_size(&self) -> u32 {
+        self.compressed_size
+    }
+
+    /// The first byte of the DCP parameters, the level the file was compressed with.
+    pub fn compression_level(&self) -> u8 {
+        self.unk30
+    }
+}
+
+impl EGDTHeader {
+    /// The uncompressed size of every block but the last.
+    pub fn block_size(&self) -> u32 {
+        self.unk5c
+    }
+
+    pub fn last_block_uncompressed_size(&self) -> u32 {
+        self.last_block_uncompressed_size
+    }
+}
+
+impl Block {
+    /// False for blocks that were stored as they are because deflating didn't make them smaller.
+    pub fn is_compressed(&self) -> bool {
+        self.unk0c == 1
+    }
+}
+
+/// A one line summary, e.g. "DCX DFLT — 65536 bytes, 12345 compressed".
+impl Display for DCX {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "DCX {} — {} bytes, {} compressed", self.header.format(), self.header.uncompressed_size, self.header.compressed_size)
+    }
+}
+
+impl Debug for DCX {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DCX")
+            .field("header", &self.header)
+            .field("content", &DataLen(self.content.len()))
+            .finish()
+    }
+}
+
 impl Validate for DCXHeader {
-    fn validate(&self) {
-        assert_eq!(self.magic, "DCX\0", "Magic was {}", self.magic);
-        assert!(self.unk04 == 0x10000 || self.unk04 == 0x11000, "DCXself.unk04 was {}", self.unk04);
-        assert_eq!(self.dcs_offset, 0x18, "self.dcs_offset was {}", self.dcs_offset);
-        assert_eq!(self.dcp_offset, 0x24, "self.dcp_offset was {}", self.dcp_offset);
-        assert!(self.unk10 == 0x24 || self.unk10 == 0x44, "self.unk10 was {}", self.unk10);
-        assert_eq!(self.dcs, "DCS\0", "self.dcs was {}", self.dcs);
-        assert_eq!(self.dcp, "DCP\0", "self.dcp was {}", self.dcp);
-        assert!(self.format == "DFLT" || self.format == "EDGE" || self.format == "KRAK", "self.format was {}", self.format);
-        assert_eq!(self.unk2c, 0x20, "self.unk2c was {}", self.unk2c);
-        assert!(self.unk30 == 6 || self.unk30 == 8 || self.unk30 == 9, "self.unk30 was {}", self.unk30);
-        assert_eq!(self.unk31, 0, "self.unk31 was {}", self.unk31);
-        assert_eq!(self.unk32, 0, "self.unk32 was {}", self.unk32);
-        assert_eq!(self.unk33, 0, "self.unk33 was {}", self.unk33);
-        assert!(self.unk34 == 0 || self.unk34 == 0x10000, "self.dcxOffset was {}", self.unk34);
-        assert!(self.unk38 == 0 || self.unk38 == 0xF000000, "self.dcxOffset was {}", self.unk38);
-        assert_eq!(self.unk3c, 0, "self.unk3c was {}", self.unk3c);
-        assert_eq!(self.dca, "DCA\0", "self.dca was {}", self.dca);
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.magic == "DCX\0", "Magic was {}", self.magic);
+        ensure!(self.unk04 == 0x10000 || self.unk04 == 0x11000, "self.unk04 was {}", self.unk04);
+        ensure!(self.dcs_offset == 0x18, "self.dcs_offset was {}", self.dcs_offset);
+        ensure!(self.dcp_offset == 0x24, "self.dcp_offset was {}", self.dcp_offset);
+        ensure!(self.unk10 == 0x24 || self.unk10 == 0x44, "self.unk10 was {}", self.unk10);
+        ensure!(self.dcs == "DCS\0", "self.dcs was {}", self.dcs);
+        ensure!(self.dcp == "DCP\0", "self.dcp was {}", self.dcp);
+        ensure!(self.format == "DFLT" || self.format == "EDGE" || self.format == "KRAK", "self.format was {}", self.format);
+        ensure!(self.unk2c == 0x20, "self.unk2c was {}", self.unk2c);
+        ensure!(self.unk30 == 6 || self.unk30 == 8 || self.unk30 == 9, "self.unk30 was {}", self.unk30);
+        ensure!(self.unk31 == 0, "self.unk31 was {}", self.unk31);
+        ensure!(self.unk32 == 0, "self.unk32 was {}", self.unk32);
+        ensure!(self.unk33 == 0, "self.unk33 was {}", self.unk33);
+        ensure!(self.unk34 == 0 || self.unk34 == 0x10000, "self.unk34 was {}", self.unk34);
+        ensure!(self.unk38 == 0 || self.unk38 == 0xF000000, "self.unk38 was {}", self.unk38);
+        ensure!(self.unk3c == 0, "self.unk3c was {}", self.unk3c);
+        ensure!(self.dca == "DCA\0", "self.dca was {}", self.dca);
 
         if self.format == "EDGE" {
-            let egdt = self.egdt.clone().unwrap();
-            assert_eq!(egdt.egdt, "EgdT", "self.egdt was {}", egdt.egdt);
-            assert_eq!(egdt.unk50, 0x10100, "self.unk3c was {}", egdt.unk50);
-            assert_eq!(egdt.unk54, 0x24, "self.unk54 was {}", egdt.unk54);
-            assert_eq!(egdt.unk58, 0x10, "self.unk58 was {}", egdt.unk58);
-            assert_eq!(egdt.unk5c, 0x10000, "self.unk5C was {}", egdt.unk5c);
-            assert_eq!(egdt.unk6c, 0x100000, "self.unk6C was {}", egdt.unk6c);
-
-            for block in egdt.blocks {
-                assert_eq!(block.unk00, 0, "block.unk00 was {}", block.unk00);
-                assert_eq!(block.unk0c, 1, "block.unk0c was {}", block.unk0c);
+            let Some(egdt) = &self.egdt else { return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, "EDGE DCX has no EgdT header"))) };
+            ensure!(egdt.egdt == "EgdT", "self.egdt was {}", egdt.egdt);
+            ensure!(egdt.unk50 == 0x10100, "egdt.unk50 was {}", egdt.unk50);
+            ensure!(egdt.unk54 == 0x24, "self.unk54 was {}", egdt.unk54);
+            ensure!(egdt.unk58 == 0x10, "self.unk58 was {}", egdt.unk58);
+            ensure!(egdt.unk5c == 0x10000, "self.unk5C was {}", egdt.unk5c);
+            ensure!(egdt.unk6c == 0x100000, "self.unk6C was {}", egdt.unk6c);
+
+            for block in &egdt.blocks {
+                ensure!(block.unk00 == 0, "block.unk00 was {}", block.unk00);
+                ensure!(block.unk0c == 0 || block.unk0c == 1, "block.unk0c was {}", block.unk0c);
             }
         }
+
+        Ok(())
     }
 }
@@ -0,0 +1,227 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use crate::bhd5::{BHD5, BHD5Format, GameType};
+use crate::error::DantelionFormatsError;
+use crate::parsed_file::{open_bytes, ParsedFile};
+use crate::util;
+
+/// Which path hash a dictionary is keyed by. Elden Ring's archives use 64-bit hashes, every other game's 32-bit ones.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum HashWidth {
+    Bits32,
+    Bits64,
+}
+
+impl HashWidth {
+    pub fn for_game(game: GameType) -> HashWidth {
+        match game {
+            GameType::EldenRing => HashWidth::Bits64,
+            _ => HashWidth::Bits32,
+        }
+    }
+
+    pub fn for_format(format: BHD5Format) -> HashWidth {
+        match format {
+            BHD5Format::EldenRing => HashWidth::Bits64,
+            _ => HashWidth::Bits32,
+        }
+    }
+
+    pub fn hash(&self, path: &str) -> u64 {
+        match self {
+            HashWidth::Bits32 => util::path_hash(path) as u64,
+            HashWidth::Bits64 => util::path_hash_64(path),
+        }
+    }
+}
+
+/// Dictionary paths keyed by their hash. Paths that hash the same as one already in it are reported in `issues`
+/// instead of replacing it, so the first path for a hash is always the one kept.
+#[derive(Debug)]
+pub struct Dictionary {
+    pub width: HashWidth,
+    pub paths: HashMap<u64, String>,
+    pub issues: Vec<DictionaryIssue>,
+}
+
+/// A path `Dictionary::insert` didn't add.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum DictionaryIssue {
+    /// A different path with the same hash. Either could be the real one, only the archive's contents can tell.
+    Collision { hash: u64, kept: String, other: String },
+    /// The same path written differently, e.g. in another case or with backslashes, which hash the same.
+    Ambiguous { hash: u64, kept: String, other: String },
+}
+
+impl Dictionary {
+    pub fn new(width: HashWidth) -> Dictionary {
+        Dictionary {
+            width,
+            paths: HashMap::new(),
+            issues: vec![],
+        }
+    }
+
+    pub fn for_game(game: GameType) -> Dictionary {
+        Dictionary::new(HashWidth::for_game(game))
+    }
+
+    /// Reads a dictionary in UXM's format, see `Vfs::read_dictionary`.
+    pub fn from_path(path: &str, width: HashWidth) -> Result<Dictionary, DantelionFormatsError> {
+        Ok(Dictionary::parse(&fs::read_to_string(path)?, width))
+    }
+
+    pub fn parse(text: &str, width: HashWidth) -> Dictionary {
+        let mut dictionary = Dictionary::new(width);
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            dictionary.insert(line);
+        }
+
+        dictionary
+    }
+
+    /// Adds `path` unless its hash is already taken, returning whether it was added. Exact repeats are skipped without
+    /// an issue.
+    pub fn insert(&mut self, path: &str) -> bool {
+        let hash = self.width.hash(path);
+        let Some(kept) = self.paths.get(&hash) else {
+            self.paths.insert(hash, path.to_string());
+            return true;
+        };
+
+        if kept != path {
+            let (kept, other) = (kept.clone(), path.to_string());
+            self.issues.push(if normalize(&kept) == normalize(&other) {
+                DictionaryIssue::Ambiguous { hash, kept, other }
+            } else {
+                DictionaryIssue::Collision { hash, kept, other }
+            });
+        }
+
+        false
+    }
+
+    pub fn get(&self, hash: u64) -> Option<&str> {
+        self.paths.get(&hash).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// The kept paths, sorted, e.g. for `Vfs::new`.
+    pub fn to_paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self.paths.values().cloned().collect();
+        paths.sort();
+        paths
+    }
+}
+
+// What the path hashes see.
+fn normalize(path: &str) -> String {
+    let path = path.to_lowercase().replace('\\', "/");
+    if path.starts_with('/') { path } else { format!("/{}", path) }
+}
+
+/// A path whose hash is in the archive but wasn't in the dictionary.
+#[derive(Debug)]
+pub struct DictionaryEntry {
+    pub hash: u64,
+    pub path: String,
+}
+
+/// Walks an extracted archive and returns every binder name that resolves to a hash in `bhd5` that isn't in `known`.
+/// Files that can't be read or parsed are skipped, so one bad file doesn't stop the walk.
+pub fn discover_entries(extraction_dir: &str, bhd5: &BHD5, known: &HashSet<u64>) -> Result<Vec<DictionaryEntry>, DantelionFormatsError> {
+    let archive_hashes: HashSet<u64> = bhd5.buckets.iter()
+        .flat_map(|bucket| &bucket.file_headers)
+        .map(|file_header| file_header.file_path_hash)
+        .collect();
+
+    let mut names = vec![];
+    walk(Path::new(extraction_dir), &mut names)?;
+
+    let mut entries = BTreeMap::new();
+    for path in names.iter().flat_map(|name| candidate_paths(name)) {
+        let hash = BHD5::hash_path(&path, bhd5.format);
+        if archive_hashes.contains(&hash) && !known.contains(&hash) {
+            entries.entry(hash).or_insert(path);
+        }
+    }
+
+    Ok(entries.into_iter().map(|(hash, path)| DictionaryEntry { hash, path }).collect())
+}
+
+/// Every name in the binder, including the names in binders nested inside it.
+pub fn binder_names(file: &[u8]) -> Result<Vec<String>, DantelionFormatsError> {
+    let files: Vec<(Option<String>, Option<Vec<u8>>)> = match open_bytes(file)? {
+        ParsedFile::BND3(bnd3) => bnd3.files.into_iter().map(|file| (file.name, file.data)).collect(),
+        ParsedFile::BND4(bnd4) => bnd4.files.into_iter().map(|file| (file.name, file.data)).collect(),
+        _ => return Ok(vec![]),
+    };
+
+    let mut names = vec![];
+    for (name, data) in files {
+        names.extend(name);
+        if let Some(data) = data {
+            names.extend(binder_names(&data).unwrap_or_default());
+        }
+    }
+
+    Ok(names)
+}
+
+/// Archive paths a binder name might correspond to. Binder names are full paths on the developers' machines, like
+/// "N:\GR\data\INTERROOT_win64\chr\c2010\c2010.anibnd", while the archives usually flatten the last folder and
+/// compress the file, so a few variants are tried.
+pub fn candidate_paths(name: &str) -> Vec<String> {
+    let name = name.replace('\\', "/");
+    let relative = match name.find("/INTERROOT_") {
+        Some(interroot) => name[interroot + 1..].split_once('/').map_or("", |(_, rest)| rest),
+        // Drive letter and nothing else to go on
+        None => name.split_once(":/").map_or(name.as_str(), |(_, rest)| rest),
+    };
+    if relative.is_empty() {
+        return vec![];
+    }
+
+    let mut paths = vec![format!("/{}", relative)];
+    let parts: Vec<&str> = relative.split('/').collect();
+    if parts.len() > 2 {
+        paths.push(format!("/{}/{}", parts[..parts.len() - 2].join("/"), parts[parts.len() - 1]));
+    }
+
+    let compressed: Vec<String> = paths.iter().map(|path| format!("{}.dcx", path)).collect();
+    paths.extend(compressed);
+    paths
+}
+
+/// Appends the entries' paths to a dictionary file, one per line, same as UXM's dictionaries.
+pub fn append_entries(dictionary_path: &str, entries: &[DictionaryEntry]) -> Result<(), DantelionFormatsError> {
+    let mut dictionary = OpenOptions::new().create(true).append(true).open(dictionary_path)?;
+    for entry in entries {
+        writeln!(dictionary, "{}", entry.path)?;
+    }
+
+    Ok(())
+}
+
+fn walk(dir: &Path, names: &mut Vec<String>) -> Result<(), DantelionFormatsError> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk(&path, names)?;
+        } else if let Ok(file) = fs::read(&path) {
+            names.extend(binder_names(&file).unwrap_or_default());
+        }
+    }
+
+    Ok(())
+}
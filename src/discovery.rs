@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+#[cfg(windows)]
+use winreg::enums::*;
+#[cfg(windows)]
+use winreg::{RegKey};
+use sysinfo::System;
+use crate::bhd5::GameType;
+use crate::config::Config;
+use crate::error::DantelionFormatsError;
+
+#[cfg(windows)]
+pub(crate) static STEAM_REGISTRY_LOCATIONS: [(&str, &str, &str); 4] = [
+    ("HKCU", r"SOFTWARE\Valve\Steam", "SteamPath"),
+    ("HKLM", r"SOFTWARE\Wow6432Node\Valve\Steam", "InstallPath"),
+    ("HKLM", r"SOFTWARE\Valve\Steam", "InstallPath"),
+    ("HKCU", r"SOFTWARE\Wow6432Node\Valve\Steam", "SteamPath"),
+];
+
+#[cfg(windows)]
+pub(crate) static GOG_REGISTRY_LOCATIONS: [&str; 2] = [r"SOFTWARE\WOW6432Node\GOG.com\Games", r"SOFTWARE\GOG.com\Games"];
+
+/// Finds installed games through Steam's library folders and app manifests, so install dirs come from Steam itself
+/// rather than guessing at `steamapps/common` folder names. Installs from the Epic Games Store and GOG are found by
+/// title, if the game isn't installed through Steam.
+#[derive(Debug)]
+pub struct GameLocator {
+    pub steam_path: Option<PathBuf>,
+    // Used instead of looking the game up, see `Config::with_game_dir`.
+    pub game_dirs: HashMap<GameType, PathBuf>,
+    // The folder with the launcher's .item manifests, usually ProgramData\Epic\EpicGamesLauncher\Data\Manifests
+    pub epic_manifests_path: Option<PathBuf>,
+    pub search_gog: bool,
+}
+
+impl GameLocator {
+    /// Uses the overrides from `Config::load`, and the Steam install from the registry for everything else. Fails if
+    /// there's a config file and it can't be read.
+    pub fn new() -> Result<GameLocator, DantelionFormatsError> {
+        Ok(Config::load()?.locator())
+    }
+
+    pub fn with_steam_path(steam_path: &str) -> GameLocator {
+        GameLocator {
+            steam_path: Some(PathBuf::from(steam_path)),
+            game_dirs: HashMap::new(),
+            epic_manifests_path: None,
+            search_gog: false,
+        }
+    }
+
+    /// Where the Epic Games Launcher keeps its manifests, based on `ProgramData`.
+    pub fn default_epic_manifests_path() -> PathBuf {
+        let program_data = env::var("PROGRAMDATA").unwrap_or_else(|_| r"C:\ProgramData".to_string());
+        Path::new(&program_data).join("Epic").join("EpicGamesLauncher").join("Data").join("Manifests")
+    }
+
+    /// Every Steam library, from `libraryfolders.vdf`. The Steam install itself is always first.
+    pub fn library_folders(&self) -> Vec<PathBuf> {
+        let steam_path = match &self.steam_path {
+            Some(steam_path) => steam_path,
+            None => return vec![],
+        };
+
+        let mut folders = vec![steam_path.clone()];
+        if let Ok(vdf) = fs::read_to_string(steam_path.join("steamapps").join("libraryfolders.vdf")) {
+            for (key, value) in parse_vdf(&vdf) {
+                let folder = PathBuf::from(value);
+                if key == "path" && !folders.contains(&folder) {
+                    folders.push(folder);
+                }
+            }
+        }
+
+        folders
+    }
+
+    /// The `steamapps/appmanifest_<appid>.acf` for `game`, from whichever library it's installed in.
+    pub fn app_manifest(&self, game: GameType) -> Option<PathBuf> {
+        let app_id = game.steam_app_id()?;
+        self.library_folders().into_iter()
+            .map(|folder| folder.join("steamapps").join(format!("appmanifest_{}.acf", app_id)))
+            .find(|manifest| manifest.exists())
+    }
+
+    /// The game's root install dir, from Steam, then the Epic Games Store, then GOG.
+    pub fn install_dir(&self, game: GameType) -> Option<PathBuf> {
+        self.steam_install_dir(game)
+            .or_else(|| self.epic_install_dir(game))
+            .or_else(|| self.gog_install_dir(game))
+    }
+
+    /// From the `installdir` in the game's app manifest.
+    pub fn steam_install_dir(&self, game: GameType) -> Option<PathBuf> {
+        let manifest = self.app_manifest(game)?;
+        let acf = fs::read_to_string(&manifest).ok()?;
+        let install_dir = parse_vdf(&acf).into_iter().find(|(key, _)| key.eq_ignore_ascii_case("installdir"))?.1;
+
+        // appmanifest_<appid>.acf sits in steamapps, next to common.
+        let dir = manifest.parent()?.join("common").join(install_dir);
+        if dir.is_dir() { Some(dir) } else { None }
+    }
+
+    /// From the `InstallLocation` of the .item manifest with the game's title.
+    pub fn epic_install_dir(&self, game: GameType) -> Option<PathBuf> {
+        let manifests = fs::read_dir(self.epic_manifests_path.as_ref()?).ok()?;
+        manifests.filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "item"))
+            .filter_map(|path| serde_json::from_str::<serde_json::Value>(&fs::read_to_string(path).ok()?).ok())
+            .find(|item| item["DisplayName"].as_str().is_some_and(|name| is_title(name, game)))
+            .and_then(|item| item["InstallLocation"].as_str().map(PathBuf::from))
+            .filter(|dir| dir.is_dir())
+    }
+
+    /// From the `path` of the GOG Galaxy registry key with the game's title. Always `None` off Windows.
+    #[cfg(windows)]
+    pub fn gog_install_dir(&self, game: GameType) -> Option<PathBuf> {
+        if !self.search_gog {
+            return None;
+        }
+
+        for location in GOG_REGISTRY_LOCATIONS {
+            let games = match RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey(location) {
+                Ok(games) => games,
+                Err(_) => continue,
+            };
+
+            for id in games.enum_keys().filter_map(|id| id.ok()) {
+                let key = match games.open_subkey(&id) {
+                    Ok(key) => key,
+                    Err(_) => continue,
+                };
+                let name: String = key.get_value("gameName").unwrap_or_default();
+                if is_title(&name, game) {
+                    let path: String = key.get_value("path").ok()?;
+                    return Some(PathBuf::from(path)).filter(|dir| dir.is_dir());
+                }
+            }
+        }
+
+        None
+    }
+
+    #[cfg(not(windows))]
+    pub fn gog_install_dir(&self, _game: GameType) -> Option<PathBuf> {
+        None
+    }
+
+    /// The folder with the game's exe and data archives.
+    pub fn game_dir(&self, game: GameType) -> Option<PathBuf> {
+        if let Some(game_dir) = self.game_dirs.get(&game) {
+            return Some(game_dir.clone());
+        }
+
+        let install_dir = self.install_dir(game)?;
+        match game {
+            GameType::DarkSouls => Some(install_dir.join("DATA")),
+            GameType::DarkSoulsRemastered => Some(install_dir),
+            _ => Some(install_dir.join("Game")),
+        }
+    }
+}
+
+// Store names differ in case, punctuation and trademark signs, so only letters and digits are compared.
+fn is_title(name: &str, game: GameType) -> bool {
+    let normalize = |s: &str| s.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect::<String>();
+    normalize(name) == normalize(game.title())
+}
+
+/// Flattens a VDF/ACF file into its key value pairs, ignoring nesting. Keys and values are unescaped.
+pub(crate) fn parse_vdf(text: &str) -> Vec<(String, String)> {
+    let mut tokens = vec![];
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let mut token = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => token.extend(chars.next()),
+                        c => token.push(c),
+                    }
+                }
+                tokens.push(Some(token));
+            }
+            '{' | '}' => tokens.push(None),
+            _ => {}
+        }
+    }
+
+    // A string followed by a string is a key value pair. A string followed by a brace is a section name.
+    let mut pairs = vec![];
+    let mut i = 0;
+    while i + 1 < tokens.len() {
+        if let (Some(key), Some(value)) = (&tokens[i], &tokens[i + 1]) {
+            pairs.push((key.clone(), value.clone()));
+            i += 2;
+        } else {
+            i += 1;
+        }
+    }
+
+    pairs
+}
+
+#[cfg(windows)]
+pub(crate) fn get_steam_install_path() -> Option<String> {
+    for location in STEAM_REGISTRY_LOCATIONS {
+        let hkey = if location.0 == "HKCU" { HKEY_CURRENT_USER } //I hate this :(
+        else if location.0 == "HKLM" { HKEY_LOCAL_MACHINE } else { return None; };
+
+        let reg_key = RegKey::predef(hkey)
+            .open_subkey(location.1);
+
+        match reg_key {
+            Ok(key) => return Some(key.get_value(location.2).unwrap()),
+            Err(_) => {}
+        }
+    }
+
+    None
+}
+
+// There's no registry to ask, so the Steam path has to be configured, see `Config::steam_path`.
+#[cfg(not(windows))]
+pub(crate) fn get_steam_install_path() -> Option<String> {
+    None
+}
+
+/// Checks for the game's process by exe name. Case insensitive, since Proton and Windows don't agree on case.
+pub fn is_game_running(game: GameType) -> bool {
+    let exe_name = match game.exe_name() {
+        Some(exe_name) => exe_name,
+        None => return false,
+    };
+
+    let mut system = System::new();
+    system.refresh_processes();
+    system.processes().values().any(|process| process.name().eq_ignore_ascii_case(exe_name))
+}
+
+/// Errors with `GameRunning` if any of `games` is running. Used before writing to installed game files.
+pub fn ensure_not_running(games: &[GameType]) -> Result<(), DantelionFormatsError> {
+    for &game in games {
+        if is_game_running(game) {
+            return Err(DantelionFormatsError::GameRunning { exe_name: game.exe_name().unwrap_or_default() });
+        }
+    }
+
+    Ok(())
+}
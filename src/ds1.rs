@@ -0,0 +1,59 @@
+use crate::bnd3::BND3;
+use crate::dcx::DCX;
+use crate::error::DantelionFormatsError;
+use crate::parsed_file::strip_dcx;
+
+// Root folder in binder file names. Everything else in the path is the same between the two.
+const PTDE_INTERROOT: &str = "INTERROOT_win32";
+const DSR_INTERROOT: &str = "INTERROOT_x64";
+
+// Files that PTDE ships uncompressed, but DSR ships as DCX.
+const DSR_DCX_EXTENSIONS: [&str; 4] = [".emevd", ".luabnd", ".talkesdbnd", ".ffxbnd"];
+
+/// Re-targets a PTDE file for DSR. `name` is the file's name on disk, and the name the file should be saved
+/// under in DSR is returned along with the bytes, since DSR compresses some files PTDE doesn't.
+pub fn ptde_to_dsr(name: &str, file: &[u8]) -> Result<(String, Vec<u8>), DantelionFormatsError> {
+    retarget(name, file, PTDE_INTERROOT, DSR_INTERROOT, true)
+}
+
+/// Re-targets a DSR file for PTDE. Same as `ptde_to_dsr`, the other way around.
+pub fn dsr_to_ptde(name: &str, file: &[u8]) -> Result<(String, Vec<u8>), DantelionFormatsError> {
+    retarget(name, file, DSR_INTERROOT, PTDE_INTERROOT, false)
+}
+
+fn retarget(name: &str, file: &[u8], from: &str, to: &str, to_dsr: bool) -> Result<(String, Vec<u8>), DantelionFormatsError> {
+    let was_dcx = DCX::is(file);
+    let mut bytes = strip_dcx(file)?;
+
+    if bytes.starts_with(b"BND3") {
+        let mut bnd3 = BND3::from_bytes(&bytes)?;
+        for file in bnd3.files.iter_mut() {
+            let Some(file_name) = &file.name else { continue };
+            let file_name = replace_interroot(file_name, from, to);
+            // Binders nested in binders, like the ones in chrbnds, need the same treatment.
+            if let Some(data) = &file.data {
+                let (_, data) = retarget(&file_name, data, from, to, to_dsr)?;
+                file.data = Some(data);
+            }
+            file.name = Some(file_name);
+        }
+        bytes = bnd3.to_bytes()?;
+    }
+
+    let base_name = name.strip_suffix(".dcx").unwrap_or(name);
+    let dcx_by_game = DSR_DCX_EXTENSIONS.iter().any(|ext| base_name.to_lowercase().ends_with(ext));
+    let compress = if dcx_by_game { to_dsr } else { was_dcx };
+    if !compress {
+        return Ok((base_name.to_string(), bytes));
+    }
+
+    Ok((format!("{}.dcx", base_name), DCX::compress_dflt(&bytes).to_bytes()?))
+}
+
+fn replace_interroot(name: &str, from: &str, to: &str) -> String {
+    // Only ASCII is lowercased, so indices stay the same.
+    match name.to_ascii_lowercase().find(&from.to_ascii_lowercase()) {
+        Some(i) => format!("{}{}{}", &name[..i], to, &name[i + from.len()..]),
+        None => name.to_string(),
+    }
+}
@@ -1,6 +1,7 @@
 use std::fmt::{Debug, Display, Formatter};
 use std::string::{FromUtf16Error, FromUtf8Error};
 use miniz_oxide::inflate::DecompressError;
+#[cfg(feature = "crypto")]
 use openssl::error::ErrorStack;
 use crate::error::DantelionFormatsError::*;
 use thiserror::Error;
@@ -9,15 +10,43 @@ use thiserror::Error;
 pub enum DantelionFormatsError {
     #[error(transparent)]
     IoError(#[from] std::io::Error),
+    #[cfg(feature = "oodle")]
     #[error(transparent)]
     LibLoading(#[from] libloading::Error),
     #[error(transparent)]
     Utf8Error(#[from] FromUtf8Error),
     #[error(transparent)]
     Utf16Error(#[from] FromUtf16Error),
+    #[cfg(feature = "crypto")]
     #[error(transparent)]
     OpenSSLErrorStack(#[from] ErrorStack),
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+    #[error(transparent)]
+    XmlError(#[from] roxmltree::Error),
+    #[error(transparent)]
+    TomlError(#[from] toml::de::Error),
+    #[error(transparent)]
+    TomlWriteError(#[from] toml::ser::Error),
+    #[error(transparent)]
+    RegexError(#[from] regex::Error),
     DecompressionError(DecompressError),
+    DecompressedSizeMismatch { expected: usize, actual: usize },
+    // KRAK DCX found but the Oodle DLL it needs could not be loaded.
+    OodleUnavailable { required_dll: &'static str, compression_level: u8 },
+    // Refused to write game files while the game has them open.
+    GameRunning { exe_name: &'static str },
+    // RSA encrypted data, like a BHD5, that isn't a whole number of key sized blocks. `len` is how many bytes there were.
+    MisalignedRsaData { len: u64, key_size: usize },
+    #[cfg(feature = "watch")]
+    #[error(transparent)]
+    NotifyError(#[from] notify::Error),
+    #[cfg(feature = "http")]
+    #[error(transparent)]
+    HttpError(#[from] Box<ureq::Error>),
+    #[cfg(feature = "libdeflate")]
+    #[error(transparent)]
+    LibDeflateError(#[from] libdeflater::DecompressionError),
 }
 
 impl From<DecompressError> for DantelionFormatsError {
@@ -0,0 +1,139 @@
+#[cfg(feature = "crypto")]
+use std::fs;
+#[cfg(feature = "crypto")]
+use std::path::Path;
+#[cfg(feature = "crypto")]
+use crate::bhd5::{BHD5Archive, BHD5ArchiveBuilder, BHD5Format};
+use crate::bhd5::GameType;
+use crate::bnd4::{BND4, BND4Builder, BND4WriteOptions};
+use crate::dcx::DCX;
+use crate::error::DantelionFormatsError;
+use crate::tpf::{dds, TPF};
+
+// Small, valid sample files generated with the writers, for benches and tests that can't rely on a game install.
+// Everything is deterministic, so the same arguments always give the same bytes (apart from BHD5 keys).
+
+/// `size` bytes that compress about as well as game data does, rather than all zeroes or pure noise.
+pub fn sample_data(size: usize) -> Vec<u8> {
+    let mut state: u32 = 0x2545F491;
+    let mut data = Vec::with_capacity(size);
+    while data.len() < size {
+        // xorshift32
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        // Runs of repeated bytes every so often, so deflate has something to find.
+        let run = if state & 0x7 == 0 { (state >> 8) as usize % 32 + 1 } else { 1 };
+        for _ in 0..run.min(size - data.len()) {
+            data.push((state >> 24) as u8);
+        }
+    }
+
+    data
+}
+
+/// A BND4 with `file_count` files named like "N:\\FDP\\data\\sample\\file_0000.bin", each `file_size` bytes.
+pub fn bnd4(file_count: usize, file_size: usize) -> BND4 {
+    let mut builder = BND4Builder::new();
+    for i in 0..file_count {
+        builder = builder.add_file(i as i32, &sample_file_name(i), sample_data(file_size));
+    }
+
+    builder.build()
+}
+
+pub fn bnd4_bytes(file_count: usize, file_size: usize) -> Result<Vec<u8>, DantelionFormatsError> {
+    bnd4(file_count, file_size).to_bytes(&BND4WriteOptions::default())
+}
+
+pub fn dflt_dcx_bytes(size: usize) -> Result<Vec<u8>, DantelionFormatsError> {
+    DCX::compress_dflt(&sample_data(size)).to_bytes()
+}
+
+pub fn edge_dcx_bytes(size: usize) -> Result<Vec<u8>, DantelionFormatsError> {
+    DCX::compress_edge(&sample_data(size)).to_bytes()
+}
+
+/// A BND4 wrapped in a DFLT DCX, like most binders the games ship.
+pub fn dflt_bnd4_bytes(file_count: usize, file_size: usize) -> Result<Vec<u8>, DantelionFormatsError> {
+    DCX::compress_dflt(&bnd4_bytes(file_count, file_size)?).to_bytes()
+}
+
+/// A PC TPF with `texture_count` BC1 textures named "sample_0000" and so on, each `size` by `size` with a full mip
+/// chain. Odd numbered textures are stored DCX compressed, like some games do.
+pub fn tpf(texture_count: usize, size: u32) -> Result<TPF, DantelionFormatsError> {
+    let mipmaps = size.max(1).ilog2() + 1;
+    let mut dds = dds::build_header(size, size, mipmaps, dds::DXGI_FORMAT_BC1_UNORM, false);
+    dds.extend(sample_data(dds::data_len(size, size, mipmaps, 1, dds::DXGI_FORMAT_BC1_UNORM)));
+
+    let mut tpf = TPF::new();
+    for i in 0..texture_count {
+        tpf.import_dds(&format!("sample_{:04}", i), &dds, GameType::EldenRing)?;
+        if i % 2 == 1 {
+            tpf.textures[i].flags1 = 2;
+        }
+    }
+
+    Ok(tpf)
+}
+
+pub fn tpf_bytes(texture_count: usize, size: u32) -> Result<Vec<u8>, DantelionFormatsError> {
+    tpf(texture_count, size)?.to_bytes()
+}
+
+#[cfg(feature = "crypto")]
+/// An encrypted BHD5/BDT pair with `file_count` files at "/sample/file_0000.bin" and so on. Generating the RSA
+/// key is slow, so build this once and reuse it.
+pub fn bhd5_archive(format: BHD5Format, file_count: usize, file_size: usize) -> Result<BHD5Archive, DantelionFormatsError> {
+    let mut builder = BHD5ArchiveBuilder::new(format);
+    for i in 0..file_count {
+        builder = builder.add_file(&sample_archive_path(i), sample_data(file_size));
+    }
+
+    builder.build()
+}
+
+pub fn sample_file_name(index: usize) -> String {
+    format!("N:\\FDP\\data\\sample\\file_{:04}.bin", index)
+}
+
+pub fn sample_archive_path(index: usize) -> String {
+    format!("/sample/file_{:04}.bin", index)
+}
+
+#[cfg(feature = "crypto")]
+/// Paths to sample files written by `write_fixtures`, for code that only takes paths.
+#[derive(Debug)]
+pub struct FixtureFiles {
+    pub bnd4_path: String,
+    pub dcx_bnd4_path: String,
+    pub edge_dcx_path: String,
+    pub bhd5_path: String,
+    pub bdt_path: String,
+    // PKCS#1 PEM needed to decrypt the BHD5 at `bhd5_path`.
+    pub bhd5_public_key: String,
+}
+
+#[cfg(feature = "crypto")]
+/// Writes a small set of sample files into `dir`, creating it if needed.
+pub fn write_fixtures(dir: &str) -> Result<FixtureFiles, DantelionFormatsError> {
+    fs::create_dir_all(dir)?;
+    let path = |name: &str| Path::new(dir).join(name).to_string_lossy().to_string();
+
+    let archive = bhd5_archive(BHD5Format::EldenRing, 4, 0x100)?;
+    let files = FixtureFiles {
+        bnd4_path: path("sample.bnd"),
+        dcx_bnd4_path: path("sample.bnd.dcx"),
+        edge_dcx_path: path("sample.edge.dcx"),
+        bhd5_path: path("sample.bhd"),
+        bdt_path: path("sample.bdt"),
+        bhd5_public_key: archive.public_key.clone(),
+    };
+
+    fs::write(&files.bnd4_path, bnd4_bytes(4, 0x100)?)?;
+    fs::write(&files.dcx_bnd4_path, dflt_bnd4_bytes(4, 0x100)?)?;
+    fs::write(&files.edge_dcx_path, edge_dcx_bytes(0x18000)?)?;
+    archive.write(&files.bhd5_path, &files.bdt_path)?;
+
+    Ok(files)
+}
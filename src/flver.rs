@@ -0,0 +1,677 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io::{Cursor, Error, ErrorKind};
+use binary_interpreter::binary_reader::BinaryReader;
+use byteorder::{BE, LE, ByteOrder, ReadBytesExt};
+use crate::dcx::DCX;
+use crate::error::DantelionFormatsError;
+use crate::source::{DataSource, FileSource};
+use crate::util;
+use crate::util::{DataLen, ensure, Validate};
+
+#[cfg(feature = "mesh-decode")]
+pub mod mesh;
+#[cfg(feature = "gltf")]
+pub mod gltf;
+
+/// A FLVER2 model, as used from Dark Souls II on. Face sets and vertex buffers are kept as they are in the file,
+/// see `mesh` for turning them into triangles and `gltf` for exporting them. The FLVER0 models of Demon's Souls and
+/// Dark Souls aren't supported. Cloth mapping from the same binder is read by `clm2`, the skeleton and physics next to
+/// it are Havok files, see `behbnd::havok_info`.
+#[derive(Debug)]
+#[repr(C)]
+pub struct FLVER {
+    pub header: FLVERHeader,
+    pub dummies: Vec<Dummy>,
+    pub materials: Vec<Material>,
+    pub bones: Vec<Bone>,
+    pub meshes: Vec<Mesh>,
+    pub buffer_layouts: Vec<BufferLayout>,
+    pub textures: Vec<Texture>,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+#[non_exhaustive]
+pub struct FLVERHeader {
+    pub magic: String,
+    pub big_endian: bool,
+    pub version: u32,
+    pub data_offset: u32,
+    pub data_size: u32,
+    pub bounding_box_min: [f32; 3],
+    pub bounding_box_max: [f32; 3],
+    // Not counting motion blur meshes or degenerate faces
+    pub true_face_count: u32,
+    pub total_face_count: u32,
+    // Face sets that store 0 use this one
+    pub vertex_index_size: u8,
+    pub unicode: bool,
+    pub unk4a: bool,
+    pub unk4c: i32,
+    pub unk5c: u8,
+    pub unk5d: u8,
+    pub unk68: i32,
+}
+
+/// A point other files attach things to, e.g. sfx and hitboxes.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Dummy {
+    pub position: [f32; 3],
+    pub color: [u8; 4],
+    pub forward: [f32; 3],
+    pub reference_id: i16,
+    pub parent_bone_index: i16,
+    pub upward: [f32; 3],
+    pub attach_bone_index: i16,
+    pub flag1: bool,
+    pub use_upward_vector: bool,
+    pub unk30: i32,
+    pub unk34: i32,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct Material {
+    pub name: String,
+    // The material definition, e.g. "N:\GR\data\Material\mtd\...\c[amsn].mtd"
+    pub mtd: String,
+    pub texture_index: u32,
+    pub texture_count: u32,
+    pub flags: i32,
+    pub gx_offset: u32,
+    pub unk18: i32,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct Bone {
+    pub name: String,
+    pub translation: [f32; 3],
+    // Euler angles in radians
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+    // -1 for none
+    pub parent_index: i16,
+    pub child_index: i16,
+    pub next_sibling_index: i16,
+    pub previous_sibling_index: i16,
+    pub bounding_box_min: [f32; 3],
+    pub bounding_box_max: [f32; 3],
+    pub unk3c: i32,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct Mesh {
+    // Skinned to several bones, rather than following `default_bone_index`
+    pub dynamic: bool,
+    pub material_index: u32,
+    pub default_bone_index: i32,
+    // Maps the bone indices in the vertices to `FLVER::bones`. Empty when they already index it, as in Sekiro and
+    // later.
+    pub bone_indices: Vec<i32>,
+    pub bounding_box: Option<([f32; 3], [f32; 3])>,
+    pub face_sets: Vec<FaceSet>,
+    pub vertex_buffers: Vec<VertexBuffer>,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct FaceSet {
+    // See `FaceSet::LOD_LEVEL_1` and friends
+    pub flags: u32,
+    pub triangle_strip: bool,
+    pub cull_backfaces: bool,
+    pub unk06: u8,
+    pub unk07: u8,
+    // 16 or 32
+    pub index_size: u8,
+    pub indices: Vec<u32>,
+}
+
+#[repr(C)]
+pub struct VertexBuffer {
+    pub layout_index: u32,
+    pub vertex_size: u32,
+    pub vertex_count: u32,
+    // `vertex_count` vertices of `vertex_size` bytes, laid out as `FLVER::buffer_layouts[layout_index]` says
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct BufferLayout {
+    pub members: Vec<LayoutMember>,
+}
+
+/// One attribute of a vertex. `member_type` and `semantic` are kept raw, see `member_type` and `semantic` for the
+/// known values.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct LayoutMember {
+    pub unk00: i32,
+    pub struct_offset: u32,
+    pub member_type: u32,
+    pub semantic: u32,
+    // Which one of several members with the same semantic, e.g. the second UV
+    pub index: i32,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct Texture {
+    pub path: String,
+    // The sampler it's bound to, e.g. "g_DiffuseTexture"
+    pub texture_type: String,
+    pub scale: [f32; 2],
+    pub unk10: u8,
+    pub unk11: bool,
+    pub unk14: f32,
+    pub unk18: f32,
+    pub unk1c: f32,
+}
+
+/// `LayoutMember::member_type` values.
+pub mod member_type {
+    pub const FLOAT2: u32 = 0x01;
+    pub const FLOAT3: u32 = 0x02;
+    pub const FLOAT4: u32 = 0x03;
+    pub const BYTE4A: u32 = 0x10;
+    pub const BYTE4B: u32 = 0x11;
+    pub const SHORT2_TO_FLOAT2: u32 = 0x12;
+    pub const BYTE4C: u32 = 0x13;
+    pub const UV: u32 = 0x15;
+    pub const UV_PAIR: u32 = 0x16;
+    pub const SHORT_BONE_INDICES: u32 = 0x18;
+    pub const SHORT4_TO_FLOAT4A: u32 = 0x1A;
+    pub const SHORT4_TO_FLOAT4B: u32 = 0x2E;
+    pub const BYTE4E: u32 = 0x2F;
+    // PS3 only, the whole vertex is compressed
+    pub const EDGE_COMPRESSED: u32 = 0xF0;
+
+    /// The size of a member of type `member_type`, None if it isn't known.
+    pub fn size(member_type: u32) -> Option<u32> {
+        match member_type {
+            BYTE4A | BYTE4B | SHORT2_TO_FLOAT2 | BYTE4C | UV | BYTE4E => Some(4),
+            FLOAT2 | UV_PAIR | SHORT_BONE_INDICES | SHORT4_TO_FLOAT4A | SHORT4_TO_FLOAT4B => Some(8),
+            FLOAT3 => Some(12),
+            FLOAT4 => Some(16),
+            _ => None,
+        }
+    }
+}
+
+/// `LayoutMember::semantic` values.
+pub mod semantic {
+    pub const POSITION: u32 = 0;
+    pub const BONE_WEIGHTS: u32 = 1;
+    pub const BONE_INDICES: u32 = 2;
+    pub const NORMAL: u32 = 3;
+    pub const UV: u32 = 5;
+    pub const TANGENT: u32 = 6;
+    pub const BITANGENT: u32 = 7;
+    pub const VERTEX_COLOR: u32 = 10;
+}
+
+impl FLVER {
+    const MAGIC_SIZE: usize = 6;
+    const ENDIANNESS_OFFSET: usize = 6;
+    const FLVER2_VERSION: u32 = 0x20000;
+
+    pub(crate) fn is(bytes: &[u8]) -> bool {
+        bytes.starts_with(b"FLVER\0")
+    }
+
+    pub fn from_path(path: &str) -> Result<FLVER, DantelionFormatsError> {
+        FLVER::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<FLVER, DantelionFormatsError> {
+        let file = source.read_all()?;
+
+        FLVER::from_bytes(&file)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<FLVER, DantelionFormatsError> {
+        let bytes = if DCX::is(file) {
+            DCX::from_bytes(file)?.decompress()?
+        } else {
+            file.to_vec()
+        };
+        if !FLVER::is(&bytes) || bytes.len() < FLVER::ENDIANNESS_OFFSET + 2 {
+            return Err(invalid("Not a FLVER".to_string()));
+        }
+
+        let mut c = Cursor::new(&bytes[..]);
+        if &bytes[FLVER::ENDIANNESS_OFFSET..FLVER::ENDIANNESS_OFFSET + 2] == b"B\0" {
+            FLVER::read_flver::<BE>(&mut c)
+        } else {
+            FLVER::read_flver::<LE>(&mut c)
+        }
+    }
+
+    fn read_flver<T: ByteOrder>(c: &mut Cursor<&[u8]>) -> Result<FLVER, DantelionFormatsError> {
+        let magic = c.read_fixed_cstr(FLVER::MAGIC_SIZE)?;
+        let big_endian = c.read_bytes(2)? == b"B\0";
+        let version = c.read_u32::<T>()?;
+        if version < FLVER::FLVER2_VERSION {
+            return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::Unsupported, format!("FLVER version {:#X} is a FLVER0, which isn't supported", version))));
+        }
+        let data_offset = c.read_u32::<T>()?;
+        let data_size = c.read_u32::<T>()?;
+        let dummy_count = c.read_u32::<T>()?;
+        let material_count = c.read_u32::<T>()?;
+        let bone_count = c.read_u32::<T>()?;
+        let mesh_count = c.read_u32::<T>()?;
+        let vertex_buffer_count = c.read_u32::<T>()?;
+        let bounding_box_min = read_vector3::<T>(c)?;
+        let bounding_box_max = read_vector3::<T>(c)?;
+        let true_face_count = c.read_u32::<T>()?;
+        let total_face_count = c.read_u32::<T>()?;
+        let vertex_index_size = c.read_u8()?;
+        let unicode = c.read_u8()? != 0;
+        let unk4a = c.read_u8()? != 0;
+        c.read_u8()?;
+        let unk4c = c.read_i32::<T>()?;
+        let face_set_count = c.read_u32::<T>()?;
+        let buffer_layout_count = c.read_u32::<T>()?;
+        let texture_count = c.read_u32::<T>()?;
+        let unk5c = c.read_u8()?;
+        let unk5d = c.read_u8()?;
+        c.read_bytes(10)?;
+        let unk68 = c.read_i32::<T>()?;
+        c.read_bytes(20)?;
+
+        let header = FLVERHeader {
+            magic,
+            big_endian,
+            version,
+            data_offset,
+            data_size,
+            bounding_box_min,
+            bounding_box_max,
+            true_face_count,
+            total_face_count,
+            vertex_index_size,
+            unicode,
+            unk4a,
+            unk4c,
+            unk5c,
+            unk5d,
+            unk68,
+        };
+
+        header.validate()?;
+
+        let dummies = read_records(c, dummy_count, FLVER::read_dummy::<T>)?;
+        let materials = read_records(c, material_count, |c| FLVER::read_material::<T>(c, &header))?;
+        let bones = read_records(c, bone_count, |c| FLVER::read_bone::<T>(c, &header))?;
+        let mesh_headers = read_records(c, mesh_count, FLVER::read_mesh_header::<T>)?;
+        let mut face_sets = read_records(c, face_set_count, |c| FLVER::read_face_set::<T>(c, &header).map(Some))?;
+        let mut vertex_buffers = read_records(c, vertex_buffer_count, |c| FLVER::read_vertex_buffer::<T>(c, &header).map(Some))?;
+        let buffer_layouts = read_records(c, buffer_layout_count, FLVER::read_buffer_layout::<T>)?;
+        let textures = read_records(c, texture_count, |c| FLVER::read_texture::<T>(c, &header))?;
+
+        let mut meshes = Vec::with_capacity(mesh_headers.len());
+        for mesh_header in mesh_headers {
+            meshes.push(FLVER::read_mesh::<T>(c, mesh_header, &mut face_sets, &mut vertex_buffers)?);
+        }
+
+        Ok(FLVER {
+            header,
+            dummies,
+            materials,
+            bones,
+            meshes,
+            buffer_layouts,
+            textures,
+        })
+    }
+
+    fn read_dummy<T: ByteOrder>(c: &mut Cursor<&[u8]>) -> Result<Dummy, DantelionFormatsError> {
+        let dummy = Dummy {
+            position: read_vector3::<T>(c)?,
+            color: [c.read_u8()?, c.read_u8()?, c.read_u8()?, c.read_u8()?],
+            forward: read_vector3::<T>(c)?,
+            reference_id: c.read_i16::<T>()?,
+            parent_bone_index: c.read_i16::<T>()?,
+            upward: read_vector3::<T>(c)?,
+            attach_bone_index: c.read_i16::<T>()?,
+            flag1: c.read_u8()? != 0,
+            use_upward_vector: c.read_u8()? != 0,
+            unk30: c.read_i32::<T>()?,
+            unk34: c.read_i32::<T>()?,
+        };
+        c.read_bytes(8)?;
+
+        Ok(dummy)
+    }
+
+    fn read_material<T: ByteOrder>(c: &mut Cursor<&[u8]>, header: &FLVERHeader) -> Result<Material, DantelionFormatsError> {
+        let name_offset = c.read_u32::<T>()?;
+        let mtd_offset = c.read_u32::<T>()?;
+        let texture_count = c.read_u32::<T>()?;
+        let texture_index = c.read_u32::<T>()?;
+        let flags = c.read_i32::<T>()?;
+        let gx_offset = c.read_u32::<T>()?;
+        let unk18 = c.read_i32::<T>()?;
+        c.read_u32::<T>()?;
+
+        Ok(Material {
+            name: FLVER::peek_string::<T>(c, name_offset, header)?,
+            mtd: FLVER::peek_string::<T>(c, mtd_offset, header)?,
+            texture_index,
+            texture_count,
+            flags,
+            gx_offset,
+            unk18,
+        })
+    }
+
+    fn read_bone<T: ByteOrder>(c: &mut Cursor<&[u8]>, header: &FLVERHeader) -> Result<Bone, DantelionFormatsError> {
+        let translation = read_vector3::<T>(c)?;
+        let name_offset = c.read_u32::<T>()?;
+        let rotation = read_vector3::<T>(c)?;
+        let parent_index = c.read_i16::<T>()?;
+        let child_index = c.read_i16::<T>()?;
+        let scale = read_vector3::<T>(c)?;
+        let next_sibling_index = c.read_i16::<T>()?;
+        let previous_sibling_index = c.read_i16::<T>()?;
+        let bounding_box_min = read_vector3::<T>(c)?;
+        let unk3c = c.read_i32::<T>()?;
+        let bounding_box_max = read_vector3::<T>(c)?;
+        c.read_bytes(0x34)?;
+
+        Ok(Bone {
+            name: FLVER::peek_string::<T>(c, name_offset, header)?,
+            translation,
+            rotation,
+            scale,
+            parent_index,
+            child_index,
+            next_sibling_index,
+            previous_sibling_index,
+            bounding_box_min,
+            bounding_box_max,
+            unk3c,
+        })
+    }
+
+    fn read_mesh_header<T: ByteOrder>(c: &mut Cursor<&[u8]>) -> Result<MeshHeader, DantelionFormatsError> {
+        let dynamic = c.read_u8()? != 0;
+        c.read_bytes(3)?;
+        let material_index = c.read_u32::<T>()?;
+        c.read_bytes(8)?;
+        let default_bone_index = c.read_i32::<T>()?;
+        let bone_count = c.read_u32::<T>()?;
+        let bounding_box_offset = c.read_u32::<T>()?;
+        let bone_offset = c.read_u32::<T>()?;
+        let face_set_count = c.read_u32::<T>()?;
+        let face_set_offset = c.read_u32::<T>()?;
+        let vertex_buffer_count = c.read_u32::<T>()?;
+        let vertex_buffer_offset = c.read_u32::<T>()?;
+
+        Ok(MeshHeader {
+            dynamic,
+            material_index,
+            default_bone_index,
+            bone_indices: (bone_offset, bone_count),
+            bounding_box_offset,
+            face_set_indices: (face_set_offset, face_set_count),
+            vertex_buffer_indices: (vertex_buffer_offset, vertex_buffer_count),
+        })
+    }
+
+    // Meshes point at their face sets and vertex buffers by index. Each one belongs to a single mesh, so they're
+    // moved out rather than copied.
+    fn read_mesh<T: ByteOrder>(
+        c: &mut Cursor<&[u8]>,
+        mesh_header: MeshHeader,
+        face_sets: &mut [Option<FaceSet>],
+        vertex_buffers: &mut [Option<VertexBuffer>],
+    ) -> Result<Mesh, DantelionFormatsError> {
+        let bone_indices = peek_i32s::<T>(c, mesh_header.bone_indices)?;
+        let bounding_box = if mesh_header.bounding_box_offset != 0 {
+            let start = c.position();
+            c.set_position(mesh_header.bounding_box_offset as u64);
+            let bounding_box = (read_vector3::<T>(c)?, read_vector3::<T>(c)?);
+            c.set_position(start);
+            Some(bounding_box)
+        } else {
+            None
+        };
+
+        let take = |indices: Vec<i32>, what: &str, count: usize| -> Result<Vec<usize>, DantelionFormatsError> {
+            indices.into_iter()
+                .map(|index| usize::try_from(index).ok().filter(|&index| index < count)
+                    .ok_or_else(|| invalid(format!("Mesh refers to {} {}, which doesn't exist", what, index))))
+                .collect()
+        };
+        let face_set_indices = take(peek_i32s::<T>(c, mesh_header.face_set_indices)?, "face set", face_sets.len())?;
+        let vertex_buffer_indices = take(peek_i32s::<T>(c, mesh_header.vertex_buffer_indices)?, "vertex buffer", vertex_buffers.len())?;
+
+        let shared = || invalid("A face set or vertex buffer is used by more than one mesh".to_string());
+        let face_sets = face_set_indices.into_iter()
+            .map(|index| face_sets[index].take().ok_or_else(shared))
+            .collect::<Result<_, _>>()?;
+        let vertex_buffers = vertex_buffer_indices.into_iter()
+            .map(|index| vertex_buffers[index].take().ok_or_else(shared))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Mesh {
+            dynamic: mesh_header.dynamic,
+            material_index: mesh_header.material_index,
+            default_bone_index: mesh_header.default_bone_index,
+            bone_indices,
+            bounding_box,
+            face_sets,
+            vertex_buffers,
+        })
+    }
+
+    fn read_face_set<T: ByteOrder>(c: &mut Cursor<&[u8]>, header: &FLVERHeader) -> Result<FaceSet, DantelionFormatsError> {
+        let flags = c.read_u32::<T>()?;
+        let triangle_strip = c.read_u8()? != 0;
+        let cull_backfaces = c.read_u8()? != 0;
+        let unk06 = c.read_u8()?;
+        let unk07 = c.read_u8()?;
+        let index_count = c.read_u32::<T>()?;
+        let indices_offset = c.read_u32::<T>()?;
+        let mut index_size = 0;
+        if header.version > 0x20005 {
+            c.read_u32::<T>()?; // indices length
+            c.read_u32::<T>()?;
+            index_size = c.read_u32::<T>()?;
+            c.read_u32::<T>()?;
+        }
+        if index_size == 0 {
+            index_size = header.vertex_index_size as u32;
+        }
+
+        let start = c.position();
+        c.set_position(header.data_offset as u64 + indices_offset as u64);
+        let indices = match index_size {
+            16 => read_records(c, index_count, |c| Ok(c.read_u16::<T>()? as u32))?,
+            32 => read_records(c, index_count, |c| Ok(c.read_u32::<T>()?))?,
+            // 8 is PS3 edge compression
+            _ => return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::Unsupported, format!("Unsupported face set index size {}", index_size)))),
+        };
+        c.set_position(start);
+
+        Ok(FaceSet {
+            flags,
+            triangle_strip,
+            cull_backfaces,
+            unk06,
+            unk07,
+            index_size: index_size as u8,
+            indices,
+        })
+    }
+
+    fn read_vertex_buffer<T: ByteOrder>(c: &mut Cursor<&[u8]>, header: &FLVERHeader) -> Result<VertexBuffer, DantelionFormatsError> {
+        c.read_u32::<T>()?; // buffer index
+        let layout_index = c.read_u32::<T>()?;
+        let vertex_size = c.read_u32::<T>()?;
+        let vertex_count = c.read_u32::<T>()?;
+        c.read_bytes(8)?;
+        c.read_u32::<T>()?; // buffer length
+        let buffer_offset = c.read_u32::<T>()?;
+
+        let len = vertex_size as u64 * vertex_count as u64;
+        let start = header.data_offset as u64 + buffer_offset as u64;
+        let data = c.get_ref().get(start as usize..).and_then(|data| data.get(..len as usize))
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Vertex buffer out of bounds"))?
+            .to_vec();
+
+        Ok(VertexBuffer {
+            layout_index,
+            vertex_size,
+            vertex_count,
+            data,
+        })
+    }
+
+    fn read_buffer_layout<T: ByteOrder>(c: &mut Cursor<&[u8]>) -> Result<BufferLayout, DantelionFormatsError> {
+        let member_count = c.read_u32::<T>()?;
+        c.read_bytes(8)?;
+        let members_offset = c.read_u32::<T>()?;
+
+        let start = c.position();
+        c.set_position(members_offset as u64);
+        let members = read_records(c, member_count, |c| Ok(LayoutMember {
+            unk00: c.read_i32::<T>()?,
+            struct_offset: c.read_u32::<T>()?,
+            member_type: c.read_u32::<T>()?,
+            semantic: c.read_u32::<T>()?,
+            index: c.read_i32::<T>()?,
+        }))?;
+        c.set_position(start);
+
+        Ok(BufferLayout { members })
+    }
+
+    fn read_texture<T: ByteOrder>(c: &mut Cursor<&[u8]>, header: &FLVERHeader) -> Result<Texture, DantelionFormatsError> {
+        let path_offset = c.read_u32::<T>()?;
+        let type_offset = c.read_u32::<T>()?;
+        let scale = [c.read_f32::<T>()?, c.read_f32::<T>()?];
+        let unk10 = c.read_u8()?;
+        let unk11 = c.read_u8()? != 0;
+        c.read_bytes(2)?;
+        let unk14 = c.read_f32::<T>()?;
+        let unk18 = c.read_f32::<T>()?;
+        let unk1c = c.read_f32::<T>()?;
+
+        Ok(Texture {
+            path: FLVER::peek_string::<T>(c, path_offset, header)?,
+            texture_type: FLVER::peek_string::<T>(c, type_offset, header)?,
+            scale,
+            unk10,
+            unk11,
+            unk14,
+            unk18,
+            unk1c,
+        })
+    }
+
+    fn peek_string<T: ByteOrder>(c: &Cursor<&[u8]>, offset: u32, header: &FLVERHeader) -> Result<String, DantelionFormatsError> {
+        if header.unicode {
+            util::peek_utf16::<T>(c, offset as u64)
+        } else {
+            util::peek_shift_jis(c, offset as u64)
+        }
+    }
+}
+
+// The parts of a mesh header that point elsewhere in the file, as (offset, count).
+struct MeshHeader {
+    dynamic: bool,
+    material_index: u32,
+    default_bone_index: i32,
+    bone_indices: (u32, u32),
+    bounding_box_offset: u32,
+    face_set_indices: (u32, u32),
+    vertex_buffer_indices: (u32, u32),
+}
+
+impl FaceSet {
+    pub const LOD_LEVEL_1: u32 = 0x01000000;
+    pub const LOD_LEVEL_2: u32 = 0x02000000;
+    pub const EDGE_COMPRESSED: u32 = 0x40000000;
+    pub const MOTION_BLUR: u32 = 0x80000000;
+
+    /// The full detail faces, rather than a lower LOD or the motion blur copy.
+    pub fn is_main(&self) -> bool {
+        self.flags & (FaceSet::LOD_LEVEL_1 | FaceSet::LOD_LEVEL_2 | FaceSet::MOTION_BLUR) == 0
+    }
+}
+
+fn invalid(message: String) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, message))
+}
+
+fn read_vector3<T: ByteOrder>(c: &mut Cursor<&[u8]>) -> Result<[f32; 3], DantelionFormatsError> {
+    Ok([c.read_f32::<T>()?, c.read_f32::<T>()?, c.read_f32::<T>()?])
+}
+
+fn read_records<R>(c: &mut Cursor<&[u8]>, count: u32, mut read: impl FnMut(&mut Cursor<&[u8]>) -> Result<R, DantelionFormatsError>) -> Result<Vec<R>, DantelionFormatsError> {
+    let mut records = Vec::with_capacity(util::capacity_for(c, count as u64));
+    for _ in 0..count {
+        records.push(read(c)?);
+    }
+
+    Ok(records)
+}
+
+fn peek_i32s<T: ByteOrder>(c: &mut Cursor<&[u8]>, (offset, count): (u32, u32)) -> Result<Vec<i32>, DantelionFormatsError> {
+    let start = c.position();
+    c.set_position(offset as u64);
+    let values = read_records(c, count, |c| Ok(c.read_i32::<T>()?))?;
+    c.set_position(start);
+
+    Ok(values)
+}
+
+/// A one line summary, e.g. "FLVER 0x2001A — 3 meshes, 120 bones".
+impl Display for FLVER {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "FLVER {:#X} — {} meshes, {} bones", self.header.version, self.meshes.len(), self.bones.len())
+    }
+}
+
+impl Debug for VertexBuffer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VertexBuffer")
+            .field("layout_index", &self.layout_index)
+            .field("vertex_size", &self.vertex_size)
+            .field("vertex_count", &self.vertex_count)
+            .field("data", &DataLen(self.data.len()))
+            .finish()
+    }
+}
+
+impl FLVERHeader {
+    // Stable accessors, see `BND4Header`.
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    pub fn unicode(&self) -> bool {
+        self.unicode
+    }
+}
+
+impl Validate for FLVERHeader {
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.magic == "FLVER\0", "Magic was {}", self.magic);
+        ensure!(matches!(self.vertex_index_size, 0 | 8 | 16 | 32), "vertex_index_size was {}", self.vertex_index_size);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+use std::io::{Error, ErrorKind};
+use serde_json::{json, Value};
+use crate::error::DantelionFormatsError;
+use super::FLVER;
+
+// Accessor component types and buffer view targets, from the glTF spec
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+impl FLVER {
+    /// The model as glTF 2.0 JSON and the binary buffer it expects to find at `bin_uri`. See `to_glb`.
+    pub fn to_gltf(&self, bin_uri: &str) -> Result<(String, Vec<u8>), DantelionFormatsError> {
+        let (mut gltf, bin) = self.build_gltf()?;
+        gltf["buffers"] = json!([{ "uri": bin_uri, "byteLength": bin.len() }]);
+
+        Ok((gltf.to_string(), bin))
+    }
+
+    /// The model as a single binary glTF 2.0 file. Every bone is a node and every mesh is skinned to the skeleton
+    /// with its decoded weights, see `triangle_mesh`. The games are left handed, so X is mirrored and triangles are
+    /// rewound to get glTF's right handed space. Materials only carry their names.
+    pub fn to_glb(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        let (mut gltf, mut bin) = self.build_gltf()?;
+        gltf["buffers"] = json!([{ "byteLength": bin.len() }]);
+
+        let mut json = gltf.to_string().into_bytes();
+        pad(&mut json, b' ');
+        pad(&mut bin, 0);
+        let mut glb = Vec::with_capacity(12 + 8 + json.len() + 8 + bin.len());
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend(json);
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend(bin);
+
+        Ok(glb)
+    }
+
+    fn build_gltf(&self) -> Result<(Value, Vec<u8>), DantelionFormatsError> {
+        let mut buffer = Buffer::default();
+        let mut nodes = vec![];
+        let mut roots = vec![];
+
+        // Bones come first, so joint i of the skin is node i and bone i.
+        let locals: Vec<_> = self.bones.iter().map(|bone| {
+            let translation = [-bone.translation[0], bone.translation[1], bone.translation[2]];
+            let [x, y, z, w] = euler_to_quaternion(bone.rotation);
+            (translation, [x, -y, -z, w], bone.scale)
+        }).collect();
+        for (index, (bone, &(translation, rotation, scale))) in self.bones.iter().zip(&locals).enumerate() {
+            let children: Vec<usize> = self.bones.iter().enumerate()
+                .filter(|(_, child)| child.parent_index as isize == index as isize)
+                .map(|(child, _)| child)
+                .collect();
+            let mut node = json!({ "name": bone.name, "translation": translation, "rotation": rotation, "scale": scale });
+            if !children.is_empty() {
+                node["children"] = json!(children);
+            }
+            nodes.push(node);
+            if bone.parent_index < 0 || bone.parent_index as usize >= self.bones.len() {
+                roots.push(index);
+            }
+        }
+
+        let mut skins = vec![];
+        if !self.bones.is_empty() {
+            let globals: Vec<[f32; 16]> = (0..self.bones.len()).map(|index| {
+                // Walks up to the root, at most once through every bone in case the parents loop.
+                let mut global = trs_matrix(locals[index].0, locals[index].1, locals[index].2);
+                let mut parent = self.bones[index].parent_index;
+                for _ in 0..self.bones.len() {
+                    let Some(&(translation, rotation, scale)) = usize::try_from(parent).ok().and_then(|parent| locals.get(parent)) else { break };
+                    global = multiply(&trs_matrix(translation, rotation, scale), &global);
+                    parent = self.bones[parent as usize].parent_index;
+                }
+                global
+            }).collect();
+            let inverse_binds: Vec<u8> = globals.iter().flat_map(invert_affine).flat_map(f32::to_le_bytes).collect();
+            let accessor = buffer.accessor(&inverse_binds, FLOAT, globals.len(), "MAT4", None, None);
+            skins.push(json!({ "joints": (0..self.bones.len()).collect::<Vec<_>>(), "inverseBindMatrices": accessor }));
+        }
+
+        let mut meshes = vec![];
+        for (index, mut mesh) in self.triangle_meshes()?.into_iter().enumerate() {
+            if mesh.positions.is_empty() || mesh.indices.is_empty() {
+                continue;
+            }
+            for position in &mut mesh.positions {
+                position[0] = -position[0];
+            }
+            for normal in &mut mesh.normals {
+                let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+                *normal = if length > 0.0 { [-normal[0] / length, normal[1] / length, normal[2] / length] } else { [0.0, 1.0, 0.0] };
+            }
+            for triangle in mesh.indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+
+            let count = mesh.positions.len();
+            let mut min = [f32::MAX; 3];
+            let mut max = [f32::MIN; 3];
+            for position in &mesh.positions {
+                for axis in 0..3 {
+                    min[axis] = min[axis].min(position[axis]);
+                    max[axis] = max[axis].max(position[axis]);
+                }
+            }
+            let mut attributes = json!({
+                "POSITION": buffer.accessor(&floats(&mesh.positions), FLOAT, count, "VEC3", Some(ARRAY_BUFFER), Some((&min, &max))),
+            });
+            if mesh.normals.len() == count {
+                attributes["NORMAL"] = json!(buffer.accessor(&floats(&mesh.normals), FLOAT, count, "VEC3", Some(ARRAY_BUFFER), None));
+            }
+            for (set, uvs) in mesh.uvs.iter().enumerate().filter(|(_, uvs)| uvs.len() == count) {
+                attributes[format!("TEXCOORD_{}", set)] = json!(buffer.accessor(&floats(uvs), FLOAT, count, "VEC2", Some(ARRAY_BUFFER), None));
+            }
+            let skinned = !skins.is_empty() && mesh.bone_indices.len() == count && mesh.bone_weights.len() == count;
+            if skinned {
+                if let Some(&bone) = mesh.bone_indices.iter().flatten().find(|&&bone| bone as usize >= self.bones.len()) {
+                    return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("Mesh {} uses bone {}, which doesn't exist", index, bone))));
+                }
+                let joints: Vec<u8> = mesh.bone_indices.iter().flatten().flat_map(|joint| joint.to_le_bytes()).collect();
+                let weights: Vec<[f32; 4]> = mesh.bone_weights.iter().map(|&weights| normalize_weights(weights)).collect();
+                attributes["JOINTS_0"] = json!(buffer.accessor(&joints, UNSIGNED_SHORT, count, "VEC4", Some(ARRAY_BUFFER), None));
+                attributes["WEIGHTS_0"] = json!(buffer.accessor(&floats(&weights), FLOAT, count, "VEC4", Some(ARRAY_BUFFER), None));
+            }
+            let indices: Vec<u8> = mesh.indices.iter().flat_map(|index| index.to_le_bytes()).collect();
+            let indices = buffer.accessor(&indices, UNSIGNED_INT, mesh.indices.len(), "SCALAR", Some(ELEMENT_ARRAY_BUFFER), None);
+
+            let mut primitive = json!({ "attributes": attributes, "indices": indices });
+            if (mesh.material_index as usize) < self.materials.len() {
+                primitive["material"] = json!(mesh.material_index);
+            }
+            let mut node = json!({ "name": format!("Mesh {}", index), "mesh": meshes.len() });
+            if skinned {
+                node["skin"] = json!(0);
+            }
+            meshes.push(json!({ "name": format!("Mesh {}", index), "primitives": [primitive] }));
+            roots.push(nodes.len());
+            nodes.push(node);
+        }
+
+        let materials: Vec<Value> = self.materials.iter().map(|material| json!({ "name": material.name })).collect();
+        let mut gltf = json!({
+            "asset": { "version": "2.0", "generator": "dantelion-formats" },
+            "scene": 0,
+            "scenes": [{ "nodes": roots }],
+            "nodes": nodes,
+            "meshes": meshes,
+            "bufferViews": buffer.views,
+            "accessors": buffer.accessors,
+        });
+        // glTF doesn't allow empty arrays
+        if !materials.is_empty() {
+            gltf["materials"] = json!(materials);
+        }
+        if !skins.is_empty() {
+            gltf["skins"] = json!(skins);
+        }
+
+        Ok((gltf, buffer.bytes))
+    }
+}
+
+// The single binary buffer, with a buffer view and an accessor for each array written to it.
+#[derive(Default)]
+struct Buffer {
+    bytes: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl Buffer {
+    fn accessor(&mut self, data: &[u8], component_type: u32, count: usize, kind: &str, target: Option<u32>, bounds: Option<(&[f32; 3], &[f32; 3])>) -> usize {
+        pad(&mut self.bytes, 0);
+        let mut view = json!({ "buffer": 0, "byteOffset": self.bytes.len(), "byteLength": data.len() });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.bytes.extend_from_slice(data);
+        self.views.push(view);
+
+        let mut accessor = json!({ "bufferView": self.views.len() - 1, "componentType": component_type, "count": count, "type": kind });
+        if let Some((min, max)) = bounds {
+            accessor["min"] = json!(min);
+            accessor["max"] = json!(max);
+        }
+        self.accessors.push(accessor);
+
+        self.accessors.len() - 1
+    }
+}
+
+// GLB chunks and buffer views start on 4 byte boundaries.
+fn pad(bytes: &mut Vec<u8>, with: u8) {
+    bytes.resize(bytes.len().div_ceil(4) * 4, with);
+}
+
+fn floats<const N: usize>(values: &[[f32; N]]) -> Vec<u8> {
+    values.iter().flatten().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+// glTF wants weights that add up to 1. Vertices with none follow their first bone.
+fn normalize_weights(weights: [f32; 4]) -> [f32; 4] {
+    let sum: f32 = weights.iter().sum();
+    if sum > 0.0 {
+        weights.map(|weight| weight / sum)
+    } else {
+        [1.0, 0.0, 0.0, 0.0]
+    }
+}
+
+// Bones rotate around X, then Z, then Y.
+fn euler_to_quaternion([x, y, z]: [f32; 3]) -> [f32; 4] {
+    let axis = |angle: f32, axis: usize| {
+        let mut q = [0.0, 0.0, 0.0, (angle / 2.0).cos()];
+        q[axis] = (angle / 2.0).sin();
+        q
+    };
+
+    quaternion_multiply(quaternion_multiply(axis(y, 1), axis(z, 2)), axis(x, 0))
+}
+
+fn quaternion_multiply([ax, ay, az, aw]: [f32; 4], [bx, by, bz, bw]: [f32; 4]) -> [f32; 4] {
+    [
+        aw * bx + ax * bw + ay * bz - az * by,
+        aw * by - ax * bz + ay * bw + az * bx,
+        aw * bz + ax * by - ay * bx + az * bw,
+        aw * bw - ax * bx - ay * by - az * bz,
+    ]
+}
+
+// Column major, as glTF stores them
+fn trs_matrix(t: [f32; 3], [x, y, z, w]: [f32; 4], s: [f32; 3]) -> [f32; 16] {
+    [
+        (1.0 - 2.0 * (y * y + z * z)) * s[0], 2.0 * (x * y + z * w) * s[0], 2.0 * (x * z - y * w) * s[0], 0.0,
+        2.0 * (x * y - z * w) * s[1], (1.0 - 2.0 * (x * x + z * z)) * s[1], 2.0 * (y * z + x * w) * s[1], 0.0,
+        2.0 * (x * z + y * w) * s[2], 2.0 * (y * z - x * w) * s[2], (1.0 - 2.0 * (x * x + y * y)) * s[2], 0.0,
+        t[0], t[1], t[2], 1.0,
+    ]
+}
+
+fn multiply(a: &[f32; 16], b: &[f32; 16]) -> [f32; 16] {
+    let mut out = [0.0; 16];
+    for column in 0..4 {
+        for row in 0..4 {
+            out[column * 4 + row] = (0..4).map(|k| a[k * 4 + row] * b[column * 4 + k]).sum();
+        }
+    }
+
+    out
+}
+
+// Inverts a matrix whose last row is 0 0 0 1. A singular one, from a zero scale, gives the identity.
+fn invert_affine(m: &[f32; 16]) -> [f32; 16] {
+    let a = |row: usize, column: usize| m[column * 4 + row];
+    let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| a(r0, c0) * a(r1, c1) - a(r0, c1) * a(r1, c0);
+    let det = a(0, 0) * cofactor(1, 2, 1, 2) - a(0, 1) * cofactor(1, 2, 0, 2) + a(0, 2) * cofactor(1, 2, 0, 1);
+    let mut out = [0.0; 16];
+    out[15] = 1.0;
+    if det.abs() < f32::EPSILON {
+        out[0] = 1.0;
+        out[5] = 1.0;
+        out[10] = 1.0;
+        return out;
+    }
+
+    let inverse = [
+        [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+        [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+        [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+    ];
+    for row in 0..3 {
+        for column in 0..3 {
+            out[column * 4 + row] = inverse[row][column] / det;
+        }
+        out[12 + row] = -(0..3).map(|k| out[k * 4 + row] * a(k, 3)).sum::<f32>();
+    }
+
+    out
+}
@@ -0,0 +1,199 @@
+use std::io::{Error, ErrorKind};
+use byteorder::{BE, LE, ByteOrder};
+use crate::error::DantelionFormatsError;
+use super::{member_type, semantic, FaceSet, LayoutMember, Mesh, FLVER};
+
+/// A mesh as plain indexed triangles, for handing to a glTF or other exporter. Every attribute has one entry per
+/// vertex, or is empty if the mesh doesn't have it. Positions are in the game's left handed space and triangles keep
+/// the game's winding, so exporters for right handed formats have to flip both.
+#[derive(Debug, Default, PartialEq)]
+pub struct TriangleMesh {
+    pub material_index: u32,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    // One list per UV set, in layout order
+    pub uvs: Vec<Vec<[f32; 2]>>,
+    // Indices into `FLVER::bones`. Static meshes are bound to a single bone with a weight of 1.
+    pub bone_indices: Vec<[u16; 4]>,
+    pub bone_weights: Vec<[f32; 4]>,
+    // Three per triangle
+    pub indices: Vec<u32>,
+}
+
+impl FLVER {
+    /// Every mesh as triangles, see `triangle_mesh`.
+    pub fn triangle_meshes(&self) -> Result<Vec<TriangleMesh>, DantelionFormatsError> {
+        (0..self.meshes.len()).map(|index| self.triangle_mesh(index)).collect()
+    }
+
+    /// Decodes mesh `index`'s vertex buffers and its full detail face set into a `TriangleMesh`. Triangle strips are
+    /// unrolled and their degenerate triangles dropped. Fails on edge compressed (PS3) meshes and on vertex formats it
+    /// doesn't know.
+    pub fn triangle_mesh(&self, index: usize) -> Result<TriangleMesh, DantelionFormatsError> {
+        let mesh = self.meshes.get(index)
+            .ok_or_else(|| invalid(format!("Mesh {} doesn't exist, there are {} meshes", index, self.meshes.len())))?;
+        let mut out = TriangleMesh { material_index: mesh.material_index, ..TriangleMesh::default() };
+
+        let vertex_count = mesh.vertex_buffers.first().map_or(0, |buffer| buffer.vertex_count);
+        // UVs are stored as shorts over this
+        let uv_factor = if self.header.version >= 0x2000F { 2048.0 } else { 1024.0 };
+        for buffer in &mesh.vertex_buffers {
+            if buffer.vertex_count != vertex_count {
+                return Err(invalid(format!("Mesh {} has vertex buffers of {} and {} vertices", index, vertex_count, buffer.vertex_count)));
+            }
+            let layout = self.buffer_layouts.get(buffer.layout_index as usize)
+                .ok_or_else(|| invalid(format!("Mesh {} uses buffer layout {}, which doesn't exist", index, buffer.layout_index)))?;
+            if buffer.vertex_size == 0 {
+                return Err(invalid(format!("Mesh {} has a vertex buffer with a vertex size of 0", index)));
+            }
+            for member in &layout.members {
+                let size = member_type::size(member.member_type)
+                    .ok_or_else(|| unsupported(format!("Unsupported vertex member type {:#X}", member.member_type)))?;
+                if member.struct_offset + size > buffer.vertex_size {
+                    return Err(invalid(format!("Vertex member at {:#X} doesn't fit in {} bytes", member.struct_offset, buffer.vertex_size)));
+                }
+            }
+
+            let uv_start = out.uvs.len();
+            for vertex in buffer.data.chunks_exact(buffer.vertex_size as usize) {
+                let mut uv_set = uv_start;
+                for member in &layout.members {
+                    let bytes = &vertex[member.struct_offset as usize..];
+                    if self.header.big_endian {
+                        decode_member::<BE>(&mut out, member, bytes, uv_factor, &mut uv_set)?;
+                    } else {
+                        decode_member::<LE>(&mut out, member, bytes, uv_factor, &mut uv_set)?;
+                    }
+                }
+            }
+        }
+
+        remap_bones(&mut out, mesh, index)?;
+        if let Some(face_set) = mesh.face_sets.iter().find(|face_set| face_set.is_main()).or(mesh.face_sets.first()) {
+            out.indices = triangles(face_set);
+        }
+        if let Some(&bad) = out.indices.iter().find(|&&vertex| vertex >= vertex_count) {
+            return Err(invalid(format!("Mesh {} has {} vertices, but a face uses vertex {}", index, vertex_count, bad)));
+        }
+
+        Ok(out)
+    }
+}
+
+fn decode_member<T: ByteOrder>(out: &mut TriangleMesh, member: &LayoutMember, bytes: &[u8], uv_factor: f32, uv_set: &mut usize) -> Result<(), DantelionFormatsError> {
+    let f32_at = |i: usize| T::read_f32(&bytes[i * 4..]);
+    let i16_at = |i: usize| T::read_i16(&bytes[i * 2..]);
+    let u16_at = |i: usize| T::read_u16(&bytes[i * 2..]);
+    let unorm8 = |i: usize| (bytes[i] as f32 - 127.0) / 127.0;
+    let unsupported_member = || unsupported(format!("Unsupported type {:#X} for vertex semantic {}", member.member_type, member.semantic));
+
+    match (member.semantic, member.member_type) {
+        (semantic::POSITION, member_type::FLOAT3 | member_type::FLOAT4) => out.positions.push([f32_at(0), f32_at(1), f32_at(2)]),
+        (semantic::POSITION, _) => return Err(unsupported_member()),
+
+        (semantic::NORMAL, member_type::FLOAT3 | member_type::FLOAT4) => out.normals.push([f32_at(0), f32_at(1), f32_at(2)]),
+        (semantic::NORMAL, member_type::BYTE4A | member_type::BYTE4B | member_type::BYTE4C | member_type::BYTE4E) => {
+            out.normals.push([unorm8(0), unorm8(1), unorm8(2)])
+        }
+        (semantic::NORMAL, member_type::SHORT2_TO_FLOAT2) => {
+            // The first byte is the normal's w
+            out.normals.push([bytes[1] as i8 as f32 / 127.0, bytes[2] as i8 as f32 / 127.0, bytes[3] as i8 as f32 / 127.0])
+        }
+        (semantic::NORMAL, member_type::SHORT4_TO_FLOAT4A) => {
+            out.normals.push([i16_at(0) as f32 / 32767.0, i16_at(1) as f32 / 32767.0, i16_at(2) as f32 / 32767.0])
+        }
+        (semantic::NORMAL, member_type::SHORT4_TO_FLOAT4B) => {
+            let unorm16 = |i: usize| (u16_at(i) as f32 - 32767.0) / 32767.0;
+            out.normals.push([unorm16(0), unorm16(1), unorm16(2)])
+        }
+        (semantic::NORMAL, _) => return Err(unsupported_member()),
+
+        (semantic::UV, _) => {
+            let short_uv = |i: usize| [i16_at(i) as f32 / uv_factor, i16_at(i + 1) as f32 / uv_factor];
+            let uvs = match member.member_type {
+                member_type::FLOAT2 | member_type::FLOAT3 => vec![[f32_at(0), f32_at(1)]],
+                member_type::FLOAT4 => vec![[f32_at(0), f32_at(1)], [f32_at(2), f32_at(3)]],
+                member_type::BYTE4A | member_type::SHORT2_TO_FLOAT2 | member_type::UV | member_type::SHORT4_TO_FLOAT4B => vec![short_uv(0)],
+                member_type::UV_PAIR => vec![short_uv(0), short_uv(2)],
+                _ => return Err(unsupported_member()),
+            };
+            for uv in uvs {
+                if *uv_set == out.uvs.len() {
+                    out.uvs.push(vec![]);
+                }
+                out.uvs[*uv_set].push(uv);
+                *uv_set += 1;
+            }
+        }
+
+        (semantic::BONE_INDICES, member_type::BYTE4B | member_type::BYTE4E) => {
+            out.bone_indices.push([bytes[0] as u16, bytes[1] as u16, bytes[2] as u16, bytes[3] as u16])
+        }
+        (semantic::BONE_INDICES, member_type::SHORT_BONE_INDICES) => out.bone_indices.push([u16_at(0), u16_at(1), u16_at(2), u16_at(3)]),
+        (semantic::BONE_INDICES, _) => return Err(unsupported_member()),
+
+        (semantic::BONE_WEIGHTS, member_type::BYTE4A) => out.bone_weights.push([0, 1, 2, 3].map(|i| bytes[i] as i8 as f32 / 127.0)),
+        (semantic::BONE_WEIGHTS, member_type::BYTE4C) => out.bone_weights.push([0, 1, 2, 3].map(|i| bytes[i] as f32 / 255.0)),
+        (semantic::BONE_WEIGHTS, member_type::UV_PAIR | member_type::SHORT4_TO_FLOAT4A) => {
+            out.bone_weights.push([0, 1, 2, 3].map(|i| i16_at(i) as f32 / 32767.0))
+        }
+        (semantic::BONE_WEIGHTS, _) => return Err(unsupported_member()),
+
+        // Tangents, bitangents and colors aren't part of `TriangleMesh`
+        _ => {}
+    }
+
+    Ok(())
+}
+
+// Turns mesh local bone indices into `FLVER::bones` indices, and gives static meshes their implied weight.
+fn remap_bones(out: &mut TriangleMesh, mesh: &Mesh, index: usize) -> Result<(), DantelionFormatsError> {
+    if !mesh.bone_indices.is_empty() {
+        for bones in &mut out.bone_indices {
+            for bone in bones {
+                let global = mesh.bone_indices.get(*bone as usize)
+                    .and_then(|&global| u16::try_from(global).ok())
+                    .ok_or_else(|| invalid(format!("Mesh {} uses bone {}, which it doesn't map", index, bone)))?;
+                *bone = global;
+            }
+        }
+    }
+    if out.bone_weights.is_empty() && !out.bone_indices.is_empty() {
+        out.bone_weights = vec![[1.0, 0.0, 0.0, 0.0]; out.bone_indices.len()];
+    }
+
+    Ok(())
+}
+
+// A triangle list as it is, or a strip unrolled with alternating winding. Strips restart at an all ones index.
+fn triangles(face_set: &FaceSet) -> Vec<u32> {
+    let indices = &face_set.indices;
+    if !face_set.triangle_strip {
+        return indices[..indices.len() / 3 * 3].to_vec();
+    }
+
+    let restart = if face_set.index_size == 16 { u16::MAX as u32 } else { u32::MAX };
+    let mut out = Vec::with_capacity(indices.len().saturating_sub(2) * 3);
+    let mut flip = false;
+    for window in indices.windows(3) {
+        let [a, b, c] = [window[0], window[1], window[2]];
+        if a == restart || b == restart || c == restart {
+            flip = false;
+            continue;
+        }
+        if a != b && b != c && a != c {
+            out.extend(if flip { [c, b, a] } else { [a, b, c] });
+        }
+        flip = !flip;
+    }
+
+    out
+}
+
+fn invalid(message: String) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, message))
+}
+
+fn unsupported(message: String) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::Unsupported, message))
+}
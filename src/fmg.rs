@@ -0,0 +1,84 @@
+use std::io::{Cursor, Error, ErrorKind};
+use binary_interpreter::binary_reader::BinaryPeeker;
+use byteorder::{BE, LE, ByteOrder, ReadBytesExt};
+use crate::error::DantelionFormatsError;
+use crate::util;
+
+/// The ids an FMG has text for, found from its groups and string offsets alone, e.g. to check that every weapon row
+/// has a name. Ids whose string offset is 0 have no text and are left out.
+#[derive(Debug)]
+pub struct FmgIds {
+    // In file order
+    pub ids: Vec<i32>,
+}
+
+impl FmgIds {
+    const BIG_ENDIAN_OFFSET: u64 = 1;
+    const VERSION_OFFSET: u64 = 2;
+    // DS3 on, with 64-bit string offsets
+    const WIDE_VERSION: u8 = 2;
+
+    pub fn from_bytes(fmg: &[u8]) -> Result<FmgIds, DantelionFormatsError> {
+        let mut c = Cursor::new(fmg);
+        if c.peek_u8(FmgIds::BIG_ENDIAN_OFFSET)? == 1 {
+            FmgIds::read_ids::<BE>(&mut c)
+        } else {
+            FmgIds::read_ids::<LE>(&mut c)
+        }
+    }
+
+    pub fn contains(&self, id: i32) -> bool {
+        self.ids.contains(&id)
+    }
+
+    fn read_ids<T: ByteOrder>(c: &mut Cursor<&[u8]>) -> Result<FmgIds, DantelionFormatsError> {
+        let wide = c.peek_u8(FmgIds::VERSION_OFFSET)? == FmgIds::WIDE_VERSION;
+        c.set_position(0xC);
+        let group_count = c.read_u32::<T>()?;
+        let string_count = c.read_u32::<T>()? as u64;
+        let string_offsets_offset = if wide {
+            c.read_u32::<T>()?;
+            c.read_u64::<T>()?
+        } else {
+            c.read_u32::<T>()? as u64
+        };
+        c.set_position(if wide { 0x28 } else { 0x1C });
+        let offset_size = if wide { 8 } else { 4 };
+
+        let group_count = util::checked_count(c, group_count as u64, if wide { 0x10 } else { 0xC }, "FMG group count")?;
+        let mut groups = Vec::with_capacity(group_count);
+        for _ in 0..group_count {
+            let offset_index = c.read_i32::<T>()?;
+            let first_id = c.read_i32::<T>()?;
+            let last_id = c.read_i32::<T>()?;
+            if wide {
+                c.read_i32::<T>()?;
+            }
+            groups.push((offset_index, first_id, last_id));
+        }
+
+        let mut ids = vec![];
+        for (offset_index, first_id, last_id) in groups {
+            let count = last_id as i64 - first_id as i64 + 1;
+            let in_range = offset_index >= 0 && count >= 0 && offset_index as u64 + count as u64 <= string_count;
+            if !in_range {
+                return Err(invalid(format!("FMG group {}..={} at string {} is outside the {} strings", first_id, last_id, offset_index, string_count)));
+            }
+
+            c.set_position(string_offsets_offset + offset_index as u64 * offset_size);
+            let count = util::checked_count(c, count as u64, offset_size, "FMG group size")?;
+            for i in 0..count {
+                let string_offset = if wide { c.read_u64::<T>()? } else { c.read_u32::<T>()? as u64 };
+                if string_offset != 0 {
+                    ids.push(first_id + i as i32);
+                }
+            }
+        }
+
+        Ok(FmgIds { ids })
+    }
+}
+
+fn invalid(message: String) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, message))
+}
@@ -0,0 +1,322 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use crate::bnd3::BND3;
+use crate::bnd4::BND4;
+use crate::error::DantelionFormatsError;
+use crate::flver::FLVER;
+use crate::fmg::FmgIds;
+use crate::kind::FileKind;
+use crate::msb::MSB;
+use crate::param::ParamRows;
+use crate::tpf::TPF;
+use crate::vfs::Vfs;
+use crate::parsed_file::{self, strip_dcx, ParsedFile};
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum NodeKind {
+    // A BHD5/BDT pair
+    Archive,
+    // A loose folder overriding archive files, e.g. a mod's
+    ModFolder,
+    // Binders included, so a binder found in an archive and then added with `add_bnd4` is one node
+    File,
+    // By name without the extension, e.g. "c1000_a", the way TPFs name them and FLVERs refer to them
+    Texture,
+    // An MTD or MATBIN by file name without the extension, e.g. "c[amsn]", since FLVERs refer to ER's MATBINs as
+    // .matxml
+    Material,
+    // A model an MSB part can use, named after the binder it's in, e.g. "c1000" for /chr/c1000.chrbnd.dcx
+    Model,
+    // One FMG entry, by the FMG's name and the id, e.g. "WeaponName[1000000]"
+    Text,
+}
+
+/// How two nodes are linked. Files contain what's in them, and the binders named after a model or material contain
+/// that `Model` or `Material` node. References come from `add_flver` (materials and textures), `add_msb` (the
+/// models its parts use) and `add_param` (the text for each row), or from callers reading other formats through
+/// `add_reference`.
+#[non_exhaustive]
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum Relation {
+    Contains,
+    References,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub name: String,
+}
+
+/// A cross-reference between parsed files, e.g. which archive holds a binder and which binders hold a texture.
+/// Nodes are unique by kind and name, ignoring case, so a file added from two places is one node with two parents.
+#[derive(Debug, Default)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    // (from, relation, to), indices into `nodes`
+    pub edges: Vec<(usize, Relation, usize)>,
+    index: HashMap<(NodeKind, String), usize>,
+    linked: HashSet<(usize, Relation, usize)>,
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph::default()
+    }
+
+    /// The node's index, adding it if it's new.
+    pub fn add_node(&mut self, kind: NodeKind, name: &str) -> usize {
+        let nodes = &mut self.nodes;
+        *self.index.entry((kind, name.to_lowercase())).or_insert_with(|| {
+            nodes.push(Node { kind, name: name.to_string() });
+            nodes.len() - 1
+        })
+    }
+
+    pub fn link(&mut self, from: usize, relation: Relation, to: usize) {
+        if self.linked.insert((from, relation, to)) {
+            self.edges.push((from, relation, to));
+        }
+    }
+
+    /// Every archive and the named entries in it.
+    pub fn add_vfs(&mut self, vfs: &Vfs) {
+        for archive in &vfs.archives {
+            let archive_node = self.add_node(NodeKind::Archive, &archive.bhd_path.to_string_lossy());
+            let mut names: Vec<&String> = archive.names.values().collect();
+            names.sort();
+            for name in names {
+                let file = self.add_file(name);
+                self.link(archive_node, Relation::Contains, file);
+            }
+        }
+    }
+
+    /// Every file under `dir` by its virtual path, e.g. "/parts/am_m_1600.partsbnd.dcx", so files overriding archive
+    /// entries are the same nodes. Binders and TPFs are gone into like in `add_bnd4`. Returns the folder's node.
+    pub fn add_mod_folder(&mut self, dir: &Path) -> Result<usize, DantelionFormatsError> {
+        let folder = self.add_node(NodeKind::ModFolder, &dir.to_string_lossy());
+        self.add_folder_files(folder, dir, "")?;
+
+        Ok(folder)
+    }
+
+    fn add_folder_files(&mut self, folder: usize, dir: &Path, virtual_dir: &str) -> Result<(), DantelionFormatsError> {
+        let mut entries = fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.path())).collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        for path in entries {
+            let virtual_path = format!("{}/{}", virtual_dir, path.file_name().unwrap_or_default().to_string_lossy());
+            if path.is_dir() {
+                self.add_folder_files(folder, &path, &virtual_path)?;
+                continue;
+            }
+
+            let data = fs::read(&path)?;
+            self.add_entry(folder, Some(&virtual_path), || Ok(FileKind::sniff(&strip_dcx(&data)?)), || parsed_file::open_bytes(&data))?;
+        }
+
+        Ok(())
+    }
+
+    /// Records that `from` refers to the file or texture `name`, returning the target's node. Nothing has to hold the
+    /// target yet, `dangling` reports the ones that nothing ends up holding.
+    pub fn add_reference(&mut self, from: usize, kind: NodeKind, name: &str) -> usize {
+        let to = self.add_node(kind, name);
+        self.link(from, Relation::References, to);
+
+        to
+    }
+
+    /// Every reference whose target isn't held by any archive, mod folder, binder, TPF or FMG in the graph, as the
+    /// referring node and the missing one. E.g. an MSB using a model no binder is named after, a FLVER whose MTD
+    /// isn't in any material binder, or a param row without text.
+    pub fn dangling(&self) -> Vec<(&Node, &Node)> {
+        let held: HashSet<usize> = self.edges.iter()
+            .filter(|(_, relation, _)| *relation == Relation::Contains)
+            .map(|(_, _, to)| *to)
+            .collect();
+
+        self.edges.iter()
+            .filter(|(_, relation, to)| *relation == Relation::References && !held.contains(to))
+            .map(|(from, _, to)| (&self.nodes[*from], &self.nodes[*to]))
+            .collect()
+    }
+
+    /// A binder and its files, going into nested binders and TPFs. Returns the binder's node.
+    pub fn add_bnd4(&mut self, name: &str, bnd4: &BND4) -> Result<usize, DantelionFormatsError> {
+        let binder = self.add_file(name);
+        for file in &bnd4.files {
+            self.add_entry(binder, file.name(), || file.kind(), || file.open())?;
+        }
+
+        Ok(binder)
+    }
+
+    /// Same as `add_bnd4`.
+    pub fn add_bnd3(&mut self, name: &str, bnd3: &BND3) -> Result<usize, DantelionFormatsError> {
+        let binder = self.add_file(name);
+        for file in &bnd3.files {
+            self.add_entry(binder, file.name.as_deref(), || file.kind(), || file.open())?;
+        }
+
+        Ok(binder)
+    }
+
+    /// A TPF and its textures. Returns the TPF's node.
+    pub fn add_tpf(&mut self, name: &str, tpf: &TPF) -> usize {
+        let node = self.add_file(name);
+        for texture in &tpf.textures {
+            let texture = self.add_node(NodeKind::Texture, &texture.name);
+            self.link(node, Relation::Contains, texture);
+        }
+
+        node
+    }
+
+    /// A FLVER and the materials and textures it refers to. Returns the FLVER's node.
+    pub fn add_flver(&mut self, name: &str, flver: &FLVER) -> usize {
+        let node = self.add_file(name);
+        for material in &flver.materials {
+            self.add_reference(node, NodeKind::Material, stem(&material.mtd));
+        }
+        for texture in flver.textures.iter().filter(|texture| !texture.path.is_empty()) {
+            self.add_reference(node, NodeKind::Texture, stem(&texture.path));
+        }
+
+        node
+    }
+
+    /// An MSB and the models its parts use, other than collisions and navmeshes, which aren't in binders. Map piece
+    /// models are named per map, "m000100" in m30_00_00_00's MSB is m30_00_00_00_000100.mapbnd. A part whose model
+    /// index is past the end of the models refers to a model named after the index, e.g. "MODEL_PARAM_ST[12]", which
+    /// nothing holds. Returns the MSB's node.
+    pub fn add_msb(&mut self, name: &str, msb: &MSB) -> Result<usize, DantelionFormatsError> {
+        let node = self.add_file(name);
+        for (part, model) in msb.part_models() {
+            let model_name = match model {
+                None => format!("MODEL_PARAM_ST[{}]", part.model_index("PARTS_PARAM_ST").unwrap_or(-1)),
+                Some(model) => match model.entry_type("MODEL_PARAM_ST") {
+                    Some(MAP_PIECE) => format!("{}_{}", stem(name), model.name()?.trim_start_matches('m')),
+                    Some(model_type) if BINDER_MODEL_TYPES.contains(&model_type) => model.name()?,
+                    _ => continue,
+                },
+            };
+            self.add_reference(node, NodeKind::Model, &model_name);
+        }
+
+        Ok(node)
+    }
+
+    /// An FMG and the ids it has text for, see `add_param`. Returns the FMG's node.
+    pub fn add_fmg(&mut self, name: &str, fmg: &FmgIds) -> usize {
+        let node = self.add_file(name);
+        for id in &fmg.ids {
+            let text = self.add_node(NodeKind::Text, &format!("{}[{}]", stem(name), id));
+            self.link(node, Relation::Contains, text);
+        }
+
+        node
+    }
+
+    /// A param whose row ids are ids in the FMG named `fmg`, e.g. "WeaponName" for "EquipParamWeapon.param", and the
+    /// text for each row it refers to. Returns the param's node.
+    pub fn add_param(&mut self, name: &str, rows: &ParamRows, fmg: &str) -> usize {
+        let node = self.add_file(name);
+        for (id, _) in &rows.rows {
+            self.add_reference(node, NodeKind::Text, &format!("{}[{}]", stem(fmg), id));
+        }
+
+        node
+    }
+
+    // A file node, and the model or material it holds if its name says it's one.
+    fn add_file(&mut self, name: &str) -> usize {
+        let node = self.add_node(NodeKind::File, name);
+        let file_name = file_name(name).to_lowercase();
+        let extension = file_name.trim_end_matches(".dcx").rsplit('.').next().unwrap_or_default();
+        let provides = if MODEL_EXTENSIONS.contains(&extension) {
+            Some(NodeKind::Model)
+        } else if MATERIAL_EXTENSIONS.contains(&extension) {
+            Some(NodeKind::Material)
+        } else {
+            None
+        };
+        if let Some(kind) = provides {
+            let provided = self.add_node(kind, stem(name));
+            self.link(node, Relation::Contains, provided);
+        }
+
+        node
+    }
+
+    // Binders, TPFs, FLVERs, MSBs and FMGs are parsed, everything else is just a file node. FMGs are recognized by
+    // their header layout alone, so one that doesn't read is taken to be some other file.
+    fn add_entry(
+        &mut self,
+        binder: usize,
+        name: Option<&str>,
+        kind: impl FnOnce() -> Result<FileKind, DantelionFormatsError>,
+        open: impl FnOnce() -> Result<ParsedFile, DantelionFormatsError>,
+    ) -> Result<(), DantelionFormatsError> {
+        let Some(name) = name else { return Ok(()) };
+        let node = match kind()? {
+            FileKind::BND3 | FileKind::BND4 | FileKind::TPF | FileKind::FLVER | FileKind::MSB => match open()? {
+                ParsedFile::BND4(bnd4) => self.add_bnd4(name, &bnd4)?,
+                ParsedFile::BND3(bnd3) => self.add_bnd3(name, &bnd3)?,
+                ParsedFile::TPF(tpf) => self.add_tpf(name, &tpf),
+                ParsedFile::FLVER(flver) => self.add_flver(name, &flver),
+                ParsedFile::MSB(msb) => self.add_msb(name, &msb)?,
+                _ => self.add_file(name),
+            },
+            FileKind::FMG => match open()? {
+                ParsedFile::Unknown(bytes) => match FmgIds::from_bytes(&bytes) {
+                    Ok(fmg) => self.add_fmg(name, &fmg),
+                    Err(_) => self.add_file(name),
+                },
+                _ => self.add_file(name),
+            },
+            _ => self.add_file(name),
+        };
+        self.link(binder, Relation::Contains, node);
+
+        Ok(())
+    }
+
+    pub fn find(&self, kind: NodeKind, name: &str) -> Option<usize> {
+        self.index.get(&(kind, name.to_lowercase())).copied()
+    }
+
+    /// The nodes `node` links to.
+    pub fn children(&self, node: usize) -> Vec<(Relation, &Node)> {
+        self.edges.iter()
+            .filter(|(from, _, _)| *from == node)
+            .map(|(_, relation, to)| (*relation, &self.nodes[*to]))
+            .collect()
+    }
+
+    /// The nodes that link to `node`, e.g. every binder holding a texture.
+    pub fn parents(&self, node: usize) -> Vec<(Relation, &Node)> {
+        self.edges.iter()
+            .filter(|(_, _, to)| *to == node)
+            .map(|(from, relation, _)| (*relation, &self.nodes[*from]))
+            .collect()
+    }
+}
+
+// MSB model types that ship as binders: map pieces, objects (assets in ER), enemies and players.
+const MAP_PIECE: u32 = 0;
+const BINDER_MODEL_TYPES: [u32; 3] = [1, 2, 4];
+const MODEL_EXTENSIONS: [&str; 4] = ["chrbnd", "objbnd", "geombnd", "mapbnd"];
+const MATERIAL_EXTENSIONS: [&str; 2] = ["mtd", "matbin"];
+
+// Binder entries have Windows paths, e.g. "N:\FDP\data\Material\mtd\c[amsn].mtd", archive entries virtual ones.
+fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+// The file name up to its first extension, e.g. "c1000" for "/chr/c1000.chrbnd.dcx".
+fn stem(path: &str) -> &str {
+    let file_name = file_name(path);
+    file_name.split('.').next().unwrap_or(file_name)
+}
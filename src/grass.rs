@@ -0,0 +1,203 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind};
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
+use crate::dcx::DCX;
+use crate::error::DantelionFormatsError;
+use crate::source::{DataSource, FileSource};
+use crate::util;
+use crate::util::{DataLen, ensure, Validate};
+
+/// Grass placement from an Elden Ring map binder (".grass"). The header and the table of grass volumes are read, each
+/// volume kept as its raw record. The placement data after the table, which the volumes point into, is kept as it is,
+/// so a file reads and writes back byte for byte.
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+pub struct GRASS {
+    pub table: MapTable,
+}
+
+/// Decal placement from an Elden Ring map binder (".decal"). Uses the same layout as `GRASS`, with one record per
+/// decal.
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+pub struct Decal {
+    pub table: MapTable,
+}
+
+/// The layout `GRASS` and `Decal` share: a header, a table of fixed size records and the data after it.
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+pub struct MapTable {
+    pub header: MapTableHeader,
+    pub entries: Vec<MapTableEntry>,
+    // Everything after the table
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+#[non_exhaustive]
+pub struct MapTableHeader {
+    // 2 in every shipped file
+    pub version: u32,
+    // Always 0
+    pub unk04: u32,
+    // Size of each entry
+    pub entry_size: u32,
+}
+
+#[derive(PartialEq, Clone)]
+#[repr(C)]
+pub struct MapTableEntry {
+    pub data: Vec<u8>,
+}
+
+impl GRASS {
+    pub fn from_path(path: &str) -> Result<GRASS, DantelionFormatsError> {
+        GRASS::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<GRASS, DantelionFormatsError> {
+        let file = source.read_all()?;
+
+        GRASS::from_bytes(&file)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<GRASS, DantelionFormatsError> {
+        Ok(GRASS { table: MapTable::from_bytes(file)? })
+    }
+
+    pub fn to_path(&self, path: &str) -> Result<(), DantelionFormatsError> {
+        Ok(fs::write(path, self.to_bytes()?)?)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        self.table.to_bytes()
+    }
+}
+
+impl Decal {
+    pub fn from_path(path: &str) -> Result<Decal, DantelionFormatsError> {
+        Decal::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<Decal, DantelionFormatsError> {
+        let file = source.read_all()?;
+
+        Decal::from_bytes(&file)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<Decal, DantelionFormatsError> {
+        Ok(Decal { table: MapTable::from_bytes(file)? })
+    }
+
+    pub fn to_path(&self, path: &str) -> Result<(), DantelionFormatsError> {
+        Ok(fs::write(path, self.to_bytes()?)?)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        self.table.to_bytes()
+    }
+}
+
+impl MapTable {
+    const VERSION: u32 = 2;
+    const HEADER_SIZE: u64 = 0x10;
+
+    pub fn from_bytes(file: &[u8]) -> Result<MapTable, DantelionFormatsError> {
+        let bytes = if DCX::is(file) {
+            DCX::from_bytes(file)?.decompress()?
+        } else {
+            file.to_vec()
+        };
+        let mut c = Cursor::new(&bytes[..]);
+
+        let version = c.read_u32::<LE>()?;
+        let unk04 = c.read_u32::<LE>()?;
+        let entry_count = c.read_u32::<LE>()?;
+        let header = MapTableHeader {
+            version,
+            unk04,
+            entry_size: c.read_u32::<LE>()?,
+        };
+        header.validate()?;
+
+        let mut entries = Vec::with_capacity(util::capacity_for(&c, entry_count as u64));
+        for _ in 0..entry_count {
+            entries.push(MapTableEntry { data: util::read_len(&mut c, header.entry_size as u64)? });
+        }
+        let data = bytes[c.position() as usize..].to_vec();
+
+        Ok(MapTable {
+            header,
+            entries,
+            data,
+        })
+    }
+
+    /// Serializes the table. Fails if an entry isn't `entry_size` bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        let header = &self.header;
+        header.validate()?;
+        if let Some(index) = self.entries.iter().position(|entry| entry.data.len() != header.entry_size as usize) {
+            return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("Entry {} is {} bytes, but entries are {}", index, self.entries[index].data.len(), header.entry_size))));
+        }
+
+        let mut bytes = Vec::with_capacity(MapTable::HEADER_SIZE as usize + self.entries.len() * header.entry_size as usize + self.data.len());
+        bytes.write_u32::<LE>(header.version)?;
+        bytes.write_u32::<LE>(header.unk04)?;
+        bytes.write_u32::<LE>(self.entries.len() as u32)?;
+        bytes.write_u32::<LE>(header.entry_size)?;
+        for entry in &self.entries {
+            bytes.extend_from_slice(&entry.data);
+        }
+        bytes.extend_from_slice(&self.data);
+
+        Ok(bytes)
+    }
+}
+
+/// A one line summary, e.g. "GRASS v2 — 40 volumes".
+impl Display for GRASS {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "GRASS v{} — {} volumes", self.table.header.version, self.table.entries.len())
+    }
+}
+
+/// A one line summary, e.g. "Decal v2 — 300 decals".
+impl Display for Decal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Decal v{} — {} decals", self.table.header.version, self.table.entries.len())
+    }
+}
+
+impl Debug for MapTableEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MapTableEntry")
+            .field("data", &DataLen(self.data.len()))
+            .finish()
+    }
+}
+
+impl MapTableHeader {
+    // Stable accessors, see `BND4Header`.
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn entry_size(&self) -> u32 {
+        self.entry_size
+    }
+}
+
+impl Validate for MapTableHeader {
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.version == MapTable::VERSION, "version was {}", self.version);
+        ensure!(self.unk04 == 0, "unk04 was {}", self.unk04);
+        ensure!(self.entry_size != 0, "entry_size was {}", self.entry_size);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind, Read};
+use std::time::UNIX_EPOCH;
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
+use crate::bhd5::{BHD5Format, BHD5Header, CompactBHD5, Range, Span};
+use crate::dictionary::Dictionary;
+use crate::error::DantelionFormatsError;
+use crate::util;
+
+/// A BHD5's parsed headers and the names resolved for them, saved to a small cache file so a frontend can open an
+/// install in milliseconds instead of decrypting and parsing every BHD5 on each launch. The file records the size and
+/// modified time of the BHD it was built from, and a version, and is rebuilt by `load_or_build` when either changes.
+#[derive(Debug)]
+pub struct ArchiveIndex {
+    pub bhd5: CompactBHD5,
+    // Path hash to name, for the files that have one
+    pub names: HashMap<u64, String>,
+}
+
+impl ArchiveIndex {
+    const MAGIC: &'static [u8; 4] = b"DFIX";
+    /// Bumped whenever the layout changes. Older files are rebuilt rather than read.
+    pub const VERSION: u32 = 1;
+
+    /// Names the files of `bhd5` that `dictionary` knows.
+    pub fn new(bhd5: CompactBHD5, dictionary: &Dictionary) -> ArchiveIndex {
+        let names = bhd5.file_path_hashes.iter()
+            .filter_map(|&hash| Some((hash, dictionary.get(hash)?.to_string())))
+            .collect();
+
+        ArchiveIndex { bhd5, names }
+    }
+
+    /// The name of file `index`, if it was resolved.
+    pub fn name(&self, index: usize) -> Option<&str> {
+        self.names.get(&self.bhd5.file_path_hashes[index]).map(String::as_str)
+    }
+
+    /// The index cached at `index_path` if it was built from the BHD at `bhd_path` as it is now, otherwise parses the
+    /// BHD, decrypting it if it needs to, and writes a new cache. A missing, stale or unreadable cache is just rebuilt.
+    pub fn load_or_build(bhd_path: &str, index_path: &str, dictionary: &Dictionary) -> Result<ArchiveIndex, DantelionFormatsError> {
+        let stamp = ArchiveIndex::stamp(bhd_path)?;
+        if let Ok(bytes) = fs::read(index_path) {
+            if let Ok((cached_stamp, index)) = ArchiveIndex::read(&bytes) {
+                if cached_stamp == stamp {
+                    return Ok(index);
+                }
+            }
+        }
+
+        let index = ArchiveIndex::new(CompactBHD5::from_path(bhd_path)?, dictionary);
+        // Written under a temp name so a crash can't leave a partial index behind.
+        let temp = format!("{}.tmp", index_path);
+        fs::write(&temp, index.write(stamp)?)?;
+        fs::rename(&temp, index_path)?;
+
+        Ok(index)
+    }
+
+    // The BHD's size and modified time in nanoseconds.
+    fn stamp(bhd_path: &str) -> Result<(u64, u128), DantelionFormatsError> {
+        let metadata = fs::metadata(bhd_path)?;
+        let mtime = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+
+        Ok((metadata.len(), mtime))
+    }
+
+    fn write(&self, (len, mtime): (u64, u128)) -> Result<Vec<u8>, DantelionFormatsError> {
+        let bhd5 = &self.bhd5;
+        let header = &bhd5.bhd5_header;
+        let mut bytes = ArchiveIndex::MAGIC.to_vec();
+        bytes.write_u32::<LE>(ArchiveIndex::VERSION)?;
+        bytes.write_u64::<LE>(len)?;
+        bytes.write_u128::<LE>(mtime)?;
+
+        bytes.write_u8(match bhd5.format {
+            BHD5Format::DarkSoulsII => 0,
+            BHD5Format::DarkSoulsIII => 1,
+            BHD5Format::EldenRing => 2,
+        })?;
+        write_bytes(&mut bytes, header.magic.as_bytes())?;
+        bytes.extend_from_slice(&[header.unk04, header.unk05, header.unk06, header.unk07]);
+        for value in [header.unk08, header.file_size, header.bucket_count, header.buckets_offset, header.salt_len] {
+            bytes.write_u32::<LE>(value)?;
+        }
+        write_bytes(&mut bytes, &header.salt)?;
+
+        write_array(&mut bytes, &bhd5.buckets, |bytes, span| write_span(bytes, *span))?;
+        write_array(&mut bytes, &bhd5.file_path_hashes, |bytes, &value| bytes.write_u64::<LE>(value))?;
+        write_array(&mut bytes, &bhd5.padded_file_sizes, |bytes, &value| bytes.write_u32::<LE>(value))?;
+        write_array(&mut bytes, &bhd5.file_sizes, |bytes, &value| bytes.write_u64::<LE>(value))?;
+        write_array(&mut bytes, &bhd5.file_offsets, |bytes, &value| bytes.write_u64::<LE>(value))?;
+        write_array(&mut bytes, &bhd5.salted_hashes, |bytes, &value| bytes.write_u32::<LE>(value))?;
+        write_array(&mut bytes, &bhd5.aes_keys, |bytes, &value| bytes.write_u32::<LE>(value))?;
+        write_array(&mut bytes, &bhd5.entries, |bytes, &(key, ranges)| {
+            write_span(bytes, key)?;
+            write_span(bytes, ranges)
+        })?;
+        write_bytes(&mut bytes, &bhd5.bytes)?;
+        write_array(&mut bytes, &bhd5.ranges, |bytes, range| {
+            bytes.write_u64::<LE>(range.begin)?;
+            bytes.write_u64::<LE>(range.end)
+        })?;
+
+        // Sorted so the same index always writes the same bytes
+        let mut names: Vec<_> = self.names.iter().collect();
+        names.sort();
+        write_array(&mut bytes, &names, |bytes, (&hash, name)| {
+            bytes.write_u64::<LE>(hash)?;
+            write_bytes(bytes, name.as_bytes())
+        })?;
+
+        Ok(bytes)
+    }
+
+    fn read(bytes: &[u8]) -> Result<((u64, u128), ArchiveIndex), DantelionFormatsError> {
+        let mut c = Cursor::new(bytes);
+        let mut magic = [0; 4];
+        c.read_exact(&mut magic)?;
+        let version = c.read_u32::<LE>()?;
+        if &magic != ArchiveIndex::MAGIC || version != ArchiveIndex::VERSION {
+            return Err(invalid(format!("Not a version {} archive index", ArchiveIndex::VERSION)));
+        }
+        let stamp = (c.read_u64::<LE>()?, c.read_u128::<LE>()?);
+
+        let format = match c.read_u8()? {
+            0 => BHD5Format::DarkSoulsII,
+            1 => BHD5Format::DarkSoulsIII,
+            2 => BHD5Format::EldenRing,
+            format => return Err(invalid(format!("Unknown BHD5 format {}", format))),
+        };
+        let magic = String::from_utf8(read_bytes(&mut c)?)?;
+        let bhd5_header = BHD5Header {
+            magic,
+            unk04: c.read_u8()?,
+            unk05: c.read_u8()?,
+            unk06: c.read_u8()?,
+            unk07: c.read_u8()?,
+            unk08: c.read_u32::<LE>()?,
+            file_size: c.read_u32::<LE>()?,
+            bucket_count: c.read_u32::<LE>()?,
+            buckets_offset: c.read_u32::<LE>()?,
+            salt_len: c.read_u32::<LE>()?,
+            salt: read_bytes(&mut c)?,
+        };
+
+        let bhd5 = CompactBHD5 {
+            format,
+            bhd5_header,
+            buckets: read_array(&mut c, 8, read_span)?,
+            file_path_hashes: read_array(&mut c, 8, |c| c.read_u64::<LE>())?,
+            padded_file_sizes: read_array(&mut c, 4, |c| c.read_u32::<LE>())?,
+            file_sizes: read_array(&mut c, 8, |c| c.read_u64::<LE>())?,
+            file_offsets: read_array(&mut c, 8, |c| c.read_u64::<LE>())?,
+            salted_hashes: read_array(&mut c, 4, |c| c.read_u32::<LE>())?,
+            aes_keys: read_array(&mut c, 4, |c| c.read_u32::<LE>())?,
+            entries: read_array(&mut c, 16, |c| Ok((read_span(c)?, read_span(c)?)))?,
+            bytes: read_bytes(&mut c)?,
+            ranges: read_array(&mut c, 16, |c| Ok(Range { begin: c.read_u64::<LE>()?, end: c.read_u64::<LE>()? }))?,
+        };
+        let names = read_array(&mut c, 12, |c| {
+            let hash = c.read_u64::<LE>()?;
+            let name = String::from_utf8(read_bytes(c)?).map_err(|_| Error::new(ErrorKind::InvalidData, "Name is not UTF-8"))?;
+            Ok((hash, name))
+        })?;
+
+        ArchiveIndex::check(&bhd5)?;
+
+        Ok((stamp, ArchiveIndex { bhd5, names: names.into_iter().collect() }))
+    }
+
+    // The accessors index the arrays directly, so a damaged file is turned away here rather than panicking later.
+    fn check(bhd5: &CompactBHD5) -> Result<(), DantelionFormatsError> {
+        let file_count = bhd5.file_path_hashes.len();
+        let lengths_ok = [bhd5.padded_file_sizes.len(), bhd5.file_sizes.len(), bhd5.file_offsets.len(), bhd5.salted_hashes.len(), bhd5.aes_keys.len()]
+            .iter()
+            .all(|&len| len == file_count);
+        let in_bounds = |span: Span, len: usize| span.start as usize + span.len as usize <= len;
+        let buckets_ok = bhd5.buckets.iter().all(|&span| in_bounds(span, file_count));
+        let entries_ok = bhd5.entries.iter().all(|&(key, ranges)| in_bounds(key, bhd5.bytes.len()) && in_bounds(ranges, bhd5.ranges.len()));
+        let references_ok = bhd5.salted_hashes.iter().chain(&bhd5.aes_keys)
+            .all(|&entry| entry == CompactBHD5::NONE || (entry as usize) < bhd5.entries.len());
+        if !(lengths_ok && buckets_ok && entries_ok && references_ok) {
+            return Err(invalid("Archive index is inconsistent".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid(message: String) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, message))
+}
+
+fn write_span(bytes: &mut Vec<u8>, span: Span) -> std::io::Result<()> {
+    bytes.write_u32::<LE>(span.start)?;
+    bytes.write_u32::<LE>(span.len)
+}
+
+fn read_span(c: &mut Cursor<&[u8]>) -> std::io::Result<Span> {
+    Ok(Span { start: c.read_u32::<LE>()?, len: c.read_u32::<LE>()? })
+}
+
+fn write_bytes(bytes: &mut Vec<u8>, data: &[u8]) -> std::io::Result<()> {
+    bytes.write_u64::<LE>(data.len() as u64)?;
+    bytes.extend_from_slice(data);
+
+    Ok(())
+}
+
+// Lengths past the end of the file fail the read, rather than allocating them first.
+fn read_bytes(c: &mut Cursor<&[u8]>) -> std::io::Result<Vec<u8>> {
+    let len = c.read_u64::<LE>()?;
+    if len > (c.get_ref().len() as u64).saturating_sub(c.position()) {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Archive index is truncated"));
+    }
+    let mut data = vec![0; len as usize];
+    c.read_exact(&mut data)?;
+
+    Ok(data)
+}
+
+fn write_array<T>(bytes: &mut Vec<u8>, items: &[T], write: impl Fn(&mut Vec<u8>, &T) -> std::io::Result<()>) -> Result<(), DantelionFormatsError> {
+    bytes.write_u64::<LE>(items.len() as u64)?;
+    for item in items {
+        write(bytes, item)?;
+    }
+
+    Ok(())
+}
+
+// `size` is how many bytes an item takes at least, so a bad count can't reserve more than the file holds.
+fn read_array<T>(c: &mut Cursor<&[u8]>, size: usize, read: impl Fn(&mut Cursor<&[u8]>) -> std::io::Result<T>) -> Result<Vec<T>, DantelionFormatsError> {
+    let count: usize = util::checked_cast(c.read_u64::<LE>()?, "Count")?;
+    let mut items = Vec::with_capacity(count.min(c.get_ref().len() / size));
+    for _ in 0..count {
+        items.push(read(c)?);
+    }
+
+    Ok(items)
+}
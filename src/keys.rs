@@ -0,0 +1,122 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{Error, ErrorKind};
+use std::ops::RangeInclusive;
+use crate::bhd5::GameType;
+use crate::crypto_util;
+use crate::error::DantelionFormatsError;
+
+/// A game version like "1.10.1", compared part by part, so "1.9" < "1.10". Missing parts count as 0.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
+pub struct PatchVersion(Vec<u32>);
+
+impl PatchVersion {
+    pub fn parse(version: &str) -> Option<PatchVersion> {
+        let mut parts: Vec<u32> = version.trim().split('.').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+        while parts.len() > 1 && parts.last() == Some(&0) {
+            parts.pop();
+        }
+
+        Some(PatchVersion(parts))
+    }
+}
+
+impl Display for PatchVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(u32::to_string).collect();
+        write!(f, "{}", parts.join("."))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BHD5Key {
+    pub game: GameType,
+    // Path relative to the game folder, e.g. "Data0" or "sd/sd", see `crypto_util::bhd5_key_matches`
+    pub archive: String,
+    // The patches the key is used in, None if it never changed
+    pub versions: Option<RangeInclusive<PatchVersion>>,
+    // PKCS#1 PEM
+    pub public_key: String,
+}
+
+/// BHD5 public keys by game, archive and the patches they're valid for, for games that change keys between patches.
+/// Several keys can be registered for one archive, `decrypt` tries each and keeps the one that gives a BHD5. The
+/// readers use the one `Config::key_store` builds.
+#[derive(Clone, Debug, Default)]
+pub struct KeyStore {
+    pub keys: Vec<BHD5Key>,
+}
+
+impl KeyStore {
+    pub fn new() -> KeyStore {
+        KeyStore::default()
+    }
+
+    /// The built in Elden Ring keys, which haven't changed since release.
+    pub fn elden_ring() -> KeyStore {
+        crypto_util::ELDEN_RING_KEYS.iter().fold(KeyStore::new(), |store, (archive, public_key)| {
+            store.add(GameType::EldenRing, archive, None, public_key)
+        })
+    }
+
+    /// Appends `other`'s keys, which lose ties to the ones already here.
+    pub fn extend(mut self, other: KeyStore) -> KeyStore {
+        self.keys.extend(other.keys);
+        self
+    }
+
+    pub fn add(mut self, game: GameType, archive: &str, versions: Option<RangeInclusive<PatchVersion>>, public_key: &str) -> KeyStore {
+        self.keys.push(BHD5Key {
+            game,
+            archive: archive.to_string(),
+            versions,
+            public_key: public_key.to_string(),
+        });
+        self
+    }
+
+    /// The keys that could be the one for the archive at `path`. Keys whose range holds `version` come first, then
+    /// ones without a range. With no `version`, every key for the archive is a candidate, newest range first.
+    pub fn candidates(&self, game: GameType, path: &str, version: Option<&PatchVersion>) -> Vec<&BHD5Key> {
+        let mut candidates: Vec<&BHD5Key> = self.keys.iter()
+            .filter(|key| key.game == game && crypto_util::bhd5_key_matches(path, &key.archive))
+            .filter(|key| match (&key.versions, version) {
+                (Some(versions), Some(version)) => versions.contains(version),
+                _ => true,
+            })
+            .collect();
+        // Stable, so keys registered first win ties.
+        candidates.sort_by(|a, b| match (&a.versions, &b.versions) {
+            (Some(a), Some(b)) => b.end().cmp(a.end()),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        });
+
+        candidates
+    }
+
+    /// Decrypts the BHD5 at `path` with the first candidate key that gives a BHD5 magic, returning the decrypted
+    /// header and the key that worked.
+    pub fn decrypt(&self, game: GameType, path: &str, file: &[u8], version: Option<&PatchVersion>) -> Result<(Vec<u8>, &BHD5Key), DantelionFormatsError> {
+        let mut decrypted = vec![];
+        let key = self.decrypt_into(game, path, file, version, &mut decrypted)?;
+        Ok((decrypted, key))
+    }
+
+    /// Same as `decrypt`, but decrypts into `buffer` so the scratch space can be reused across archives.
+    pub fn decrypt_into(&self, game: GameType, path: &str, file: &[u8], version: Option<&PatchVersion>, buffer: &mut Vec<u8>) -> Result<&BHD5Key, DantelionFormatsError> {
+        let candidates = self.candidates(game, path, version);
+        if candidates.is_empty() {
+            return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, format!("No BHD5 key for {}, add it to the config's bhd5_keys", path))));
+        }
+
+        for key in candidates {
+            // A wrong key decrypts to garbage rather than failing, so the magic is what tells them apart.
+            if crypto_util::decrypt_bhd5_file_into(file, key.public_key.as_bytes(), buffer).is_ok() && buffer.starts_with(b"BHD5") {
+                return Ok(key);
+            }
+        }
+
+        Err(DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, format!("No key for {:?} decrypts {}", game, path))))
+    }
+}
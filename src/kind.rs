@@ -0,0 +1,170 @@
+use std::fmt::{self, Display, Formatter};
+use byteorder::{BE, ByteOrder, LE};
+use serde::{Deserialize, Serialize};
+use crate::behbnd;
+use crate::bink::BinkHeader;
+use crate::dcx::DCX;
+use crate::lua::LuaHeader;
+use crate::mqb::MQB;
+use crate::sound::{BNK, FSB5};
+
+/// What a file is, going by its first bytes. Compressed files sniff as `DCX`, strip them with `strip_dcx` first to
+/// see what's inside.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum FileKind {
+    BND3,
+    BND4,
+    BHF3,
+    BHF4,
+    BDF3,
+    BDF4,
+    BHD5,
+    DCX,
+    DCP,
+    TPF,
+    FLVER,
+    CLM2,
+    FMG,
+    PARAM,
+    TAE,
+    MSB,
+    FXR,
+    HKX,
+    DDS,
+    BNK,
+    FSB5,
+    Bink,
+    MQB,
+    Lua,
+    EMEVD,
+    ESD,
+    DRB,
+    ENFL,
+    #[default]
+    Unknown,
+}
+
+impl FileKind {
+    // Enough for every magic and for the FMG and PARAM header checks.
+    pub const SNIFF_SIZE: usize = 0x40;
+
+    const MAGICS: &'static [(&'static [u8], FileKind)] = &[
+        (b"BND3", FileKind::BND3),
+        (b"BND4", FileKind::BND4),
+        (b"BHF3", FileKind::BHF3),
+        (b"BHF4", FileKind::BHF4),
+        (b"BDF3", FileKind::BDF3),
+        (b"BDF4", FileKind::BDF4),
+        (b"BHD5", FileKind::BHD5),
+        (b"DCP\0", FileKind::DCP),
+        (b"TPF\0", FileKind::TPF),
+        (b"FLVER\0", FileKind::FLVER),
+        (b"CLM2", FileKind::CLM2),
+        (b"TAE ", FileKind::TAE),
+        (b"MSB ", FileKind::MSB),
+        (b"FXR\0", FileKind::FXR),
+        (b"DDS ", FileKind::DDS),
+        (b"EVD\0", FileKind::EMEVD),
+        (b"fSSL", FileKind::ESD),
+        (b"fsSL", FileKind::ESD),
+        (b"DRB\0", FileKind::DRB),
+        (b"ENFL", FileKind::ENFL),
+    ];
+
+    /// Sniffs `bytes`, which only needs to be the start of the file, `SNIFF_SIZE` bytes covers every check. FMGs and
+    /// PARAMs have no magic, so they're recognized by their header layout, which is a guess rather than a match.
+    pub fn sniff(bytes: &[u8]) -> FileKind {
+        if let Some((_, kind)) = FileKind::MAGICS.iter().find(|(magic, _)| bytes.starts_with(magic)) {
+            return *kind;
+        }
+
+        if DCX::is(bytes) {
+            FileKind::DCX
+        } else if MQB::is(bytes) {
+            FileKind::MQB
+        } else if FSB5::is(bytes) {
+            FileKind::FSB5
+        } else if BNK::is(bytes) {
+            FileKind::BNK
+        } else if BinkHeader::is(bytes) {
+            FileKind::Bink
+        } else if LuaHeader::is(bytes) {
+            FileKind::Lua
+        } else if behbnd::havok_info(bytes).is_some() {
+            FileKind::HKX
+        } else if is_fmg(bytes) {
+            FileKind::FMG
+        } else if is_param(bytes) {
+            FileKind::PARAM
+        } else {
+            FileKind::Unknown
+        }
+    }
+
+    /// The usual extension for the kind, e.g. "flver". Empty for `Unknown`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileKind::BND3 | FileKind::BND4 => "bnd",
+            FileKind::BHF3 | FileKind::BHF4 => "bhd",
+            FileKind::BDF3 | FileKind::BDF4 => "bdt",
+            FileKind::BHD5 => "bhd",
+            FileKind::DCX => "dcx",
+            FileKind::DCP => "dcp",
+            FileKind::TPF => "tpf",
+            FileKind::FLVER => "flver",
+            FileKind::CLM2 => "clm2",
+            FileKind::FMG => "fmg",
+            FileKind::PARAM => "param",
+            FileKind::TAE => "tae",
+            FileKind::MSB => "msb",
+            FileKind::FXR => "fxr",
+            FileKind::HKX => "hkx",
+            FileKind::DDS => "dds",
+            FileKind::BNK => "bnk",
+            FileKind::FSB5 => "fsb",
+            FileKind::Bink => "bk2",
+            FileKind::MQB => "mqb",
+            FileKind::Lua => "lua",
+            FileKind::EMEVD => "emevd",
+            FileKind::ESD => "esd",
+            FileKind::DRB => "drb",
+            FileKind::ENFL => "entryfilelist",
+            FileKind::Unknown => "",
+        }
+    }
+}
+
+impl Display for FileKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+// 0x00 is always 0, 0x01 the big endian flag and 0x02 the version, 0 to 2. The file size at 0x04 can't be less than
+// what's there.
+fn is_fmg(bytes: &[u8]) -> bool {
+    if bytes.len() < 0x1C || bytes[0] != 0 || bytes[1] > 1 || bytes[2] > 2 || bytes[3] != 0 {
+        return false;
+    }
+
+    let file_size = if bytes[1] == 1 { BE::read_u32(&bytes[4..8]) } else { LE::read_u32(&bytes[4..8]) } as usize;
+    let group_count = if bytes[1] == 1 { BE::read_u32(&bytes[0xC..0x10]) } else { LE::read_u32(&bytes[0xC..0x10]) };
+    file_size >= 0x1C && group_count < 0x100000 && bytes.len() <= file_size
+}
+
+// The strings offset at 0x00 has to leave room for the header and one row header per row, and the big endian flag
+// at 0x2C is 0x00 or 0xFF.
+fn is_param(bytes: &[u8]) -> bool {
+    if bytes.len() < 0x30 || !matches!(bytes[0x2C], 0x00 | 0xFF) {
+        return false;
+    }
+
+    let (strings_offset, row_count) = if bytes[0x2C] == 0xFF {
+        (BE::read_u32(&bytes[0..4]), BE::read_u16(&bytes[0xA..0xC]))
+    } else {
+        (LE::read_u32(&bytes[0..4]), LE::read_u16(&bytes[0xA..0xC]))
+    };
+    // 0xC is the smallest row header, for the 32-bit formats.
+    let rows_end = 0x30 + row_count as u32 * 0xC;
+    row_count > 0 && strings_offset >= rows_end
+}
@@ -1,12 +1,69 @@
 extern crate core;
 
-mod crypto_util;
+#[cfg(feature = "crypto")]
+pub mod crypto_util;
 pub mod bhd5;
+pub mod bdt;
 pub mod dcx;
+pub mod bnd3;
 pub mod bnd4;
-mod util;
-mod oodle;
+pub mod ds1;
+pub mod tpf;
+pub mod flver;
+pub mod msb;
+pub mod mqb;
+pub mod navgraph;
+pub mod btpb;
+pub mod grass;
+pub mod clm2;
+pub mod sound;
+pub mod bink;
+pub mod lua;
+pub mod binder;
+pub mod kind;
+pub mod param;
+pub mod fmg;
+pub mod paramdef;
+pub mod save;
+#[cfg(feature = "memory")]
+pub mod memory;
+pub mod behbnd;
+pub mod manifest;
+#[cfg(feature = "crypto")]
+pub mod patch;
+#[cfg(feature = "crypto")]
+pub mod keys;
+pub mod config;
+pub mod dictionary;
+pub mod cache;
+pub mod index;
+pub mod vfs;
+pub mod graph;
+pub mod unpack;
+pub mod carve;
+pub mod package;
+pub mod source;
+pub mod spill;
+pub mod stats;
+#[cfg(feature = "watch")]
+pub mod watch;
+#[cfg(feature = "bruteforce")]
+pub mod bruteforce;
+pub mod fixtures;
+pub mod testdata;
+pub mod util;
+#[cfg(feature = "steam-discovery")]
+pub mod discovery;
+#[cfg(feature = "oodle")]
+pub mod oodle;
 pub mod error;
+pub mod prelude;
+mod parsed_file;
+#[cfg(test)]
+mod roundtrip;
+
+pub use parsed_file::{open, open_bytes, open_source, strip_dcx, ParsedFile};
+pub use error::DantelionFormatsError;
 
 
 const TEST_DECRYPT_PATH: &str = ".decrypted";
@@ -20,11 +77,17 @@ const ER_REGULATION_PATH: &str = r"G:\Steam\steamapps\common\ELDEN RING\Game\reg
 mod tests {
     use std::fs;
     use std::path::Path;
-    use crate::bhd5::{BHD5, BHD5Format};
+    use crate::bhd5::{BHD5, BHD5Format, GameType};
+    #[cfg(feature = "crypto")]
+    use crate::bhd5::BHD5ArchiveBuilder;
     use super::*;
     use crate::dcx::*;
+    use crate::bnd3::*;
     use crate::bnd4::*;
+    use crate::tpf::*;
+    use crate::binder::*;
 
+    #[cfg(feature = "crypto")]
     #[test]
     fn read_bhd5() {
         let file = fs::read(TEST_BHD5_PATH)
@@ -40,6 +103,7 @@ mod tests {
         assert_eq!(magic, "BHD5")
     }
 
+    #[cfg(feature = "crypto")]
     #[test]
     fn decrypt_regulation() {
         let file = fs::read(ER_REGULATION_PATH)
@@ -61,6 +125,7 @@ mod tests {
         assert_eq!(bnd.header.magic, "BND4");
     }
 
+    #[cfg(feature = "crypto")]
     #[test]
     fn parse_bhd5()
     {
@@ -68,6 +133,910 @@ mod tests {
         assert!(bhd5.format == BHD5Format::EldenRing);
     }
 
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn custom_bhd5_archive() {
+        let data = vec![7u8; 0x123];
+        let archive = BHD5ArchiveBuilder::new(BHD5Format::EldenRing)
+            .add_file("/parts/am_m_1600.partsbnd.dcx", data.clone())
+            .build()
+            .expect("Could not build archive!");
+
+        let bhd5 = BHD5::from_encrypted_bytes(&archive.bhd, archive.public_key.as_bytes()).expect("Could not parse BHD5!");
+        assert!(bhd5.format == BHD5Format::EldenRing);
+
+        let hash = BHD5::hash_path("/parts/am_m_1600.partsbnd.dcx", BHD5Format::EldenRing);
+        let bucket = &bhd5.buckets[(hash % bhd5.buckets.len() as u64) as usize];
+        let file_header = bucket.file_headers.iter().find(|f| f.file_path_hash == hash).expect("File not in its bucket!");
+        assert_eq!(file_header.read_data(&archive.bdt).expect("Could not read file!"), data);
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn read_fixture_files() {
+        let dir = std::env::temp_dir().join("dantelion-formats-fixtures");
+        let files = fixtures::write_fixtures(&dir.to_string_lossy()).expect("Could not write fixtures!");
+
+        let bnd4 = BND4::from_path(&files.bnd4_path).expect("Could not parse BND4!");
+        assert_eq!(bnd4.files.len(), 4);
+        assert_eq!(bnd4.files[0].name.as_deref(), Some(fixtures::sample_file_name(0).as_str()));
+
+        match open(&files.dcx_bnd4_path).expect("Could not open file!") {
+            ParsedFile::BND4(dcx_bnd4) => assert_eq!(dcx_bnd4.files[3].data, bnd4.files[3].data),
+            _ => panic!("DCX did not contain a BND4!"),
+        }
+
+        let edge = DCX::from_path(&files.edge_dcx_path).expect("Could not parse DCX!");
+        assert_eq!(edge.decompress().expect("Could not decompress EDGE DCX!"), fixtures::sample_data(0x18000));
+
+        let bhd = fs::read(&files.bhd5_path).expect("Could not read BHD5!");
+        let bhd5 = BHD5::from_encrypted_bytes(&bhd, files.bhd5_public_key.as_bytes()).expect("Could not parse BHD5!");
+        let bdt = fs::read(&files.bdt_path).expect("Could not read BDT!");
+        let hash = BHD5::hash_path(&fixtures::sample_archive_path(2), BHD5Format::EldenRing);
+        let file_header = bhd5.buckets.iter()
+            .flat_map(|bucket| &bucket.file_headers)
+            .find(|f| f.file_path_hash == hash)
+            .expect("File not in BHD5!");
+        assert_eq!(file_header.read_data(&bdt).expect("Could not read file!"), fixtures::sample_data(0x100));
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn convert_bhd5_format() {
+        let paths = ["/parts/am_m_1600.partsbnd.dcx", "/chr/c0000.chrbnd.dcx"];
+        let archive = BHD5ArchiveBuilder::new(BHD5Format::DarkSoulsIII)
+            .add_file(paths[0], vec![1; 0x20])
+            .add_file(paths[1], vec![2; 0x30])
+            .build()
+            .expect("Could not build archive!");
+
+        let bhd5 = BHD5::from_encrypted_bytes(&archive.bhd, archive.public_key.as_bytes()).expect("Could not parse BHD5!");
+        let converted = bhd5.convert(BHD5Format::EldenRing, &paths).expect("Could not convert BHD5!");
+        let bhd5 = BHD5::from_bytes(&converted.to_bytes().expect("Could not write BHD5!")).expect("Could not parse BHD5!");
+        assert!(bhd5.format == BHD5Format::EldenRing);
+
+        let hash = BHD5::hash_path(paths[1], BHD5Format::EldenRing);
+        let file_header = bhd5.buckets.iter()
+            .flat_map(|bucket| &bucket.file_headers)
+            .find(|f| f.file_path_hash == hash)
+            .expect("File not in BHD5!");
+        assert_eq!(file_header.read_data(&archive.bdt).expect("Could not read file!"), vec![2; 0x30]);
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn bhd5_edit_session() {
+        let dir = std::env::temp_dir().join("dantelion-formats-edit-session");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let bhd_path = dir.join("Data0.bhd").to_string_lossy().to_string();
+        let bdt_path = dir.join("Data0.bdt").to_string_lossy().to_string();
+        let archive = BHD5ArchiveBuilder::new(BHD5Format::EldenRing)
+            .add_file("/a.bin", vec![1; 0x20])
+            .add_file("/b.bin", vec![2; 0x20])
+            .build()
+            .expect("Could not build archive!");
+        archive.write(&bhd_path, &bdt_path).expect("Could not write archive!");
+
+        let mut session = patch::BHD5EditSession::open(&bhd_path, &bdt_path, &archive.public_key, &archive.private_key)
+            .expect("Could not open archive!");
+        session.replace_file("/a.bin", vec![3; 0x31]).replace_file("/c.bin", vec![4; 0x8]);
+        session.commit().expect("Could not commit edits!");
+
+        let bhd5 = BHD5::from_encrypted_bytes(&fs::read(&bhd_path).unwrap(), archive.public_key.as_bytes()).expect("Could not parse BHD5!");
+        let bdt = fs::read(&bdt_path).unwrap();
+        let read = |path: &str| {
+            let hash = BHD5::hash_path(path, BHD5Format::EldenRing);
+            bhd5.buckets.iter().flat_map(|bucket| &bucket.file_headers)
+                .find(|f| f.file_path_hash == hash)
+                .map(|f| f.read_data(&bdt).expect("Could not read file!"))
+        };
+        assert_eq!(read("/a.bin"), Some(vec![3; 0x31]));
+        assert_eq!(read("/b.bin"), Some(vec![2; 0x20]));
+        assert_eq!(read("/c.bin"), Some(vec![4; 0x8]));
+        assert!(!Path::new(&format!("{}.dantelion.tmp", bdt_path)).exists());
+        assert!(util::has_backup(&bhd_path) && util::has_backup(&bdt_path));
+
+        // A second commit must not replace the pristine backup.
+        let mut session = patch::BHD5EditSession::open(&bhd_path, &bdt_path, &archive.public_key, &archive.private_key)
+            .expect("Could not open archive!");
+        session.replace_file("/b.bin", vec![5; 0x10]);
+        session.commit().expect("Could not commit edits!");
+        assert_eq!(fs::read(util::backup_path(&bdt_path)).unwrap(), archive.bdt);
+
+        util::restore(&bhd_path).expect("Could not restore BHD!");
+        util::restore(&bdt_path).expect("Could not restore BDT!");
+        assert_eq!(fs::read(&bhd_path).unwrap(), archive.bhd);
+        assert_eq!(fs::read(&bdt_path).unwrap(), archive.bdt);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn splice_bdt_stream() {
+        let archive = BHD5ArchiveBuilder::new(BHD5Format::EldenRing)
+            .add_file("/a.bin", vec![1; 0x20])
+            .add_file("/b.bin", vec![2; 0x20])
+            .add_file("/c.bin", vec![3; 0x15])
+            .build()
+            .expect("Could not build archive!");
+        let bhd5 = BHD5::from_encrypted_bytes(&archive.bhd, archive.public_key.as_bytes()).expect("Could not parse BHD5!");
+
+        let mut bdt = vec![];
+        let replacements = [("/b.bin".to_string(), vec![9; 0x10]), ("/b.bin".to_string(), vec![4; 0x41]), ("/d.bin".to_string(), vec![5; 0x8])];
+        let bhd5 = patch::splice_bdt(&archive.bdt, &mut bdt, bhd5, &replacements).expect("Could not splice BDT!");
+
+        let file_header = |path: &str| {
+            let hash = BHD5::hash_path(path, BHD5Format::EldenRing);
+            bhd5.iter().find(|f| f.file_path_hash == hash).unwrap()
+        };
+        assert_eq!(bhd5.iter().count(), 4);
+        assert_eq!(file_header("/a.bin").read_data(&bdt).unwrap(), vec![1; 0x20]);
+        assert_eq!(file_header("/b.bin").read_data(&bdt).unwrap(), vec![4; 0x41]);
+        assert_eq!(file_header("/c.bin").read_data(&bdt).unwrap(), vec![3; 0x15]);
+        assert_eq!(file_header("/d.bin").read_data(&bdt).unwrap(), vec![5; 0x8]);
+        // Entries stay in order, with the replaced one resized in place and the new one last.
+        let offsets: Vec<u64> = ["/a.bin", "/b.bin", "/c.bin", "/d.bin"].iter().map(|path| file_header(path).file_offset).collect();
+        assert_eq!(offsets, [0x10, 0x30, 0x80, 0xA0]);
+        assert_eq!((&bdt[..0x10], &bdt[0x10..0x30]), (&archive.bdt[..0x10], &archive.bdt[0x10..0x30]));
+        assert_eq!(bdt.len(), 0xB0);
+    }
+
+    #[test]
+    fn bdt_writer_sparse() {
+        use std::io::Write;
+        use bdt::BdtWriter;
+
+        let dir = std::env::temp_dir().join("dantelion-formats-bdt-writer");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut expected = b"BDF307D7R6\0\0\0\0\0\0".to_vec();
+        expected.extend_from_slice(&[0; 0x3000]);
+        expected.extend_from_slice(&[1; 0x1234]);
+        expected.extend_from_slice(&[0; 0x2000]);
+
+        for (name, sparse, expected_len) in [("sparse.bdt", true, 0), ("dense.bdt", false, 0x10000)] {
+            let path = dir.join(name);
+            let mut writer = BdtWriter::create(&path, expected_len).unwrap().sparse(sparse);
+            writer.write_all(&expected[..0x10 + 0x3000 + 0x1234]).unwrap();
+            writer.write_zeros(0x2000).unwrap();
+            assert_eq!(writer.finish().unwrap(), expected.len() as u64);
+            assert_eq!(fs::read(&path).unwrap(), expected);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn package_mod_folder() {
+        use package::PackageTarget;
+
+        let dir = std::env::temp_dir().join("dantelion-formats-package");
+        let _ = fs::remove_dir_all(&dir);
+        let mod_dir = dir.join("mod");
+        fs::create_dir_all(mod_dir.join("parts")).unwrap();
+        fs::create_dir_all(mod_dir.join("param/gameparam")).unwrap();
+        fs::write(mod_dir.join("parts/am_m_1600.partsbnd.dcx"), vec![3; 0x20]).unwrap();
+        fs::write(mod_dir.join("param/gameparam/AtkParam_Npc.param"), vec![4; 0x10]).unwrap();
+
+        let out = dir.join("modengine");
+        let packaged = package::package(&mod_dir, &PackageTarget::ModEngine2 { out: out.clone() }).unwrap();
+        assert_eq!(packaged, ["/param/gameparam/AtkParam_Npc.param", "/parts/am_m_1600.partsbnd.dcx"]);
+        assert_eq!(fs::read(out.join("parts/am_m_1600.partsbnd.dcx")).unwrap(), vec![3; 0x20]);
+
+        let mut params = BND4Builder::new()
+            .add_file(0, "N:\\GR\\data\\Param\\param\\GameParam\\AtkParam_Npc.param", vec![1; 0x10])
+            .add_file(1, "N:\\GR\\data\\Param\\param\\GameParam\\AtkParam_Pc.param", vec![2; 0x10])
+            .build();
+        params.dcx = Some(dcx::DcxInfo { format: "DFLT".to_string(), compression_level: 9, layers: 1 });
+        let regulation = dir.join("regulation.bin");
+        let key = crypto_util::ER_REGULATION_KEY.to_vec();
+        let dcx = params.to_bytes(&BND4WriteOptions::default()).unwrap();
+        fs::write(&regulation, crypto_util::encrypt_regulation(&dcx, &key).unwrap()).unwrap();
+        let target = PackageTarget::Regulation { regulation: regulation.clone(), key: key.clone(), out: regulation.clone() };
+        assert_eq!(package::package(&mod_dir, &target).unwrap(), ["/param/gameparam/AtkParam_Npc.param"]);
+        let (params, _) = crypto_util::read_regulation(&fs::read(&regulation).unwrap(), &key).unwrap();
+        assert_eq!(params.get("AtkParam_Npc.param").unwrap().data, Some(vec![4; 0x10]));
+        assert_eq!(params.get("AtkParam_Pc.param").unwrap().data, Some(vec![2; 0x10]));
+
+        let bhd_path = dir.join("Data0.bhd");
+        let bdt_path = dir.join("Data0.bdt");
+        let archive = BHD5ArchiveBuilder::new(BHD5Format::EldenRing)
+            .add_file("/parts/am_m_1600.partsbnd.dcx", vec![1; 0x20])
+            .build()
+            .unwrap();
+        archive.write(&bhd_path.to_string_lossy(), &bdt_path.to_string_lossy()).unwrap();
+        let target = PackageTarget::Archive {
+            bhd_path: bhd_path.clone(),
+            bdt_path: bdt_path.clone(),
+            public_key: archive.public_key.clone(),
+            private_key: archive.private_key.clone(),
+        };
+        assert_eq!(package::package(&mod_dir, &target).unwrap(), ["/parts/am_m_1600.partsbnd.dcx"]);
+        let bhd5 = BHD5::from_encrypted_bytes(&fs::read(&bhd_path).unwrap(), archive.public_key.as_bytes()).unwrap();
+        let hash = BHD5::hash_path("/parts/am_m_1600.partsbnd.dcx", BHD5Format::EldenRing);
+        let file_header = bhd5.iter().find(|f| f.file_path_hash == hash).unwrap();
+        assert_eq!(file_header.read_data(&fs::read(&bdt_path).unwrap()).unwrap(), vec![3; 0x20]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mod_engine_config() {
+        use package::{ModEngineConfig, ModEngineIssue, PackageTarget};
+
+        let dir = std::env::temp_dir().join("dantelion-formats-modengine");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("loose/parts")).unwrap();
+        fs::write(dir.join("loose/parts/am_m_1600.partsbnd.dcx"), vec![3; 0x20]).unwrap();
+        let config_path = dir.join("config_eldenring.toml");
+        fs::write(&config_path, r#"
+[modengine]
+debug = false
+external_dlls = ["missing.dll"]
+
+[extension.mod_loader]
+enabled = true
+loose_params = false
+mods = [
+    { enabled = true, name = "default", path = "mod" },
+]
+
+[extension.scylla_hide]
+enabled = false
+"#).unwrap();
+
+        package::package(&dir.join("loose"), &PackageTarget::ModEngine2 { out: dir.join("mymod") }).unwrap();
+        let issues = package::register_mod_engine(&config_path, "mymod", &dir.join("mymod")).unwrap();
+        assert_eq!(issues, [
+            ModEngineIssue::MissingModFolder { name: "default".to_string(), path: dir.join("mod") },
+            ModEngineIssue::MissingDll(dir.join("missing.dll")),
+        ]);
+
+        let config = ModEngineConfig::from_path(&config_path).unwrap();
+        let mods: Vec<(&str, &str)> = config.extension.mod_loader.mods.iter().map(|entry| (entry.name.as_str(), entry.path.as_str())).collect();
+        assert_eq!(mods, [("default", "mod"), ("mymod", "mymod")]);
+        assert!(config.extension.other.contains_key("scylla_hide"));
+        assert_eq!(ModEngineConfig::parse(&config.to_toml().unwrap()).unwrap(), config);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn patch_regulation_rows() {
+        use param::{ParamRows, RowEdit};
+
+        // ER style: long data offsets and an offset param type, so a 0x40 header and 0x18 byte row headers.
+        let mut atk_param = vec![0; 0x40];
+        atk_param[0xA..0xC].copy_from_slice(&2u16.to_le_bytes());
+        atk_param[0x2D] = 0x85;
+        for (id, data_offset) in [(100i32, 0x70u64), (200, 0x78)] {
+            atk_param.extend_from_slice(&id.to_le_bytes());
+            atk_param.extend_from_slice(&[0; 4]);
+            atk_param.extend_from_slice(&data_offset.to_le_bytes());
+            atk_param.extend_from_slice(&[0; 8]);
+        }
+        atk_param.extend_from_slice(&[1; 8]);
+        atk_param.extend_from_slice(&[2; 8]);
+        let strings_offset = atk_param.len() as u32;
+        atk_param[0..4].copy_from_slice(&strings_offset.to_le_bytes());
+        atk_param.extend_from_slice(b"ATK_PARAM_ST\0");
+
+        let rows = ParamRows::from_bytes(&atk_param).unwrap();
+        assert_eq!((rows.rows.as_slice(), rows.row_size), ([(100, 0x70), (200, 0x78)].as_slice(), 8));
+
+        let mut params = BND4Builder::new()
+            .add_file(0, "N:\\GR\\data\\Param\\param\\GameParam\\AtkParam_Npc.param", atk_param.clone())
+            .add_file(1, "N:\\GR\\data\\Param\\param\\GameParam\\AtkParam_Pc.param", vec![5; 0x10])
+            .build();
+        params.dcx = Some(dcx::DcxInfo { format: "DFLT".to_string(), compression_level: 9, layers: 1 });
+        let key = crypto_util::ER_REGULATION_KEY;
+        let regulation = crypto_util::encrypt_regulation(&params.to_bytes(&BND4WriteOptions::default()).unwrap(), &key).unwrap();
+
+        let edit = |row, offset, data: &[u8]| RowEdit { param: "AtkParam_Npc.param".to_string(), row, offset, data: data.to_vec() };
+        let patched = param::patch_regulation(&regulation, &key, &[edit(200, 4, &[9, 9])]).unwrap();
+        let (params, _) = crypto_util::read_regulation(&patched, &key).unwrap();
+        let npc = params.get("AtkParam_Npc.param").unwrap().data.as_ref().unwrap();
+        assert_eq!(&npc[0x70..0x80], &[1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 9, 9, 2, 2]);
+        assert_eq!(params.get("AtkParam_Pc.param").unwrap().data, Some(vec![5; 0x10]));
+
+        assert!(param::patch_regulation(&regulation, &key, &[edit(300, 0, &[0])]).is_err());
+        assert!(param::patch_regulation(&regulation, &key, &[edit(100, 7, &[0, 0])]).is_err());
+    }
+
+    #[test]
+    fn param_field_meta() {
+        use param::ParamMeta;
+
+        let mut meta = ParamMeta::from_paramdef_xml(r#"<?xml version="1.0" encoding="utf-8"?>
+<PARAMDEF XmlVersion="3">
+  <ParamType>ATK_PARAM_ST</ParamType>
+  <Fields>
+    <Field Def="f32 hit0_Radius">
+      <DisplayName>Hit 0 Radius</DisplayName>
+      <Minimum>0</Minimum>
+      <Maximum>99.99</Maximum>
+    </Field>
+    <Field Def="u8 atkType:4 = 0">
+      <DisplayName>atkType</DisplayName>
+    </Field>
+  </Fields>
+</PARAMDEF>"#).unwrap();
+        meta.merge(ParamMeta::from_meta_xml(r#"<?xml version="1.0" encoding="utf-8"?>
+<PARAMMETA XmlVersion="0">
+  <Enums>
+    <Enum Name="ATK_TYPE" type="u8">
+      <Option Value="0" Name="Normal" />
+      <Option Value="1" Name="Slash" />
+    </Enum>
+  </Enums>
+  <Field>
+    <atkType AltName="Attack Type" Enum="ATK_TYPE" />
+  </Field>
+</PARAMMETA>"#).unwrap());
+
+        let atk_type = meta.field("atkType").unwrap();
+        assert_eq!((atk_type.display_name.as_deref(), atk_type.enum_name.as_deref()), (Some("Attack Type"), Some("ATK_TYPE")));
+        assert_eq!(meta.value_name("atkType", 1), Some("Slash"));
+        assert_eq!(meta.value_name("atkType", 7), None);
+        assert_eq!(meta.field("hit0_Radius").unwrap().display_name.as_deref(), Some("Hit 0 Radius"));
+        assert!(meta.in_range("hit0_Radius", 2.5) && !meta.in_range("hit0_Radius", -1.0));
+        assert!(meta.in_range("unknownField", -1.0));
+    }
+
+    #[test]
+    fn param_row_names() {
+        use param::RowNames;
+
+        let dir = std::env::temp_dir().join("dantelion-formats-row-names");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("AtkParam_Npc.txt"), "\u{feff}100 Godrick - Slash\r\n101 \r\n100 Duplicate\r\n").unwrap();
+        fs::write(dir.join("readme.md"), "not names").unwrap();
+
+        let mut names = RowNames::from_dir(&dir).unwrap();
+        let regulation_name = "N:\\GR\\data\\Param\\param\\GameParam\\AtkParam_Npc.param";
+        assert_eq!(names.get(regulation_name, 100), Some("Godrick - Slash"));
+        assert_eq!(names.get("atkparam_npc", 101), None);
+        assert_eq!(names.rows("AtkParam_Npc").unwrap().len(), 3);
+
+        names.set(regulation_name, 101, "Godrick - Stomp");
+        names.set("EquipParamWeapon", 1000000, "Dagger");
+        names.write_dir(&dir).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("AtkParam_Npc.txt")).unwrap(), "100 Godrick - Slash\n101 Godrick - Stomp\n100 Duplicate\n");
+        assert_eq!(RowNames::from_dir(&dir).unwrap(), names);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn paramdef_codegen() {
+        use paramdef::Paramdef;
+
+        let paramdef = Paramdef::from_xml(r#"<?xml version="1.0" encoding="utf-8"?>
+<PARAMDEF XmlVersion="3">
+  <ParamType>ATK_PARAM_ST</ParamType>
+  <Fields>
+    <Field Def="f32 hit0_Radius" />
+    <Field Def="u8 atkType:4 = 0" />
+    <Field Def="u8 isDisable:1" />
+    <Field Def="dummy8 pad0:3" />
+    <Field Def="u16 atkPhys" />
+    <Field Def="s32 spEffectId[2]" />
+    <Field Def="u8 oldField" RemovedRegVersion="10000" />
+    <Field Def="b32 type" />
+    <Field Def="fixstr name[3]" />
+    <Field Def="dummy8 endPad[1]" />
+  </Fields>
+</PARAMDEF>"#).unwrap();
+
+        // (name, offset, bitfield)
+        type FieldLayout<'a> = (&'a str, usize, Option<(u32, u32)>);
+        let layout: Vec<FieldLayout> = paramdef.fields.iter().map(|field| (field.name.as_str(), field.offset, field.bits)).collect();
+        assert_eq!(layout, [
+            ("hit0_Radius", 0x0, None),
+            ("atkType", 0x4, Some((0, 4))),
+            ("isDisable", 0x4, Some((4, 1))),
+            ("pad0", 0x4, Some((5, 3))),
+            ("atkPhys", 0x5, None),
+            ("spEffectId", 0x7, None),
+            ("type", 0xF, None),
+            ("name", 0x13, None),
+            ("endPad", 0x16, None),
+        ]);
+        assert_eq!((paramdef.struct_name().as_str(), paramdef.row_size), ("AtkParamSt", 0x17));
+
+        let source = paramdef.to_rust();
+        assert!(source.contains("pub struct AtkParamSt {"));
+        assert!(source.contains("    pub hit0_radius: f32,\n    pub atk_type: u8,\n    pub is_disable: u8,\n    pub atk_phys: u16,"));
+        assert!(source.contains("pub sp_effect_id: [i32; 2],"));
+        assert!(source.contains("pub r#type: bool,"));
+        assert!(source.contains("is_disable: (row[0x4] >> 4) & 0x1,"));
+        assert!(source.contains("row[0x4] = (row[0x4] & !(0x1 << 4)) | (self.is_disable & 0x1) << 4;"));
+        assert!(!source.contains("pad0") && !source.contains("old_field"));
+    }
+
+    #[test]
+    fn elden_ring_save() {
+        use save::SaveFile;
+
+        let old_id = 76561198000000001u64.to_le_bytes();
+        let mut builder = BND4Builder::new();
+        for slot in 0..10 {
+            let mut data = vec![0; 0x110];
+            data[0x80..0x88].copy_from_slice(&old_id);
+            builder = builder.add_file(slot, &format!("USER_DATA{:03}", slot), data);
+        }
+        let mut menu_data = vec![0; 0x60010];
+        menu_data[0x14..0x1C].copy_from_slice(&old_id);
+        menu_data[0x10 + 0x1954] = 1;
+        let summary = 0x10 + 0x195E;
+        for (i, c) in "Tarnished".encode_utf16().enumerate() {
+            menu_data[summary + i * 2..summary + i * 2 + 2].copy_from_slice(&c.to_le_bytes());
+        }
+        menu_data[summary + 0x22..summary + 0x26].copy_from_slice(&150u32.to_le_bytes());
+        menu_data[summary + 0x26..summary + 0x2A].copy_from_slice(&3600u32.to_le_bytes());
+        let mut save = SaveFile { game: GameType::EldenRing, bnd4: builder.add_file(10, "USER_DATA010", menu_data).build() };
+        assert_eq!(save.bad_checksums().len(), 11);
+        save.fix_checksums();
+
+        let mut save = SaveFile::from_bytes(GameType::EldenRing, &save.to_bytes().unwrap()).unwrap();
+        assert!(save.bad_checksums().is_empty());
+        assert_eq!(save.steam_id().unwrap(), 76561198000000001);
+        let characters = save.characters().unwrap();
+        assert_eq!((characters[0].active, characters[0].name.as_str(), characters[0].level), (true, "Tarnished", 150));
+        assert_eq!(characters[0].playtime, std::time::Duration::from_secs(3600));
+        assert!(!characters[1].active && characters[1].name.is_empty());
+
+        assert_eq!(save.set_steam_id(76561198000000002).unwrap(), 11);
+        assert_eq!(save.steam_id().unwrap(), 76561198000000002);
+        assert_eq!(&save.slot(3).unwrap()[0x70..0x78], &76561198000000002u64.to_le_bytes());
+        assert!(save.bad_checksums().is_empty());
+        assert!(save.slot(10).is_err());
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn ds3_and_sekiro_saves() {
+        use save::SaveFile;
+
+        let old_id = 76561198000000001u64;
+        for game in [GameType::DarkSoulsIII, GameType::Sekiro] {
+            let mut builder = BND4Builder::new();
+            for slot in 0..11 {
+                builder = builder.add_file(slot, &format!("USER_DATA{:03}", slot), vec![0; 0x10]);
+            }
+            let mut save = SaveFile { game, bnd4: builder.build() };
+            for slot in 0..11 {
+                let mut data = vec![slot as u8; 0x40];
+                data[0x20..0x28].copy_from_slice(&old_id.to_le_bytes());
+                save.set_entry(&format!("USER_DATA{:03}", slot), &data).unwrap();
+            }
+
+            let mut save = SaveFile::from_bytes(game, &save.to_bytes().unwrap()).unwrap();
+            assert!(save.bad_checksums().is_empty());
+            assert_eq!(save.slot(2).unwrap()[..0x20], [2; 0x20]);
+            // DS3 entries are an IV and encrypted data, so the raw entry isn't the slot.
+            let raw = &save.bnd4.get("USER_DATA002").unwrap().data.as_ref().unwrap()[0x10..];
+            assert_eq!(raw == save.slot(2).unwrap().as_slice(), game == GameType::Sekiro);
+
+            // The menu data isn't mapped, so the id is found by its shape and the summaries aren't available
+            assert_eq!(save.steam_id().unwrap(), old_id);
+            assert!(save.characters().is_err());
+            assert_eq!(save.set_steam_id(76561198000000002).unwrap(), 11);
+            assert_eq!(save.slot(9).unwrap()[0x20..0x28], 76561198000000002u64.to_le_bytes());
+            assert_eq!(save.steam_id().unwrap(), 76561198000000002);
+            assert!(save.bad_checksums().is_empty());
+
+            save.set_entry("USER_DATA010", &[0; 0x40]).unwrap();
+            assert!(save.steam_id().is_err());
+        }
+        assert!(SaveFile::from_bytes(GameType::DarkSouls, &[]).is_err());
+    }
+
+    #[cfg(feature = "memory")]
+    #[test]
+    fn memory_param_patching() {
+        use std::cell::RefCell;
+        use memory::{ParamRepository, ParamRepositoryLayout, ProcessMemory};
+        use param::RowEdit;
+
+        struct FakeProcess(RefCell<Vec<u8>>);
+        impl ProcessMemory for FakeProcess {
+            fn read(&self, address: u64, buf: &mut [u8]) -> Result<(), DantelionFormatsError> {
+                let memory = self.0.borrow();
+                let bytes = memory.get(address as usize..address as usize + buf.len()).ok_or(DantelionFormatsError::IoError(std::io::ErrorKind::UnexpectedEof.into()))?;
+                buf.copy_from_slice(bytes);
+                Ok(())
+            }
+            fn write(&self, address: u64, data: &[u8]) -> Result<(), DantelionFormatsError> {
+                self.0.borrow_mut()[address as usize..address as usize + data.len()].copy_from_slice(data);
+                Ok(())
+            }
+            fn main_module(&self) -> Result<(u64, usize), DantelionFormatsError> {
+                Ok((0, 0x100))
+            }
+        }
+
+        // The module at 0, its mov pointing at 0x80, which holds the repository at 0x100. Its one entry is a resource
+        // at 0x400, named by a heap wstring at 0x500, with the PARAM at 0x700 through 0x600.
+        let mut memory = vec![0; 0x800];
+        let put = |memory: &mut Vec<u8>, address: usize, bytes: &[u8]| memory[address..address + bytes.len()].copy_from_slice(bytes);
+        let signature = memory::parse_signature(ParamRepositoryLayout::ELDEN_RING.signature).unwrap();
+        put(&mut memory, 0x10, &signature.iter().map(|byte| byte.unwrap_or(0)).collect::<Vec<u8>>());
+        put(&mut memory, 0x13, &(0x80 - 0x17i32).to_le_bytes());
+        put(&mut memory, 0x80, &0x100u64.to_le_bytes());
+        put(&mut memory, 0x188, &0x400u64.to_le_bytes());
+        put(&mut memory, 0x418, &0x500u64.to_le_bytes());
+        put(&mut memory, 0x428, &12u64.to_le_bytes());
+        put(&mut memory, 0x430, &15u64.to_le_bytes());
+        put(&mut memory, 0x500, &"AtkParam_Npc".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<u8>>());
+        put(&mut memory, 0x480, &0x600u64.to_le_bytes());
+        put(&mut memory, 0x680, &0x700u64.to_le_bytes());
+        let mut atk_param = vec![0; 0x40];
+        atk_param[0xA..0xC].copy_from_slice(&2u16.to_le_bytes());
+        atk_param[0x2D] = 0x85;
+        for (id, data_offset) in [(100i32, 0x70u64), (200, 0x78)] {
+            atk_param.extend_from_slice(&id.to_le_bytes());
+            atk_param.extend_from_slice(&[0; 4]);
+            atk_param.extend_from_slice(&data_offset.to_le_bytes());
+            atk_param.extend_from_slice(&[0; 8]);
+        }
+        atk_param.extend_from_slice(&[1; 8]);
+        atk_param.extend_from_slice(&[2; 8]);
+        atk_param[0..4].copy_from_slice(&0x80u32.to_le_bytes());
+        put(&mut memory, 0x700, &atk_param);
+
+        let process = FakeProcess(RefCell::new(memory));
+        let repository = ParamRepository::find(&process, &ParamRepositoryLayout::ELDEN_RING).unwrap();
+        assert_eq!(repository.address, 0x100);
+        assert_eq!(repository.params.len(), 1);
+        let npc = repository.param("N:\\GR\\data\\Param\\param\\GameParam\\AtkParam_Npc.param").unwrap();
+        assert_eq!((npc.name.as_str(), npc.address), ("AtkParam_Npc", 0x700));
+        assert_eq!(repository.read_row(npc, 100).unwrap(), vec![1; 8]);
+
+        let edit = |row, offset, data: &[u8]| RowEdit { param: "AtkParam_Npc".to_string(), row, offset, data: data.to_vec() };
+        repository.apply_row_edits(&[edit(200, 4, &[9, 9])]).unwrap();
+        assert_eq!(repository.read_row(npc, 200).unwrap(), vec![2, 2, 2, 2, 9, 9, 2, 2]);
+        repository.write_row(npc, 100, &[3; 8]).unwrap();
+        assert_eq!(&process.0.borrow()[0x770..0x778], &[3; 8]);
+
+        assert!(repository.apply_row_edits(&[edit(200, 0, &[0]), edit(300, 0, &[0])]).is_err());
+        assert_eq!(repository.read_row(npc, 200).unwrap()[0], 2);
+        assert!(repository.write_row(npc, 100, &[0; 9]).is_err());
+    }
+
+    #[test]
+    fn carve_memory_dump() {
+        use carve::Carved;
+        use kind::FileKind;
+
+        let big_endian = BND4Builder::new().big_endian(true).add_file(0, "a.bin", vec![7; 0x20]).build()
+            .to_bytes(&BND4WriteOptions::default().big_endian(true)).unwrap();
+        let mut dump = vec![0xCC; 0x33];
+        dump.extend_from_slice(testdata::BND4_BYTES);
+        // Magics that don't start a real header
+        dump.extend_from_slice(b"BND4\0\0\0\0DCX\0\x00\x01\x00\x00");
+        dump.extend_from_slice(&[0; 0x80]);
+        dump.extend_from_slice(testdata::DFLT_DCX_BYTES);
+        dump.extend_from_slice(&big_endian);
+        let edge = DCX::compress_edge(&[3; 0x11000]).to_bytes().unwrap();
+        dump.extend_from_slice(&edge);
+        dump.extend_from_slice(&[0xCC; 7]);
+
+        let bnd4_at = 0x33;
+        let dcx_at = bnd4_at + testdata::BND4_BYTES.len() + 0x90;
+        let found = carve::carve(&dump);
+        assert_eq!(found, vec![
+            Carved { offset: bnd4_at, len: testdata::BND4_BYTES.len(), kind: FileKind::BND4 },
+            Carved { offset: dcx_at, len: testdata::DFLT_DCX_BYTES.len(), kind: FileKind::DCX },
+            Carved { offset: dcx_at + testdata::DFLT_DCX_BYTES.len(), len: big_endian.len(), kind: FileKind::BND4 },
+            Carved { offset: dcx_at + testdata::DFLT_DCX_BYTES.len() + big_endian.len(), len: edge.len(), kind: FileKind::DCX },
+        ]);
+        assert_eq!(found[1].bytes(&dump), testdata::DFLT_DCX_BYTES);
+        assert_eq!(BND4::from_bytes(found[2].bytes(&dump)).unwrap().files[0].data, Some(vec![7; 0x20]));
+
+        let out = std::env::temp_dir().join("dantelion_carve_test");
+        let _ = std::fs::remove_dir_all(&out);
+        let paths = carve::carve_to_dir(&dump, &out).unwrap();
+        assert_eq!(paths[0], out.join("00000033.bnd4"));
+        assert_eq!(std::fs::read(&paths[1]).unwrap(), testdata::DFLT_DCX_BYTES);
+        std::fs::remove_dir_all(&out).unwrap();
+    }
+
+    #[cfg(feature = "steam-discovery")]
+    #[test]
+    fn running_game_check() {
+        assert_eq!(GameType::DemonSouls.exe_name(), None);
+        assert!(!discovery::is_game_running(GameType::DemonSouls));
+        assert!(BHD5Format::DarkSoulsIII.games().contains(&GameType::Sekiro));
+        for &game in BHD5Format::EldenRing.games() {
+            assert_eq!(game.exe_name(), Some("eldenring.exe"));
+        }
+    }
+
+    #[cfg(feature = "steam-discovery")]
+    #[test]
+    fn locate_steam_game() {
+        let dir = std::env::temp_dir().join("dantelion-formats-locator");
+        let _ = fs::remove_dir_all(&dir);
+        let steam = dir.join("Steam");
+        let library = dir.join("Library");
+        fs::create_dir_all(steam.join("steamapps")).unwrap();
+        fs::create_dir_all(library.join("steamapps").join("common").join("ELDEN RING").join("Game")).unwrap();
+        let library_path = library.to_string_lossy().replace('\\', "\\\\");
+        fs::write(steam.join("steamapps").join("libraryfolders.vdf"), format!(
+            "\"libraryfolders\"\n{{\n\t\"0\"\n\t{{\n\t\t\"path\"\t\t\"{}\"\n\t}}\n}}\n", library_path)).unwrap();
+        fs::write(library.join("steamapps").join("appmanifest_1245620.acf"),
+            "\"AppState\"\n{\n\t\"appid\"\t\t\"1245620\"\n\t\"installdir\"\t\t\"ELDEN RING\"\n}\n").unwrap();
+
+        let mut locator = discovery::GameLocator::with_steam_path(&steam.to_string_lossy());
+        assert_eq!(locator.library_folders(), vec![steam.clone(), library.clone()]);
+        assert_eq!(locator.game_dir(GameType::EldenRing), Some(library.join("steamapps").join("common").join("ELDEN RING").join("Game")));
+        assert_eq!(locator.install_dir(GameType::Sekiro), None);
+
+        let epic = dir.join("Epic");
+        let sekiro = dir.join("Sekiro");
+        fs::create_dir_all(&epic).unwrap();
+        fs::create_dir_all(&sekiro).unwrap();
+        let item = serde_json::json!({ "DisplayName": "Sekiro™: Shadows Die Twice", "InstallLocation": sekiro });
+        fs::write(epic.join("0123456789ABCDEF.item"), item.to_string()).unwrap();
+        locator.epic_manifests_path = Some(epic);
+        assert_eq!(locator.install_dir(GameType::Sekiro), Some(sekiro));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "steam-discovery")]
+    #[test]
+    fn config_overrides() {
+        let _env = ENV_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = std::env::temp_dir().join("dantelion-formats-config");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("dantelion.json").to_string_lossy().to_string();
+        fs::write(&config_path, r#"{ "steam_path": "/nowhere/Steam", "game_dirs": { "Sekiro": "/games/Sekiro" } }"#).unwrap();
+
+        let config = config::Config::from_path(&config_path).expect("Could not read config!")
+            .with_game_dir(GameType::EldenRing, "/games/ELDEN RING/Game");
+        let locator = config.locator();
+        assert_eq!(locator.steam_path, Some(std::path::PathBuf::from("/nowhere/Steam")));
+        assert_eq!(locator.game_dir(GameType::Sekiro), Some(std::path::PathBuf::from("/games/Sekiro")));
+        assert_eq!(locator.game_dir(GameType::EldenRing), Some(std::path::PathBuf::from("/games/ELDEN RING/Game")));
+        assert_eq!(locator.game_dir(GameType::DarkSoulsIII), None);
+
+        fs::write(&config_path, config.to_json().unwrap()).unwrap();
+        assert_eq!(config::Config::from_path(&config_path).unwrap().game_dirs.len(), 2);
+
+        // A config that can't be read is an error rather than no config at all
+        fs::write(&config_path, "{ not json").unwrap();
+        std::env::set_var(config::CONFIG_PATH_ENV_VAR, &config_path);
+        let located = discovery::GameLocator::new();
+        std::env::remove_var(config::CONFIG_PATH_ENV_VAR);
+        assert!(located.is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "bruteforce")]
+    #[test]
+    fn brute_force_hashes() {
+        let targets = ["/parts/am_m_1600.partsbnd.dcx", "/parts/wp_f_0042.partsbnd.dcx"]
+            .map(|path| BHD5::hash_path(path, BHD5Format::EldenRing));
+        let forcer = bruteforce::HashBruteForcer::new(BHD5Format::EldenRing, targets);
+        let words = ["am", "wp", "m", "f"].map(String::from);
+
+        let found = forcer.search_pattern("/parts/{word}_{word}_{0000-9999}.partsbnd.dcx", &words)
+            .expect("Could not search pattern!");
+        assert_eq!(found.get(&targets[0]).map(String::as_str), Some("/parts/am_m_1600.partsbnd.dcx"));
+        assert_eq!(found.get(&targets[1]).map(String::as_str), Some("/parts/wp_f_0042.partsbnd.dcx"));
+
+        let found = forcer.search_wordlist(&["/parts/wp_f_0042.partsbnd.dcx".to_string(), "/nope".to_string()]);
+        assert_eq!(found.len(), 1);
+        assert!(forcer.search_pattern("/parts/{bad}", &words).is_err());
+
+        let forcer = bruteforce::HashBruteForcer::new(BHD5Format::EldenRing, targets).with_threads(2).unwrap();
+        let found = forcer.search_pattern("/parts/{word}_{word}_{0000-0999}.partsbnd.dcx", &words).unwrap();
+        assert_eq!(found.len(), 1);
+    }
+
+    #[cfg(feature = "bruteforce")]
+    #[test]
+    fn brute_force_thread_pools() {
+        use std::sync::Arc;
+
+        let targets = ["/parts/am_m_1600.partsbnd.dcx", "/parts/wp_f_0042.partsbnd.dcx"]
+            .map(|path| BHD5::hash_path(path, BHD5Format::EldenRing));
+        let words = ["am", "wp", "m", "f"].map(String::from);
+        let pattern = "/parts/{word}_{word}_{0000-1999}.partsbnd.dcx";
+        let expected = bruteforce::HashBruteForcer::new(BHD5Format::EldenRing, targets).search_pattern(pattern, &words).unwrap();
+        assert_eq!(expected.len(), 2);
+
+        // A single thread finds the same names as every core
+        let forcer = bruteforce::HashBruteForcer::new(BHD5Format::EldenRing, targets).with_threads(1).unwrap();
+        assert_eq!(forcer.search_pattern(pattern, &words).unwrap(), expected);
+
+        // A pool the application also uses, including searching from one of its own threads
+        let pool = Arc::new(rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap());
+        let forcer = bruteforce::HashBruteForcer::new(BHD5Format::EldenRing, targets).with_thread_pool(pool.clone());
+        assert_eq!(forcer.search_pattern(pattern, &words).unwrap(), expected);
+        assert_eq!(pool.install(|| forcer.search_pattern(pattern, &words)).unwrap(), expected);
+        let candidates = ["/parts/am_m_1600.partsbnd.dcx".to_string(), "/nope".to_string()];
+        assert_eq!(pool.install(|| forcer.search_wordlist(&candidates)).len(), 1);
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn discover_dictionary_entries() {
+        let dir = std::env::temp_dir().join("dantelion-formats-dictionary");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("chr")).unwrap();
+        let archive = BHD5ArchiveBuilder::new(BHD5Format::EldenRing)
+            .add_file("/chr/c2010.anibnd.dcx", vec![1; 0x10])
+            .add_file("/chr/c2010.behbnd.dcx", vec![2; 0x10])
+            .build()
+            .expect("Could not build archive!");
+        let bhd5 = BHD5::from_encrypted_bytes(&archive.bhd, archive.public_key.as_bytes()).expect("Could not parse BHD5!");
+
+        let bnd4 = BND4Builder::for_game(GameType::EldenRing)
+            .add_file(200, r"N:\GR\data\INTERROOT_win64\chr\c2010\c2010.anibnd", vec![3; 0x10])
+            .add_file(300, r"N:\GR\data\INTERROOT_win64\chr\c2010\c2010.behbnd", vec![4; 0x10])
+            .build();
+        fs::write(dir.join("chr").join("c2010.chrbnd"), bnd4.to_bytes(&BND4WriteOptions::for_game(GameType::EldenRing)).unwrap()).unwrap();
+
+        let known = std::collections::HashSet::from([BHD5::hash_path("/chr/c2010.behbnd.dcx", BHD5Format::EldenRing)]);
+        let entries = dictionary::discover_entries(&dir.to_string_lossy(), &bhd5, &known).expect("Could not discover entries!");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, "/chr/c2010.anibnd.dcx");
+        assert_eq!(entries[0].hash, BHD5::hash_path("/chr/c2010.anibnd.dcx", BHD5Format::EldenRing));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dictionary_collisions() {
+        use dictionary::{Dictionary, DictionaryIssue, HashWidth};
+
+        // "/aa" and "/b<" collide in the 32-bit hash, "/ba" and "/aæ" in the 64-bit one.
+        let dictionary = Dictionary::parse("# Data0\n/aa\n/b<\n/ba\n/aa\n\\AA\n", HashWidth::Bits32);
+        assert_eq!(dictionary.len(), 2);
+        assert_eq!(dictionary.get(HashWidth::Bits32.hash("/b<")), Some("/aa"));
+        assert_eq!(dictionary.issues, vec![
+            DictionaryIssue::Collision { hash: HashWidth::Bits32.hash("/aa"), kept: "/aa".to_string(), other: "/b<".to_string() },
+            DictionaryIssue::Ambiguous { hash: HashWidth::Bits32.hash("/aa"), kept: "/aa".to_string(), other: "\\AA".to_string() },
+        ]);
+        assert_eq!(dictionary.to_paths(), vec!["/aa", "/ba"]);
+
+        let mut dictionary = Dictionary::for_game(GameType::EldenRing);
+        assert_eq!(dictionary.width, HashWidth::for_format(BHD5Format::EldenRing));
+        assert!(dictionary.insert("/ba") && !dictionary.insert("/aæ") && dictionary.insert("/b<"));
+        assert_eq!(dictionary.get(BHD5::hash_path("/aa", BHD5Format::EldenRing)), None);
+        assert_eq!(dictionary.get(BHD5::hash_path("/ba", BHD5Format::EldenRing)), Some("/ba"));
+        assert!(matches!(&dictionary.issues[..], [DictionaryIssue::Collision { other, .. }] if other == "/aæ"));
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn extraction_cache() {
+        let dir = std::env::temp_dir().join("dantelion-formats-cache");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let bhd_path = dir.join("Data0.bhd").to_string_lossy().to_string();
+        let bdt_path = dir.join("Data0.bdt").to_string_lossy().to_string();
+        let archive = BHD5ArchiveBuilder::new(BHD5Format::EldenRing)
+            .add_file("/a.bin.dcx", fixtures::dflt_dcx_bytes(0x400).unwrap())
+            .add_file("/b.bin", vec![2; 0x400])
+            .build()
+            .expect("Could not build archive!");
+        archive.write(&bhd_path, &bdt_path).expect("Could not write archive!");
+        let bhd5 = BHD5::from_encrypted_bytes(&archive.bhd, archive.public_key.as_bytes()).expect("Could not parse BHD5!");
+        let file_header = |path: &str| {
+            let hash = BHD5::hash_path(path, BHD5Format::EldenRing);
+            bhd5.buckets.iter().flat_map(|bucket| &bucket.file_headers).find(|f| f.file_path_hash == hash).unwrap()
+        };
+
+        let cache = cache::ExtractionCache::new(&dir.join("cache").to_string_lossy()).unwrap().with_max_size(0x600);
+        let a = file_header("/a.bin.dcx");
+        assert_eq!(cache.read(&bdt_path, a).unwrap(), fixtures::sample_data(0x400));
+        assert!(cache.get(&bdt_path, a.file_path_hash, a.file_size).unwrap().is_some());
+
+        // Over the limit, so the least recently used entry goes.
+        cache.read(&bdt_path, file_header("/b.bin")).unwrap();
+        assert_eq!(cache.size().unwrap(), 0x400);
+        assert!(cache.get(&bdt_path, a.file_path_hash, a.file_size).unwrap().is_none());
+
+        // Touching the BDT drops everything cached from it.
+        let bdt = fs::File::options().write(true).open(&bdt_path).unwrap();
+        bdt.set_modified(std::time::SystemTime::UNIX_EPOCH).unwrap();
+        assert_eq!(cache.size().unwrap(), 0x400);
+        let b = file_header("/b.bin");
+        assert!(cache.get(&bdt_path, b.file_path_hash, b.file_size).unwrap().is_none());
+
+        // Archives that leave file_size at 0 are keyed by the padded size, and the total follows along
+        let padded_only = crate::bhd5::FileHeader { file_size: 0, salted_hash: None, aes_key: None, ..*b };
+        let data = cache.read(&bdt_path, &padded_only).unwrap();
+        assert!(cache.get(&bdt_path, b.file_path_hash, b.padded_file_size as u64).unwrap().is_some());
+        assert!(cache.get(&bdt_path, b.file_path_hash, 0).unwrap().is_none());
+        assert_eq!(cache.size().unwrap(), data.len() as u64);
+        cache.clear().unwrap();
+        assert_eq!(cache.size().unwrap(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn watch_mod_folder() {
+        use std::time::Duration;
+        use crate::watch::{ModChange, ModWatcher};
+
+        let dir = std::env::temp_dir().join("dantelion-formats-watch");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("parts")).unwrap();
+        let watcher = ModWatcher::new(&dir.to_string_lossy()).expect("Could not watch mod folder!");
+
+        fs::write(dir.join("parts").join("am_m_1600.partsbnd.dcx"), [1, 2, 3]).unwrap();
+        let change = watcher.wait(Duration::from_secs(5));
+        assert_eq!(change, Some(ModChange::Changed("/parts/am_m_1600.partsbnd.dcx".to_string())));
+
+        fs::remove_file(dir.join("parts").join("am_m_1600.partsbnd.dcx")).unwrap();
+        let mut changes = vec![];
+        while let Some(change) = watcher.wait(Duration::from_secs(5)) {
+            changes.push(change);
+            if matches!(changes.last(), Some(ModChange::Removed(_))) { break; }
+        }
+        assert_eq!(changes.last(), Some(&ModChange::Removed("/parts/am_m_1600.partsbnd.dcx".to_string())));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(all(feature = "http", feature = "crypto"))]
+    #[test]
+    fn read_bdt_over_http() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+        use crate::source::{DataSource, HttpSource};
+
+        let archive = BHD5ArchiveBuilder::new(BHD5Format::EldenRing)
+            .add_file("/a.bin", vec![1; 0x20])
+            .add_file("/b.bin", vec![2; 0x30])
+            .build()
+            .expect("Could not build archive!");
+        let bhd5 = BHD5::from_encrypted_bytes(&archive.bhd, archive.public_key.as_bytes()).expect("Could not parse BHD5!");
+
+        // Just enough of a server to answer range and HEAD requests.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/Data0.bdt", listener.local_addr().unwrap());
+        let bdt = archive.bdt.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut range = None;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() { break; }
+                    if let Some(bytes) = line.to_lowercase().strip_prefix("range: bytes=") {
+                        let (start, end) = bytes.trim().split_once('-').unwrap();
+                        range = Some((start.parse::<usize>().unwrap(), end.parse::<usize>().unwrap()));
+                    }
+                }
+                let (status, body) = match range {
+                    Some((start, end)) => ("206 Partial Content", &bdt[start..=end]),
+                    None => ("200 OK", &bdt[..]),
+                };
+                let body = if request.starts_with("HEAD") { &[][..] } else { body };
+                let length = if request.starts_with("HEAD") { bdt.len() } else { body.len() };
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, length).unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+
+        let source = HttpSource::new(&url);
+        assert_eq!(source.size().unwrap(), archive.bdt.len() as u64);
+        for (path, expected) in [("/a.bin", vec![1; 0x20]), ("/b.bin", vec![2; 0x30])] {
+            let hash = BHD5::hash_path(path, BHD5Format::EldenRing);
+            let file_header = bhd5.buckets.iter().flat_map(|bucket| &bucket.file_headers).find(|f| f.file_path_hash == hash).unwrap();
+            assert_eq!(file_header.read_data_from(&source).unwrap(), expected);
+        }
+    }
+
     #[test]
     fn read_bnd4() {
         let bnd4 = BND4::from_path(TEST_BND4_PATH).unwrap();
@@ -77,6 +1046,180 @@ mod tests {
         }
     }
 
+    #[test]
+    fn bnd4_round_trip() {
+        let bnd4 = BND4Builder::for_game(GameType::EldenRing)
+            .add_file(200, r"N:\GR\data\INTERROOT_win64\parts\am_m_1600\am_m_1600.flver", vec![1; 0x21])
+            .add_file(201, r"N:\GR\data\INTERROOT_win64\parts\am_m_1600\am_m_1600.tpf", vec![2; 0x10])
+            .build();
+        let bytes = bnd4.to_bytes(&BND4WriteOptions::for_game(GameType::EldenRing)).expect("Could not write BND4!");
+
+        let read = BND4::from_bytes(&bytes).expect("Could not parse BND4!");
+        assert_eq!(read.header.version, "07D7R6\0\0");
+        assert_eq!(read.files.len(), 2);
+        assert_eq!(read.files[1].id, Some(201));
+        assert_eq!(read.files[1].name, bnd4.files[1].name);
+        assert_eq!(read.files[0].data, bnd4.files[0].data);
+        assert_eq!(read.buckets.expect("No hash table!").hashes.len(), 2);
+    }
+
+    #[test]
+    fn bnd3_bnd4_conversion() {
+        let bnd4 = BND4Builder::new()
+            .add_file(100, r"N:\FRPG\data\INTERROOT_x64\chr\c0000\c0000.tpf", vec![1; 0x21])
+            .add_file(200, r"N:\FRPG\data\INTERROOT_x64\chr\c0000\c0000.flver", vec![2; 0x10])
+            .build();
+
+        let bnd3 = BND3::from_bnd4(&bnd4).expect("Could not convert to BND3!");
+        let bnd3 = BND3::from_bytes(&bnd3.to_bytes().expect("Could not write BND3!")).expect("Could not parse BND3!");
+        assert_eq!(bnd3.header.magic, "BND3");
+        assert_eq!(bnd3.files[1].id, Some(200));
+        assert_eq!(bnd3.files[1].name, bnd4.files[1].name);
+        assert_eq!(bnd3.files[0].data, bnd4.files[0].data);
+
+        let bytes = bnd3.to_bnd4().to_bytes(&BND4WriteOptions::default()).expect("Could not write BND4!");
+        let read = BND4::from_bytes(&bytes).expect("Could not parse BND4!");
+        assert_eq!(read.header.format(), bnd4.header.format());
+        assert_eq!(read.files[0].raw_flags, bnd4.files[0].raw_flags);
+        assert_eq!(read.files[1].name, bnd4.files[1].name);
+    }
+
+    #[test]
+    fn ptde_to_dsr() {
+        let bnd4 = BND4Builder::new()
+            .add_file(200, r"N:\FRPG\data\INTERROOT_win32\chr\c0000\c0000.flver", vec![2; 0x10])
+            .build();
+        let bnd3 = BND3::from_bnd4(&bnd4).expect("Could not convert to BND3!").to_bytes().expect("Could not write BND3!");
+
+        let (name, bytes) = ds1::ptde_to_dsr("c0000.chrbnd", &bnd3).expect("Could not convert chrbnd!");
+        assert_eq!(name, "c0000.chrbnd");
+        let bnd3 = BND3::from_bytes(&bytes).expect("Could not parse BND3!");
+        assert_eq!(bnd3.files[0].name.as_deref(), Some(r"N:\FRPG\data\INTERROOT_x64\chr\c0000\c0000.flver"));
+
+        let (name, bytes) = ds1::ptde_to_dsr("m10_00_00_00.emevd", &[0; 0x10]).expect("Could not convert emevd!");
+        assert_eq!(name, "m10_00_00_00.emevd.dcx");
+        assert!(DCX::is(&bytes));
+
+        let (name, bytes) = ds1::dsr_to_ptde(&name, &bytes).expect("Could not convert emevd!");
+        assert_eq!(name, "m10_00_00_00.emevd");
+        assert_eq!(bytes, vec![0; 0x10]);
+    }
+
+    #[test]
+    fn bnd4_sort_files() {
+        let mut bnd4 = BND4Builder::new()
+            .add_file(300, "b.hkx", vec![])
+            .add_file(100, "C.tpf", vec![])
+            .add_file(200, "a.flver", vec![])
+            .build();
+
+        let ids = |bnd4: &BND4| bnd4.files.iter().map(|file| file.id.unwrap()).collect::<Vec<i32>>();
+        bnd4.sort_files(BND4FileOrder::Id);
+        assert_eq!(ids(&bnd4), vec![100, 200, 300]);
+        bnd4.sort_files(BND4FileOrder::Name);
+        assert_eq!(ids(&bnd4), vec![200, 300, 100]);
+
+        let read = BND4::from_bytes(&bnd4.to_bytes(&BND4WriteOptions::default()).expect("Could not write BND4!")).expect("Could not parse BND4!");
+        assert_eq!(ids(&read), vec![200, 300, 100]);
+        bnd4.sort_files(BND4FileOrder::Original);
+        assert_eq!(ids(&bnd4), vec![300, 100, 200]);
+    }
+
+    #[test]
+    fn bnd4_edit_files() {
+        let mut bnd4 = BND4Builder::new()
+            .add_file(200, r"N:\GR\data\INTERROOT_win64\parts\am_m_1600\am_m_1600.flver", vec![1; 0x21])
+            .add_file(201, r"N:\GR\data\INTERROOT_win64\parts\am_m_1600\am_m_1600.tpf", vec![2; 0x10])
+            .build();
+
+        bnd4.replace_file("AM_M_1600.flver", vec![3; 0x40]).expect("Could not replace file!");
+        bnd4.add_file(202, r"N:\GR\data\INTERROOT_win64\parts\am_m_1600\am_m_1600_c.hkx", vec![4; 0x8]);
+        bnd4.remove_file(201).expect("Could not remove file!");
+        assert!(bnd4.remove_file(201).is_err());
+
+        let read = BND4::from_bytes(&bnd4.to_bytes(&BND4WriteOptions::default()).expect("Could not write BND4!")).expect("Could not parse BND4!");
+        assert_eq!(read.files.len(), 2);
+        assert_eq!(read.files[0].data, Some(vec![3; 0x40]));
+        assert_eq!(read.files[0].uncompressed_size, Some(0x40));
+        assert_eq!(read.files[1].id, Some(202));
+        assert_eq!(read.file_index(r"N:\GR\data\INTERROOT_win64\parts\am_m_1600\am_m_1600_c.hkx"), Some(1));
+    }
+
+    #[test]
+    fn chrbnd_id_conventions() {
+        let bnd4 = BND4Builder::new()
+            .binder_type(BinderType::Chrbnd)
+            .add_file_auto_id(r"N:\GR\data\INTERROOT_win64\chr\c0000\c0000.flver", vec![])
+            .add_file_auto_id(r"N:\GR\data\INTERROOT_win64\chr\c0000\c0000_1.flver", vec![])
+            .add_file_auto_id(r"N:\GR\data\INTERROOT_win64\chr\c0000\c0000_c.hkx", vec![])
+            .build();
+
+        let ids: Vec<i32> = bnd4.files.iter().map(|file| file.id.unwrap()).collect();
+        assert_eq!(ids, vec![200, 201, 500]);
+    }
+
+    #[test]
+    fn behbnd_entry_types() {
+        let mut packfile = vec![0x57, 0xE0, 0xE0, 0x57, 0x10, 0xC0, 0xC0, 0x10];
+        packfile.resize(0x10, 0);
+        packfile.extend_from_slice(&[8, 1, 0, 1]);
+        packfile.resize(0x28, 0);
+        packfile.extend_from_slice(b"hk_2014.1.0-r1\0\0");
+
+        let mut tagfile = vec![0x40, 0, 0, 0x18];
+        tagfile.extend_from_slice(b"TAG0");
+        tagfile.extend_from_slice(&[0x40, 0, 0, 0x10]);
+        tagfile.extend_from_slice(b"SDKV20180100");
+
+        let bnd4 = BND4Builder::new()
+            .add_file(0, r"N:\GR\data\INTERROOT_win64\chr\c0000\Behaviors\c0000.hkx", packfile)
+            .add_file(1, r"N:\GR\data\INTERROOT_win64\chr\c0000\Characters\c0000.hkx", tagfile)
+            .add_file(2, r"N:\GR\data\INTERROOT_win64\chr\c0000\c0000.txt", vec![])
+            .build();
+
+        assert!(BinderType::from_file_name("c0000.behbnd.dcx") == BinderType::Behbnd);
+        let entries = behbnd::entries(&bnd4);
+        assert_eq!(entries[0].entry_type, behbnd::BehaviorEntryType::Behavior);
+        assert_eq!(entries[0].havok.as_ref().unwrap().version, "hk_2014.1.0-r1");
+        assert_eq!(entries[0].havok.as_ref().unwrap().pointer_size, Some(8));
+        assert_eq!(entries[1].entry_type, behbnd::BehaviorEntryType::Character);
+        assert_eq!(entries[1].havok.as_ref().unwrap().format, behbnd::HavokFormat::Tagfile);
+        assert_eq!(entries[1].havok.as_ref().unwrap().version, "20180100");
+        assert_eq!(entries[2].entry_type, behbnd::BehaviorEntryType::Other);
+    }
+
+    #[test]
+    fn read_yabber_manifest() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<bnd4>
+  <filename>am_m_6200.partsbnd.dcx</filename>
+  <compression>DCX_DFLT_10000_24_9</compression>
+  <version>07D7R6</version>
+  <format>IDs, Names1, Names2, Compression</format>
+  <bigendian>False</bigendian>
+  <bitbigendian>False</bitbigendian>
+  <unicode>True</unicode>
+  <extended>0x04</extended>
+  <unk04>False</unk04>
+  <unk05>False</unk05>
+  <files>
+    <file>
+      <flags>Flag1</flags>
+      <id>200</id>
+      <root>N:\FDP\data\INTERROOT_win64\</root>
+      <path>parts\am_m_6200\am_m_6200.flver</path>
+    </file>
+  </files>
+</bnd4>"#;
+        let manifest = manifest::BND4Manifest::from_xml(xml).expect("Could not parse manifest!");
+        assert_eq!(manifest.format, 0b00101110);
+        assert_eq!(manifest.extended, 4);
+        assert_eq!(manifest.files[0].flags, 0b00000010);
+        assert_eq!(manifest.files[0].id, Some(200));
+        assert_eq!(manifest.files[0].name.as_deref(), Some(r"N:\FDP\data\INTERROOT_win64\parts\am_m_6200\am_m_6200.flver"));
+        assert_eq!(manifest.files[0].path, "parts/am_m_6200/am_m_6200.flver");
+    }
+
     #[test]
     fn test_dcx_is() {
         let file = fs::read(TEST_BND4_PATH)
@@ -95,6 +1238,74 @@ mod tests {
         }
     }
 
+    // Held by tests that set the Oodle path or config variables, or depend on Oodle not being found
+    static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    #[cfg(feature = "oodle")]
+    #[test]
+    fn oodle_path_overrides() {
+        let _env = ENV_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = std::env::temp_dir().join("dantelion-formats-oodle");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dll = dir.join("oo2core_6_win64.dll");
+        fs::write(&dll, b"MZ").unwrap();
+        let dll_path = Some(dll.to_string_lossy().to_string());
+        let saved: Vec<_> = oodle::OODLE_PATH_ENV_VARS.iter().map(|&var| (var, std::env::var_os(var))).collect();
+        for var in oodle::OODLE_PATH_ENV_VARS {
+            std::env::remove_var(var);
+        }
+
+        // No game dirs here, so only the variables can find it
+        for var in oodle::OODLE_PATH_ENV_VARS {
+            // The DLL or the folder it's in
+            std::env::set_var(var, &dll);
+            assert_eq!(oodle::find_oodle_path(&[]), dll_path);
+            std::env::set_var(var, &dir);
+            assert_eq!(oodle::find_oodle_path(&[]), dll_path);
+            std::env::set_var(var, dir.join("missing"));
+            assert_eq!(oodle::find_oodle_path(&[]), None);
+            std::env::remove_var(var);
+        }
+        // OODLE_PATH is checked first
+        std::env::set_var("DANTELION_OODLE", dir.join("missing"));
+        std::env::set_var("OODLE_PATH", &dll);
+        assert_eq!(oodle::find_oodle_path(&[]), dll_path);
+
+        for (var, value) in saved {
+            match value {
+                Some(value) => std::env::set_var(var, value),
+                None => std::env::remove_var(var),
+            }
+        }
+        // An explicit path beats the search
+        assert_eq!(config::Config::new().with_oodle_path(&dir.to_string_lossy()).oodle_path(), dll_path);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn krak_without_oodle() {
+        #[cfg(feature = "oodle")]
+        let _env = ENV_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        assert!(DCX::from_bytes(testdata::DFLT_DCX_BYTES).unwrap().can_decompress());
+
+        let mut bytes = testdata::DFLT_DCX_BYTES.to_vec();
+        bytes[0x28..0x2C].copy_from_slice(b"KRAK");
+        bytes[0x30] = 6;
+        let dcx = DCX::from_bytes(&bytes).unwrap();
+        // Nothing to check on a machine with Oodle installed
+        if dcx.can_decompress() {
+            return;
+        }
+
+        for result in [dcx.decompress(), dcx.decompress_spilling(usize::MAX).and_then(|payload| payload.into_vec())] {
+            match result {
+                Err(DantelionFormatsError::OodleUnavailable { required_dll, compression_level }) => assert_eq!((required_dll, compression_level), ("oo2core_6_win64.dll", 6)),
+                other => panic!("Expected OodleUnavailable, got {:?}", other.map(|data| data.len())),
+            }
+        }
+    }
+
     #[test]
     fn read_dflt_dcx() {
         let file = fs::read(TEST_BND4_PATH)
@@ -117,9 +1328,2004 @@ mod tests {
         assert_eq!(dcx.header.format, "KRAK");
     }
 
+    #[test]
+    fn edge_dcx_round_trip() {
+        let data: Vec<u8> = (0..0x28000u32).map(|i| (i % 251) as u8).collect();
+        let bytes = DCX::compress_edge(&data).to_bytes().expect("Could not write EDGE DCX!");
+
+        let dcx = DCX::from_bytes(&bytes).expect("Could not get DCX from Bytes!");
+        assert_eq!(dcx.header.format, "EDGE");
+        assert_eq!(dcx.header.egdt.as_ref().unwrap().block_count, 3);
+        assert_eq!(dcx.decompress().expect("Could not decompress EDGE DCX!"), data);
+
+        // A block that runs past the content
+        let mut dcx = dcx;
+        dcx.header.egdt.as_mut().unwrap().blocks[2].data_length = u32::MAX;
+        let Err(error::DantelionFormatsError::IoError(err)) = dcx.decompress() else { panic!("Truncated EDGE block was accepted") };
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("EDGE block 2 at "), "{}", err);
+    }
+
+    #[test]
+    fn raw_deflate_dflt() {
+        // Some DFLT files have no zlib header, just the deflate stream
+        let content = fixtures::sample_data(testdata::DCX_CONTENT_SIZE);
+        let raw = miniz_oxide::deflate::compress_to_vec(&content, 6);
+        let mut dcx = DCX::compress_dflt(&content);
+        dcx.header.compressed_size = raw.len() as u32;
+        dcx.content = raw;
+
+        let dcx = DCX::from_bytes(&dcx.to_bytes().unwrap()).unwrap();
+        assert_eq!(dcx.decompress().expect("Could not inflate raw deflate DCX!"), content);
+        assert_eq!(dcx.decompress_spilling(usize::MAX).unwrap().into_vec().unwrap(), content);
+    }
+
+    #[test]
+    fn dflt_size_mismatch() {
+        let real = testdata::DCX_CONTENT_SIZE;
+        // Shorter and longer than the content, and more than could be reserved
+        for claimed in [real as u32 - 5, real as u32 + 5, u32::MAX] {
+            let mut bytes = testdata::DFLT_DCX_BYTES.to_vec();
+            bytes[0x1C..0x20].copy_from_slice(&claimed.to_be_bytes());
+            let dcx = DCX::from_bytes(&bytes).unwrap();
+            match dcx.decompress() {
+                Err(error::DantelionFormatsError::DecompressedSizeMismatch { expected, actual }) => assert_eq!((expected, actual), (claimed as usize, real)),
+                other => panic!("Expected a size mismatch, got {:?}", other.map(|data| data.len())),
+            }
+        }
+    }
+
+    #[test]
+    fn dflt_inflate_backend() {
+        // Whichever inflate the build uses, miniz or libdeflate, reads what miniz wrote at any level
+        let content = fixtures::sample_data(0x10000);
+        for level in [1, 6, 10] {
+            let mut dcx = DCX::compress_dflt(&content);
+            dcx.content = miniz_oxide::deflate::compress_to_vec_zlib(&content, level);
+            dcx.header.compressed_size = dcx.content.len() as u32;
+            assert_eq!(dcx.decompress().unwrap(), content);
+        }
+
+        let mut dcx = DCX::compress_dflt(&content);
+        dcx.content.truncate(dcx.content.len() / 2);
+        let error = dcx.decompress().expect_err("Inflated a truncated stream!");
+        #[cfg(feature = "libdeflate")]
+        assert!(matches!(error, DantelionFormatsError::LibDeflateError(_)), "{:?}", error);
+        #[cfg(not(feature = "libdeflate"))]
+        assert!(matches!(error, DantelionFormatsError::DecompressionError(_)), "{:?}", error);
+    }
+
+    #[test]
+    fn reuse_output_buffers() {
+        let mut buffer = vec![];
+        DCX::decompress_bytes_into(&fixtures::dflt_dcx_bytes(0x400).unwrap(), &mut buffer).unwrap();
+        assert_eq!(buffer, fixtures::sample_data(0x400));
+        let capacity = buffer.capacity();
+
+        // A smaller file replaces the contents and keeps the allocation
+        DCX::decompress_bytes_into(testdata::DFLT_DCX_BYTES, &mut buffer).unwrap();
+        assert_eq!(buffer, fixtures::sample_data(testdata::DCX_CONTENT_SIZE));
+        assert_eq!(buffer.capacity(), capacity);
+
+        let bnd4 = BND4::from_bytes_with_buffer(&fixtures::dflt_bnd4_bytes(2, 0x20).unwrap(), &mut buffer).unwrap();
+        assert_eq!(bnd4.files[1].data, Some(fixtures::sample_data(0x20)));
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn reuse_decrypt_buffers() {
+        let key = crypto_util::ER_REGULATION_KEY;
+        let mut buffer = vec![];
+        for size in [0x400, 0x40] {
+            let dcx = fixtures::dflt_dcx_bytes(size).unwrap();
+            crypto_util::decrypt_regulation_into(&crypto_util::encrypt_regulation(&dcx, &key).unwrap(), &key, &mut buffer).unwrap();
+            // Zero padded to the AES block size
+            assert_eq!(&buffer[..dcx.len()], dcx.as_slice());
+            assert!(buffer[dcx.len()..].iter().all(|&b| b == 0));
+        }
+
+        let (public_key, private_key) = crypto_util::generate_bhd5_key_pair().unwrap();
+        let data = fixtures::sample_data(1000);
+        let encrypted = crypto_util::encrypt_bhd5_file(&data, private_key.as_bytes()).unwrap();
+        crypto_util::decrypt_bhd5_file_into(&encrypted, public_key.as_bytes(), &mut buffer).unwrap();
+        assert_eq!(buffer, crypto_util::decrypt_bhd5_file(&encrypted, public_key.as_bytes()).unwrap());
+        assert_eq!(&buffer[..data.len()], data.as_slice());
+    }
+
+    #[test]
+    fn open_dcx_bnd4() {
+        let parsed = open_bytes(&fixtures::dflt_bnd4_bytes(2, 0x20).unwrap()).expect("Could not open file!");
+        let ParsedFile::BND4(bnd4) = parsed else { panic!("Not parsed as BND4!") };
+        // The binder remembers it was compressed
+        assert_eq!(bnd4.dcx.map(|dcx| dcx.format), Some("DFLT".to_string()));
+        assert_eq!(bnd4.files[1].data, Some(fixtures::sample_data(0x20)));
+    }
+
+    #[test]
+    fn read_tpf() {
+        let tpf = TPF::from_bytes(&fixtures::tpf_bytes(2, 16).unwrap()).expect("Could not read TPF!");
+        assert_eq!(tpf.header.magic, "TPF\0");
+        assert_eq!(tpf.textures.iter().map(|texture| texture.name.as_str()).collect::<Vec<_>>(), ["sample_0000", "sample_0001"]);
+
+        let dds = tpf.textures[0].to_dds(tpf.header.platform, true).expect("Could not convert texture to DDS!");
+        assert_eq!(&dds[..4], b"DDS ");
+    }
+
+    // A PC TPF, whose texture data is already a DDS file.
+    fn dds_tpf(names: &[&str], dds: &[u8]) -> TPF {
+        let textures = names.iter().map(|name| Texture {
+            data_offset: 0,
+            data_size: dds.len() as u32,
+            format: 0,
+            tex_type: TexType::Texture,
+            mipmaps: 1,
+            flags1: 0,
+            tex_header: None,
+            name_offset: 0,
+            float_struct: None,
+            name: name.to_string(),
+            data: dds.to_vec(),
+        }).collect();
+
+        TPF {
+            header: TPFHeader { magic: "TPF\0".to_string(), data_size: 0, file_count: names.len() as u32, platform: TPFPlatform::PC, flag2: 0, encoding: 1, unk0f: 0 },
+            textures,
+        }
+    }
+
+    #[test]
+    fn tpf_export() {
+        let dir = std::env::temp_dir().join("dantelion-formats-tpf-export");
+        let _ = fs::remove_dir_all(&dir);
+        let mut dds = dds::build_header(4, 4, 1, dds::DXGI_FORMAT_BC1_UNORM, false);
+        dds.extend_from_slice(&[0; 8]);
+
+        let paths = dds_tpf(&["c1000_a", r"..\..\c1000_n"], &dds).export_dds(&dir, false).unwrap();
+        assert_eq!(paths, vec![dir.join("c1000_a.dds"), dir.join("c1000_n.dds")]);
+        assert_eq!(fs::read(&paths[1]).unwrap(), dds);
+
+        let info = dds::parse_header(&dds).unwrap();
+        assert_eq!((info.width, info.height, info.dxgi_format, info.data_offset), (4, 4, dds::DXGI_FORMAT_BC1_UNORM, 0x94));
+        assert!(dds::parse_header(b"DDS ").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tpf_import_dds() {
+        // 16x16 BC7 with two mips, 16 blocks and 4 blocks
+        let mut bc7 = dds::build_header(16, 16, 2, dds::DXGI_FORMAT_BC7_UNORM, false);
+        bc7.extend((0..320).map(|i| i as u8));
+        let mut tpf = TPF::new();
+        tpf.import_dds("c1000_a", &bc7, GameType::EldenRing).unwrap();
+        tpf.import_dds("c1000_n", &bc7, GameType::EldenRing).unwrap();
+        tpf.import_dds("c1000_a", &bc7, GameType::EldenRing).unwrap();
+        assert_eq!(tpf.header.file_count, 2);
+        tpf.textures[1].flags1 = 2;
+
+        let bytes = tpf.to_bytes().unwrap();
+        let read = TPF::from_bytes(&bytes).unwrap();
+        assert_eq!(bytes.len(), (read.textures[1].data_offset + read.textures[1].data_size) as usize);
+        assert!(read.header.data_size >= read.textures[0].data_size + read.textures[1].data_size);
+        assert_eq!(read.textures.iter().map(|texture| texture.name.as_str()).collect::<Vec<_>>(), ["c1000_a", "c1000_n"]);
+        for texture in &read.textures {
+            assert_eq!((texture.format, texture.mipmaps, texture.tex_type), (102, 2, TexType::Texture));
+            assert_eq!(texture.data_offset % 0x10, 0);
+            assert_eq!(texture.to_dds(TPFPlatform::PC, false).unwrap(), bc7);
+        }
+
+        // DX9 games need a legacy header and no BC7
+        let mut dxt1 = dds::build_header(4, 4, 1, dds::DXGI_FORMAT_BC1_UNORM, true);
+        dxt1[0x54..0x58].copy_from_slice(b"DXT1");
+        dxt1.drain(dds::LEGACY_DATA_OFFSET..dds::LEGACY_DATA_OFFSET + 20);
+        dxt1.extend_from_slice(&[0; 6 * 8]);
+        let texture = Texture::from_dds("cube", &dxt1, GameType::DarkSouls).unwrap();
+        assert_eq!((texture.format, texture.mipmaps, texture.tex_type), (0, 1, TexType::Cubemap));
+        assert!(Texture::from_dds("c1000_a", &bc7, GameType::DarkSouls).is_err());
+        let mut bc1 = dds::build_header(4, 4, 1, dds::DXGI_FORMAT_BC1_UNORM, false);
+        bc1.extend_from_slice(&[0; 8]);
+        assert!(Texture::from_dds("c1000_a", &bc1, GameType::DarkSoulsII).is_err());
+        assert!(Texture::from_dds("c1000_a", &bc1, GameType::DarkSoulsIISotFS).is_ok());
+        assert!(Texture::from_dds("c1000_a", &bc1, GameType::DemonSouls).is_err());
+
+        // Short data, formats TPFs have no byte for and impossible mip counts
+        assert!(Texture::from_dds("c1000_a", &bc7[..bc7.len() - 1], GameType::EldenRing).is_err());
+        let mut rgba = dds::build_header(4, 4, 1, dds::DXGI_FORMAT_R8G8B8A8_UNORM, false);
+        rgba.extend_from_slice(&[0; 64]);
+        assert!(Texture::from_dds("c1000_a", &rgba, GameType::EldenRing).is_err());
+        let mut mips = dds::build_header(4, 4, 4, dds::DXGI_FORMAT_BC1_UNORM, false);
+        mips.extend_from_slice(&[0; 32]);
+        assert!(Texture::from_dds("c1000_a", &mips, GameType::EldenRing).is_err());
+
+        // Console textures keep their header
+        let mut ps4 = dds_tpf(&["c1000_a"], &[1; 32]);
+        ps4.header.platform = TPFPlatform::PS4;
+        ps4.header.encoding = 0;
+        assert!(ps4.to_bytes().is_err());
+        ps4.textures[0].tex_header = Some(TexHeader { width: 8, height: 8, unk1: 0, unk2: 0xD, texture_count: 1, dxgi_format: dds::DXGI_FORMAT_BC1_UNORM });
+        ps4.textures[0].float_struct = Some(FloatStruct { unk00: 3, values: vec![0.5, 2.0] });
+        let read = TPF::from_bytes(&ps4.to_bytes().unwrap()).unwrap();
+        let texture = &read.textures[0];
+        let tex_header = texture.tex_header.as_ref().unwrap();
+        assert_eq!((tex_header.width, tex_header.unk2, tex_header.dxgi_format), (8, 0xD, dds::DXGI_FORMAT_BC1_UNORM));
+        assert_eq!(texture.float_struct.as_ref().unwrap().values, [0.5, 2.0]);
+        assert_eq!((texture.name.as_str(), &texture.data[..]), ("c1000_a", &[1; 32][..]));
+        assert!(ps4.import_dds("c1000_n", &bc1, GameType::EldenRing).is_err());
+    }
+
+    #[cfg(feature = "texture-decode")]
+    #[test]
+    fn decode_textures() {
+        use crate::tpf::decode::{decode, decode_dds};
+
+        let pixels = |image: crate::tpf::decode::RgbaImage, count: usize| -> Vec<[u8; 4]> {
+            image.pixels.chunks(4).take(count).map(|pixel| pixel.try_into().unwrap()).collect()
+        };
+        // Fields packed lowest bit first, for BC6H and BC7 blocks
+        let pack = |fields: &[(u32, u128)]| {
+            let mut bits = 0u128;
+            let mut position = 0;
+            for &(count, value) in fields {
+                bits |= value << position;
+                position += count;
+            }
+            assert_eq!(position, 128);
+            bits.to_le_bytes()
+        };
+
+        // Red and blue, in four color and then three color mode
+        let mut bc1 = [0x00, 0xF8, 0x1F, 0x00, 0xE4, 0, 0, 0].to_vec();
+        bc1.extend_from_slice(&[0x1F, 0x00, 0x00, 0xF8, 0xE4, 0, 0, 0]);
+        let image = decode(&bc1, 8, 4, dds::DXGI_FORMAT_BC1_UNORM).unwrap();
+        assert_eq!(pixels(image.clone(), 4), vec![[255, 0, 0, 255], [0, 0, 255, 255], [170, 0, 85, 255], [85, 0, 170, 255]]);
+        assert_eq!(image.pixels[16..32], [0, 0, 255, 255, 255, 0, 0, 255, 127, 0, 127, 255, 0, 0, 0, 0]);
+
+        let mut bc3 = vec![255, 0, 0b10_000_001, 0, 0, 0, 0, 0];
+        bc3.extend_from_slice(&bc1[..8]);
+        assert_eq!(pixels(decode(&bc3, 4, 4, dds::DXGI_FORMAT_BC3_UNORM).unwrap(), 3), vec![[255, 0, 0, 0], [0, 0, 255, 255], [170, 0, 85, 218]]);
+
+        // Mode 6, black to white with p-bits 0 and 1, alpha 254 to 255
+        let mut bc7 = vec![(7, 1 << 6)];
+        bc7.extend([0, 127, 0, 127, 0, 127, 127, 127].map(|value| (7, value)));
+        bc7.extend([(1, 0), (1, 1), (3, 0), (4, 15), (4, 8), (52, 0)]);
+        let image = decode(&pack(&bc7), 4, 4, dds::DXGI_FORMAT_BC7_UNORM).unwrap();
+        assert_eq!(pixels(image, 3), vec![[0, 0, 0, 254], [255, 255, 255, 255], [135, 135, 135, 255]]);
+        assert_eq!(decode(&[0; 16], 4, 4, dds::DXGI_FORMAT_BC7_UNORM).unwrap().pixels, vec![0; 64]);
+
+        // Mode 11, one region from black to 1.0
+        let mut bc6 = vec![(5, 0x03), (10, 0), (10, 0), (10, 0), (10, 495), (10, 495), (10, 495), (3, 0), (4, 15)];
+        bc6.push((56, 0));
+        let image = decode(&pack(&bc6), 4, 4, dds::DXGI_FORMAT_BC6H_UF16).unwrap();
+        assert_eq!(pixels(image, 2), vec![[0, 0, 0, 255], [255, 255, 255, 255]]);
+
+        assert_eq!(decode(&[1, 2, 3, 4], 1, 1, dds::DXGI_FORMAT_B8G8R8A8_UNORM).unwrap().pixels, vec![3, 2, 1, 4]);
+        assert!(decode(&[0; 8], 8, 4, dds::DXGI_FORMAT_BC1_UNORM).is_err());
+        assert!(decode(&[0; 16], 4, 4, 2).is_err());
+
+        // 2x2 from a single block, through the DDS header and PNG
+        let mut dds = dds::build_header(2, 2, 1, dds::DXGI_FORMAT_BC1_UNORM, false);
+        dds.extend_from_slice(&bc1[..8]);
+        let image = decode_dds(&dds).unwrap();
+        assert_eq!((image.width, image.height, image.pixels.len()), (2, 2, 16));
+        assert_eq!(image.pixels, [255, 0, 0, 255, 0, 0, 255, 255, 255, 0, 0, 255, 255, 0, 0, 255]);
+
+        let png = dds_tpf(&["c1000_a"], &dds).textures[0].to_png(TPFPlatform::PC).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+        let idat_len = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        let rows = miniz_oxide::inflate::decompress_to_vec_zlib(&png[41..41 + idat_len]).unwrap();
+        assert_eq!(rows, [&[0][..], &image.pixels[..8], &[0], &image.pixels[8..]].concat());
+    }
+
+    // A DS3 era FLVER with two bones, one dynamic mesh of four vertices, a LOD face set and then the full detail strip.
+    fn flver_sample() -> Vec<u8> {
+        use byteorder::{LE, WriteBytesExt};
+        use crate::flver::{member_type, semantic};
+
+        // Everything after the records, which end at 0x260
+        let mut tail: Vec<u8> = vec![];
+        let members_offset = 0x260 + tail.len() as u32;
+        let members = [
+            (0, member_type::FLOAT3, semantic::POSITION),
+            (12, member_type::BYTE4C, semantic::NORMAL),
+            (16, member_type::UV_PAIR, semantic::UV),
+            (24, member_type::BYTE4B, semantic::BONE_INDICES),
+            (28, member_type::BYTE4C, semantic::BONE_WEIGHTS),
+        ];
+        for (offset, member_type, semantic) in members {
+            for value in [0, offset, member_type, semantic, 0] {
+                tail.write_u32::<LE>(value).unwrap();
+            }
+        }
+        let mut offsets = vec![];
+        for values in [&[1, 0][..], &[0, 1], &[0]] {
+            offsets.push(0x260 + tail.len() as u32);
+            for &value in values {
+                tail.write_i32::<LE>(value).unwrap();
+            }
+        }
+        let bounding_box_offset = 0x260 + tail.len() as u32;
+        for value in [0.0, 0.0, 0.0, 1.0, 1.0, 0.0] {
+            tail.write_f32::<LE>(value).unwrap();
+        }
+        let mut names = vec![];
+        for name in ["Body", r"N:\FDP\data\Material\mtd\c[amsn].mtd", "Root", "Spine", "c1000_a.tif", "g_DiffuseTexture"] {
+            names.push(0x260 + tail.len() as u32);
+            tail.extend(name.encode_utf16().chain([0]).flat_map(u16::to_le_bytes));
+        }
+        let data_offset = (0x260 + tail.len() as u32 + 0xF) & !0xF;
+        tail.resize((data_offset - 0x260) as usize, 0);
+
+        // Indices, the LOD list at 0 and the strip at 8, then the vertices at 0x10
+        let mut data = vec![];
+        for index in [0u16, 1, 2, 0, 0, 1, 2, 3] {
+            data.write_u16::<LE>(index).unwrap();
+        }
+        for (i, position) in [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0f32]].into_iter().enumerate() {
+            for value in position {
+                data.write_f32::<LE>(value).unwrap();
+            }
+            data.extend_from_slice(&[127, 127, 254, 0]);
+            for value in [1024, 512 * i as i16, 0, 2048] {
+                data.write_i16::<LE>(value).unwrap();
+            }
+            data.extend_from_slice(&[i as u8 % 2, 0, 0, 0, 255, 0, 0, 0]);
+        }
+
+        let mut bytes = b"FLVER\0L\0".to_vec();
+        for value in [0x20014, data_offset, data.len() as u32, 0, 1, 2, 1, 1] {
+            bytes.write_u32::<LE>(value).unwrap();
+        }
+        for value in [0.0, 0.0, 0.0, 1.0, 1.0, 0.0] {
+            bytes.write_f32::<LE>(value).unwrap();
+        }
+        for value in [2, 3] {
+            bytes.write_u32::<LE>(value).unwrap();
+        }
+        bytes.extend_from_slice(&[16, 1, 0, 0]);
+        for value in [0, 2, 1, 1] {
+            bytes.write_u32::<LE>(value).unwrap();
+        }
+        bytes.resize(0x80, 0);
+
+        // Material
+        for value in [names[0], names[1], 1, 0, 0, 0, 0, 0] {
+            bytes.write_u32::<LE>(value).unwrap();
+        }
+        // Bones, each with (parent, child)
+        for (name, (parent, child)) in [(names[2], (-1, 1)), (names[3], (0, -1))] {
+            bytes.extend_from_slice(&[0; 12]);
+            bytes.write_u32::<LE>(name).unwrap();
+            bytes.extend_from_slice(&[0; 12]);
+            bytes.write_i16::<LE>(parent).unwrap();
+            bytes.write_i16::<LE>(child).unwrap();
+            for _ in 0..3 {
+                bytes.write_f32::<LE>(1.0).unwrap();
+            }
+            bytes.write_i16::<LE>(-1).unwrap();
+            bytes.write_i16::<LE>(-1).unwrap();
+            bytes.resize(bytes.len() + 0x50, 0);
+        }
+        // Mesh
+        bytes.extend_from_slice(&[1, 0, 0, 0]);
+        for value in [0, 0, 0, 0, 2, bounding_box_offset, offsets[0], 2, offsets[1], 1, offsets[2]] {
+            bytes.write_u32::<LE>(value).unwrap();
+        }
+        // Face sets as (flags, strip, count, offset)
+        for (flags, strip, count, offset) in [(0x01000000, 0, 3, 0), (0, 1, 4, 8)] {
+            bytes.write_u32::<LE>(flags).unwrap();
+            bytes.extend_from_slice(&[strip, 1, 0, 0]);
+            for value in [count, offset, count * 2, 0, 0, 0] {
+                bytes.write_u32::<LE>(value).unwrap();
+            }
+        }
+        // Vertex buffer, layout and texture
+        for value in [0, 0, 32, 4, 0, 0, 128, 0x10] {
+            bytes.write_u32::<LE>(value).unwrap();
+        }
+        for value in [members.len() as u32, 0, 0, members_offset] {
+            bytes.write_u32::<LE>(value).unwrap();
+        }
+        for value in [names[4], names[5]] {
+            bytes.write_u32::<LE>(value).unwrap();
+        }
+        for value in [1.0, 1.0] {
+            bytes.write_f32::<LE>(value).unwrap();
+        }
+        bytes.extend_from_slice(&[1, 0, 0, 0]);
+        bytes.resize(0x260, 0);
+
+        bytes.extend(tail);
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn read_flver() {
+        let ParsedFile::FLVER(flver) = open_bytes(&flver_sample()).unwrap() else { panic!("Not parsed as FLVER!") };
+        assert_eq!(flver.header.version, 0x20014);
+        assert_eq!(flver.bones.iter().map(|bone| bone.name.as_str()).collect::<Vec<_>>(), ["Root", "Spine"]);
+        assert_eq!((flver.bones[1].parent_index, flver.bones[1].scale), (0, [1.0; 3]));
+        assert_eq!((flver.materials[0].name.as_str(), flver.materials[0].texture_count), ("Body", 1));
+        assert_eq!((flver.textures[0].path.as_str(), flver.textures[0].texture_type.as_str()), ("c1000_a.tif", "g_DiffuseTexture"));
+        assert_eq!(flver.buffer_layouts[0].members.len(), 5);
+
+        let mesh = &flver.meshes[0];
+        assert_eq!((mesh.dynamic, &mesh.bone_indices[..]), (true, &[1, 0][..]));
+        assert_eq!(mesh.bounding_box, Some(([0.0; 3], [1.0, 1.0, 0.0])));
+        assert_eq!(mesh.face_sets.iter().map(|face_set| face_set.is_main()).collect::<Vec<_>>(), [false, true]);
+        assert_eq!(mesh.face_sets[1].indices, [0, 1, 2, 3]);
+        assert_eq!((mesh.vertex_buffers[0].vertex_count, mesh.vertex_buffers[0].data.len()), (4, 128));
+
+        let mut flver0 = flver_sample();
+        flver0[8..12].copy_from_slice(&0xCu32.to_le_bytes());
+        assert!(crate::flver::FLVER::from_bytes(&flver0).is_err());
+        assert!(crate::flver::FLVER::from_bytes(&flver_sample()[..0x200]).is_err());
+        let mut bad_index_size = flver_sample();
+        bad_index_size[0x48] = 12;
+        let Err(DantelionFormatsError::IoError(err)) = crate::flver::FLVER::from_bytes(&bad_index_size) else { panic!("vertex_index_size of 12 was accepted") };
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "mesh-decode")]
+    #[test]
+    fn flver_triangle_meshes() {
+        let flver = crate::flver::FLVER::from_bytes(&flver_sample()).unwrap();
+        let meshes = flver.triangle_meshes().unwrap();
+        let mesh = &meshes[0];
+        assert_eq!(mesh.positions, [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0]]);
+        assert_eq!(mesh.normals, [[0.0, 0.0, 1.0]; 4]);
+        // UV pairs are two sets, over 2048 from DS3 on
+        assert_eq!(mesh.uvs.len(), 2);
+        assert_eq!(mesh.uvs[0], [[0.5, 0.0], [0.5, 0.25], [0.5, 0.5], [0.5, 0.75]]);
+        assert_eq!(mesh.uvs[1], [[0.0, 1.0]; 4]);
+        // Mesh local bones mapped to the skeleton
+        assert_eq!(mesh.bone_indices, [[1, 1, 1, 1], [0, 1, 1, 1], [1, 1, 1, 1], [0, 1, 1, 1]]);
+        assert_eq!(mesh.bone_weights, [[1.0, 0.0, 0.0, 0.0]; 4]);
+        // The strip, not the LOD list, with every other triangle flipped
+        assert_eq!(mesh.indices, [0, 1, 2, 3, 2, 1]);
+
+        let mut bad = flver_sample();
+        let strip_end = bad.len() - 128 - 2;
+        bad[strip_end..strip_end + 2].copy_from_slice(&9u16.to_le_bytes());
+        assert!(crate::flver::FLVER::from_bytes(&bad).unwrap().triangle_mesh(0).is_err());
+        assert!(flver.triangle_mesh(1).is_err());
+        let mut zero_size = flver;
+        zero_size.meshes[0].vertex_buffers[0].vertex_size = 0;
+        assert!(zero_size.triangle_mesh(0).is_err());
+    }
+
+    #[cfg(feature = "gltf")]
+    #[test]
+    fn flver_to_glb() {
+        let mut flver = crate::flver::FLVER::from_bytes(&flver_sample()).unwrap();
+        flver.bones[1].translation = [1.0, 2.0, 0.0];
+        flver.bones[1].rotation = [0.0, std::f32::consts::FRAC_PI_2, 0.0];
+        let glb = flver.to_glb().unwrap();
+        assert_eq!(&glb[..4], b"glTF");
+        assert_eq!(u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize, glb.len());
+        let json_len = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        let gltf: serde_json::Value = serde_json::from_slice(&glb[20..20 + json_len]).unwrap();
+        let bin = &glb[20 + json_len + 8..];
+        let floats = |accessor: &serde_json::Value| -> Vec<f32> {
+            let view = &gltf["bufferViews"][accessor.as_u64().unwrap() as usize];
+            let start = view["byteOffset"].as_u64().unwrap() as usize;
+            bin[start..start + view["byteLength"].as_u64().unwrap() as usize].chunks(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect()
+        };
+
+        // Two bone nodes, then the mesh node skinned to them
+        assert_eq!(gltf["nodes"].as_array().unwrap().len(), 3);
+        assert_eq!(gltf["nodes"][0]["children"], serde_json::json!([1]));
+        assert_eq!(gltf["scenes"][0]["nodes"], serde_json::json!([0, 2]));
+        assert_eq!(gltf["skins"][0]["joints"], serde_json::json!([0, 1]));
+        assert_eq!(gltf["nodes"][2]["skin"], 0);
+        assert_eq!(gltf["materials"][0]["name"], "Body");
+
+        // Mirrored on X, with the winding flipped to match
+        let primitive = &gltf["meshes"][0]["primitives"][0];
+        let position = &gltf["accessors"][primitive["attributes"]["POSITION"].as_u64().unwrap() as usize];
+        assert_eq!((&position["min"], &position["max"]), (&serde_json::json!([-1.0, 0.0, 0.0]), &serde_json::json!([0.0, 1.0, 0.0])));
+        assert!(primitive["attributes"]["TEXCOORD_1"].is_u64() && primitive["attributes"]["JOINTS_0"].is_u64());
+        let indices = &gltf["accessors"][primitive["indices"].as_u64().unwrap() as usize];
+        let view = &gltf["bufferViews"][indices["bufferView"].as_u64().unwrap() as usize];
+        let start = view["byteOffset"].as_u64().unwrap() as usize;
+        let indices: Vec<u32> = bin[start..start + 24].chunks(4).map(|b| u32::from_le_bytes(b.try_into().unwrap())).collect();
+        assert_eq!(indices, [0, 2, 1, 3, 1, 2]);
+
+        // The second bone's inverse bind undoes its mirrored translation and its quarter turn around Y
+        let inverse_binds = floats(&gltf["accessors"][gltf["skins"][0]["inverseBindMatrices"].as_u64().unwrap() as usize]["bufferView"]);
+        let expected = [0.0, 0.0, -1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, -2.0, -1.0, 1.0];
+        assert!(inverse_binds[16..].iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-6), "{:?}", &inverse_binds[16..]);
+        assert_eq!(&inverse_binds[..16], &[1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+
+        let (json, bin) = flver.to_gltf("c1000.bin").unwrap();
+        assert!(json.contains("\"uri\":\"c1000.bin\""));
+        assert_eq!(bin.len() % 4, 0);
+    }
+
+    // An entry whose name sits after 0x50 bytes of fields, with `position` at 0x20 like a part's.
+    fn msb_entry(name: &str, entry_type: u32, position: [f32; 3]) -> crate::msb::MSBEntry {
+        let mut data = vec![0u8; 0x50];
+        data[..8].copy_from_slice(&0x50u64.to_le_bytes());
+        data[8..12].copy_from_slice(&entry_type.to_le_bytes());
+        for (i, value) in position.iter().enumerate() {
+            data[0x20 + i * 4..0x24 + i * 4].copy_from_slice(&value.to_le_bytes());
+        }
+        data[0x38..0x44].copy_from_slice(&[1.0f32, 1.0, 1.0].map(f32::to_le_bytes).concat());
+        data.extend(name.encode_utf16().chain([0]).flat_map(u16::to_le_bytes));
+        crate::msb::MSBEntry { data }
+    }
+
+    #[test]
+    fn msb_json_round_trip() {
+        use crate::msb::*;
+
+        let msb = MSB {
+            header: MSBHeader { magic: "MSB ".to_string(), unk04: 1, header_size: 0x10, big_endian: false, bit_big_endian: false, text_encoding: 1, long_offsets: 0xFF },
+            params: vec![
+                MSBParam { version: 3, name: "MODEL_PARAM_ST".to_string(), entries: vec![] },
+                MSBParam { version: 3, name: "PARTS_PARAM_ST".to_string(), entries: vec![msb_entry("c1000_0000", 2, [1.0, 2.0, 3.0]), msb_entry("m30_00_00_00", 0, [0.0; 3])] },
+            ],
+        };
+
+        let bytes = msb.to_bytes().expect("Could not write MSB!");
+        let ParsedFile::MSB(read) = open_bytes(&bytes).expect("Could not open MSB!") else { panic!("Not parsed as MSB!") };
+        // Entries come back with the padding up to the next one
+        let parts = &read.param("PARTS_PARAM_ST").unwrap().entries;
+        assert_eq!(parts[0].name().unwrap(), "c1000_0000");
+        assert!(parts[1].data.starts_with(&msb.params[1].entries[1].data) && parts[1].data.len().is_multiple_of(8));
+        assert_eq!(read.to_bytes().unwrap(), bytes);
+        // A bad magic or header size is an error, not a panic
+        for (offset, value) in [(0, b'X'), (8, 0x20)] {
+            let mut bad = bytes.clone();
+            bad[offset] = value;
+            let Err(DantelionFormatsError::IoError(err)) = MSB::from_bytes(&bad) else { panic!("Corrupt MSB header was accepted") };
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        }
+
+        let json = read.to_json().expect("Could not write JSON!");
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["schema_version"], MSB::JSON_SCHEMA_VERSION);
+        let part = &value["params"][1]["entries"][0];
+        assert_eq!((&part["name"], &part["type"]), (&serde_json::json!("c1000_0000"), &serde_json::json!(2)));
+        assert_eq!(part["position"], serde_json::json!([1.0, 2.0, 3.0]));
+        assert!(value["params"][0]["entries"].as_array().unwrap().is_empty());
+        assert_eq!(MSB::from_json(&json).unwrap(), read);
+
+        // An edited position is written into the entry
+        let edited = json.replacen("\"position\": [\n            1.0,", "\"position\": [\n            5.0,", 1);
+        assert_ne!(edited, json);
+        let edited = MSB::from_json(&edited).unwrap();
+        let data = &edited.param("PARTS_PARAM_ST").unwrap().entries[0].data;
+        assert_eq!(f32::from_le_bytes(data[0x20..0x24].try_into().unwrap()), 5.0);
+
+        assert!(MSB::from_json(&json.replace("\"schema_version\": 1", "\"schema_version\": 2")).is_err());
+    }
+
+    #[test]
+    fn mqb_round_trip() {
+        let mut file = b"MQB \0\0\0\0".to_vec();
+        file.extend_from_slice(&0xCCu32.to_le_bytes());
+        file.extend_from_slice(&0x14u32.to_le_bytes());
+        file.extend_from_slice(&[1, 2, 3, 4]);
+
+        let parsed = open_bytes(&file).expect("Could not open file!");
+        let ParsedFile::MQB(mqb) = parsed else { panic!("Not parsed as MQB!") };
+        assert_eq!(mqb.header.version, 0xCC);
+        assert_eq!(mqb.to_bytes().expect("Could not write MQB!"), file);
+    }
+
+    #[test]
+    fn navgraph_round_trip() {
+        use crate::navgraph::*;
+
+        let room = |local_index, connected_rooms| MCPRoom { map_id: 0x1E000000, local_index, bounding_box_min: [0.0; 3], bounding_box_max: [4.0, 2.0, 4.0], connected_rooms };
+        let mcp = MCP {
+            header: MCPHeader { big_endian: false, version: 2, unk04: 0 },
+            rooms: vec![room(0, vec![1]), room(1, vec![0, 2]), room(2, vec![1])],
+        };
+        let bytes = mcp.to_bytes().unwrap();
+        let read = MCP::from_bytes(&bytes).unwrap();
+        assert_eq!(read, mcp);
+        assert_eq!(read.links().collect::<Vec<_>>(), [(0, 1), (1, 0), (1, 2), (2, 1)]);
+        assert_eq!(read.to_string(), "MCP — 3 rooms, 4 links");
+
+        let mcg = MCG {
+            header: MCGHeader { big_endian: true, version: 1, unk04: 0, unk18: 0, unk1c: 0 },
+            nodes: vec![
+                MCGNode { position: [0.0; 3], connected_nodes: vec![1], connected_edges: vec![0], unk18: -1, unk1c: 0 },
+                MCGNode { position: [8.0, 0.0, 2.0], connected_nodes: vec![0], connected_edges: vec![0], unk18: -1, unk1c: 0 },
+            ],
+            edges: vec![MCGEdge { node_a: 0, rooms_a: vec![0, 1], node_b: 1, rooms_b: vec![2], map_id: 0x1E000000, cost: 8.25 }],
+        };
+        let bytes = mcg.to_bytes().unwrap();
+        assert_eq!(&bytes[..4], &[0, 0, 0, 1]);
+        assert_eq!(MCG::from_bytes(&bytes).unwrap(), mcg);
+
+        // Lists past the end of the file are errors
+        let mut bad = bytes.clone();
+        bad[0x20 + 3] = 0x7F;
+        assert!(MCG::from_bytes(&bad).is_err());
+        assert!(MCP::from_bytes(&mcp.to_bytes().unwrap()[..0xC]).is_err());
+    }
+
+    #[test]
+    fn btpb_replace_probe() {
+        use crate::btpb::*;
+
+        let probe = |value| BTPBProbe { data: vec![value; 0x38] };
+        let btpb = BTPB {
+            header: BTPBHeader { version: 3, group_size: 0x48, probe_size: 0x38, unk18: [0; 0x20] },
+            groups: vec![
+                BTPBGroup { name: "m60_42_36_00".to_string(), unk0c: 0, probes: vec![probe(1), probe(2)], unk18: vec![0xAB; 0x30] },
+                BTPBGroup { name: "m60_42_37_00".to_string(), unk0c: 1, probes: vec![probe(3)], unk18: vec![0; 0x30] },
+            ],
+        };
+        let bytes = btpb.to_bytes().unwrap();
+        let mut read = BTPB::from_bytes(&bytes).unwrap();
+        assert_eq!(read, btpb);
+        assert_eq!(read.to_string(), "BTPB v3 — 2 groups, 3 probes");
+
+        // Replaced probes go back in place, inside the gi binder too
+        read.groups[1].probes[0] = probe(9);
+        let binder = BND4Builder::new().add_file(0, "m60_42_36_00.btpb", read.to_bytes().unwrap()).build()
+            .to_bytes(&BND4WriteOptions::for_game(GameType::EldenRing)).unwrap();
+        let binder = BND4::from_bytes(&binder).unwrap();
+        let reread = BTPB::from_bytes(binder.files[0].data.as_ref().unwrap()).unwrap();
+        assert_eq!(reread.group("m60_42_37_00").unwrap().probes[0], probe(9));
+        assert_eq!(reread.groups[0].probes, [probe(1), probe(2)]);
+
+        read.groups[0].probes[0].data.pop();
+        assert!(read.to_bytes().is_err());
+        assert!(BTPB::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn grass_and_decal_round_trip() {
+        use crate::grass::*;
+
+        let mut bytes = vec![];
+        for value in [2u32, 0, 3, 0x20] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for entry in 0..3u8 {
+            bytes.extend_from_slice(&[entry; 0x20]);
+        }
+        bytes.extend_from_slice(b"placement data");
+
+        let mut grass = GRASS::from_bytes(&bytes).unwrap();
+        assert_eq!((grass.table.entries.len(), &grass.table.data[..]), (3, &b"placement data"[..]));
+        assert_eq!(grass.to_string(), "GRASS v2 — 3 volumes");
+        assert_eq!(grass.to_bytes().unwrap(), bytes);
+        assert_eq!(Decal::from_bytes(&bytes).unwrap().to_bytes().unwrap(), bytes);
+
+        grass.table.entries[1].data[0] = 9;
+        assert_eq!(GRASS::from_bytes(&grass.to_bytes().unwrap()).unwrap(), grass);
+        grass.table.entries[1].data.push(0);
+        assert!(grass.to_bytes().is_err());
+        // More entries than fit in the file, or a version we haven't seen
+        bytes[8] = 0xFF;
+        assert!(GRASS::from_bytes(&bytes).is_err());
+        bytes[8] = 3;
+        bytes[0] = 3;
+        assert!(Decal::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn clm2_round_trip() {
+        use crate::clm2::*;
+
+        let vertex = |vertex_index| ClothVertex { position: [0.0, 1.0, vertex_index as f32], vertex_index, weight: 0.5 };
+        let mut clm2 = CLM2 {
+            header: CLM2Header { magic: "CLM2".to_string(), version: 1 },
+            meshes: vec![ClothMesh { mesh_index: 0, bone_index: 1, vertices: vec![vertex(0), vertex(3)] }],
+        };
+        let bytes = clm2.to_bytes().unwrap();
+        let ParsedFile::CLM2(read) = open_bytes(&bytes).unwrap() else { panic!("Not parsed as CLM2!") };
+        assert_eq!(read, clm2);
+        assert_eq!(read.to_string(), "CLM2 — 1 meshes, 2 vertices");
+        assert_eq!(kind::FileKind::sniff(&bytes), kind::FileKind::CLM2);
+
+        // Indices are checked against the FLVER the cloth goes with
+        let flver = crate::flver::FLVER::from_bytes(&flver_sample()).unwrap();
+        clm2.check(&flver).unwrap();
+        clm2.meshes[0].vertices.push(vertex(4));
+        assert!(clm2.check(&flver).is_err());
+        clm2.meshes[0].vertices.pop();
+        clm2.meshes[0].bone_index = 2;
+        assert!(clm2.check(&flver).is_err());
+
+        assert!(CLM2::from_bytes(&bytes[..bytes.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn read_sound_banks() {
+        let sample = |has_chunk: u64, data_offset: u64, samples: u64| has_chunk | (8 << 1) | (data_offset / 16) << 6 | samples << 34;
+        let mut fsb = b"FSB5".to_vec();
+        for value in [1u32, 2, 0x15, 0x10, 0x30, 15] {
+            fsb.extend_from_slice(&value.to_le_bytes());
+        }
+        fsb.resize(0x3C, 0);
+        fsb.extend_from_slice(&sample(1, 0, 100).to_le_bytes());
+        fsb.extend_from_slice(&((1u32 << 25) | (1 << 1)).to_le_bytes());
+        fsb.push(6);
+        fsb.extend_from_slice(&sample(0, 0x10, 200).to_le_bytes());
+        fsb.extend_from_slice(&[8, 0, 0, 0, 12, 0, 0, 0]);
+        fsb.extend_from_slice(b"first\0\0\0");
+        fsb.extend_from_slice(&[1; 0x10]);
+        fsb.extend_from_slice(&[2; 0x20]);
+
+        let ParsedFile::FSB5(fsb5) = open_bytes(&fsb).expect("Could not open FSB5!") else { panic!("Not parsed as FSB5!") };
+        assert_eq!(fsb5.samples[0].name.as_deref(), Some("first"));
+        assert_eq!(fsb5.samples[0].channels, 6);
+        assert_eq!(fsb5.samples[1].frequency, 44100);
+        assert_eq!(fsb5.samples[1].sample_count, 200);
+        assert_eq!(fsb5.samples[1].data, vec![2; 0x20]);
+
+        let mut bnk = b"BKHD".to_vec();
+        bnk.extend_from_slice(&[8, 0, 0, 0, 0x8C, 0, 0, 0, 0x39, 0x30, 0, 0]);
+        bnk.extend_from_slice(b"DIDX");
+        bnk.extend_from_slice(&[12, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0]);
+        bnk.extend_from_slice(b"DATA");
+        bnk.extend_from_slice(&[3, 0, 0, 0, 9, 9, 9]);
+
+        let ParsedFile::BNK(bnk) = open_bytes(&bnk).expect("Could not open BNK!") else { panic!("Not parsed as BNK!") };
+        assert_eq!(bnk.version, 0x8C);
+        assert_eq!(bnk.bank_id, 12345);
+        assert_eq!(bnk.files[0].id, 7);
+        assert_eq!(bnk.files[0].data, vec![9, 9, 9]);
+    }
+
+    #[test]
+    fn read_bink_header() {
+        let mut file = b"KB2j".to_vec();
+        for value in [0x1000u32 - 8, 300, 0x800, 300, 1920, 1080, 30000, 1001, 0, 1] {
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let ParsedFile::Bink(header) = open_bytes(&file).expect("Could not open movie!") else { panic!("Not parsed as Bink!") };
+        assert!(header.is_bink2());
+        assert_eq!(header.revision, b'j');
+        assert_eq!(header.file_size, 0x1000);
+        assert_eq!((header.width, header.height), (1920, 1080));
+        assert!((header.duration_seconds() - 10.01).abs() < 0.001);
+    }
+
+    #[test]
+    fn read_from_sources() {
+        use crate::source::{DataSource, FileSource};
+
+        let dir = std::env::temp_dir().join("dantelion-formats-sources");
+        fs::create_dir_all(&dir).unwrap();
+        let bytes = fixtures::dflt_bnd4_bytes(3, 0x40).unwrap();
+        let path = dir.join("sample.bnd.dcx").to_string_lossy().to_string();
+        fs::write(&path, &bytes).unwrap();
+
+        let file_source = FileSource::open(&path).unwrap();
+        assert_eq!(file_source.size().unwrap(), bytes.len() as u64);
+        assert_eq!(file_source.read_at(4, 8).unwrap(), bytes[4..12].to_vec());
+        assert!(file_source.read_at(bytes.len() as u64 - 2, 4).is_err());
+        assert!(bytes[..].read_at(u64::MAX, 1).is_err());
+        let sequential = FileSource::open_sequential(&path).unwrap();
+        sequential.prefetch(0x10, 0x40);
+        assert_eq!(sequential.read_at(0, bytes.len()).unwrap(), bytes);
+
+        // Small reads come from a window, they still have to line up across its edges and the end of the file
+        let big: Vec<u8> = (0..0x25003u32).map(|i| (i * 7 % 251) as u8).collect();
+        let big_path = dir.join("big.bin").to_string_lossy().to_string();
+        fs::write(&big_path, &big).unwrap();
+        let big_source = FileSource::open(&big_path).unwrap();
+        for offset in [0, 0xFFFE, 0x10000, 0x8, 0x1FFFC, 0x24FF0, 0x25000] {
+            assert_eq!(big_source.read_at(offset, 3).unwrap(), big[offset as usize..offset as usize + 3].to_vec());
+            assert_eq!(big_source.read_at(offset, 0x2000).ok(), big.get(offset as usize..offset as usize + 0x2000).map(<[u8]>::to_vec));
+        }
+        assert!(big_source.read_at(0x25001, 4).is_err());
+        assert_eq!(big_source.read_at(0x24FFF, 4).unwrap(), big[0x24FFF..].to_vec());
+
+        for bnd4 in [BND4::from_source(&bytes).unwrap(), BND4::from_source(&file_source).unwrap()] {
+            assert_eq!(bnd4.files.len(), 3);
+        }
+        assert!(matches!(open_source(&bytes[..]).unwrap(), ParsedFile::BND4(_)));
+
+        #[cfg(feature = "mmap")]
+        {
+            let mmap_source = crate::source::MmapSource::open(&path).unwrap();
+            assert_eq!(BND4::from_source(&mmap_source).unwrap().files.len(), 3);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_source_window() {
+        use std::io::{Seek, SeekFrom, Write};
+        use crate::source::{DataSource, FileSource};
+
+        let dir = std::env::temp_dir().join("dantelion-formats-source-window");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("window.bin").to_string_lossy().to_string();
+        fs::write(&path, vec![1; 0x30000]).unwrap();
+        let source = FileSource::open(&path).unwrap();
+        assert_eq!(source.read_at(0x100, 4).unwrap(), vec![1; 4]);
+
+        // Overwrite the file in place. Small reads inside the window don't go back to it, the rest do.
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(&vec![2; 0x30000]).unwrap();
+        file.flush().unwrap();
+        assert_eq!(source.read_at(0x8000, 8).unwrap(), vec![1; 8]);
+        assert_eq!(source.read_at(0x8000, 0x2000).unwrap(), vec![2; 0x2000]);
+        assert_eq!(source.read_at(0x20000, 8).unwrap(), vec![2; 8]);
+        // That moved the window, so what was cached before is read again
+        assert_eq!(source.read_at(0x100, 4).unwrap(), vec![2; 4]);
+
+        // One source shared between threads, each scanning its own headers
+        let source = FileSource::open(&path).unwrap();
+        std::thread::scope(|scope| {
+            for offset in [0u64, 0x10000, 0x2FFF0] {
+                let source = &source;
+                scope.spawn(move || for i in 0..0x100 {
+                    assert_eq!(source.read_at(offset + i % 0x10, 1).unwrap(), vec![2]);
+                });
+            }
+        });
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn checked_offsets() {
+        let archive = BHD5ArchiveBuilder::new(BHD5Format::EldenRing)
+            .add_file("/a.bin", vec![1; 0x20])
+            .build()
+            .expect("Could not build archive!");
+        let bhd5 = BHD5::from_encrypted_bytes(&archive.bhd, archive.public_key.as_bytes()).expect("Could not parse BHD5!");
+        let mut file_header = bhd5.buckets.into_iter().flat_map(|bucket| bucket.file_headers).next().unwrap();
+        assert_eq!(file_header.read_data(&archive.bdt).unwrap(), vec![1; 0x20]);
+
+        // Corrupt offsets are errors rather than panics.
+        file_header.file_offset = u64::MAX - 4;
+        assert!(file_header.read_data(&archive.bdt).is_err());
+        file_header.file_offset = archive.bdt.len() as u64;
+        assert!(file_header.read_data(&archive.bdt).is_err());
+
+        // ER stores file sizes in 32 bits.
+        file_header.file_offset = 0;
+        file_header.file_size = u32::MAX as u64 + 1;
+        assert!(BHD5::new(BHD5Format::EldenRing, "GR_salt".to_string(), vec![file_header]).to_bytes().is_err());
+        assert!(util::checked_cast::<u32, u64>(u32::MAX as u64 + 1, "Offset").is_err());
+    }
+
+    #[test]
+    fn round_trip_writers() {
+        use crate::roundtrip::check_round_trip;
+
+        check_round_trip::<BND4>();
+        check_round_trip::<BND3>();
+        check_round_trip::<DCX>();
+        check_round_trip::<BHD5>();
+        check_round_trip::<mqb::MQB>();
+    }
+
+    #[test]
+    fn golden_testdata() {
+        // Writers still produce the embedded bytes.
+        assert_eq!(fixtures::bnd4_bytes(testdata::BND4_FILE_COUNT, testdata::BND4_FILE_SIZE).unwrap(), testdata::BND4_BYTES);
+        assert_eq!(testdata::bhd5_bytes().unwrap(), testdata::BHD5_BYTES);
+        assert_eq!(fixtures::dflt_dcx_bytes(testdata::DCX_CONTENT_SIZE).unwrap(), testdata::DFLT_DCX_BYTES);
+
+        let bnd4 = BND4::from_bytes(testdata::BND4_BYTES).expect("Could not parse sample BND4!");
+        let names: Vec<_> = bnd4.files.iter().map(|file| (file.id, file.name.clone().unwrap())).collect();
+        assert_eq!(names, vec![(Some(0), fixtures::sample_file_name(0)), (Some(1), fixtures::sample_file_name(1))]);
+        assert_eq!(bnd4.files[1].data, Some(fixtures::sample_data(testdata::BND4_FILE_SIZE)));
+
+        let bhd5 = BHD5::from_bytes(testdata::BHD5_BYTES).expect("Could not parse sample BHD5!");
+        assert!(bhd5.format == BHD5Format::EldenRing);
+        assert_eq!(bhd5.bhd5_header.salt, testdata::BHD5_SALT.as_bytes());
+        let file_headers: Vec<_> = bhd5.buckets.iter().flat_map(|bucket| &bucket.file_headers).collect();
+        assert_eq!(file_headers.len(), 3);
+        assert_eq!(file_headers.iter().filter(|f| f.aes_key.as_ref().is_some_and(|key| key.key == vec![0x11; 16])).count(), 1);
+        assert_eq!(file_headers.iter().filter(|f| f.salted_hash.is_some()).count(), 1);
+
+        let dcx = DCX::from_bytes(testdata::DFLT_DCX_BYTES).expect("Could not parse sample DCX!");
+        assert_eq!(dcx.header.format, "DFLT");
+        assert_eq!(dcx.decompress().unwrap(), fixtures::sample_data(testdata::DCX_CONTENT_SIZE));
+        assert_eq!(dcx.to_bytes().unwrap(), testdata::DFLT_DCX_BYTES);
+    }
+
+    #[test]
+    fn corrupt_headers_are_errors() {
+        let invalid_data = |result: Result<(), error::DantelionFormatsError>| {
+            matches!(result, Err(error::DantelionFormatsError::IoError(e)) if e.kind() == std::io::ErrorKind::InvalidData)
+        };
+        // Constant header fields that don't match
+        let mut bnd4 = testdata::BND4_BYTES.to_vec();
+        bnd4[6] = 1;
+        assert!(invalid_data(BND4::from_bytes(&bnd4).map(drop)));
+        let mut dcx = testdata::DFLT_DCX_BYTES.to_vec();
+        dcx[0x2C] = 0xFF;
+        assert!(invalid_data(DCX::from_bytes(&dcx).map(drop)));
+
+        // Counts far past the end of the file fail without reserving room for them
+        let mut bhd5 = testdata::BHD5_BYTES.to_vec();
+        bhd5[0x10..0x14].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(BHD5::from_bytes(&bhd5).is_err());
+        let mut tpf = fixtures::tpf_bytes(2, 16).unwrap();
+        tpf[8..0xC].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(TPF::from_bytes(&tpf).is_err());
+    }
+
+    #[test]
+    fn bhd5_counts_past_end() {
+        let invalid_data = |file: &[u8]| matches!(BHD5::from_bytes(file), Err(error::DantelionFormatsError::IoError(e)) if e.kind() == std::io::ErrorKind::InvalidData);
+
+        // The first bucket's file header count
+        let buckets_offset = u32::from_le_bytes(testdata::BHD5_BYTES[0x14..0x18].try_into().unwrap()) as usize;
+        let mut bhd5 = testdata::BHD5_BYTES.to_vec();
+        bhd5[buckets_offset..buckets_offset + 4].copy_from_slice(&0x0FFFFFFFu32.to_le_bytes());
+        assert!(invalid_data(&bhd5));
+
+        // The range count after an AES key
+        let parsed = BHD5::from_bytes(testdata::BHD5_BYTES).unwrap();
+        let aes_key_offset = parsed.iter().find(|file_header| file_header.aes_key.is_some()).unwrap().aes_key_offset as usize;
+        let mut bhd5 = testdata::BHD5_BYTES.to_vec();
+        bhd5[aes_key_offset + 16..aes_key_offset + 20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(invalid_data(&bhd5));
+    }
+
+    #[cfg(not(feature = "crypto"))]
+    #[test]
+    fn encrypted_entries_without_crypto() {
+        let bhd5 = testdata::bhd5();
+        let bdt = vec![0; 0x100];
+        for file_header in bhd5.buckets.iter().flat_map(|bucket| &bucket.file_headers) {
+            assert_eq!(file_header.read_data(&bdt).is_ok(), file_header.aes_key.is_none());
+        }
+    }
+
+    #[test]
+    fn stable_accessors() {
+        let bnd4 = BND4::from_bytes(testdata::BND4_BYTES).unwrap();
+        assert_eq!(bnd4.header.version(), "07D7R6");
+        assert_eq!(bnd4.header.file_count(), 2);
+        assert_eq!(bnd4.files[1].data(), Some(&fixtures::sample_data(testdata::BND4_FILE_SIZE)[..]));
+
+        let edge = DCX::compress_edge(&fixtures::sample_data(0x18000));
+        assert_eq!(edge.header.format(), "EDGE");
+        assert_eq!(edge.header.egdt.as_ref().unwrap().block_size(), 0x10000);
+        assert!(edge.header.egdt.as_ref().unwrap().blocks.iter().all(|block| block.is_compressed()));
+
+        let options = BND4WriteOptions::default().data_alignment(0x20).pad_end(true);
+        let bytes = bnd4.to_bytes(&options).unwrap();
+        assert_eq!(bytes.len() % 0x20, 0);
+    }
+
+    #[test]
+    fn display_summaries() {
+        let bnd4 = BND4::from_bytes(testdata::BND4_BYTES).unwrap();
+        assert!(bnd4.to_string().starts_with("BND4 v07D7R6 — 2 files"), "{}", bnd4);
+        assert!(format!("{:?}", bnd4.files[1]).contains("[16 bytes]"));
+
+        let bhd5 = BHD5::from_bytes(testdata::BHD5_BYTES).unwrap();
+        assert!(bhd5.to_string().starts_with("BHD5 EldenRing — "), "{}", bhd5);
+
+        let dcx = DCX::from_bytes(testdata::DFLT_DCX_BYTES).unwrap();
+        assert!(dcx.to_string().starts_with("DCX DFLT — "), "{}", dcx);
+        assert!(format!("{:?}", dcx).contains("bytes]"));
+
+        let parsed = parsed_file::open_bytes(testdata::BND4_BYTES).unwrap();
+        assert_eq!(parsed.to_string(), bnd4.to_string());
+    }
+
+    #[test]
+    fn iterate_containers() {
+        let mut bnd4 = BND4::from_bytes(testdata::BND4_BYTES).unwrap();
+        let ids: Vec<_> = bnd4.iter().filter_map(|file| file.id()).collect();
+        assert_eq!(ids, (&bnd4).into_iter().map(|file| file.id.unwrap()).collect::<Vec<_>>());
+        for file in &mut bnd4 {
+            file.id = file.id.map(|id| id + 1);
+        }
+        assert_eq!(bnd4.into_iter().map(|file| file.id.unwrap() - 1).collect::<Vec<_>>(), ids);
+
+        let bnd3 = BND3::from_bnd4(&BND4::from_bytes(testdata::BND4_BYTES).unwrap()).unwrap();
+        assert_eq!(bnd3.iter().count(), 2);
+
+        let bhd5 = BHD5::from_bytes(testdata::BHD5_BYTES).unwrap();
+        let count: usize = bhd5.buckets.iter().map(|bucket| bucket.file_headers.len()).sum();
+        assert_eq!(count, 3);
+        assert_eq!(bhd5.iter().count(), count);
+        assert!((&bhd5).into_iter().any(|file_header| file_header.file_path_hash == BHD5::hash_path("/sample/c.bin", bhd5.format)));
+        assert_eq!(bhd5.into_iter().count(), count);
+    }
+
+    #[test]
+    fn index_binders() {
+        let mut bnd4 = BND4::from_bytes(testdata::BND4_BYTES).unwrap();
+        let name = bnd4.files[1].name.clone().unwrap();
+        assert_eq!(bnd4[1].name(), Some(&name[..]));
+        assert_eq!(bnd4[&name[..]].data(), Some(&fixtures::sample_data(testdata::BND4_FILE_SIZE)[..]));
+        assert_eq!(bnd4.get(bnd4[1].id.unwrap()).unwrap().name(), Some(&name[..]));
+        assert!(bnd4.get("missing.bin").is_none());
+        bnd4[&name[..]].data = Some(b"replaced".to_vec());
+        assert_eq!(bnd4[1].data(), Some(&b"replaced"[..]));
+
+        let bnd3 = BND3::from_bnd4(&bnd4).unwrap();
+        assert_eq!(bnd3[&name[..]].data.as_deref(), Some(&b"replaced"[..]));
+        assert!(std::panic::catch_unwind(|| bnd3["missing.bin"].id).is_err());
+    }
+
+    #[test]
+    fn decode_binder_versions() {
+        let bnd4 = BND4::from_bytes(testdata::BND4_BYTES).unwrap();
+        let version = bnd4.header.binder_version().unwrap();
+        assert_eq!(version, BinderVersion { year: 2007, month: 4, day: 7, revision: 'R', revision_number: 6 });
+        assert_eq!(version.to_string(), "07D7R6");
+
+        let later = BinderVersion::parse("16K17A1").unwrap();
+        assert!(later > version);
+        assert!(BinderVersion::parse("07D7R7").unwrap() > version);
+        assert!(BinderVersion::parse("07D7R10").unwrap() > BinderVersion::parse("07D7R9").unwrap());
+        assert_eq!(BinderVersion::parse(&BND4Version::Timestamp { year: 2022, month: 2, day: 25, hour: 3, minute: 41 }.to_version_string()),
+            Some(BinderVersion { year: 2022, month: 2, day: 25, revision: 'D', revision_number: 41 }));
+        // Out of range months and hours are clamped rather than running past the letters
+        assert_eq!(BND4Version::Timestamp { year: 2022, month: 200, day: 1, hour: 255, minute: 0 }.to_version_string(), "22L1X0");
+        assert_eq!(BND4Version::Timestamp { year: 2022, month: 0, day: 1, hour: 0, minute: 0 }.to_version_string(), "22A1A0");
+        assert_eq!(BND4Builder::default().build().header.version, BND4Builder::new().build().header.version);
+        assert_eq!(BinderVersion::parse("custom"), None);
+        assert_eq!(BinderVersion::parse("07Z7R6"), None);
+    }
+
+    #[test]
+    fn glob_segments() {
+        assert!(util::glob_matches("/chr/*.dcx", "/chr/c2010.chrbnd.dcx"));
+        assert!(!util::glob_matches("/chr/*.dcx", "/chr/sub/c2010.chrbnd.dcx"));
+        assert!(util::glob_matches("/chr/**.dcx", "/chr/sub/c2010.chrbnd.dcx"));
+        assert!(util::glob_matches("/CHR/**", "/chr/"));
+        // `**/` is zero or more whole folders
+        assert!(util::glob_matches("/chr/**/c2010.*", "/chr/c2010.chrbnd"));
+        assert!(util::glob_matches("/chr/**/c2010.*", "/chr/a/b/c2010.chrbnd"));
+        assert!(!util::glob_matches("/chr/**/c2010.*", "/chr/xc2010.chrbnd"));
+        assert!(util::glob_matches("/chr/c????.chrbnd", "/chr/c2010.chrbnd"));
+        assert!(!util::glob_matches("/chr?c2010.chrbnd", "/chr/c2010.chrbnd"));
+        assert!(util::glob_matches("*", ""));
+        assert!(!util::glob_matches("*", "/"));
+    }
+
+    #[test]
+    fn find_across_archives() {
+        let dictionary = vec!["/sample/a.bin".to_string(), "/sample/C.bin".to_string(), "/other/c.bin".to_string()];
+        let mut vfs = vfs::Vfs::new(dictionary);
+        vfs.add_archive("Data0.bhd", testdata::bhd5());
+        vfs.add_archive("Data1.bhd", BHD5::new(BHD5Format::EldenRing, testdata::BHD5_SALT.to_string(), vec![]));
+
+        let paths = |matches: Vec<vfs::VfsMatch>| matches.into_iter().map(|m| m.path).collect::<Vec<_>>();
+        assert_eq!(paths(vfs.find("/sample/*.bin")), ["/sample/C.bin", "/sample/a.bin"]);
+        assert_eq!(paths(vfs.find("c.BIN")), ["/sample/C.bin"]);
+        assert_eq!(paths(vfs.find("/sample/?.bin")).len(), 2);
+        assert!(vfs.find("/other/*").is_empty());
+
+        let found = &vfs.find("a.bin")[0];
+        assert_eq!(found.bhd_path, std::path::PathBuf::from("Data0.bhd"));
+        assert_eq!(found.hash, BHD5::hash_path("/sample/a.bin", BHD5Format::EldenRing));
+
+        assert_eq!(paths(vfs.find("\\SAMPLE\\a")), ["/sample/a.bin"]);
+        // `*` stays within a folder, `**` doesn't
+        assert!(vfs.find("/*.bin").is_empty());
+        assert_eq!(paths(vfs.find("/**.bin")), ["/sample/C.bin", "/sample/a.bin"]);
+        assert_eq!(paths(vfs.find("**/a.bin")), ["/sample/a.bin"]);
+
+        assert_eq!(paths(vfs.find_regex(r"^/SAMPLE/[a-c]\.bin$").unwrap()), ["/sample/C.bin", "/sample/a.bin"]);
+        assert_eq!(paths(vfs.find_regex(r"a\.").unwrap()), ["/sample/a.bin"]);
+        assert!(vfs.find_regex("(").is_err());
+
+        assert_eq!(vfs.get("sample\\A.bin").unwrap().path, "/sample/a.bin");
+        assert_eq!(vfs.get("N:\\sample\\c.bin").unwrap().path, "/sample/C.bin");
+        assert_eq!(vfs.get("c.bin").unwrap().path, "/sample/C.bin");
+        assert!(vfs.get("other.bin").is_none());
+    }
+
+    #[test]
+    fn name_matcher() {
+        use util::NameMatcher;
+
+        let matcher = NameMatcher::new();
+        assert!(matcher.eq("N:\\GR\\data\\Ä.bin", "n:/gr/data/ä.BIN"));
+        assert!(!matcher.eq("N:\\GR\\data\\a.bin", "/GR/data/a.bin"));
+        assert!(matcher.matches("N:\\GR\\data\\a.bin", "A.BIN") && !matcher.matches("N:\\GR\\data\\a.bin", "data"));
+        let matcher = matcher.ignore_roots(true);
+        assert_eq!(matcher.normalize("N:\\GR\\data\\a.bin"), "gr/data/a.bin");
+        assert!(matcher.eq("N:\\GR\\data\\a.bin", "/GR/data/a.bin"));
+
+        let bnd4 = BND4Builder::new().add_file(0, "N:\\GR\\data\\a.bin", vec![1]).add_file(1, "N:\\GR\\data\\b.bin", vec![2]).build();
+        assert_eq!(bnd4.get("n:/gr/DATA/b.bin").unwrap().id, Some(1));
+        let manifest = manifest::BND4Manifest::from_bnd4(&bnd4);
+        assert_eq!(manifest.files[1].path, "GR/data/b.bin");
+        assert_eq!(manifest.file("/gr/data/B.bin").unwrap().id, Some(1));
+        assert_eq!(manifest.file("a.bin").unwrap().id, Some(0));
+        assert!(manifest.file("c.bin").is_none());
+    }
+
+    // Unencrypted entries in "Data0.bhd"/"Data0.bdt" under `dir`, with every path in the dictionary.
+    fn unpack_fixture(dir: &Path, paths: &[&str]) -> vfs::Vfs {
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+
+        let mut bdt = vec![];
+        let mut file_headers = vec![];
+        for (i, path) in paths.iter().enumerate() {
+            let data = vec![i as u8; 0x10 * (i + 1)];
+            file_headers.push(bhd5::FileHeader {
+                file_path_hash: BHD5::hash_path(path, BHD5Format::EldenRing),
+                padded_file_size: data.len() as u32,
+                file_size: data.len() as u64,
+                file_offset: bdt.len() as u64,
+                salted_hash_offset: 0,
+                aes_key_offset: 0,
+                salted_hash: None,
+                aes_key: None,
+            });
+            bdt.extend(data);
+        }
+        std::fs::write(dir.join("Data0.bdt"), bdt).unwrap();
+
+        let mut vfs = vfs::Vfs::new(paths.iter().map(|path| path.to_string()).collect());
+        vfs.add_archive(&dir.join("Data0.bhd").to_string_lossy(), BHD5::new(BHD5Format::EldenRing, testdata::BHD5_SALT.to_string(), file_headers));
+        vfs
+    }
+
+    #[test]
+    fn unpack_filtered() {
+        let dir = std::env::temp_dir().join("dantelion-formats-unpack");
+        let vfs = unpack_fixture(&dir, &["/msg/engus/item.msgbnd.dcx", "/parts/am_m_1600.tpf.dcx", "/parts/am_m_1600.partsbnd.dcx"]);
+
+        let out = dir.join("out");
+        let unpacker = unpack::Unpacker::new(&out.to_string_lossy());
+        assert!(unpacker.matches("/msg/engus/item.msgbnd.dcx", 0x10));
+        assert_eq!(unpacker.include("/msg/**").unpack(&vfs).unwrap().files, 1);
+        assert_eq!(std::fs::read(out.join("msg/engus/item.msgbnd.dcx")).unwrap(), vec![0; 0x10]);
+
+        let unpacker = unpack::Unpacker::new(&out.to_string_lossy()).extension("TPF.DCX");
+        assert!(!unpacker.matches("/parts/am_m_1600.partsbnd.dcx", 0x10));
+        assert_eq!(unpacker.unpack(&vfs).unwrap().files, 1);
+        let unpacker = unpack::Unpacker::new(&out.to_string_lossy()).include("/parts/*").exclude("**.tpf.dcx").max_size(0x20);
+        assert_eq!(unpacker.unpack(&vfs).unwrap().files, 0);
+        assert!(unpack::Unpacker::new(&out.to_string_lossy()).include("/parts/*").exclude("**.tpf.dcx").matches("/parts/am_m_1600.partsbnd.dcx", 0x30));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sequential_unpack() {
+        use crate::source::{DataSource, FileSource};
+
+        // Enough files that hash order and BDT order disagree
+        let dir = std::env::temp_dir().join("dantelion-formats-sequential-unpack");
+        let paths: Vec<String> = (0..20).map(|i| format!("/chr/c{:04}.anibnd.dcx", i * 10)).collect();
+        let vfs = unpack_fixture(&dir, &paths.iter().map(String::as_str).collect::<Vec<_>>());
+        let offsets: Vec<u64> = vfs.archives[0].bhd5.iter().map(|file_header| file_header.file_offset).collect();
+        assert!(!offsets.is_sorted());
+        let out = dir.join("out");
+        assert_eq!(unpack::Unpacker::new(&out.to_string_lossy()).unpack(&vfs).unwrap().files, 20);
+        for (i, path) in paths.iter().enumerate() {
+            assert_eq!(fs::read(out.join(&path[1..])).unwrap(), vec![i as u8; 0x10 * (i + 1)], "{}", path);
+        }
+
+        // Hints are best effort, even past the end of the file
+        let bdt = dir.join("Data0.bdt").to_string_lossy().to_string();
+        let source = FileSource::open_sequential(&bdt).unwrap();
+        source.prefetch(u64::MAX, usize::MAX);
+        source.prefetch(0, 0x10000);
+        assert_eq!(source.read_at(0x10, 0x20).unwrap(), vec![1; 0x20]);
+        assert!(FileSource::open_sequential(&dir.join("missing.bdt").to_string_lossy()).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn extraction_manifest() {
+        let dir = std::env::temp_dir().join("dantelion-formats-extraction-manifest");
+        let vfs = unpack_fixture(&dir, &["/b.bin", "/a.bin"]);
+        let out = dir.join("out");
+        let out_dir = out.to_string_lossy();
+
+        assert_eq!(unpack::Unpacker::new(&out_dir).write_manifest(true).unpack(&vfs).unwrap().files, 2);
+        let manifest = unpack::ExtractionManifest::from_path(&out.join(unpack::EXTRACTION_MANIFEST_NAME).to_string_lossy()).unwrap();
+        let paths: Vec<_> = manifest.files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, ["/a.bin", "/b.bin"]);
+        assert_eq!(manifest.files[0].offset, 0x10);
+        assert_eq!(manifest.files[0].sha256.len(), 64);
+        assert!(unpack::verify_extraction(&out_dir, &manifest).unwrap().is_empty());
+
+        std::fs::write(out.join("a.bin"), b"modded").unwrap();
+        assert_eq!(unpack::verify_extraction(&out_dir, &manifest).unwrap(), ["/a.bin"]);
+
+        // Nothing moved, so only the deleted file is written again.
+        std::fs::remove_file(out.join("b.bin")).unwrap();
+        assert_eq!(unpack::Unpacker::new(&out_dir).previous(manifest).unpack(&vfs).unwrap().files, 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unpack_patch_changes() {
+        let dir = std::env::temp_dir().join("dantelion-formats-patch-changes");
+        let old = unpack_fixture(&dir.join("old"), &["/same.bin", "/changed.bin", "/removed.bin"]);
+        let mut new = unpack_fixture(&dir.join("new"), &["/same.bin", "/changed.bin", "/added.bin"]);
+
+        let mut changes: Vec<_> = old.diff(&new).into_iter().map(|change| (change.kind, change.path.unwrap())).collect();
+        changes.sort_by(|a, b| a.1.cmp(&b.1));
+        // "/changed.bin" is the same size in both fixtures, so it only counts as changed once its size differs.
+        assert_eq!(changes, [(vfs::ChangeKind::Added, "/added.bin".to_string()), (vfs::ChangeKind::Removed, "/removed.bin".to_string())]);
+
+        for file_header in &mut new.archives[0].bhd5 {
+            if file_header.file_path_hash == BHD5::hash_path("/changed.bin", BHD5Format::EldenRing) {
+                file_header.file_size = 0x18;
+            }
+        }
+        assert!(old.diff(&new).iter().any(|change| change.kind == vfs::ChangeKind::Changed && change.path.as_deref() == Some("/changed.bin")));
+
+        let out = dir.join("out");
+        assert_eq!(unpack::Unpacker::new(&out.to_string_lossy()).unpack_changes(&old, &new).unwrap().files, 2);
+        assert!(out.join("added.bin").is_file() && out.join("changed.bin").is_file() && !out.join("same.bin").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn decompress_spilling() {
+        use crate::source::DataSource;
+
+        let data = fixtures::sample_data(0x28000);
+        for dcx in [DCX::compress_dflt(&data), DCX::compress_edge(&data)] {
+            let payload = dcx.decompress_spilling(0x1000).unwrap();
+            assert!(payload.is_spilled());
+            let spill::Payload::File(file) = &payload else { unreachable!() };
+            let path = file.path.clone();
+            assert_eq!(payload.read_at(0x10000, 0x10).unwrap(), data[0x10000..0x10010]);
+            assert_eq!(payload.into_vec().unwrap(), data);
+            assert!(!path.exists());
+        }
+
+        let dcx = DCX::from_bytes(testdata::DFLT_DCX_BYTES).unwrap();
+        let payload = dcx.decompress_spilling(0x1000).unwrap();
+        assert!(!payload.is_spilled());
+        assert_eq!(payload.into_vec().unwrap(), fixtures::sample_data(testdata::DCX_CONTENT_SIZE));
+
+        // A file already at the next spill name is left alone
+        let next = spill::SPILL_COUNTER.load(std::sync::atomic::Ordering::Relaxed);
+        let planted = std::env::temp_dir().join(format!("dantelion-spill-{}-{}", std::process::id(), next));
+        fs::write(&planted, b"planted").unwrap();
+        let payload = spill::Payload::from_vec(data.clone(), 0x1000).unwrap();
+        assert_eq!(payload.into_vec().unwrap(), data);
+        assert_eq!(fs::read(&planted).unwrap(), b"planted");
+        fs::remove_file(&planted).unwrap();
+    }
+
+    #[test]
+    fn unpack_stats() {
+        let dir = std::env::temp_dir().join("dantelion-formats-unpack-stats");
+        let vfs = unpack_fixture(&dir, &["/a.tpf.dcx", "/b.tpf.dcx", "/c.fmg"]);
+        let mut stats = unpack::Unpacker::new(&dir.join("out").to_string_lossy()).unpack(&vfs).unwrap();
+        assert_eq!(stats.files, 3);
+        assert_eq!(stats.bytes_read, 0x60);
+        assert_eq!(stats.bytes_written, 0x60);
+        assert_eq!(stats.formats.get("tpf.dcx"), Some(&2));
+        assert_eq!(stats.formats.get("fmg"), Some(&1));
+
+        stats.merge(&stats.clone());
+        assert_eq!((stats.files, stats.formats["fmg"]), (6, 2));
+        assert!(stats.to_string().starts_with("6 files, 192 bytes read"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_big_endian_bnd4() {
+        let bnd4 = BND4::from_bytes(testdata::BND4_BYTES).unwrap();
+        let options = BND4WriteOptions::default().big_endian(true);
+        let be = BND4::from_bytes(&bnd4.to_bytes(&options).unwrap()).unwrap();
+        assert!(be.header.big_endian());
+        assert_eq!(be.header.format(), bnd4.header.format());
+        assert_eq!(be.header.raw_format, bnd4.header.format());
+        for (be_file, file) in be.files.iter().zip(&bnd4.files) {
+            assert_eq!(be_file.raw_flags, util::reverse_bits(file.raw_flags));
+            assert_eq!((be_file.id, be_file.name(), be_file.data()), (file.id, file.name(), file.data()));
+        }
+
+        // And back, byte for byte.
+        let le = be.to_bytes(&BND4WriteOptions::default().big_endian(false)).unwrap();
+        assert_eq!(le, bnd4.to_bytes(&BND4WriteOptions::default()).unwrap());
+
+        let built = BND4Builder::new().big_endian(true).add_file(0, "a.bin", b"data".to_vec()).build();
+        let built = BND4::from_bytes(&built.to_bytes(&BND4WriteOptions::default()).unwrap()).unwrap();
+        assert!(built.header.big_endian());
+        assert_eq!(built.get("a.bin").unwrap().data(), Some(&b"data"[..]));
+    }
+
+    #[test]
+    fn bnd4_name_hashes() {
+        assert_eq!(util::path_hash("a"), '/' as u32 * 37 + 'a' as u32);
+        assert_eq!(BND4::name_hash("N:\\FDP\\data\\A.bin"), BND4::name_hash("/n:/fdp/data/a.bin"));
+
+        let built = BND4Builder::new()
+            .extended(true)
+            .add_file(0, "a.bin", b"a".to_vec())
+            .add_file(1, "b.bin", b"b".to_vec())
+            .build();
+        let mut bnd4 = BND4::from_bytes(&built.to_bytes(&BND4WriteOptions::default()).unwrap()).unwrap();
+        let hashes = &bnd4.buckets.as_ref().unwrap().hashes;
+        let hash = hashes.iter().find(|hash| hash.index == 1).unwrap().hash;
+        assert_eq!(hash, BND4::name_hash("b.bin"));
+        assert!(bnd4.mismatched_hashes().is_empty());
+
+        bnd4.files[1].name = Some("c.bin".to_string());
+        assert_eq!(bnd4.mismatched_hashes(), vec![1]);
+    }
+
+    #[test]
+    fn bnd4_duplicates() {
+        let mut bnd4 = BND4Builder::new()
+            .add_file(0, "N:\\GR\\data\\a.bin", vec![])
+            .add_file(1, "b.bin", vec![])
+            .add_file(0, "c.bin", vec![])
+            .add_file(2, "n:/gr/data/A.BIN", vec![])
+            .build();
+        assert_eq!(bnd4.duplicates(), vec![
+            BND4Duplicate::Id { id: 0, indices: vec![0, 2] },
+            BND4Duplicate::Name { name: "N:\\GR\\data\\a.bin".to_string(), indices: vec![0, 3] },
+        ]);
+
+        bnd4.files[2].id = Some(3);
+        bnd4.files[3].name = Some("d.bin".to_string());
+        assert!(bnd4.duplicates().is_empty());
+    }
+
+    #[test]
+    fn bhd5_bucket_sizing() {
+        assert_eq!(BHD5::bucket_count_for(0, BHD5::DEFAULT_LOAD_FACTOR).unwrap(), 2);
+        assert_eq!(BHD5::bucket_count_for(100, BHD5::DEFAULT_LOAD_FACTOR).unwrap(), 17);
+        assert_eq!(BHD5::bucket_count_for(100, 1.0).unwrap(), 101);
+        assert_eq!(BHD5::bucket_count_for(100, 0.5).unwrap(), 211);
+        for load_factor in [0.0, -1.0, f64::NAN, f64::INFINITY, 1e-300, 1e6] {
+            assert!(BHD5::bucket_count_for(100, load_factor).is_err(), "{}", load_factor);
+        }
+        assert!(BHD5::bucket_count_for(usize::MAX, 0.1).is_err());
+
+        let file_headers: Vec<_> = (0..50).map(|i| bhd5::FileHeader {
+            file_path_hash: BHD5::hash_path(&format!("/file_{}.bin", i), BHD5Format::EldenRing),
+            padded_file_size: 0x10,
+            file_size: 0x10,
+            file_offset: i * 0x10,
+            salted_hash_offset: 0,
+            aes_key_offset: 0,
+            salted_hash: None,
+            aes_key: None,
+        }).collect();
+        let bhd5 = BHD5::with_load_factor(BHD5Format::EldenRing, testdata::BHD5_SALT.to_string(), file_headers, 2.0).unwrap();
+        assert_eq!(bhd5.buckets.len(), 29);
+        assert_eq!(bhd5.bhd5_header.bucket_count, 29);
+        for (index, bucket) in bhd5.buckets.iter().enumerate() {
+            assert_eq!(bucket.file_header_count as usize, bucket.file_headers.len());
+            assert!(bucket.file_headers.iter().all(|file_header| file_header.file_path_hash % 29 == index as u64));
+        }
+
+        let reread = BHD5::from_bytes(&bhd5.to_bytes().unwrap()).unwrap();
+        assert_eq!(reread.iter().count(), 50);
+    }
+
+    #[test]
+    fn compact_bhd5() {
+        use crate::bhd5::CompactBHD5;
+
+        let paths = ["/sample/a.bin", "/sample/b.bin", "/sample/c.bin"];
+        let ds3 = testdata::bhd5().convert(BHD5Format::DarkSoulsIII, &paths).unwrap();
+        for bytes in [testdata::bhd5_bytes().unwrap(), ds3.to_bytes().unwrap()] {
+            let bhd5 = BHD5::from_bytes(&bytes).unwrap();
+            let compact = CompactBHD5::from_bytes(&bytes).unwrap();
+            assert_eq!(compact.format, bhd5.format);
+            assert_eq!(compact.buckets.len(), bhd5.buckets.len());
+            assert_eq!(compact.len(), 3);
+            assert_eq!((compact.entries.len(), compact.bytes.len(), compact.ranges.len()), (2, 48, 2));
+            for (index, bucket) in bhd5.buckets.iter().enumerate() {
+                for (file_header, file) in bucket.file_headers.iter().zip(compact.bucket(index)) {
+                    let rebuilt = compact.file_header(file);
+                    assert_eq!(rebuilt.file_path_hash, file_header.file_path_hash);
+                    assert_eq!(rebuilt.file_size, file_header.file_size);
+                    assert_eq!(rebuilt.file_offset, file_header.file_offset);
+                    assert_eq!(rebuilt.salted_hash, file_header.salted_hash);
+                    assert_eq!(rebuilt.aes_key, file_header.aes_key);
+                }
+            }
+            assert_eq!(compact.to_bhd5().to_bytes().unwrap(), bytes);
+
+            let a = compact.find("/sample/a.bin").unwrap();
+            assert_eq!(compact.aes_key(a).unwrap(), (&[0x11; 16][..], &[bhd5::Range { begin: 0, end: 0x20 }][..]));
+            assert!(compact.salted_hash(a).is_none());
+            assert_eq!(compact.salted_hash(compact.find("/sample/b.bin").unwrap()).unwrap().0, &[0x22; 32]);
+            assert!(compact.find("/sample/missing.bin").is_none());
+        }
+    }
+
+    #[test]
+    fn compact_bhd5_matches_bhd5() {
+        use crate::bhd5::CompactBHD5;
+
+        let bhd5 = BHD5::from_bytes(testdata::BHD5_BYTES).unwrap();
+        let compact = CompactBHD5::from_bytes(testdata::BHD5_BYTES).unwrap();
+        assert_eq!((compact.bhd5_header.salt(), compact.buckets.len()), (bhd5.bhd5_header.salt(), bhd5.buckets.len()));
+        // Files are numbered in bucket order, so they line up with `BHD5::iter`
+        assert_eq!(compact.len(), bhd5.iter().count());
+        for (index, file_header) in bhd5.iter().enumerate() {
+            let rebuilt = compact.file_header(index);
+            assert_eq!(
+                (rebuilt.file_path_hash, rebuilt.padded_file_size, rebuilt.file_size, rebuilt.file_offset),
+                (file_header.file_path_hash, file_header.padded_file_size, file_header.file_size, file_header.file_offset),
+            );
+            assert_eq!((&rebuilt.salted_hash, &rebuilt.aes_key), (&file_header.salted_hash, &file_header.aes_key));
+            let key = file_header.aes_key.as_ref().map(|key| (&key.key[..], &key.ranges[..]));
+            assert_eq!(compact.aes_key(index), key);
+        }
+        assert_eq!(compact.to_bhd5().to_bytes().unwrap(), testdata::BHD5_BYTES);
+
+        let mut corrupt = testdata::BHD5_BYTES.to_vec();
+        corrupt[0x10..0x14].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(CompactBHD5::from_bytes(&corrupt).is_err());
+    }
+
+    #[test]
+    fn archive_index_cache() {
+        use crate::dictionary::{Dictionary, HashWidth};
+        use crate::index::ArchiveIndex;
+
+        let dir = std::env::temp_dir().join("dantelion-formats-index");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let bhd_path = dir.join("Data0.bhd").to_string_lossy().to_string();
+        let index_path = dir.join("Data0.index").to_string_lossy().to_string();
+        fs::write(&bhd_path, testdata::bhd5_bytes().unwrap()).unwrap();
+        let mut dictionary = Dictionary::new(HashWidth::Bits64);
+        dictionary.insert("/sample/a.bin");
+
+        let built = ArchiveIndex::load_or_build(&bhd_path, &index_path, &dictionary).unwrap();
+        let written = fs::read(&index_path).unwrap();
+        // Read back from the cache, which an empty dictionary can't have named
+        let loaded = ArchiveIndex::load_or_build(&bhd_path, &index_path, &Dictionary::new(HashWidth::Bits64)).unwrap();
+        assert_eq!(fs::read(&index_path).unwrap(), written);
+        for index in 0..built.bhd5.len() {
+            assert_eq!(loaded.name(index), built.name(index));
+            assert_eq!(loaded.bhd5.file_header(index).aes_key, built.bhd5.file_header(index).aes_key);
+        }
+        let a = loaded.bhd5.find("/sample/a.bin").unwrap();
+        assert_eq!(loaded.name(a), Some("/sample/a.bin"));
+        assert_eq!(loaded.bhd5.to_bhd5().to_bytes().unwrap(), testdata::bhd5_bytes().unwrap());
+
+        // Damaged and stale caches are rebuilt
+        fs::write(&index_path, &written[..written.len() / 2]).unwrap();
+        assert_eq!(ArchiveIndex::load_or_build(&bhd_path, &index_path, &dictionary).unwrap().names.len(), 1);
+        assert_eq!(fs::read(&index_path).unwrap(), written);
+        let mut bhd = testdata::bhd5();
+        bhd.buckets.iter_mut().for_each(|bucket| bucket.file_headers.retain(|file_header| file_header.aes_key.is_none()));
+        fs::write(&bhd_path, bhd.to_bytes().unwrap()).unwrap();
+        let rebuilt = ArchiveIndex::load_or_build(&bhd_path, &index_path, &dictionary).unwrap();
+        assert_eq!((rebuilt.bhd5.len(), rebuilt.names.len()), (2, 0));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn read_bdt_prefix() {
+        let data: Vec<u8> = (0..0x40).collect();
+        let archive = BHD5ArchiveBuilder::new(BHD5Format::EldenRing)
+            .add_file("/sample/a.bin", data.clone())
+            .add_file("/sample/b.bin", b"DCX\0".to_vec())
+            .build()
+            .unwrap();
+        let bhd5 = BHD5::from_encrypted_bytes(&archive.bhd, archive.public_key.as_bytes()).unwrap();
+        let file_header = |path: &str| bhd5.iter()
+            .find(|f| f.file_path_hash == BHD5::hash_path(path, BHD5Format::EldenRing))
+            .unwrap();
+
+        let bdt = archive.bdt.as_slice();
+        assert_eq!(file_header("/sample/a.bin").read_prefix_from(bdt, 4).unwrap(), &data[..4]);
+        assert_eq!(file_header("/sample/a.bin").read_prefix_from(bdt, 0x21).unwrap(), &data[..0x21]);
+        assert_eq!(file_header("/sample/a.bin").read_prefix_from(bdt, 0x100).unwrap(), data);
+        assert_eq!(file_header("/sample/b.bin").read_prefix_from(bdt, 0x10).unwrap(), b"DCX\0");
+    }
+
+    #[test]
+    fn sniff_file_kinds() {
+        use kind::FileKind;
+
+        assert_eq!(FileKind::sniff(testdata::BND4_BYTES), FileKind::BND4);
+        assert_eq!(FileKind::sniff(b"DCX\0\0\x01\0\0"), FileKind::DCX);
+        assert_eq!(FileKind::sniff(b"FLVER\0L\0"), FileKind::FLVER);
+        assert_eq!(FileKind::sniff(b"TPF\0"), FileKind::TPF);
+        assert_eq!(FileKind::sniff(b"\x1bLuaQ"), FileKind::Lua);
+        assert_eq!(FileKind::sniff(b"BKHD"), FileKind::BNK);
+        assert_eq!(FileKind::sniff(b"BND"), FileKind::Unknown);
+        assert_eq!(FileKind::sniff(&[]), FileKind::Unknown);
+
+        // Little endian FMG, version 2, 0x100 bytes long with one group.
+        let mut fmg = vec![0, 0, 2, 0, 0, 1, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0];
+        fmg.resize(0x100, 0);
+        assert_eq!(FileKind::sniff(&fmg), FileKind::FMG);
+        assert_eq!(FileKind::sniff(&fmg[..FileKind::SNIFF_SIZE]), FileKind::FMG);
+
+        // Little endian PARAM with 2 rows and its strings at 0x60.
+        let mut param = vec![0x60, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0];
+        param.resize(0x60, 0);
+        assert_eq!(FileKind::sniff(&param), FileKind::PARAM);
+        param[0x2C] = 1;
+        assert_eq!(FileKind::sniff(&param), FileKind::Unknown);
+    }
+
+    #[test]
+    fn sniff_vfs_entries() {
+        let dir = std::env::temp_dir().join("dantelion-formats-sniff");
+        let vfs = unpack_fixture(&dir, &["/a.bin"]);
+        let entry = &vfs.find("a.bin")[0];
+        assert_eq!(vfs.sniff(entry).unwrap(), kind::FileKind::Unknown);
+
+        let out = dir.join("out");
+        unpack::Unpacker::new(&out.to_string_lossy()).write_manifest(true).unpack(&vfs).unwrap();
+        let manifest = unpack::ExtractionManifest::from_path(&out.join(unpack::EXTRACTION_MANIFEST_NAME).to_string_lossy()).unwrap();
+        assert_eq!(manifest.files[0].kind, kind::FileKind::Unknown);
+
+        let json = manifest.to_json().unwrap().replace(",\n      \"kind\": \"Unknown\"", "");
+        assert!(!json.contains("kind"));
+        let old: unpack::ExtractionManifest = serde_json::from_str(&json).unwrap();
+        assert_eq!(old.files[0], manifest.files[0]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unpack_unnamed() {
+        use unpack::{Unpacker, UnnamedPolicy};
+
+        let dir = std::env::temp_dir().join("dantelion-formats-unnamed");
+        let mut vfs = unpack_fixture(&dir, &["/a.bin", "/b.bin"]);
+        // Only "/a.bin" has a name, "/b.bin" holds a BND4.
+        let b_hash = BHD5::hash_path("/b.bin", BHD5Format::EldenRing);
+        vfs.archives[0].names.remove(&b_hash);
+        let b_header = vfs.archives[0].bhd5.iter().find(|f| f.file_path_hash == b_hash).unwrap();
+        let mut bdt = std::fs::read(dir.join("Data0.bdt")).unwrap();
+        bdt.truncate(b_header.file_offset as usize);
+        bdt.extend(&testdata::BND4_BYTES[..0x20]);
+        std::fs::write(dir.join("Data0.bdt"), bdt).unwrap();
+
+        let out = dir.join("out");
+        assert_eq!(Unpacker::new(&out.to_string_lossy()).unpack(&vfs).unwrap().files, 1);
+        assert_eq!(Unpacker::new(&out.to_string_lossy()).unnamed(UnnamedPolicy::Hash).unpack(&vfs).unwrap().files, 2);
+        assert!(out.join(format!("_unknown/{:016x}", b_hash)).is_file());
+
+        let stats = Unpacker::new(&out.to_string_lossy())
+            .unnamed(UnnamedPolicy::HashWithExtension)
+            .extension("bnd")
+            .write_manifest(true)
+            .unpack(&vfs)
+            .unwrap();
+        assert_eq!(stats.files, 1);
+        let path = format!("/_unknown/{:016x}.bnd", b_hash);
+        assert!(out.join(&path[1..]).is_file());
+
+        // Unchanged unnamed entries are found again by hash.
+        let manifest = unpack::ExtractionManifest::from_path(&out.join(unpack::EXTRACTION_MANIFEST_NAME).to_string_lossy()).unwrap();
+        assert_eq!(manifest.files[0].path, path);
+        let stats = Unpacker::new(&out.to_string_lossy())
+            .unnamed(UnnamedPolicy::HashWithExtension)
+            .previous(manifest)
+            .unpack(&vfs)
+            .unwrap();
+        assert_eq!(stats.files, 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn bhd5_key_lookup() {
+        assert!(crypto_util::bhd5_key_matches(r"G:\Steam\ELDEN RING\Game\sd\sd.bhd", r"sd\sd"));
+        assert!(crypto_util::bhd5_key_matches("/games/ELDEN RING/Game/SD/SD.BHD", r"sd\sd"));
+        assert!(crypto_util::bhd5_key_matches("sd/sd.bhd", r"sd\sd"));
+        assert!(!crypto_util::bhd5_key_matches("/Game/sd/sd_dlc02.bhd", r"sd\sd"));
+        assert!(!crypto_util::bhd5_key_matches("/Game/xsd/sd.bhd", r"sd\sd"));
+        assert!(crypto_util::bhd5_key_matches("/Game/Data0.bhd", "Data0"));
+
+        let sd = crypto_util::get_elden_ring_bhd5_key("/Game/sd/sd.bhd").unwrap();
+        assert!(std::str::from_utf8(sd).unwrap().starts_with("-----BEGIN RSA PUBLIC KEY-----"));
+        assert_ne!(sd, crypto_util::get_elden_ring_bhd5_key("/Game/Data0.bhd").unwrap());
+        assert!(crypto_util::get_elden_ring_bhd5_key("/Game/sd/sd_dlc02.bhd").is_err());
+
+        let key_store = config::Config::new().with_bhd5_key("sd/sd_dlc02", "dlc key").key_store();
+        let candidates = key_store.candidates(GameType::EldenRing, r"C:\Game\sd\sd_dlc02.bhd", None);
+        assert_eq!(candidates.iter().map(|key| key.public_key.as_str()).collect::<Vec<_>>(), ["dlc key"]);
+        let candidates = key_store.candidates(GameType::EldenRing, "/Game/sd/sd.bhd", None);
+        assert_eq!(candidates.iter().map(|key| key.public_key.as_bytes()).collect::<Vec<_>>(), [sd]);
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn configured_bhd5_keys() {
+        let _env = ENV_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = std::env::temp_dir().join("dantelion-formats-configured-keys");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("Game/sd")).unwrap();
+        let bhd_path = dir.join("Game/sd/sd_dlc02.bhd").to_string_lossy().to_string();
+        let archive = BHD5ArchiveBuilder::new(BHD5Format::EldenRing)
+            .add_file("/wem/1/1000.wem", vec![1; 0x20])
+            .build()
+            .unwrap();
+        archive.write(&bhd_path, &dir.join("Game/sd/sd_dlc02.bdt").to_string_lossy()).unwrap();
+
+        let config_path = dir.join("dantelion.json").to_string_lossy().to_string();
+        let game_dir = dir.join("Game").to_string_lossy().to_string();
+        std::env::set_var(config::CONFIG_PATH_ENV_VAR, &config_path);
+        fs::write(&config_path, config::Config::new().to_json().unwrap()).unwrap();
+        let unkeyed = vfs::Vfs::open_install(&game_dir, vec![]);
+        assert!(matches!(BHD5::from_path(&bhd_path), Err(DantelionFormatsError::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound));
+
+        let (wrong_public, _) = crypto_util::generate_bhd5_key_pair().unwrap();
+        fs::write(&config_path, config::Config::new().with_bhd5_key("sd/sd_dlc02", &wrong_public).to_json().unwrap()).unwrap();
+        let wrong_key = BHD5::from_path(&bhd_path);
+
+        fs::write(&config_path, config::Config::new().with_bhd5_key("sd/sd_dlc02", &archive.public_key).to_json().unwrap()).unwrap();
+        let bhd5 = BHD5::from_path(&bhd_path);
+        let keyed = vfs::Vfs::open_install(&game_dir, vec![]);
+        std::env::remove_var(config::CONFIG_PATH_ENV_VAR);
+
+        assert!(unkeyed.is_err());
+        assert!(wrong_key.is_err());
+        assert_eq!(bhd5.unwrap().iter().count(), 1);
+        assert_eq!(keyed.unwrap().archives.len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn decrypt_bhd5_blocks() {
+        let (public_key, private_key) = crypto_util::generate_bhd5_key_pair().unwrap();
+        let data: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let encrypted = crypto_util::encrypt_bhd5_file(&data, private_key.as_bytes()).unwrap();
+        assert_eq!(encrypted.len() % 256, 0);
+
+        let decrypted = crypto_util::decrypt_bhd5_file(&encrypted, public_key.as_bytes()).unwrap();
+        assert_eq!(&decrypted[..data.len()], data.as_slice());
+
+        let mut streamed = vec![];
+        let written = crypto_util::decrypt_bhd5_stream(&mut encrypted.as_slice(), public_key.as_bytes(), &mut streamed).unwrap();
+        assert_eq!(written as usize, streamed.len());
+        assert_eq!(streamed, decrypted);
+
+        // A truncated last block is an error rather than a panic.
+        let truncated = &encrypted[..encrypted.len() - 10];
+        assert!(matches!(
+            crypto_util::decrypt_bhd5_file(truncated, public_key.as_bytes()),
+            Err(DantelionFormatsError::MisalignedRsaData { len, key_size: 256 }) if len == truncated.len() as u64
+        ));
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn rotated_bhd5_keys() {
+        use keys::{KeyStore, PatchVersion};
+
+        let version = |v: &str| PatchVersion::parse(v).unwrap();
+        assert!(version("1.9") < version("1.10"));
+        assert_eq!(version("1.10.0"), version("1.10"));
+        assert_eq!(version("1.10.0").to_string(), "1.10");
+        assert!(PatchVersion::parse("1.x").is_none());
+
+        let (old_public, old_private) = crypto_util::generate_bhd5_key_pair().unwrap();
+        let (new_public, new_private) = crypto_util::generate_bhd5_key_pair().unwrap();
+        let store = KeyStore::new()
+            .add(GameType::DarkSoulsIII, "Data1", Some(version("1.0")..=version("1.14")), &old_public)
+            .add(GameType::DarkSoulsIII, "Data1", Some(version("1.15")..=version("1.15.2")), &new_public);
+
+        let candidates = store.candidates(GameType::DarkSoulsIII, "/Game/Data1.bdt", Some(&version("1.15.1")));
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].public_key, new_public);
+        assert_eq!(store.candidates(GameType::DarkSoulsIII, "/Game/Data1.bhd", None)[0].public_key, new_public);
+        assert!(store.candidates(GameType::EldenRing, "/Game/Data1.bhd", None).is_empty());
+
+        // Without a version, the wrong key is tried first and rejected by the magic check.
+        let bhd5 = testdata::bhd5().to_bytes().unwrap();
+        let old = crypto_util::encrypt_bhd5_file(&bhd5, old_private.as_bytes()).unwrap();
+        let (decrypted, key) = store.decrypt(GameType::DarkSoulsIII, "/Game/Data1.bhd", &old, None).unwrap();
+        assert_eq!(key.public_key, old_public);
+        assert_eq!(&decrypted[..bhd5.len()], bhd5.as_slice());
+
+        let new = crypto_util::encrypt_bhd5_file(&bhd5, new_private.as_bytes()).unwrap();
+        assert!(store.decrypt(GameType::DarkSoulsIII, "/Game/Data1.bhd", &new, Some(&version("1.0"))).is_err());
+        assert_eq!(store.decrypt(GameType::DarkSoulsIII, "/Game/Data1.bhd", &new, None).unwrap().1.public_key, new_public);
+
+        assert_eq!(KeyStore::elden_ring().candidates(GameType::EldenRing, "/Game/sd/sd.bhd", None).len(), 1);
+    }
+
+    #[test]
+    fn read_plain_bhd5() {
+        let dir = std::env::temp_dir().join("dantelion-formats-plain-bhd5");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Data0.bhd");
+        let bytes = testdata::bhd5_bytes().unwrap();
+        std::fs::write(&path, &bytes).unwrap();
+
+        assert!(BHD5::is_decrypted(&bytes));
+        let bhd5 = BHD5::from_path(&path.to_string_lossy()).unwrap();
+        assert_eq!(bhd5.iter().count(), 3);
+        #[cfg(feature = "crypto")]
+        assert_eq!(BHD5::from_encrypted_bytes(&bytes, b"not a key").unwrap().iter().count(), 3);
+
+        // Encrypted ones still go through the key.
+        std::fs::write(&path, [0xAB; 0x100]).unwrap();
+        assert!(BHD5::from_path(&path.to_string_lossy()).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn keep_dcx_on_write() {
+        let plain = fixtures::bnd4(testdata::BND4_FILE_COUNT, testdata::BND4_FILE_SIZE).to_bytes(&BND4WriteOptions::default()).unwrap();
+        assert!(BND4::from_bytes(&plain).unwrap().dcx.is_none());
+
+        let edge = DCX::compress_edge(&plain).to_bytes().unwrap();
+        let bnd4 = BND4::from_bytes(&edge).unwrap();
+        let dcx = bnd4.dcx.clone().unwrap();
+        assert_eq!((dcx.format.as_str(), dcx.layers), ("EDGE", 1));
+        let written = bnd4.to_bytes(&BND4WriteOptions::default()).unwrap();
+        assert_eq!(DCX::from_bytes(&written).unwrap().header.format(), "EDGE");
+        assert_eq!(DCX::decompress_bytes(&written).unwrap(), plain);
+        assert_eq!(bnd4.to_bytes(&BND4WriteOptions::default().keep_dcx(false)).unwrap(), plain);
+
+        // Double compressed files keep both layers.
+        let twice = DCX::compress_dflt(&DCX::compress_dflt(&plain).to_bytes().unwrap()).to_bytes().unwrap();
+        let ParsedFile::BND4(bnd4) = open_bytes(&twice).unwrap() else { panic!("Not a BND4!") };
+        assert_eq!(bnd4.dcx.as_ref().unwrap().layers, 2);
+        let written = bnd4.to_bytes(&BND4WriteOptions::default()).unwrap();
+        assert!(DCX::is(&DCX::decompress_bytes(&written).unwrap()));
+        assert_eq!(strip_dcx(&written).unwrap(), plain);
+
+        let bnd3_plain = BND3::from_bnd4(&fixtures::bnd4(testdata::BND4_FILE_COUNT, testdata::BND4_FILE_SIZE)).unwrap().to_bytes().unwrap();
+        let bnd3 = BND3::from_bytes(&DCX::compress_dflt(&bnd3_plain).to_bytes().unwrap()).unwrap();
+        assert_eq!(bnd3.dcx.as_ref().unwrap().format, "DFLT");
+        assert_eq!(strip_dcx(&bnd3.to_bytes().unwrap()).unwrap(), bnd3_plain);
+        assert_eq!(bnd3.to_bnd4().dcx, bnd3.dcx);
+
+        let krak = dcx::DcxInfo { format: "KRAK".to_string(), compression_level: 6, layers: 1 };
+        assert!(krak.compress(&plain).is_err());
+    }
+
+    #[test]
+    fn open_binder_entries() {
+        let nested = fixtures::dflt_bnd4_bytes(2, 0x10).unwrap();
+        let outer = BND4Builder::new()
+            .add_file(0, "inner.bnd.dcx", nested)
+            .add_file(1, "plain.bin", b"plain".to_vec())
+            .build();
+
+        let inner = outer[0].as_bnd4().unwrap();
+        assert_eq!(inner.files.len(), 2);
+        assert_eq!(inner.dcx.as_ref().unwrap().format, "DFLT");
+        assert_eq!(outer[0].kind().unwrap(), kind::FileKind::BND4);
+        assert!(outer[0].as_tpf().is_err());
+        assert!(matches!(outer[1].open().unwrap(), ParsedFile::Unknown(_)));
+
+        let bnd3 = BND3::from_bnd4(&outer).unwrap();
+        assert_eq!(bnd3[0].as_bnd4().unwrap().files.len(), 2);
+        let mut missing = BND3::from_bnd4(&outer).unwrap();
+        missing.files[1].data = None;
+        assert!(missing[1].open().is_err());
+        assert_eq!(missing[1].kind().unwrap(), kind::FileKind::Unknown);
+    }
+
+    #[test]
+    fn cross_reference_graph() {
+        use graph::{Graph, NodeKind, Relation};
+
+        let nested = BND4Builder::new().add_file(0, "c1000.flver", flver_sample()).build();
+        let outer = BND4Builder::new()
+            .add_file(0, "c1000.anibnd", nested.to_bytes(&BND4WriteOptions::default()).unwrap())
+            .add_file(1, "c1000.flver", flver_sample())
+            .build();
+
+        let mut graph = Graph::new();
+        let chrbnd = graph.add_bnd4("/chr/c1000.chrbnd.dcx", &outer).unwrap();
+        // The two files and the model the binder is named after
+        assert_eq!(graph.children(chrbnd).len(), 3);
+        assert!(graph.children(chrbnd).contains(&(Relation::Contains, &graph.nodes[graph.find(NodeKind::Model, "c1000").unwrap()])));
+
+        // The FLVER is in both binders, so it's one node with two parents.
+        let flver = graph.find(NodeKind::File, "C1000.FLVER").unwrap();
+        let parents: Vec<&str> = graph.parents(flver).iter().map(|(_, node)| node.name.as_str()).collect();
+        assert_eq!(parents, ["c1000.anibnd", "/chr/c1000.chrbnd.dcx"]);
+        let references: Vec<(Relation, NodeKind, &str)> = graph.children(flver).iter().map(|(relation, node)| (*relation, node.kind, node.name.as_str())).collect();
+        assert_eq!(references, [(Relation::References, NodeKind::Material, "c[amsn]"), (Relation::References, NodeKind::Texture, "c1000_a")]);
+
+        let dir = std::env::temp_dir().join("dantelion-formats-graph");
+        let vfs = unpack_fixture(&dir, &["/chr/c1000.chrbnd.dcx"]);
+        graph.add_vfs(&vfs);
+        let archive = &graph.parents(chrbnd)[0];
+        assert_eq!((archive.0, archive.1.kind), (Relation::Contains, NodeKind::Archive));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn msb_model_references() {
+        use crate::msb::*;
+        use graph::{Graph, NodeKind, Relation};
+
+        let part = |name: &str, model_index: i32| {
+            let mut part = msb_entry(name, 2, [0.0; 3]);
+            part.data[0x10..0x14].copy_from_slice(&model_index.to_le_bytes());
+            part
+        };
+        let msb = MSB {
+            header: MSBHeader { magic: "MSB ".to_string(), unk04: 1, header_size: 0x10, big_endian: false, bit_big_endian: false, text_encoding: 1, long_offsets: 0xFF },
+            params: vec![
+                MSBParam { version: 3, name: "MODEL_PARAM_ST".to_string(), entries: vec![msb_entry("c1000", 2, [0.0; 3]), msb_entry("m000100", 0, [0.0; 3]), msb_entry("h000100", 5, [0.0; 3])] },
+                MSBParam { version: 3, name: "PARTS_PARAM_ST".to_string(), entries: vec![part("c1000_0000", 0), part("c1000_0001", 0), part("m000100_0000", 1), part("h000100_0000", 2), part("c9999_0000", 9)] },
+            ],
+        };
+        let msb = MSB::from_bytes(&msb.to_bytes().unwrap()).unwrap();
+        let part_models: Vec<Option<String>> = msb.part_models().iter().map(|(_, model)| model.map(|model| model.name().unwrap())).collect();
+        assert_eq!(part_models, [Some("c1000"), Some("c1000"), Some("m000100"), Some("h000100"), None].map(|name| name.map(str::to_string)));
+
+        let mut graph = Graph::new();
+        let map = graph.add_msb("/map/mapstudio/m30_00_00_00.msb.dcx", &msb).unwrap();
+        let references: Vec<(Relation, NodeKind, &str)> = graph.children(map).iter().map(|(relation, node)| (*relation, node.kind, node.name.as_str())).collect();
+        // Collisions aren't in binders, and the two parts using c1000 are one reference
+        assert_eq!(references, [
+            (Relation::References, NodeKind::Model, "c1000"),
+            (Relation::References, NodeKind::Model, "m30_00_00_00_000100"),
+            (Relation::References, NodeKind::Model, "MODEL_PARAM_ST[9]"),
+        ]);
+
+        let mapbnd = graph.add_bnd4("/map/m30_00_00_00/m30_00_00_00_000100.mapbnd.dcx", &BND4Builder::new().build()).unwrap();
+        let model = graph.find(NodeKind::Model, "M30_00_00_00_000100").unwrap();
+        assert_eq!(graph.children(mapbnd), [(Relation::Contains, &graph.nodes[model])]);
+    }
+
+    #[test]
+    fn dangling_references() {
+        use graph::{Graph, NodeKind};
+
+        let dir = std::env::temp_dir().join("dantelion-formats-dangling");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("parts")).unwrap();
+        let partsbnd = BND4Builder::new().add_file(0, "am_m_1600.flver", vec![0; 0x10]).build();
+        std::fs::write(dir.join("parts/am_m_1600.partsbnd"), partsbnd.to_bytes(&BND4WriteOptions::default()).unwrap()).unwrap();
+
+        let mut graph = Graph::new();
+        let map = graph.add_node(NodeKind::File, "/map/mapstudio/m10_00_00_00.msb.dcx");
+        graph.add_reference(map, NodeKind::File, "AM_M_1600.flver");
+        graph.add_reference(map, NodeKind::File, "am_m_1700.flver");
+        graph.add_mod_folder(&dir).unwrap();
+
+        let partsbnd = graph.find(NodeKind::File, "/parts/am_m_1600.partsbnd").unwrap();
+        assert_eq!(graph.parents(partsbnd)[0].1.kind, NodeKind::ModFolder);
+        let dangling: Vec<(&str, &str)> = graph.dangling().iter().map(|(from, to)| (from.name.as_str(), to.name.as_str())).collect();
+        assert_eq!(dangling, [("/map/mapstudio/m10_00_00_00.msb.dcx", "am_m_1700.flver")]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // A DS3 style FMG with one group for ids 100 and 101, only 100 having text.
+    fn fmg_sample() -> Vec<u8> {
+        let mut fmg = vec![0, 0, 2, 0];
+        for value in [0x58u32, 1, 1, 2, 0xFF] {
+            fmg.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [0x38u64, 0] {
+            fmg.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [0i32, 100, 101, 0] {
+            fmg.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [0x48u64, 0] {
+            fmg.extend_from_slice(&value.to_le_bytes());
+        }
+        fmg.extend("Dagger".encode_utf16().chain([0]).flat_map(u16::to_le_bytes));
+        fmg.resize(0x58, 0);
+        fmg
+    }
+
+    #[test]
+    fn mod_folder_dangling_references() {
+        use crate::msb::*;
+        use graph::Graph;
+
+        let fmg = fmg_sample();
+        assert_eq!(kind::FileKind::sniff(&fmg), kind::FileKind::FMG);
+        assert_eq!(fmg::FmgIds::from_bytes(&fmg).unwrap().ids, [100]);
+        let mut past_the_strings = fmg.clone();
+        past_the_strings[0x30..0x34].copy_from_slice(&0x7FFFFFFFi32.to_le_bytes());
+        assert!(fmg::FmgIds::from_bytes(&past_the_strings).is_err());
+
+        let part = |name: &str, model_index: i32| {
+            let mut part = msb_entry(name, 2, [0.0; 3]);
+            part.data[0x10..0x14].copy_from_slice(&model_index.to_le_bytes());
+            part
+        };
+        let msb = MSB {
+            header: MSBHeader { magic: "MSB ".to_string(), unk04: 1, header_size: 0x10, big_endian: false, bit_big_endian: false, text_encoding: 1, long_offsets: 0xFF },
+            params: vec![
+                MSBParam { version: 3, name: "MODEL_PARAM_ST".to_string(), entries: vec![msb_entry("c1000", 2, [0.0; 3]), msb_entry("c2000", 2, [0.0; 3])] },
+                MSBParam { version: 3, name: "PARTS_PARAM_ST".to_string(), entries: vec![part("c1000_0000", 0), part("c2000_0000", 1), part("c9999_0000", 7)] },
+            ],
+        };
+
+        let dir = std::env::temp_dir().join("dantelion-formats-mod-dangling");
+        let _ = std::fs::remove_dir_all(&dir);
+        let write_bnd4 = |path: &str, files: &[(&str, Vec<u8>)]| {
+            let bnd4 = files.iter().enumerate().fold(BND4Builder::new(), |builder, (id, (name, data))| builder.add_file(id as i32, name, data.clone())).build();
+            std::fs::create_dir_all(dir.join(path).parent().unwrap()).unwrap();
+            std::fs::write(dir.join(path), bnd4.to_bytes(&BND4WriteOptions::default()).unwrap()).unwrap();
+        };
+        write_bnd4("chr/c1000.chrbnd", &[("c1000.flver", flver_sample())]);
+        write_bnd4("mtd/allmaterialbnd.mtdbnd", &[(r"N:\FDP\data\Material\mtd\c[amsn].mtd", vec![0; 0x10])]);
+        write_bnd4("msg/engus/item.msgbnd", &[(r"N:\FDP\data\INTERROOT_win64\msg\engUS\WeaponName.fmg", fmg)]);
+        std::fs::create_dir_all(dir.join("map/mapstudio")).unwrap();
+        std::fs::write(dir.join("map/mapstudio/m30_00_00_00.msb"), msb.to_bytes().unwrap()).unwrap();
+
+        // ER style, with a 0x40 header and 0x18 byte row headers
+        let mut weapons = vec![0; 0x40];
+        weapons[0xA..0xC].copy_from_slice(&3u16.to_le_bytes());
+        weapons[0x2D] = 0x85;
+        for (i, id) in [100i32, 101, 300].into_iter().enumerate() {
+            weapons.extend_from_slice(&id.to_le_bytes());
+            weapons.extend_from_slice(&[0; 4]);
+            weapons.extend_from_slice(&(0x88 + i as u64 * 8).to_le_bytes());
+            weapons.extend_from_slice(&[0; 8]);
+        }
+        weapons.extend_from_slice(&[0; 0x18]);
+        let strings_offset = weapons.len() as u32;
+        weapons[0..4].copy_from_slice(&strings_offset.to_le_bytes());
+        let rows = param::ParamRows::from_bytes(&weapons).unwrap();
+
+        let mut graph = Graph::new();
+        graph.add_mod_folder(&dir).unwrap();
+        graph.add_param("EquipParamWeapon.param", &rows, "WeaponName");
+        let dangling: Vec<(&str, &str)> = graph.dangling().iter().map(|(from, to)| (from.name.as_str(), to.name.as_str())).collect();
+        assert_eq!(dangling, [
+            ("c1000.flver", "c1000_a"),
+            ("/map/mapstudio/m30_00_00_00.msb", "c2000"),
+            ("/map/mapstudio/m30_00_00_00.msb", "MODEL_PARAM_ST[7]"),
+            ("EquipParamWeapon.param", "WeaponName[101]"),
+            ("EquipParamWeapon.param", "WeaponName[300]"),
+        ]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn prelude_exports() {
+        use crate::prelude::*;
+
+        let parsed: Result<ParsedFile, DantelionFormatsError> = open_bytes(testdata::BND4_BYTES);
+        assert!(matches!(parsed, Ok(ParsedFile::BND4(_))));
+        let bhd5 = BHD5::from_bytes(testdata::BHD5_BYTES).unwrap();
+        assert!(bhd5.format == BHD5Format::EldenRing && bhd5.format.games().contains(&GameType::EldenRing));
+        let dcx = DCX::from_bytes(testdata::DFLT_DCX_BYTES).unwrap();
+        assert_eq!(strip_dcx(testdata::DFLT_DCX_BYTES).unwrap(), dcx.decompress().unwrap());
+    }
+
+    #[test]
+    fn read_lua_header() {
+        let mut file = b"\x1bLua\x51\0\x01\x04\x08\x04\x08\0".to_vec();
+        file.extend_from_slice(&11u64.to_le_bytes());
+        file.extend_from_slice(b"@c0000.lua\0");
+        let header = lua::LuaHeader::from_bytes(&file).expect("Could not read Lua header!");
+        assert_eq!(header.version, 0x51);
+        assert!(header.little_endian);
+        assert_eq!(header.chunk_name.as_deref(), Some("@c0000.lua"));
+
+        let mut file = b"\x1bLua\x50\0\x04\x04\x04\x06\x08\x09\x09\x04".to_vec();
+        file.extend_from_slice(&31415926f32.to_be_bytes());
+        file.extend_from_slice(&6u32.to_be_bytes());
+        file.extend_from_slice(b"@1.lua\0");
+        let header = lua::LuaHeader::from_bytes(&file).expect("Could not read Lua header!");
+        assert!(!header.little_endian);
+        assert_eq!(header.format, None);
+        assert_eq!(header.chunk_name.as_deref(), Some("@1.lua"));
+    }
+
+    #[cfg(feature = "oodle")]
     #[test]
     fn oodle_install_path() {
-        let path = util::get_oodle_path().expect("Did not find oodle path!");
+        let path = oodle::get_oodle_path().unwrap().expect("Did not find oodle path!");
         assert!(Path::new(&path).exists())
     }
 }
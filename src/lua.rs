@@ -0,0 +1,114 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{Cursor, Error, ErrorKind};
+use binary_interpreter::binary_reader::BinaryReader;
+use byteorder::{BE, LE, ByteOrder, ReadBytesExt};
+use crate::error::DantelionFormatsError;
+
+/// The header of a compiled Lua chunk, like the AI and event scripts in luabnds. DeS and DS1 use Lua 5.0, later
+/// games use HavokScript, which is Lua 5.1 with its own format byte.
+#[derive(Debug)]
+#[repr(C)]
+#[non_exhaustive]
+pub struct LuaHeader {
+    // 0x50 for 5.0, 0x51 for 5.1 and so on
+    pub version: u8,
+    // 0 for official 5.1 bytecode, 0x0E for HavokScript. Not present in 5.0.
+    pub format: Option<u8>,
+    pub little_endian: bool,
+    pub int_size: u8,
+    pub size_t_size: u8,
+    pub instruction_size: u8,
+    pub number_size: u8,
+    // Usually "@" followed by the script's path. Only read for official 5.0 and 5.1 bytecode.
+    pub chunk_name: Option<String>,
+}
+
+impl LuaHeader {
+    const SIGNATURE: &'static [u8; 4] = b"\x1bLua";
+    const VERSION_5_0: u8 = 0x50;
+    const VERSION_5_1: u8 = 0x51;
+    const OFFICIAL_FORMAT: u8 = 0;
+
+    pub fn is(bytes: &[u8]) -> bool {
+        bytes.starts_with(LuaHeader::SIGNATURE)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<LuaHeader, DantelionFormatsError> {
+        if !LuaHeader::is(file) {
+            return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, "Not Lua bytecode")));
+        }
+
+        let mut c = Cursor::new(file);
+        c.set_position(LuaHeader::SIGNATURE.len() as u64);
+        let version = c.read_u8()?;
+        let format = if version >= LuaHeader::VERSION_5_1 { Some(c.read_u8()?) } else { None };
+        let little_endian = c.read_u8()? != 0;
+        let int_size = c.read_u8()?;
+        let size_t_size = c.read_u8()?;
+        let instruction_size = c.read_u8()?;
+
+        let mut header = LuaHeader {
+            version,
+            format,
+            little_endian,
+            int_size,
+            size_t_size,
+            instruction_size,
+            number_size: 0,
+            chunk_name: None,
+        };
+
+        match (version, format) {
+            (LuaHeader::VERSION_5_0, _) => {
+                // Sizes of the opcode and A, B and C fields
+                c.read_bytes(4)?;
+                header.number_size = c.read_u8()?;
+                // Test number, used by 5.0 to check the float format
+                c.read_bytes(header.number_size as usize)?;
+            }
+            (LuaHeader::VERSION_5_1, Some(LuaHeader::OFFICIAL_FORMAT)) => {
+                header.number_size = c.read_u8()?;
+                // Integral number flag
+                c.read_u8()?;
+            }
+            _ => {
+                header.number_size = c.read_u8()?;
+                return Ok(header);
+            }
+        }
+
+        header.chunk_name = if little_endian { LuaHeader::read_string::<LE>(&mut c, size_t_size)? } else { LuaHeader::read_string::<BE>(&mut c, size_t_size)? };
+
+        Ok(header)
+    }
+
+    // Lua strings are a size_t length including the null terminator, with 0 meaning no string.
+    fn read_string<T: ByteOrder>(c: &mut Cursor<&[u8]>, size_t_size: u8) -> Result<Option<String>, DantelionFormatsError> {
+        let len = match size_t_size {
+            4 => c.read_u32::<T>()? as usize,
+            8 => c.read_u64::<T>()? as usize,
+            size => return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("Unsupported size_t size {}", size)))),
+        };
+        if len == 0 {
+            return Ok(None);
+        }
+
+        let bytes = c.read_bytes(len)?;
+        Ok(Some(String::from_utf8_lossy(&bytes).trim_end_matches('\0').to_string()))
+    }
+}
+
+/// A one line summary, e.g. "Lua 5.1 HavokScript" or "Lua 5.0 — @c0000.lua".
+impl Display for LuaHeader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Lua {}.{}", self.version >> 4, self.version & 0xF)?;
+        if self.format.is_some_and(|format| format != LuaHeader::OFFICIAL_FORMAT) {
+            write!(f, " HavokScript")?;
+        }
+        if let Some(chunk_name) = &self.chunk_name {
+            write!(f, " — {}", chunk_name)?;
+        }
+
+        Ok(())
+    }
+}
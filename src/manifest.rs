@@ -0,0 +1,237 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::bnd4::{BND4, BND4Builder, BND4Version};
+use crate::error::DantelionFormatsError;
+use crate::util::{self, NameMatcher};
+
+pub const NATIVE_MANIFEST_NAME: &str = "_dantelion-bnd4.json";
+pub const WITCHY_MANIFEST_NAME: &str = "_witchy-bnd4.xml";
+pub const YABBER_MANIFEST_NAME: &str = "_yabber-bnd4.xml";
+
+/// Everything needed to rebuild a BND4 from an unpacked folder. Flags are stored in normalized bit order.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BND4Manifest {
+    pub version: String,
+    pub format: u8,
+    pub big_endian: bool,
+    pub unicode: bool,
+    pub extended: u8,
+    pub unk04: u8,
+    pub unk05: u8,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ManifestFile {
+    pub flags: u8,
+    pub id: Option<i32>,
+    pub name: Option<String>,
+    // Relative to the manifest's folder
+    pub path: String,
+}
+
+impl BND4Manifest {
+    pub fn from_bnd4(bnd4: &BND4) -> BND4Manifest {
+        let big_endian = bnd4.header.big_endian;
+        let files = bnd4.files.iter().enumerate().map(|(i, file)| ManifestFile {
+            flags: if big_endian { file.raw_flags } else { util::reverse_bits(file.raw_flags) },
+            id: file.id,
+            name: file.name.clone(),
+            path: match &file.name {
+                None => format!("{}", file.id.unwrap_or(i as i32)),
+                Some(name) => relative_path(name),
+            },
+        }).collect();
+
+        BND4Manifest {
+            version: bnd4.header.version.trim_end_matches('\0').to_string(),
+            format: bnd4.header.format(),
+            big_endian,
+            unicode: bnd4.header.unicode,
+            extended: bnd4.header.extended,
+            unk04: bnd4.header.unk04,
+            unk05: bnd4.header.unk05,
+            files,
+        }
+    }
+
+    /// Loads the manifest in `dir`, preferring our own and falling back to WitchyBND and Yabber manifests.
+    pub fn from_dir(dir: &str) -> Result<BND4Manifest, DantelionFormatsError> {
+        let dir = Path::new(dir);
+        let native = dir.join(NATIVE_MANIFEST_NAME);
+        if native.exists() {
+            return Ok(serde_json::from_str(&fs::read_to_string(native)?)?);
+        }
+
+        for name in [WITCHY_MANIFEST_NAME, YABBER_MANIFEST_NAME] {
+            let xml = dir.join(name);
+            if xml.exists() {
+                return BND4Manifest::from_xml(&fs::read_to_string(xml)?);
+            }
+        }
+
+        Err(DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, format!("No BND4 manifest found in {}", dir.display()))))
+    }
+
+    /// Parses a `_witchy-bnd4.xml` or `_yabber-bnd4.xml` manifest.
+    pub fn from_xml(xml: &str) -> Result<BND4Manifest, DantelionFormatsError> {
+        let doc = roxmltree::Document::parse(xml)?;
+        let root = doc.root_element();
+        // Witchy can put the root at the top level instead of on each file.
+        let shared_root = child_text(root, "root").unwrap_or("");
+
+        let mut files = vec![];
+        if let Some(files_node) = root.children().find(|n| n.has_tag_name("files")) {
+            for file in files_node.children().filter(|n| n.has_tag_name("file")) {
+                let path = child_text(file, "path")
+                    .or_else(|| child_text(file, "name"))
+                    .ok_or_else(|| invalid_manifest("file without a path"))?;
+                let root = child_text(file, "root").unwrap_or(shared_root);
+                files.push(ManifestFile {
+                    flags: parse_flags(child_text(file, "flags").unwrap_or("None"), FILE_FLAG_NAMES)?,
+                    id: child_text(file, "id").map(|id| id.parse()).transpose().map_err(|_| invalid_manifest("id"))?,
+                    name: Some(format!("{}{}", root, path)),
+                    path: path.replace('\\', "/"),
+                });
+            }
+        }
+
+        Ok(BND4Manifest {
+            version: child_text(root, "version").unwrap_or("07D7R6").to_string(),
+            format: parse_flags(child_text(root, "format").ok_or_else(|| invalid_manifest("format"))?, FORMAT_NAMES)?,
+            big_endian: parse_bool(child_text(root, "bigendian").unwrap_or("False")),
+            unicode: parse_bool(child_text(root, "unicode").unwrap_or("True")),
+            extended: parse_number(child_text(root, "extended").unwrap_or("0")).ok_or_else(|| invalid_manifest("extended"))? as u8,
+            unk04: parse_bool(child_text(root, "unk04").unwrap_or("False")) as u8,
+            unk05: parse_bool(child_text(root, "unk05").unwrap_or("False")) as u8,
+            files,
+        })
+    }
+
+    /// The file with `name` as its binder name or its path in the folder, compared like binder entries are, see
+    /// `NameMatcher`, with roots ignored, so the entry's "N:\GR\data\a.bin" and its unpacked "GR/data/a.bin" are
+    /// the same file.
+    pub fn file(&self, name: &str) -> Option<&ManifestFile> {
+        let matcher = NameMatcher::new().ignore_roots(true);
+        self.files.iter().find(|file| file.name.as_deref().is_some_and(|file_name| matcher.matches(file_name, name)))
+            .or_else(|| self.files.iter().find(|file| matcher.matches(&file.path, name)))
+    }
+
+    pub fn to_json(&self) -> Result<String, DantelionFormatsError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Writes every file in `bnd4` to `dir`, along with a manifest that `repack_bnd4` can rebuild it from.
+pub fn unpack_bnd4(bnd4: &BND4, dir: &str) -> Result<(), DantelionFormatsError> {
+    let manifest = BND4Manifest::from_bnd4(bnd4);
+    for (file, entry) in bnd4.files.iter().zip(&manifest.files) {
+        let path = Path::new(dir).join(&entry.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, file.data.as_deref().unwrap_or(&[]))?;
+    }
+
+    fs::write(Path::new(dir).join(NATIVE_MANIFEST_NAME), manifest.to_json()?)?;
+    Ok(())
+}
+
+/// Rebuilds a BND4 from a folder unpacked by this crate, WitchyBND or Yabber.
+pub fn repack_bnd4(dir: &str) -> Result<BND4, DantelionFormatsError> {
+    let manifest = BND4Manifest::from_dir(dir)?;
+    let mut builder = BND4Builder::new()
+        .version(BND4Version::Custom(manifest.version.clone()))
+        .big_endian(manifest.big_endian)
+        .unicode(manifest.unicode)
+        .format(manifest.format)
+        .extended(manifest.extended == 4);
+
+    for (i, file) in manifest.files.iter().enumerate() {
+        let data = fs::read(Path::new(dir).join(&file.path))?;
+        builder = builder.add_file(file.id.unwrap_or(i as i32), file.name.as_deref().unwrap_or(&file.path), data);
+    }
+
+    let mut bnd4 = builder.build();
+    bnd4.header.unk04 = manifest.unk04;
+    bnd4.header.unk05 = manifest.unk05;
+    for (file, entry) in bnd4.files.iter_mut().zip(&manifest.files) {
+        file.raw_flags = if manifest.big_endian { entry.flags } else { util::reverse_bits(entry.flags) };
+        if entry.id.is_none() {
+            file.id = None;
+        }
+        if entry.name.is_none() {
+            file.name = None;
+        }
+    }
+
+    Ok(bnd4)
+}
+
+const FORMAT_NAMES: &[(&str, u8)] = &[
+    ("BigEndian", 0b00000001),
+    ("IDs", 0b00000010),
+    ("Names1", 0b00000100),
+    ("Names2", 0b00001000),
+    ("LongOffsets", 0b00010000),
+    ("Compression", 0b00100000),
+    ("Flag6", 0b01000000),
+    ("Flag7", 0b10000000),
+];
+
+const FILE_FLAG_NAMES: &[(&str, u8)] = &[
+    ("Compressed", 0b00000001),
+    ("Flag1", 0b00000010),
+    ("Flag2", 0b00000100),
+    ("Flag3", 0b00001000),
+    ("Flag4", 0b00010000),
+    ("Flag5", 0b00100000),
+    ("Flag6", 0b01000000),
+    ("Flag7", 0b10000000),
+];
+
+// Strips the "N:\" style root and uses forward slashes, so the path can be joined onto the output folder.
+fn relative_path(name: &str) -> String {
+    let name = name.replace('\\', "/");
+    let path: PathBuf = util::strip_root(&name).split('/').filter(|part| !part.is_empty() && *part != "..").collect();
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children().find(|n| n.has_tag_name(name)).and_then(|n| n.text())
+}
+
+// C# enums serialize as "IDs, Names1, Names2, Compression", but accept plain numbers too.
+fn parse_flags(text: &str, names: &[(&str, u8)]) -> Result<u8, DantelionFormatsError> {
+    if let Some(value) = parse_number(text) {
+        return Ok(value as u8);
+    }
+
+    let mut flags = 0;
+    for part in text.split(',').map(|part| part.trim()).filter(|part| !part.is_empty() && *part != "None") {
+        let (_, bit) = names.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(part))
+            .ok_or_else(|| invalid_manifest(part))?;
+        flags |= bit;
+    }
+
+    Ok(flags)
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_bool(text: &str) -> bool {
+    text.trim().eq_ignore_ascii_case("true") || text.trim() == "1"
+}
+
+fn invalid_manifest(what: &str) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("Invalid manifest value: {}", what)))
+}
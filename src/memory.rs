@@ -0,0 +1,289 @@
+use std::io::{Error, ErrorKind};
+use byteorder::{ByteOrder, LE};
+use crate::error::DantelionFormatsError;
+use crate::param::{self, ParamRows, RowEdit};
+
+/// Reads and writes another process's memory. `WindowsProcess` is the real one, anything else, like a dump, can
+/// implement it too.
+pub trait ProcessMemory {
+    fn read(&self, address: u64, buf: &mut [u8]) -> Result<(), DantelionFormatsError>;
+    fn write(&self, address: u64, data: &[u8]) -> Result<(), DantelionFormatsError>;
+    /// The base address and size of the game's executable, where `ParamRepository::find` scans for its signature.
+    fn main_module(&self) -> Result<(u64, usize), DantelionFormatsError>;
+
+    fn read_u64(&self, address: u64) -> Result<u64, DantelionFormatsError> {
+        let mut buf = [0; 8];
+        self.read(address, &mut buf)?;
+        Ok(LE::read_u64(&buf))
+    }
+}
+
+/// Where a game keeps its loaded params. The repository is found through a RIP relative `mov` matched by `signature`,
+/// then holds `entry_stride` sized entries from `entries_offset`, each starting with a pointer to the param's
+/// resource. The resource has the param's name as an MSVC `std::wstring` at `name_offset`, and the PARAM file itself
+/// at the end of the `data_pointers` chain. These change between games and sometimes patches, so other layouts can be
+/// passed in.
+#[derive(Clone, Debug)]
+pub struct ParamRepositoryLayout {
+    // Bytes in hex with "??" for wildcards, e.g. "48 8B 0D ?? ?? ?? ??"
+    pub signature: &'static str,
+    // Where the rel32 is in the matched instruction, and the instruction's length
+    pub rel32_offset: usize,
+    pub instruction_size: usize,
+    pub entries_offset: u64,
+    pub entry_stride: u64,
+    // Upper bound on the entries, reading stops early at the first null
+    pub max_entries: usize,
+    pub name_offset: u64,
+    pub data_pointers: &'static [u64],
+}
+
+impl ParamRepositoryLayout {
+    /// Elden Ring's SoloParamRepository, as community cheat tables find it.
+    pub const ELDEN_RING: ParamRepositoryLayout = ParamRepositoryLayout {
+        signature: "48 8B 0D ?? ?? ?? ?? 48 85 C9 0F 84 ?? ?? ?? ?? 45 33 C0 BA 8E 00 00 00",
+        rel32_offset: 3,
+        instruction_size: 7,
+        entries_offset: 0x88,
+        entry_stride: 0x48,
+        max_entries: 0x200,
+        name_offset: 0x18,
+        data_pointers: &[0x80, 0x80],
+    };
+}
+
+/// A loaded param: its name, e.g. "AtkParam_Npc", and the address of its PARAM data.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct MemoryParam {
+    pub name: String,
+    pub address: u64,
+}
+
+/// The params loaded in a running game, with the same row access as files: `read_row` gives bytes for a generated
+/// paramdef struct's `read`, and `apply_row_edits` takes the `RowEdit`s `param::patch_regulation` does. Rows are the
+/// param's own data, so changes take effect without reloading, and are lost when the game restarts.
+pub struct ParamRepository<'a, M: ProcessMemory> {
+    memory: &'a M,
+    pub address: u64,
+    pub params: Vec<MemoryParam>,
+}
+
+impl<'a, M: ProcessMemory> ParamRepository<'a, M> {
+    pub fn find(memory: &'a M, layout: &ParamRepositoryLayout) -> Result<ParamRepository<'a, M>, DantelionFormatsError> {
+        let (base, size) = memory.main_module()?;
+        let mut module = vec![0; size];
+        memory.read(base, &mut module)?;
+        let pattern = parse_signature(layout.signature)?;
+        let found = find_signature(&module, &pattern)
+            .ok_or_else(|| DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, "Param repository signature not found")))?;
+
+        let rel32 = LE::read_i32(&module[found + layout.rel32_offset..]);
+        let static_address = (base + (found + layout.instruction_size) as u64).wrapping_add_signed(rel32 as i64);
+        let address = memory.read_u64(static_address)?;
+        if address == 0 {
+            return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, "Params aren't loaded yet")));
+        }
+
+        let mut params = vec![];
+        for i in 0..layout.max_entries as u64 {
+            let resource = memory.read_u64(address + layout.entries_offset + i * layout.entry_stride)?;
+            if resource == 0 {
+                break;
+            }
+            let data = layout.data_pointers.iter().try_fold(resource, |pointer, offset| memory.read_u64(pointer + offset))?;
+            params.push(MemoryParam {
+                name: read_wstring(memory, resource + layout.name_offset)?,
+                address: data,
+            });
+        }
+
+        Ok(ParamRepository {
+            memory,
+            address,
+            params,
+        })
+    }
+
+    /// Looked up like `param::RowNames`, so "AtkParam_Npc.param" and regulation paths work too.
+    pub fn param(&self, name: &str) -> Option<&MemoryParam> {
+        let name = param::param_key(name);
+        self.params.iter().find(|param| param.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn rows(&self, param: &MemoryParam) -> Result<ParamRows, DantelionFormatsError> {
+        // The header is 0x40 bytes at most and row headers 0x18, so this covers both without knowing the layout.
+        let mut header = [0; 0x40];
+        self.memory.read(param.address, &mut header)?;
+        let row_count = if header[0x2C] == 0xFF { byteorder::BE::read_u16(&header[0xA..]) } else { LE::read_u16(&header[0xA..]) };
+        let mut bytes = vec![0; 0x40 + row_count as usize * 0x18];
+        self.memory.read(param.address, &mut bytes)?;
+
+        ParamRows::from_bytes(&bytes)
+    }
+
+    pub fn read_row(&self, param: &MemoryParam, id: i32) -> Result<Vec<u8>, DantelionFormatsError> {
+        let rows = self.rows(param)?;
+        let offset = rows.find(id).ok_or_else(|| no_row(&param.name, id))?;
+        let mut row = vec![0; rows.row_size];
+        self.memory.read(param.address + offset as u64, &mut row)?;
+
+        Ok(row)
+    }
+
+    pub fn write_row(&self, param: &MemoryParam, id: i32, row: &[u8]) -> Result<(), DantelionFormatsError> {
+        let rows = self.rows(param)?;
+        let offset = rows.find(id).ok_or_else(|| no_row(&param.name, id))?;
+        if row.len() > rows.row_size {
+            return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidInput,
+                format!("0x{:X} bytes is more than the 0x{:X} byte rows of {}", row.len(), rows.row_size, param.name))));
+        }
+
+        self.memory.write(param.address + offset as u64, row)
+    }
+
+    /// Applies each edit to its row. Edits are checked against their param's rows before any are written.
+    pub fn apply_row_edits(&self, edits: &[RowEdit]) -> Result<(), DantelionFormatsError> {
+        let mut writes = Vec::with_capacity(edits.len());
+        for edit in edits {
+            let param = self.param(&edit.param)
+                .ok_or_else(|| DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, format!("No param named {} loaded", edit.param))))?;
+            let rows = self.rows(param)?;
+            let offset = rows.find(edit.row).ok_or_else(|| no_row(&edit.param, edit.row))?;
+            if edit.offset + edit.data.len() > rows.row_size {
+                return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidInput,
+                    format!("Edit at 0x{:X} of row {} in {} is past the 0x{:X} byte row", edit.offset, edit.row, edit.param, rows.row_size))));
+            }
+            writes.push((param.address + (offset + edit.offset) as u64, &edit.data));
+        }
+
+        for (address, data) in writes {
+            self.memory.write(address, data)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Where `pattern` first matches in `bytes`, `None` bytes matching anything.
+pub fn find_signature(bytes: &[u8], pattern: &[Option<u8>]) -> Option<usize> {
+    if pattern.is_empty() || pattern.len() > bytes.len() {
+        return None;
+    }
+
+    bytes.windows(pattern.len()).position(|window| {
+        window.iter().zip(pattern).all(|(byte, expected)| expected.is_none_or(|expected| *byte == expected))
+    })
+}
+
+/// "48 8B ?? 0D" is `[Some(0x48), Some(0x8B), None, Some(0x0D)]`.
+pub fn parse_signature(signature: &str) -> Result<Vec<Option<u8>>, DantelionFormatsError> {
+    signature.split_whitespace()
+        .map(|byte| match byte {
+            "?" | "??" => Ok(None),
+            _ => u8::from_str_radix(byte, 16).map(Some)
+                .map_err(|_| DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidInput, format!("Bad signature byte {}", byte)))),
+        })
+        .collect()
+}
+
+// An MSVC std::wstring: 16 bytes that are the characters when they fit, a pointer to them otherwise, then the length and
+// capacity.
+fn read_wstring(memory: &impl ProcessMemory, address: u64) -> Result<String, DantelionFormatsError> {
+    let len = memory.read_u64(address + 0x10)? as usize;
+    let capacity = memory.read_u64(address + 0x18)?;
+    let chars_address = if capacity >= 8 { memory.read_u64(address)? } else { address };
+    let mut bytes = vec![0; len.min(0x100) * 2];
+    memory.read(chars_address, &mut bytes)?;
+    let chars: Vec<u16> = bytes.chunks_exact(2).map(LE::read_u16).collect();
+
+    Ok(String::from_utf16(&chars)?)
+}
+
+fn no_row(param: &str, id: i32) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, format!("No row {} in {}", id, param)))
+}
+
+/// A process opened with `OpenProcess`, e.g. the game found with `discovery`.
+#[cfg(windows)]
+pub struct WindowsProcess {
+    handle: windows_sys::Win32::Foundation::HANDLE,
+    owned: bool,
+}
+
+#[cfg(windows)]
+impl WindowsProcess {
+    /// Opens a process for reading and writing its memory.
+    pub fn open(pid: u32) -> Result<WindowsProcess, DantelionFormatsError> {
+        use windows_sys::Win32::System::Threading::{OpenProcess, PROCESS_QUERY_INFORMATION, PROCESS_VM_OPERATION, PROCESS_VM_READ, PROCESS_VM_WRITE};
+
+        let access = PROCESS_VM_READ | PROCESS_VM_WRITE | PROCESS_VM_OPERATION | PROCESS_QUERY_INFORMATION;
+        let handle = unsafe { OpenProcess(access, 0, pid) };
+        if handle.is_null() {
+            return Err(Error::last_os_error().into());
+        }
+
+        Ok(WindowsProcess { handle, owned: true })
+    }
+
+    /// Wraps a handle from somewhere else, which has to have VM read, write and operation and query access. It isn't
+    /// closed on drop.
+    pub fn from_handle(handle: windows_sys::Win32::Foundation::HANDLE) -> WindowsProcess {
+        WindowsProcess { handle, owned: false }
+    }
+}
+
+#[cfg(windows)]
+impl ProcessMemory for WindowsProcess {
+    fn read(&self, address: u64, buf: &mut [u8]) -> Result<(), DantelionFormatsError> {
+        use windows_sys::Win32::System::Diagnostics::Debug::ReadProcessMemory;
+
+        let mut read = 0;
+        let ok = unsafe { ReadProcessMemory(self.handle, address as *const _, buf.as_mut_ptr().cast(), buf.len(), &mut read) };
+        if ok == 0 || read != buf.len() {
+            return Err(Error::last_os_error().into());
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, address: u64, data: &[u8]) -> Result<(), DantelionFormatsError> {
+        use windows_sys::Win32::System::Diagnostics::Debug::WriteProcessMemory;
+
+        let mut written = 0;
+        let ok = unsafe { WriteProcessMemory(self.handle, address as *const _, data.as_ptr().cast(), data.len(), &mut written) };
+        if ok == 0 || written != data.len() {
+            return Err(Error::last_os_error().into());
+        }
+
+        Ok(())
+    }
+
+    fn main_module(&self) -> Result<(u64, usize), DantelionFormatsError> {
+        use windows_sys::Win32::System::ProcessStatus::{EnumProcessModules, GetModuleInformation, MODULEINFO};
+
+        // The first module is the executable.
+        let mut module = std::ptr::null_mut();
+        let mut needed = 0;
+        let ok = unsafe { EnumProcessModules(self.handle, &mut module, std::mem::size_of_val(&module) as u32, &mut needed) };
+        if ok == 0 {
+            return Err(Error::last_os_error().into());
+        }
+
+        let mut info: MODULEINFO = unsafe { std::mem::zeroed() };
+        let ok = unsafe { GetModuleInformation(self.handle, module, &mut info, std::mem::size_of::<MODULEINFO>() as u32) };
+        if ok == 0 {
+            return Err(Error::last_os_error().into());
+        }
+
+        Ok((info.lpBaseOfDll as u64, info.SizeOfImage as usize))
+    }
+}
+
+#[cfg(windows)]
+impl Drop for WindowsProcess {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe { windows_sys::Win32::Foundation::CloseHandle(self.handle) };
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::fs;
+use std::io::Cursor;
+use binary_interpreter::binary_reader::{BinaryPeeker, BinaryReader};
+use byteorder::{BE, LE, ByteOrder, ReadBytesExt, WriteBytesExt};
+use crate::error::DantelionFormatsError;
+use crate::source::{DataSource, FileSource};
+use crate::util;
+use crate::util::{DataLen, ensure, Validate};
+
+/// A cutscene definition from a movie binder. Only the header is parsed for now. The resources, cuts and
+/// timelines after it are kept as they are, so files can be read and written back without changes.
+#[repr(C)]
+pub struct MQB {
+    pub header: MQBHeader,
+    // Everything after the header
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+#[non_exhaustive]
+pub struct MQBHeader {
+    pub magic: String,
+    pub big_endian: bool,
+    pub unk05: u8,
+    // 64-bit offsets, used from DS2 SotFS on
+    pub long_format: bool,
+    pub unk07: u8,
+    pub version: u32,
+    pub header_size: u32,
+}
+
+impl MQB {
+    const MAGIC_SIZE: usize = 4;
+    const ENDIANNESS_OFFSET: u64 = 4;
+    const HEADER_SIZE: u64 = 0x10;
+
+    pub(crate) fn is(bytes: &[u8]) -> bool {
+        bytes.starts_with(b"MQB ")
+    }
+
+    pub fn from_path(path: &str) -> Result<MQB, DantelionFormatsError> {
+        MQB::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<MQB, DantelionFormatsError> {
+        let file = source.read_all()?;
+
+        MQB::from_bytes(&file)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<MQB, DantelionFormatsError> {
+        let mut c = Cursor::new(file);
+
+        let be = c.peek_u8(MQB::ENDIANNESS_OFFSET)? != 0;
+        let header = if be { MQB::read_mqb_header::<BE>(&mut c)? } else { MQB::read_mqb_header::<LE>(&mut c)? };
+        let body = file[MQB::HEADER_SIZE as usize..].to_vec();
+
+        Ok(MQB {
+            header,
+            body,
+        })
+    }
+
+    pub fn to_path(&self, path: &str) -> Result<(), DantelionFormatsError> {
+        Ok(fs::write(path, self.to_bytes()?)?)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        if self.header.big_endian {
+            self.write_mqb::<BE>()
+        } else {
+            self.write_mqb::<LE>()
+        }
+    }
+
+    fn write_mqb<T: ByteOrder>(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        let header = &self.header;
+        let mut bytes = vec![];
+        util::write_fixed_str(&mut bytes, &header.magic, MQB::MAGIC_SIZE)?;
+        bytes.write_u8(if header.big_endian { 0xFF } else { 0 })?;
+        bytes.write_u8(header.unk05)?;
+        bytes.write_u8(if header.long_format { 0xFF } else { 0 })?;
+        bytes.write_u8(header.unk07)?;
+        bytes.write_u32::<T>(header.version)?;
+        bytes.write_u32::<T>(header.header_size)?;
+        bytes.extend_from_slice(&self.body);
+
+        Ok(bytes)
+    }
+
+    fn read_mqb_header<T: ByteOrder>(c: &mut Cursor<&[u8]>) -> Result<MQBHeader, DantelionFormatsError> {
+        let header = MQBHeader {
+            magic: c.read_fixed_cstr(MQB::MAGIC_SIZE)?,
+            big_endian: c.read_u8()? != 0,
+            unk05: c.read_u8()?,
+            long_format: c.read_u8()? != 0,
+            unk07: c.read_u8()?,
+            version: c.read_u32::<T>()?,
+            header_size: c.read_u32::<T>()?,
+        };
+
+        header.validate()?;
+
+        Ok(header)
+    }
+}
+
+/// A one line summary, e.g. "MQB v4 — 2048 byte body, long format".
+impl Display for MQB {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "MQB v{} — {} byte body", self.header.version, self.body.len())?;
+        if self.header.big_endian {
+            write!(f, ", big endian")?;
+        }
+        if self.header.long_format {
+            write!(f, ", long format")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Debug for MQB {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MQB")
+            .field("header", &self.header)
+            .field("body", &DataLen(self.body.len()))
+            .finish()
+    }
+}
+
+impl MQBHeader {
+    // Stable accessors, see `BND4Header`.
+
+    pub fn big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    pub fn long_format(&self) -> bool {
+        self.long_format
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+}
+
+impl Validate for MQBHeader {
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.magic == "MQB ", "Magic was {}", self.magic);
+        ensure!(self.unk05 == 0, "unk05 was {}", self.unk05);
+        ensure!(self.unk07 == 0, "unk07 was {}", self.unk07);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,443 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind};
+use binary_interpreter::binary_reader::BinaryReader;
+use byteorder::{ByteOrder, LE, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+use crate::dcx::DCX;
+use crate::error::DantelionFormatsError;
+use crate::source::{DataSource, FileSource};
+use crate::util;
+use crate::util::{DataLen, ensure, Validate};
+
+/// A map layout from DS3, Sekiro or Elden Ring: models, events, regions, routes, layers and parts, each a param of
+/// entries. Entries are kept as their raw bytes, which only hold offsets relative to themselves, so they can be
+/// reordered, copied between maps or written back unchanged. Names, types and transforms are read out of them for
+/// `to_json`. The 32-bit MSBs of Demon's Souls, Dark Souls and Dark Souls II aren't supported.
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+pub struct MSB {
+    pub header: MSBHeader,
+    pub params: Vec<MSBParam>,
+}
+
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+#[non_exhaustive]
+pub struct MSBHeader {
+    pub magic: String,
+    pub unk04: u32,
+    pub header_size: u32,
+    pub big_endian: bool,
+    pub bit_big_endian: bool,
+    // 1 for UTF-16
+    pub text_encoding: u8,
+    // 0xFF for 64-bit offsets
+    pub long_offsets: u8,
+}
+
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+pub struct MSBParam {
+    // Differs per game, e.g. 3 in DS3
+    pub version: u32,
+    // e.g. "PARTS_PARAM_ST"
+    pub name: String,
+    pub entries: Vec<MSBEntry>,
+}
+
+#[derive(PartialEq, Clone)]
+#[repr(C)]
+pub struct MSBEntry {
+    // Starts with the offset of the entry's name, relative to the entry
+    pub data: Vec<u8>,
+}
+
+/// The JSON written by `MSB::to_json` and read by `MSB::from_json`, for editors that don't link against this crate.
+///
+/// ```json
+/// {
+///   "schema": "dantelion-formats/msb",
+///   "schema_version": 1,
+///   "params": [
+///     {
+///       "name": "PARTS_PARAM_ST",
+///       "version": 3,
+///       "entries": [
+///         {
+///           "name": "c1000_0000",
+///           "type": 2,
+///           "position": [1.0, 2.0, 3.0],
+///           "rotation": [0.0, 90.0, 0.0],
+///           "scale": [1.0, 1.0, 1.0],
+///           "data": "2800000000000000..."
+///         }
+///       ]
+///     }
+///   ]
+/// }
+/// ```
+///
+/// `data` is the entry's bytes in lowercase hex and is what gets imported. `position`, `rotation` (degrees) and
+/// `scale` are written for parts, and the first two for regions. Where present they're written into `data` on
+/// import, so a transform can be edited without touching the hex. `name` and `type` are only there to read, renaming
+/// or retyping an entry means editing `data`. Params and entries can be added, removed and reordered, but entries
+/// refer to each other by index (e.g. a part's model), and fixing those up is left to the editor.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct MSBJson {
+    pub schema: String,
+    pub schema_version: u32,
+    pub params: Vec<MSBParamJson>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct MSBParamJson {
+    pub name: String,
+    pub version: u32,
+    pub entries: Vec<MSBEntryJson>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct MSBEntryJson {
+    pub name: String,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub entry_type: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<[f32; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<[f32; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<[f32; 3]>,
+    pub data: String,
+}
+
+// Where the fields `to_json` and `part_models` read out of an entry are, for each param that has them.
+struct EntryLayout {
+    entry_type: Option<usize>,
+    position: Option<usize>,
+    rotation: Option<usize>,
+    scale: Option<usize>,
+    // Index into MODEL_PARAM_ST
+    model_index: Option<usize>,
+}
+
+impl MSB {
+    const MAGIC_SIZE: usize = 4;
+    const HEADER_SIZE: u32 = 0x10;
+    const LONG_OFFSETS: u8 = 0xFF;
+    const ALIGNMENT: usize = 8;
+    pub const JSON_SCHEMA: &'static str = "dantelion-formats/msb";
+    /// Bumped whenever `MSBJson` changes in a way older readers would get wrong.
+    pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+    pub(crate) fn is(bytes: &[u8]) -> bool {
+        bytes.starts_with(b"MSB ")
+    }
+
+    pub fn from_path(path: &str) -> Result<MSB, DantelionFormatsError> {
+        MSB::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<MSB, DantelionFormatsError> {
+        let file = source.read_all()?;
+
+        MSB::from_bytes(&file)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<MSB, DantelionFormatsError> {
+        let bytes = if DCX::is(file) {
+            DCX::from_bytes(file)?.decompress()?
+        } else {
+            file.to_vec()
+        };
+        let mut c = Cursor::new(&bytes[..]);
+
+        let header = MSBHeader {
+            magic: c.read_fixed_cstr(MSB::MAGIC_SIZE)?,
+            unk04: c.read_u32::<LE>()?,
+            header_size: c.read_u32::<LE>()?,
+            big_endian: c.read_u8()? != 0,
+            bit_big_endian: c.read_u8()? != 0,
+            text_encoding: c.read_u8()?,
+            long_offsets: c.read_u8()?,
+        };
+        header.validate()?;
+        if header.long_offsets != MSB::LONG_OFFSETS || header.big_endian {
+            return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::Unsupported, "Only the little endian, 64-bit MSBs of DS3 and later are supported")));
+        }
+
+        let mut params = vec![];
+        let mut offset = c.position();
+        // The last param points at 0. Params are always after the one before, which also stops a loop.
+        while offset != 0 {
+            if offset < c.position() {
+                return Err(invalid(format!("Param at {:#X} points back to {:#X}", c.position(), offset)));
+            }
+            c.set_position(offset);
+            let (param, next) = MSB::read_param(&mut c)?;
+            params.push(param);
+            offset = next;
+        }
+
+        Ok(MSB {
+            header,
+            params,
+        })
+    }
+
+    fn read_param(c: &mut Cursor<&[u8]>) -> Result<(MSBParam, u64), DantelionFormatsError> {
+        let version = c.read_u32::<LE>()?;
+        let offset_count = c.read_u32::<LE>()?;
+        let name_offset = c.read_u64::<LE>()?;
+        let entry_count = offset_count.checked_sub(1).ok_or_else(|| invalid("Param has no next param offset".to_string()))?;
+        let remaining = (c.get_ref().len() as u64).saturating_sub(c.position()) / 8;
+        let mut entry_offsets = Vec::with_capacity((entry_count as u64).min(remaining) as usize);
+        for _ in 0..entry_count {
+            entry_offsets.push(c.read_u64::<LE>()?);
+        }
+        let next_param_offset = c.read_u64::<LE>()?;
+        let name = util::peek_utf16::<LE>(c, name_offset)?;
+
+        // Entries run up to the next one, the next param or the end of the file.
+        let file_len = c.get_ref().len() as u64;
+        let end = if next_param_offset != 0 { next_param_offset } else { file_len };
+        let mut entries = Vec::with_capacity(entry_offsets.len());
+        for (index, &start) in entry_offsets.iter().enumerate() {
+            let entry_end = entry_offsets.get(index + 1).copied().unwrap_or(end);
+            if start > entry_end || entry_end > file_len {
+                return Err(invalid(format!("{} entry {} at {:#X} runs to {:#X}", name, index, start, entry_end)));
+            }
+            entries.push(MSBEntry { data: c.get_ref()[start as usize..entry_end as usize].to_vec() });
+        }
+
+        Ok((MSBParam { version, name, entries }, next_param_offset))
+    }
+
+    pub fn to_path(&self, path: &str) -> Result<(), DantelionFormatsError> {
+        Ok(fs::write(path, self.to_bytes()?)?)
+    }
+
+    /// Serializes the MSB. Offsets are recalculated, entries are written as they are.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        let header = &self.header;
+        let mut bytes = vec![];
+        util::write_fixed_str(&mut bytes, &header.magic, MSB::MAGIC_SIZE)?;
+        bytes.write_u32::<LE>(header.unk04)?;
+        bytes.write_u32::<LE>(header.header_size)?;
+        bytes.write_u8(header.big_endian as u8)?;
+        bytes.write_u8(header.bit_big_endian as u8)?;
+        bytes.write_u8(header.text_encoding)?;
+        bytes.write_u8(header.long_offsets)?;
+
+        let mut next_param_position = None;
+        for param in &self.params {
+            let start = bytes.len() as u64;
+            if let Some(position) = next_param_position {
+                LE::write_u64(&mut bytes[position..position + 8], start);
+            }
+            bytes.write_u32::<LE>(param.version)?;
+            bytes.write_u32::<LE>(param.entries.len() as u32 + 1)?;
+            let name_position = bytes.len();
+            bytes.write_u64::<LE>(0)?;
+            let entries_position = bytes.len();
+            bytes.resize(bytes.len() + param.entries.len() * 8, 0);
+            next_param_position = Some(bytes.len());
+            bytes.write_u64::<LE>(0)?;
+
+            let offset = bytes.len() as u64;
+            LE::write_u64(&mut bytes[name_position..name_position + 8], offset);
+            for c in param.name.encode_utf16() {
+                bytes.write_u16::<LE>(c)?;
+            }
+            bytes.write_u16::<LE>(0)?;
+
+            for (index, entry) in param.entries.iter().enumerate() {
+                util::pad_to(&mut bytes, MSB::ALIGNMENT);
+                let offset = bytes.len() as u64;
+                let position = entries_position + index * 8;
+                LE::write_u64(&mut bytes[position..position + 8], offset);
+                bytes.extend_from_slice(&entry.data);
+            }
+            util::pad_to(&mut bytes, MSB::ALIGNMENT);
+        }
+
+        Ok(bytes)
+    }
+
+    /// The MSB as `MSBJson`, pretty printed.
+    pub fn to_json(&self) -> Result<String, DantelionFormatsError> {
+        let mut params = Vec::with_capacity(self.params.len());
+        for param in &self.params {
+            let layout = EntryLayout::for_param(&param.name);
+            let mut entries = Vec::with_capacity(param.entries.len());
+            for entry in &param.entries {
+                entries.push(MSBEntryJson {
+                    name: entry.name()?,
+                    entry_type: entry.entry_type(&param.name),
+                    position: layout.position.and_then(|offset| entry.read_vector3(offset)),
+                    rotation: layout.rotation.and_then(|offset| entry.read_vector3(offset)),
+                    scale: layout.scale.and_then(|offset| entry.read_vector3(offset)),
+                    data: entry.data.iter().map(|byte| format!("{:02x}", byte)).collect(),
+                });
+            }
+            params.push(MSBParamJson { name: param.name.clone(), version: param.version, entries });
+        }
+
+        let json = MSBJson { schema: MSB::JSON_SCHEMA.to_string(), schema_version: MSB::JSON_SCHEMA_VERSION, params };
+
+        Ok(serde_json::to_string_pretty(&json)?)
+    }
+
+    /// Reads JSON written by `to_json`, possibly edited, back into an MSB. Transforms in the JSON win over the ones in
+    /// `data`, see `MSBJson`.
+    pub fn from_json(json: &str) -> Result<MSB, DantelionFormatsError> {
+        let json: MSBJson = serde_json::from_str(json)?;
+        if json.schema != MSB::JSON_SCHEMA || json.schema_version != MSB::JSON_SCHEMA_VERSION {
+            return Err(invalid(format!("Expected {} version {}, found {} version {}", MSB::JSON_SCHEMA, MSB::JSON_SCHEMA_VERSION, json.schema, json.schema_version)));
+        }
+
+        let mut params = Vec::with_capacity(json.params.len());
+        for param in json.params {
+            let layout = EntryLayout::for_param(&param.name);
+            let mut entries = Vec::with_capacity(param.entries.len());
+            for entry_json in param.entries {
+                let mut entry = MSBEntry { data: decode_hex(&entry_json.data)? };
+                for (offset, value) in [(layout.position, entry_json.position), (layout.rotation, entry_json.rotation), (layout.scale, entry_json.scale)] {
+                    if let (Some(offset), Some(value)) = (offset, value) {
+                        entry.write_vector3(offset, value).ok_or_else(|| invalid(format!("{} is too short for its transform", entry_json.name)))?;
+                    }
+                }
+                entries.push(entry);
+            }
+            params.push(MSBParam { version: param.version, name: param.name, entries });
+        }
+
+        Ok(MSB {
+            header: MSBHeader {
+                magic: "MSB ".to_string(),
+                unk04: 1,
+                header_size: MSB::HEADER_SIZE,
+                big_endian: false,
+                bit_big_endian: false,
+                text_encoding: 1,
+                long_offsets: MSB::LONG_OFFSETS,
+            },
+            params,
+        })
+    }
+
+    /// The param called `name`, e.g. "PARTS_PARAM_ST".
+    pub fn param(&self, name: &str) -> Option<&MSBParam> {
+        self.params.iter().find(|param| param.name == name)
+    }
+
+    /// Each part with the MODEL_PARAM_ST entry its model index points at, `None` when the index is past the end of
+    /// the models.
+    pub fn part_models(&self) -> Vec<(&MSBEntry, Option<&MSBEntry>)> {
+        let Some(parts) = self.param("PARTS_PARAM_ST") else { return vec![] };
+        let models = self.param("MODEL_PARAM_ST").map_or(&[][..], |param| &param.entries[..]);
+
+        parts.entries.iter()
+            .map(|part| {
+                let model = part.model_index(&parts.name)
+                    .and_then(|index| usize::try_from(index).ok())
+                    .and_then(|index| models.get(index));
+                (part, model)
+            })
+            .collect()
+    }
+}
+
+impl MSBEntry {
+    /// The entry's name, from the UTF-16 string its first field points at.
+    pub fn name(&self) -> Result<String, DantelionFormatsError> {
+        let offset = self.data.get(..8).map(LE::read_u64).ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Entry is too short for a name"))?;
+
+        util::peek_utf16::<LE>(&Cursor::new(&self.data[..]), offset)
+    }
+
+    /// The entry's type within `param`, e.g. 2 for an enemy in PARTS_PARAM_ST. `None` for params without types.
+    pub fn entry_type(&self, param: &str) -> Option<u32> {
+        let offset = EntryLayout::for_param(param).entry_type?;
+        self.data.get(offset..offset + 4).map(LE::read_u32)
+    }
+
+    /// The index of the MODEL_PARAM_ST entry a part uses. `None` for params other than PARTS_PARAM_ST.
+    pub fn model_index(&self, param: &str) -> Option<i32> {
+        let offset = EntryLayout::for_param(param).model_index?;
+        self.data.get(offset..offset + 4).map(LE::read_i32)
+    }
+
+    fn read_vector3(&self, offset: usize) -> Option<[f32; 3]> {
+        let bytes = self.data.get(offset..offset + 12)?;
+        Some([LE::read_f32(bytes), LE::read_f32(&bytes[4..]), LE::read_f32(&bytes[8..])])
+    }
+
+    fn write_vector3(&mut self, offset: usize, value: [f32; 3]) -> Option<()> {
+        let bytes = self.data.get_mut(offset..offset + 12)?;
+        for (chunk, value) in bytes.chunks_exact_mut(4).zip(value) {
+            LE::write_f32(chunk, value);
+        }
+        Some(())
+    }
+}
+
+impl EntryLayout {
+    // The same in DS3, Sekiro and Elden Ring
+    fn for_param(name: &str) -> EntryLayout {
+        match name {
+            "MODEL_PARAM_ST" => EntryLayout { entry_type: Some(0x8), position: None, rotation: None, scale: None, model_index: None },
+            "EVENT_PARAM_ST" => EntryLayout { entry_type: Some(0xC), position: None, rotation: None, scale: None, model_index: None },
+            "POINT_PARAM_ST" => EntryLayout { entry_type: Some(0x8), position: Some(0x14), rotation: Some(0x20), scale: None, model_index: None },
+            "PARTS_PARAM_ST" => EntryLayout { entry_type: Some(0x8), position: Some(0x20), rotation: Some(0x2C), scale: Some(0x38), model_index: Some(0x10) },
+            _ => EntryLayout { entry_type: None, position: None, rotation: None, scale: None, model_index: None },
+        }
+    }
+}
+
+fn invalid(message: String) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, message))
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, DantelionFormatsError> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid("Entry data has an odd number of hex digits".to_string()));
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()).ok_or_else(|| invalid(format!("Entry data isn't hex at {}", i))))
+        .collect()
+}
+
+/// A one line summary, e.g. "MSB — 6 params, 1200 entries".
+impl Display for MSB {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let entries: usize = self.params.iter().map(|param| param.entries.len()).sum();
+        write!(f, "MSB — {} params, {} entries", self.params.len(), entries)
+    }
+}
+
+impl Debug for MSBEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MSBEntry")
+            .field("name", &self.name().ok())
+            .field("data", &DataLen(self.data.len()))
+            .finish()
+    }
+}
+
+impl MSBHeader {
+    // Stable accessors, see `BND4Header`.
+
+    pub fn long_offsets(&self) -> bool {
+        self.long_offsets == MSB::LONG_OFFSETS
+    }
+}
+
+impl Validate for MSBHeader {
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.magic == "MSB ", "Magic was {}", self.magic);
+        ensure!(self.header_size == MSB::HEADER_SIZE, "header_size was {}", self.header_size);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,446 @@
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind};
+use byteorder::{BE, LE, ByteOrder, ReadBytesExt, WriteBytesExt};
+use crate::dcx::DCX;
+use crate::error::DantelionFormatsError;
+use crate::source::{DataSource, FileSource};
+use crate::util;
+use crate::util::{ensure, Validate};
+
+/// A map's room connectivity, the ".mcp" next to its navmeshes. Each room is a box around part of the navmesh, along
+/// with the rooms that can be walked to from it, possibly in a neighbouring map.
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+pub struct MCP {
+    pub header: MCPHeader,
+    pub rooms: Vec<MCPRoom>,
+}
+
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+#[non_exhaustive]
+pub struct MCPHeader {
+    pub big_endian: bool,
+    pub version: u32,
+    pub unk04: i32,
+}
+
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+pub struct MCPRoom {
+    // The map the room is in, e.g. 0x1E000000 for m30_00_00_00
+    pub map_id: i32,
+    // The room's index within its map
+    pub local_index: i32,
+    pub bounding_box_min: [f32; 3],
+    pub bounding_box_max: [f32; 3],
+    // Indices into `MCP::rooms`
+    pub connected_rooms: Vec<i32>,
+}
+
+/// A map's navigation graph, the ".mcg" next to its ".mcp". Nodes are points on the navmesh and edges the paths
+/// between them, each going through the rooms of the map's MCP.
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+pub struct MCG {
+    pub header: MCGHeader,
+    pub nodes: Vec<MCGNode>,
+    pub edges: Vec<MCGEdge>,
+}
+
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+#[non_exhaustive]
+pub struct MCGHeader {
+    pub big_endian: bool,
+    pub version: u32,
+    pub unk04: i32,
+    pub unk18: i32,
+    pub unk1c: i32,
+}
+
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+pub struct MCGNode {
+    pub position: [f32; 3],
+    // Indices into `MCG::nodes`, one per connected edge
+    pub connected_nodes: Vec<i32>,
+    // Indices into `MCG::edges`, in the same order as `connected_nodes`
+    pub connected_edges: Vec<i32>,
+    pub unk18: i32,
+    pub unk1c: i32,
+}
+
+#[derive(Debug, PartialEq)]
+#[repr(C)]
+pub struct MCGEdge {
+    pub node_a: i32,
+    // Indices into the MCP's rooms the edge passes through on `node_a`'s side
+    pub rooms_a: Vec<i32>,
+    pub node_b: i32,
+    pub rooms_b: Vec<i32>,
+    pub map_id: i32,
+    pub cost: f32,
+}
+
+impl MCP {
+    const VERSION: u32 = 2;
+    const HEADER_SIZE: usize = 0x10;
+    const ROOM_SIZE: usize = 0x28;
+
+    pub fn from_path(path: &str) -> Result<MCP, DantelionFormatsError> {
+        MCP::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<MCP, DantelionFormatsError> {
+        let file = source.read_all()?;
+
+        MCP::from_bytes(&file)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<MCP, DantelionFormatsError> {
+        let bytes = decompress(file)?;
+        let mut c = Cursor::new(&bytes[..]);
+
+        // The version is the first field, so whichever order reads it as 2 is the file's.
+        if bytes.get(..4).is_some_and(|version| BE::read_u32(version) == MCP::VERSION) {
+            MCP::read_mcp::<BE>(&mut c)
+        } else {
+            MCP::read_mcp::<LE>(&mut c)
+        }
+    }
+
+    fn read_mcp<T: ByteOrder>(c: &mut Cursor<&[u8]>) -> Result<MCP, DantelionFormatsError> {
+        let header = MCPHeader {
+            big_endian: is_big_endian::<T>(),
+            version: c.read_u32::<T>()?,
+            unk04: c.read_i32::<T>()?,
+        };
+        header.validate()?;
+        let room_count = c.read_u32::<T>()?;
+        let rooms_offset = c.read_u32::<T>()?;
+        c.set_position(rooms_offset as u64);
+
+        let mut rooms = Vec::with_capacity(util::capacity_for(c, room_count as u64));
+        for _ in 0..room_count {
+            let map_id = c.read_i32::<T>()?;
+            let local_index = c.read_i32::<T>()?;
+            let connected_count = c.read_u32::<T>()?;
+            let bounding_box_min = read_vector3::<T>(c)?;
+            let bounding_box_max = read_vector3::<T>(c)?;
+            let connected_offset = c.read_u32::<T>()?;
+            rooms.push(MCPRoom {
+                map_id,
+                local_index,
+                bounding_box_min,
+                bounding_box_max,
+                connected_rooms: peek_i32s::<T>(c, connected_offset, connected_count)?,
+            });
+        }
+
+        Ok(MCP {
+            header,
+            rooms,
+        })
+    }
+
+    pub fn to_path(&self, path: &str) -> Result<(), DantelionFormatsError> {
+        Ok(fs::write(path, self.to_bytes()?)?)
+    }
+
+    /// Serializes the MCP. The rooms come right after the header and each room's connections after all of the rooms.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        if self.header.big_endian {
+            self.write_mcp::<BE>()
+        } else {
+            self.write_mcp::<LE>()
+        }
+    }
+
+    fn write_mcp<T: ByteOrder>(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        let mut bytes = vec![];
+        bytes.write_u32::<T>(self.header.version)?;
+        bytes.write_i32::<T>(self.header.unk04)?;
+        bytes.write_u32::<T>(self.rooms.len() as u32)?;
+        bytes.write_u32::<T>(MCP::HEADER_SIZE as u32)?;
+
+        let mut connected_offset = MCP::HEADER_SIZE + self.rooms.len() * MCP::ROOM_SIZE;
+        for room in &self.rooms {
+            bytes.write_i32::<T>(room.map_id)?;
+            bytes.write_i32::<T>(room.local_index)?;
+            bytes.write_u32::<T>(room.connected_rooms.len() as u32)?;
+            write_vector3::<T>(&mut bytes, room.bounding_box_min)?;
+            write_vector3::<T>(&mut bytes, room.bounding_box_max)?;
+            bytes.write_u32::<T>(connected_offset as u32)?;
+            connected_offset += room.connected_rooms.len() * 4;
+        }
+        for room in &self.rooms {
+            write_i32s::<T>(&mut bytes, &room.connected_rooms)?;
+        }
+
+        Ok(bytes)
+    }
+
+    /// Every connection between rooms, as `(room, connected room)` index pairs.
+    pub fn links(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.rooms.iter().enumerate()
+            .flat_map(|(index, room)| room.connected_rooms.iter().filter_map(move |&other| Some((index, usize::try_from(other).ok()?))))
+    }
+}
+
+impl MCG {
+    const VERSION: u32 = 1;
+    const HEADER_SIZE: usize = 0x20;
+    const NODE_SIZE: usize = 0x20;
+    const EDGE_SIZE: usize = 0x20;
+
+    pub fn from_path(path: &str) -> Result<MCG, DantelionFormatsError> {
+        MCG::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<MCG, DantelionFormatsError> {
+        let file = source.read_all()?;
+
+        MCG::from_bytes(&file)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<MCG, DantelionFormatsError> {
+        let bytes = decompress(file)?;
+        let mut c = Cursor::new(&bytes[..]);
+
+        // Same as the MCP, the version comes first.
+        if bytes.get(..4).is_some_and(|version| BE::read_u32(version) == MCG::VERSION) {
+            MCG::read_mcg::<BE>(&mut c)
+        } else {
+            MCG::read_mcg::<LE>(&mut c)
+        }
+    }
+
+    fn read_mcg<T: ByteOrder>(c: &mut Cursor<&[u8]>) -> Result<MCG, DantelionFormatsError> {
+        let big_endian = is_big_endian::<T>();
+        let version = c.read_u32::<T>()?;
+        let unk04 = c.read_i32::<T>()?;
+        let node_count = c.read_u32::<T>()?;
+        let nodes_offset = c.read_u32::<T>()?;
+        let edge_count = c.read_u32::<T>()?;
+        let edges_offset = c.read_u32::<T>()?;
+        let header = MCGHeader {
+            big_endian,
+            version,
+            unk04,
+            unk18: c.read_i32::<T>()?,
+            unk1c: c.read_i32::<T>()?,
+        };
+        header.validate()?;
+
+        c.set_position(nodes_offset as u64);
+        let mut nodes = Vec::with_capacity(util::capacity_for(c, node_count as u64));
+        for _ in 0..node_count {
+            let connected_count = c.read_u32::<T>()?;
+            let position = read_vector3::<T>(c)?;
+            let connected_nodes_offset = c.read_u32::<T>()?;
+            let connected_edges_offset = c.read_u32::<T>()?;
+            nodes.push(MCGNode {
+                position,
+                connected_nodes: peek_i32s::<T>(c, connected_nodes_offset, connected_count)?,
+                connected_edges: peek_i32s::<T>(c, connected_edges_offset, connected_count)?,
+                unk18: c.read_i32::<T>()?,
+                unk1c: c.read_i32::<T>()?,
+            });
+        }
+
+        c.set_position(edges_offset as u64);
+        let mut edges = Vec::with_capacity(util::capacity_for(c, edge_count as u64));
+        for _ in 0..edge_count {
+            let node_a = c.read_i32::<T>()?;
+            let rooms_a = (c.read_u32::<T>()?, c.read_u32::<T>()?);
+            let node_b = c.read_i32::<T>()?;
+            let rooms_b = (c.read_u32::<T>()?, c.read_u32::<T>()?);
+            edges.push(MCGEdge {
+                node_a,
+                rooms_a: peek_i32s::<T>(c, rooms_a.1, rooms_a.0)?,
+                node_b,
+                rooms_b: peek_i32s::<T>(c, rooms_b.1, rooms_b.0)?,
+                map_id: c.read_i32::<T>()?,
+                cost: c.read_f32::<T>()?,
+            });
+        }
+
+        Ok(MCG {
+            header,
+            nodes,
+            edges,
+        })
+    }
+
+    pub fn to_path(&self, path: &str) -> Result<(), DantelionFormatsError> {
+        Ok(fs::write(path, self.to_bytes()?)?)
+    }
+
+    /// Serializes the MCG. The nodes and edges come right after the header, and their index lists after both, in the
+    /// same order.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        if let Some(index) = self.nodes.iter().position(|node| node.connected_nodes.len() != node.connected_edges.len()) {
+            return Err(invalid(format!("Node {} has {} connected nodes but {} connected edges", index, self.nodes[index].connected_nodes.len(), self.nodes[index].connected_edges.len())));
+        }
+
+        if self.header.big_endian {
+            self.write_mcg::<BE>()
+        } else {
+            self.write_mcg::<LE>()
+        }
+    }
+
+    fn write_mcg<T: ByteOrder>(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        let header = &self.header;
+        let nodes_offset = MCG::HEADER_SIZE;
+        let edges_offset = nodes_offset + self.nodes.len() * MCG::NODE_SIZE;
+        let mut bytes = vec![];
+        bytes.write_u32::<T>(header.version)?;
+        bytes.write_i32::<T>(header.unk04)?;
+        bytes.write_u32::<T>(self.nodes.len() as u32)?;
+        bytes.write_u32::<T>(nodes_offset as u32)?;
+        bytes.write_u32::<T>(self.edges.len() as u32)?;
+        bytes.write_u32::<T>(edges_offset as u32)?;
+        bytes.write_i32::<T>(header.unk18)?;
+        bytes.write_i32::<T>(header.unk1c)?;
+
+        let mut indices_offset = edges_offset + self.edges.len() * MCG::EDGE_SIZE;
+        for node in &self.nodes {
+            let count = node.connected_nodes.len();
+            bytes.write_u32::<T>(count as u32)?;
+            write_vector3::<T>(&mut bytes, node.position)?;
+            bytes.write_u32::<T>(indices_offset as u32)?;
+            bytes.write_u32::<T>((indices_offset + count * 4) as u32)?;
+            bytes.write_i32::<T>(node.unk18)?;
+            bytes.write_i32::<T>(node.unk1c)?;
+            indices_offset += count * 8;
+        }
+        for edge in &self.edges {
+            bytes.write_i32::<T>(edge.node_a)?;
+            bytes.write_u32::<T>(edge.rooms_a.len() as u32)?;
+            bytes.write_u32::<T>(indices_offset as u32)?;
+            indices_offset += edge.rooms_a.len() * 4;
+            bytes.write_i32::<T>(edge.node_b)?;
+            bytes.write_u32::<T>(edge.rooms_b.len() as u32)?;
+            bytes.write_u32::<T>(indices_offset as u32)?;
+            indices_offset += edge.rooms_b.len() * 4;
+            bytes.write_i32::<T>(edge.map_id)?;
+            bytes.write_f32::<T>(edge.cost)?;
+        }
+
+        for node in &self.nodes {
+            write_i32s::<T>(&mut bytes, &node.connected_nodes)?;
+            write_i32s::<T>(&mut bytes, &node.connected_edges)?;
+        }
+        for edge in &self.edges {
+            write_i32s::<T>(&mut bytes, &edge.rooms_a)?;
+            write_i32s::<T>(&mut bytes, &edge.rooms_b)?;
+        }
+
+        Ok(bytes)
+    }
+}
+
+fn decompress(file: &[u8]) -> Result<Vec<u8>, DantelionFormatsError> {
+    if DCX::is(file) {
+        DCX::from_bytes(file)?.decompress()
+    } else {
+        Ok(file.to_vec())
+    }
+}
+
+fn is_big_endian<T: ByteOrder>() -> bool {
+    T::read_u16(&[0, 1]) == 1
+}
+
+fn invalid(message: String) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, message))
+}
+
+fn read_vector3<T: ByteOrder>(c: &mut Cursor<&[u8]>) -> Result<[f32; 3], DantelionFormatsError> {
+    Ok([c.read_f32::<T>()?, c.read_f32::<T>()?, c.read_f32::<T>()?])
+}
+
+fn write_vector3<T: ByteOrder>(bytes: &mut Vec<u8>, vector: [f32; 3]) -> Result<(), DantelionFormatsError> {
+    for value in vector {
+        bytes.write_f32::<T>(value)?;
+    }
+
+    Ok(())
+}
+
+fn peek_i32s<T: ByteOrder>(c: &mut Cursor<&[u8]>, offset: u32, count: u32) -> Result<Vec<i32>, DantelionFormatsError> {
+    let start = c.position();
+    c.set_position(offset as u64);
+    let mut values = Vec::with_capacity(util::capacity_for(c, count as u64));
+    for _ in 0..count {
+        values.push(c.read_i32::<T>()?);
+    }
+    c.set_position(start);
+
+    Ok(values)
+}
+
+fn write_i32s<T: ByteOrder>(bytes: &mut Vec<u8>, values: &[i32]) -> Result<(), DantelionFormatsError> {
+    for &value in values {
+        bytes.write_i32::<T>(value)?;
+    }
+
+    Ok(())
+}
+
+/// A one line summary, e.g. "MCP — 120 rooms, 310 links".
+impl Display for MCP {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "MCP — {} rooms, {} links", self.rooms.len(), self.links().count())
+    }
+}
+
+/// A one line summary, e.g. "MCG — 80 nodes, 95 edges".
+impl Display for MCG {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "MCG — {} nodes, {} edges", self.nodes.len(), self.edges.len())
+    }
+}
+
+impl MCPHeader {
+    // Stable accessors, see `BND4Header`.
+
+    pub fn big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+}
+
+impl MCGHeader {
+    // Stable accessors, see `BND4Header`.
+
+    pub fn big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+}
+
+impl Validate for MCPHeader {
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.version == MCP::VERSION, "version was {}", self.version);
+
+        Ok(())
+    }
+}
+
+impl Validate for MCGHeader {
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.version == MCG::VERSION, "version was {}", self.version);
+
+        Ok(())
+    }
+}
@@ -1,10 +1,13 @@
+use std::env;
 use std::io::{Error, ErrorKind};
-use libloading::os::windows::{Library, Symbol};
+use std::path::{Path, PathBuf};
+use libloading::{Library, Symbol};
+use crate::config::Config;
 use crate::error::DantelionFormatsError;
 use crate::oodle::CheckCRC::No;
 use crate::oodle::DecodeThreadPhase::ThreadPhaseAll;
 use crate::oodle::FuzzSafe::Yes;
-use crate::util::get_oodle_path;
+use crate::util::OODLE_DLL_NAME;
 
 #[repr(u32)]
 enum FuzzSafe {
@@ -33,6 +36,36 @@ ThreadPhase2 = 2,
 ThreadPhaseAll = 3
 }
 
+pub(crate) static OODLE_PATH_ENV_VARS: [&str; 2] = ["OODLE_PATH", "DANTELION_OODLE"];
+
+/// The Oodle DLL from the config, the environment, the working directory or a game install. Fails only when there's a
+/// config file and it can't be read.
+pub fn get_oodle_path() -> Result<Option<String>, DantelionFormatsError> {
+    Ok(Config::load()?.oodle_path())
+}
+
+pub(crate) fn find_oodle_path(game_dirs: &[PathBuf]) -> Option<String> {
+    // Either the DLL itself or the folder it's in.
+    for var in OODLE_PATH_ENV_VARS {
+        if let Ok(path) = env::var(var) {
+            let path = Path::new(&path);
+            let dll = if path.is_dir() { path.join(OODLE_DLL_NAME) } else { path.to_path_buf() };
+            if dll.exists() {
+                return Some(dll.to_string_lossy().to_string());
+            }
+        }
+    }
+
+    if Path::new(OODLE_DLL_NAME).exists() {
+        return Some(OODLE_DLL_NAME.to_string());
+    }
+
+    game_dirs.iter()
+        .map(|dir| dir.join(OODLE_DLL_NAME))
+        .find(|dll| dll.exists())
+        .map(|dll| dll.to_string_lossy().to_string())
+}
+
 // #[link(name = "oo2core_6_win64")]
 // extern {
 //     fn OodleLZ_Decompress(comp_buf: &[u8], comp_buf_size: usize, raw_buf: &[u8], raw_len: usize,
@@ -43,40 +76,71 @@ ThreadPhaseAll = 3
 //     fn OodleLZ_GetDecodeBufferSize(raw_size: usize, corruption_possible: bool) -> usize;
 // }
 
-pub unsafe fn decompress(data: &[u8], uncompressed_size: usize) -> Result<Vec<u8>, DantelionFormatsError> {
+/// A loaded Oodle DLL. Load one up front with `with_path` when the DLL isn't somewhere `get_oodle_path` looks,
+/// or to avoid loading the DLL for every file.
+#[derive(Debug)]
+pub struct OodleContext {
+    oodle: Library,
+}
 
-    let oodle_path = match get_oodle_path() {
-        None => return
-            Err(DantelionFormatsError::IoError(
+impl OodleContext {
+    /// Loads the DLL from `OODLE_PATH`/`DANTELION_OODLE`, the working directory or a game install.
+    pub fn new() -> Result<OodleContext, DantelionFormatsError> {
+        match get_oodle_path()? {
+            None => Err(DantelionFormatsError::IoError(
                 Error::new(
                     ErrorKind::NotFound,
-                    "Oodle path not found. Please move a copy of oo2core_6_win64.dll into the working directory")
+                    "Oodle path not found. Please move a copy of oo2core_6_win64.dll into the working directory or set OODLE_PATH")
                 )
             ),
-        Some(path) => path
-    };
+            Some(path) => OodleContext::with_path(&path),
+        }
+    }
 
-    let oodle = Library::new(&oodle_path)?;
-    let oodle_lz_get_decode_buffer_size: Symbol<unsafe extern fn(usize, bool) -> usize> =
-        oodle.get(b"OodleLZ_GetDecodeBufferSize")?;
+    pub fn with_path(path: &str) -> Result<OodleContext, DantelionFormatsError> {
+        let oodle = unsafe { Library::new(path)? };
+        Ok(OodleContext { oodle })
+    }
 
-    let oodle_lz_decompress :Symbol<unsafe extern fn(*const u8, usize, *mut u8, usize,
-                                                     FuzzSafe, CheckCRC, Verbosity,
-                                                     usize, usize, usize, usize,
-                                                     usize, usize, DecodeThreadPhase) -> usize> =
-        oodle.get(b"OodleLZ_Decompress")?;
+    pub unsafe fn decompress(&self, data: &[u8], uncompressed_size: usize) -> Result<Vec<u8>, DantelionFormatsError> {
+        let mut raw_buf = Vec::new();
+        self.decompress_into(data, uncompressed_size, &mut raw_buf)?;
+        Ok(raw_buf)
+    }
 
+    pub unsafe fn decompress_into(&self, data: &[u8], uncompressed_size: usize, raw_buf: &mut Vec<u8>) -> Result<(), DantelionFormatsError> {
+        let oodle_lz_get_decode_buffer_size: Symbol<unsafe extern fn(usize, bool) -> usize> =
+            self.oodle.get(b"OodleLZ_GetDecodeBufferSize")?;
 
-    let decoded_buffer_size = oodle_lz_get_decode_buffer_size(uncompressed_size, true);
+        let oodle_lz_decompress :Symbol<unsafe extern fn(*const u8, usize, *mut u8, usize,
+                                                         FuzzSafe, CheckCRC, Verbosity,
+                                                         usize, usize, usize, usize,
+                                                         usize, usize, DecodeThreadPhase) -> usize> =
+            self.oodle.get(b"OodleLZ_Decompress")?;
 
-    let mut raw_buf = Vec::with_capacity(decoded_buffer_size);
-    raw_buf.set_len(decoded_buffer_size);
 
-    let raw_len = oodle_lz_decompress(data.as_ptr(), data.len(), raw_buf.as_mut_ptr(), uncompressed_size,
-                                               Yes, No, Verbosity::None, 0, 0, 0, 0, 0, 0, ThreadPhaseAll);
+        let decoded_buffer_size = oodle_lz_get_decode_buffer_size(uncompressed_size, true);
 
-    oodle.close()?;
-    raw_buf.truncate(raw_len);
+        raw_buf.clear();
+        raw_buf.reserve(decoded_buffer_size);
+        raw_buf.set_len(decoded_buffer_size);
 
+        let raw_len = oodle_lz_decompress(data.as_ptr(), data.len(), raw_buf.as_mut_ptr(), uncompressed_size,
+                                          Yes, No, Verbosity::None, 0, 0, 0, 0, 0, 0, ThreadPhaseAll);
+
+        raw_buf.truncate(raw_len);
+
+        Ok(())
+    }
+
+}
+
+pub unsafe fn decompress(data: &[u8], uncompressed_size: usize) -> Result<Vec<u8>, DantelionFormatsError> {
+    let mut raw_buf = Vec::new();
+    decompress_into(data, uncompressed_size, &mut raw_buf)?;
     Ok(raw_buf)
 }
+
+pub unsafe fn decompress_into(data: &[u8], uncompressed_size: usize, raw_buf: &mut Vec<u8>) -> Result<(), DantelionFormatsError> {
+    OodleContext::new()?.decompress_into(data, uncompressed_size, raw_buf)
+}
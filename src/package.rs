@@ -0,0 +1,248 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "crypto")]
+use crate::bnd4::BND4WriteOptions;
+#[cfg(feature = "crypto")]
+use crate::crypto_util;
+use crate::error::DantelionFormatsError;
+#[cfg(feature = "crypto")]
+use crate::parsed_file;
+#[cfg(feature = "crypto")]
+use crate::patch::BHD5EditSession;
+
+/// Where `package` puts a mod folder's files.
+#[derive(Clone, Debug)]
+pub enum PackageTarget {
+    /// Copies every file to `out` by its virtual path, the layout ModEngine2 loads a mod folder from.
+    ModEngine2 { out: PathBuf },
+    /// Replaces the params in `regulation` with the mod folder's `.param` files, matched by file name, and writes
+    /// the re-encrypted result to `out`. `regulation` and `out` can be the same file.
+    #[cfg(feature = "crypto")]
+    Regulation { regulation: PathBuf, key: Vec<u8>, out: PathBuf },
+    /// Patches every file that isn't a param or a regulation.bin into the archive, see `BHD5EditSession`.
+    #[cfg(feature = "crypto")]
+    Archive { bhd_path: PathBuf, bdt_path: PathBuf, public_key: String, private_key: String },
+}
+
+/// Packages the loose files in `mod_dir` for `target`, returning the virtual paths of the files that went into it.
+/// Files are found by the same virtual paths the archives use, e.g. "/parts/am_m_1600.partsbnd.dcx".
+pub fn package(mod_dir: &Path, target: &PackageTarget) -> Result<Vec<String>, DantelionFormatsError> {
+    let files = loose_files(mod_dir)?;
+
+    match target {
+        PackageTarget::ModEngine2 { out } => {
+            for (virtual_path, path) in &files {
+                let dest = out.join(virtual_path.trim_start_matches('/'));
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(path, dest)?;
+            }
+
+            Ok(files.into_iter().map(|(virtual_path, _)| virtual_path).collect())
+        }
+        #[cfg(feature = "crypto")]
+        PackageTarget::Regulation { regulation, key, out } => {
+            let params: Vec<_> = files.into_iter().filter(|(virtual_path, _)| is_param(virtual_path)).collect();
+            let dcx = crypto_util::decrypt_regulation(&fs::read(regulation)?, key)?;
+            // Parsed through `open_bytes` so the BND4 keeps its DCX and compresses the same way on write.
+            let mut bnd4 = parsed_file::open_bytes(&dcx)?.into_bnd4()?;
+            for (virtual_path, path) in &params {
+                let name = virtual_path.rsplit('/').next().unwrap_or_default();
+                bnd4.replace_file(name, fs::read(path)?)?;
+            }
+
+            let dcx = bnd4.to_bytes(&BND4WriteOptions::default())?;
+            fs::write(out, crypto_util::encrypt_regulation(&dcx, key)?)?;
+
+            Ok(params.into_iter().map(|(virtual_path, _)| virtual_path).collect())
+        }
+        #[cfg(feature = "crypto")]
+        PackageTarget::Archive { bhd_path, bdt_path, public_key, private_key } => {
+            let files: Vec<_> = files.into_iter()
+                .filter(|(virtual_path, _)| !is_param(virtual_path) && !virtual_path.eq_ignore_ascii_case("/regulation.bin"))
+                .collect();
+            let mut session = BHD5EditSession::open(&bhd_path.to_string_lossy(), &bdt_path.to_string_lossy(), public_key, private_key)?;
+            for (virtual_path, path) in &files {
+                session.replace_file(virtual_path, fs::read(path)?);
+            }
+            session.commit()?;
+
+            Ok(files.into_iter().map(|(virtual_path, _)| virtual_path).collect())
+        }
+    }
+}
+
+/// A ModEngine2 config, e.g. `config_eldenring.toml`. Only the mod loader is modelled, every other section and key is
+/// kept as is, so reading and writing a config doesn't drop settings for other extensions.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug, Default)]
+pub struct ModEngineConfig {
+    #[serde(default)]
+    pub modengine: ModEngineSettings,
+    #[serde(default)]
+    pub extension: ModEngineExtensions,
+    #[serde(flatten)]
+    pub other: toml::Table,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug, Default)]
+pub struct ModEngineSettings {
+    #[serde(default)]
+    pub debug: bool,
+    // DLLs loaded alongside the game, relative to the config
+    #[serde(default)]
+    pub external_dlls: Vec<String>,
+    #[serde(flatten)]
+    pub other: toml::Table,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug, Default)]
+pub struct ModEngineExtensions {
+    #[serde(default)]
+    pub mod_loader: ModLoader,
+    #[serde(flatten)]
+    pub other: toml::Table,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ModLoader {
+    pub enabled: bool,
+    // Whether `param/gameparam/*.param` files in mod folders are loaded instead of the regulation's
+    #[serde(default)]
+    pub loose_params: bool,
+    #[serde(default)]
+    pub mods: Vec<ModEntry>,
+    #[serde(flatten)]
+    pub other: toml::Table,
+}
+
+impl Default for ModLoader {
+    fn default() -> Self {
+        ModLoader {
+            enabled: true,
+            loose_params: false,
+            mods: vec![],
+            other: toml::Table::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ModEntry {
+    pub enabled: bool,
+    pub name: String,
+    // The mod folder, relative to the config
+    pub path: String,
+}
+
+/// Something in a ModEngine2 config that would stop a mod from loading, see `ModEngineConfig::validate`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum ModEngineIssue {
+    ModLoaderDisabled,
+    DuplicateMod(String),
+    MissingModFolder { name: String, path: PathBuf },
+    MissingDll(PathBuf),
+}
+
+impl ModEngineConfig {
+    pub fn from_path(path: &Path) -> Result<ModEngineConfig, DantelionFormatsError> {
+        ModEngineConfig::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(config: &str) -> Result<ModEngineConfig, DantelionFormatsError> {
+        Ok(toml::from_str(config)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String, DantelionFormatsError> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), DantelionFormatsError> {
+        Ok(fs::write(path, self.to_toml()?)?)
+    }
+
+    /// Adds an enabled mod, or points the mod with the same name at `path` and enables it.
+    pub fn add_mod(&mut self, name: &str, path: &str) {
+        let entry = ModEntry { enabled: true, name: name.to_string(), path: path.to_string() };
+        let mods = &mut self.extension.mod_loader.mods;
+        match mods.iter_mut().find(|existing| existing.name == name) {
+            Some(existing) => *existing = entry,
+            None => mods.push(entry),
+        }
+    }
+
+    /// Checks the config against the files next to it, `config_dir` being the folder the config is in, which is what
+    /// ModEngine2 resolves relative paths against. Disabled mods aren't checked.
+    pub fn validate(&self, config_dir: &Path) -> Vec<ModEngineIssue> {
+        let mut issues = vec![];
+        let mod_loader = &self.extension.mod_loader;
+        if !mod_loader.enabled {
+            issues.push(ModEngineIssue::ModLoaderDisabled);
+        }
+
+        let mut names = vec![];
+        for entry in mod_loader.mods.iter().filter(|entry| entry.enabled) {
+            if names.contains(&&entry.name) {
+                issues.push(ModEngineIssue::DuplicateMod(entry.name.clone()));
+            }
+            names.push(&entry.name);
+
+            let path = config_dir.join(&entry.path);
+            if !path.is_dir() {
+                issues.push(ModEngineIssue::MissingModFolder { name: entry.name.clone(), path });
+            }
+        }
+
+        issues.extend(self.modengine.external_dlls.iter()
+            .map(|dll| config_dir.join(dll))
+            .filter(|dll| !dll.is_file())
+            .map(ModEngineIssue::MissingDll));
+
+        issues
+    }
+}
+
+/// Adds `mod_dir` to the ModEngine2 config at `config_path` as `name`, creating the config if there isn't one, and
+/// returns what `validate` finds wrong with the result. `mod_dir` is written relative to the config when it's under
+/// the config's folder, like ModEngine2's own configs.
+pub fn register_mod_engine(config_path: &Path, name: &str, mod_dir: &Path) -> Result<Vec<ModEngineIssue>, DantelionFormatsError> {
+    let mut config = if config_path.exists() { ModEngineConfig::from_path(config_path)? } else { ModEngineConfig::default() };
+    let config_dir = config_path.parent().unwrap_or(Path::new(""));
+    let path = match mod_dir.strip_prefix(config_dir) {
+        Ok(relative) => relative.components().map(|component| component.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/"),
+        Err(_) => mod_dir.to_string_lossy().to_string(),
+    };
+    config.add_mod(name, &path);
+    config.write(config_path)?;
+
+    Ok(config.validate(config_dir))
+}
+
+/// Every file under `dir` with its virtual path, sorted by path.
+pub fn loose_files(dir: &Path) -> Result<Vec<(String, PathBuf)>, DantelionFormatsError> {
+    let mut files = vec![];
+    walk(dir, "", &mut files)?;
+    files.sort();
+
+    Ok(files)
+}
+
+fn walk(dir: &Path, virtual_dir: &str, files: &mut Vec<(String, PathBuf)>) -> Result<(), DantelionFormatsError> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let virtual_path = format!("{}/{}", virtual_dir, path.file_name().unwrap_or_default().to_string_lossy());
+        if path.is_dir() {
+            walk(&path, &virtual_path, files)?;
+        } else {
+            files.push((virtual_path, path));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "crypto")]
+fn is_param(virtual_path: &str) -> bool {
+    virtual_path.to_lowercase().ends_with(".param")
+}
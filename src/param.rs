@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind};
+use std::path::Path;
+use binary_interpreter::binary_reader::BinaryPeeker;
+use byteorder::{BE, LE, ByteOrder, ReadBytesExt};
+#[cfg(feature = "crypto")]
+use crate::bnd4::BND4WriteOptions;
+#[cfg(feature = "crypto")]
+use crate::crypto_util;
+use crate::error::DantelionFormatsError;
+#[cfg(feature = "crypto")]
+use crate::parsed_file;
+
+/// New bytes for part of a param row, e.g. one field of a row in "AtkParam_Npc.param". Without paramdefs the fields
+/// aren't known, so edits are raw bytes at an offset into the row's data.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct RowEdit {
+    // The param's file name in the regulation, matched like `BND4::get`
+    pub param: String,
+    pub row: i32,
+    pub offset: usize,
+    pub data: Vec<u8>,
+}
+
+/// Where each row's data is in a PARAM, found from the row headers alone. Every row in a param is the same size,
+/// taken here as the smallest gap between row data offsets, or the gap to the strings for a param with one row.
+#[derive(Debug)]
+pub struct ParamRows {
+    // Row id to data offset, in file order
+    pub rows: Vec<(i32, usize)>,
+    pub row_size: usize,
+}
+
+impl ParamRows {
+    const BIG_ENDIAN_OFFSET: u64 = 0x2C;
+    const FORMAT_2D_OFFSET: u64 = 0x2D;
+    const ROW_COUNT_OFFSET: u64 = 0xA;
+    // Format2D flags
+    const FLAG_01: u8 = 0x01;
+    const INT_DATA_OFFSET: u8 = 0x02;
+    const LONG_DATA_OFFSET: u8 = 0x04;
+
+    pub fn from_bytes(param: &[u8]) -> Result<ParamRows, DantelionFormatsError> {
+        let mut c = Cursor::new(param);
+        if c.peek_u8(ParamRows::BIG_ENDIAN_OFFSET)? == 0xFF {
+            ParamRows::read_rows::<BE>(&mut c)
+        } else {
+            ParamRows::read_rows::<LE>(&mut c)
+        }
+    }
+
+    /// The data offset of the first row with `id`. Some params repeat ids, the game uses the first.
+    pub fn find(&self, id: i32) -> Option<usize> {
+        self.rows.iter().find(|(row, _)| *row == id).map(|(_, offset)| *offset)
+    }
+
+    fn read_rows<T: ByteOrder>(c: &mut Cursor<&[u8]>) -> Result<ParamRows, DantelionFormatsError> {
+        let strings_offset = c.read_u32::<T>()? as usize;
+        c.set_position(ParamRows::ROW_COUNT_OFFSET);
+        let row_count = c.read_u16::<T>()?;
+        let format_2d = c.peek_u8(ParamRows::FORMAT_2D_OFFSET)?;
+        let long_offsets = format_2d & ParamRows::LONG_DATA_OFFSET != 0;
+        let has_data_start = long_offsets || format_2d & (ParamRows::FLAG_01 | ParamRows::INT_DATA_OFFSET) == ParamRows::FLAG_01 | ParamRows::INT_DATA_OFFSET;
+        c.set_position(if has_data_start { 0x40 } else { 0x30 });
+
+        let mut rows = Vec::with_capacity(row_count as usize);
+        for _ in 0..row_count {
+            let id = c.read_i32::<T>()?;
+            let data_offset = if long_offsets {
+                c.read_i32::<T>()?;
+                let data_offset = c.read_u64::<T>()? as usize;
+                c.read_u64::<T>()?;
+                data_offset
+            } else {
+                let data_offset = c.read_u32::<T>()? as usize;
+                c.read_u32::<T>()?;
+                data_offset
+            };
+            rows.push((id, data_offset));
+        }
+
+        let mut offsets: Vec<usize> = rows.iter().map(|(_, offset)| *offset).collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        let row_size = match offsets.as_slice() {
+            [] => 0,
+            [only] => strings_offset.saturating_sub(*only),
+            _ => offsets.windows(2).map(|pair| pair[1] - pair[0]).min().unwrap_or(0),
+        };
+
+        Ok(ParamRows {
+            rows,
+            row_size,
+        })
+    }
+}
+
+/// Names, descriptions, enums and ranges for a param's fields, so editors can show "Slash" instead of 1. Loaded from
+/// Paramdex meta files and paramdefs, see `from_meta_xml` and `from_paramdef_xml`, and combined with `merge`.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct ParamMeta {
+    // By the field's internal name, e.g. "atkPhys"
+    pub fields: HashMap<String, FieldMeta>,
+    // By enum name, e.g. "ATK_TYPE"
+    pub enums: HashMap<String, ParamEnum>,
+}
+
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct FieldMeta {
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+    pub enum_name: Option<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct ParamEnum {
+    // The value type, e.g. "u8"
+    pub value_type: Option<String>,
+    // Value and name, in file order
+    pub options: Vec<(i64, String)>,
+}
+
+impl ParamMeta {
+    /// Parses a Paramdex meta file, e.g. `Meta/AtkParam.xml`. Fields are the children of `<Field>`, named by tag, with
+    /// `AltName`, `Wiki` and `Enum` attributes, and enums are `<Enums><Enum Name="..."><Option Value="..." Name="..."/>`.
+    pub fn from_meta_xml(xml: &str) -> Result<ParamMeta, DantelionFormatsError> {
+        let doc = roxmltree::Document::parse(xml)?;
+        let root = doc.root_element();
+
+        let mut meta = ParamMeta::default();
+        for fields in root.children().filter(|n| n.has_tag_name("Field")) {
+            for field in fields.children().filter(|n| n.is_element()) {
+                meta.fields.insert(field.tag_name().name().to_string(), FieldMeta {
+                    display_name: field.attribute("AltName").map(str::to_string),
+                    description: field.attribute("Wiki").map(str::to_string),
+                    enum_name: field.attribute("Enum").map(str::to_string),
+                    min: None,
+                    max: None,
+                });
+            }
+        }
+
+        for enums in root.children().filter(|n| n.has_tag_name("Enums")) {
+            for param_enum in enums.children().filter(|n| n.has_tag_name("Enum")) {
+                let name = param_enum.attribute("Name").ok_or_else(|| invalid_meta("enum without a name"))?;
+                let options = param_enum.children()
+                    .filter(|n| n.has_tag_name("Option"))
+                    .map(|option| {
+                        let value = option.attribute("Value").and_then(parse_value).ok_or_else(|| invalid_meta("option value"))?;
+                        Ok((value, option.attribute("Name").unwrap_or_default().to_string()))
+                    })
+                    .collect::<Result<_, DantelionFormatsError>>()?;
+                meta.enums.insert(name.to_string(), ParamEnum { value_type: param_enum.attribute("type").map(str::to_string), options });
+            }
+        }
+
+        Ok(meta)
+    }
+
+    /// Parses a Paramdex paramdef, e.g. `Defs/AtkParam.xml`, for its `DisplayName`, `Description`, `Enum`, `Minimum`
+    /// and `Maximum` field elements. The field name comes from `Def`, e.g. "u8 atkType:4 = 0" is "atkType".
+    pub fn from_paramdef_xml(xml: &str) -> Result<ParamMeta, DantelionFormatsError> {
+        let doc = roxmltree::Document::parse(xml)?;
+        let root = doc.root_element();
+
+        let mut meta = ParamMeta::default();
+        for fields in root.children().filter(|n| n.has_tag_name("Fields")) {
+            for field in fields.children().filter(|n| n.has_tag_name("Field")) {
+                let def = field.attribute("Def").ok_or_else(|| invalid_meta("field without a Def"))?;
+                let name = def.split_whitespace().nth(1)
+                    .and_then(|name| name.split([':', '[', '=']).next())
+                    .ok_or_else(|| invalid_meta(def))?;
+                let number = |tag| child_text(field, tag).and_then(|text| text.trim().parse().ok());
+                meta.fields.insert(name.to_string(), FieldMeta {
+                    display_name: child_text(field, "DisplayName").map(str::to_string),
+                    description: child_text(field, "Description").map(str::to_string),
+                    enum_name: child_text(field, "Enum").map(str::to_string),
+                    min: number("Minimum"),
+                    max: number("Maximum"),
+                });
+            }
+        }
+
+        Ok(meta)
+    }
+
+    /// Adds `other`'s fields and enums, its values winning where both have one. Fields missing a value in `other` keep
+    /// this one's, so a paramdef's ranges survive merging in a meta file's names.
+    pub fn merge(&mut self, other: ParamMeta) {
+        for (name, field) in other.fields {
+            let existing = self.fields.entry(name).or_default();
+            existing.display_name = field.display_name.or(existing.display_name.take());
+            existing.description = field.description.or(existing.description.take());
+            existing.enum_name = field.enum_name.or(existing.enum_name.take());
+            existing.min = field.min.or(existing.min);
+            existing.max = field.max.or(existing.max);
+        }
+        self.enums.extend(other.enums);
+    }
+
+    pub fn field(&self, name: &str) -> Option<&FieldMeta> {
+        self.fields.get(name)
+    }
+
+    /// The enum option name for a field's value, e.g. "Slash" for 1, or None if the field has no enum or the value isn't
+    /// in it.
+    pub fn value_name(&self, field: &str, value: i64) -> Option<&str> {
+        let param_enum = self.enums.get(self.field(field)?.enum_name.as_deref()?)?;
+        param_enum.options.iter().find(|(option, _)| *option == value).map(|(_, name)| name.as_str())
+    }
+
+    /// Whether `value` is within the field's minimum and maximum. Fields without a range accept anything.
+    pub fn in_range(&self, field: &str, value: f64) -> bool {
+        let Some(field) = self.field(field) else { return true };
+        field.min.is_none_or(|min| value >= min) && field.max.is_none_or(|max| value <= max)
+    }
+}
+
+/// Community row names, which regulations don't keep, in Paramdex `Names` files: one `<param>.txt` per param, e.g.
+/// `AtkParam_Npc.txt`, with a "<row id> <name>" line per row. Params are looked up by name ignoring case and a
+/// ".param" extension, so "AtkParam_Npc.param" from a regulation finds them.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct RowNames {
+    // By the param's file name without extension. Rows are kept in file order, duplicate ids included.
+    pub params: HashMap<String, Vec<(i32, String)>>,
+}
+
+impl RowNames {
+    /// Loads every `.txt` file in a `Names` folder.
+    pub fn from_dir(dir: &Path) -> Result<RowNames, DantelionFormatsError> {
+        let mut names = RowNames::default();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("txt")) {
+                continue;
+            }
+            let param = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            names.params.insert(param, RowNames::parse(&fs::read_to_string(&path)?)?);
+        }
+
+        Ok(names)
+    }
+
+    /// Parses one names file. Rows without a name are kept with an empty one.
+    pub fn parse(text: &str) -> Result<Vec<(i32, String)>, DantelionFormatsError> {
+        text.lines()
+            .map(|line| line.trim_start_matches('\u{feff}').trim_end_matches('\r'))
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let (id, name) = line.split_once(' ').unwrap_or((line, ""));
+                let id = id.trim().parse().map_err(|_| invalid_meta(line))?;
+                Ok((id, name.to_string()))
+            })
+            .collect()
+    }
+
+    /// Writes a names file for every param, replacing the ones already in `dir`.
+    pub fn write_dir(&self, dir: &Path) -> Result<(), DantelionFormatsError> {
+        fs::create_dir_all(dir)?;
+        for (param, rows) in &self.params {
+            fs::write(dir.join(format!("{}.txt", param)), RowNames::to_text(rows))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn to_text(rows: &[(i32, String)]) -> String {
+        rows.iter().map(|(id, name)| format!("{} {}\n", id, name)).collect()
+    }
+
+    pub fn rows(&self, param: &str) -> Option<&Vec<(i32, String)>> {
+        let param = param_key(param);
+        self.params.iter().find(|(name, _)| name.eq_ignore_ascii_case(param)).map(|(_, rows)| rows)
+    }
+
+    /// The first name for `id`, like the game's first-row-wins lookup. Empty names count as no name.
+    pub fn get(&self, param: &str, id: i32) -> Option<&str> {
+        self.rows(param)?.iter()
+            .find(|(row, _)| *row == id)
+            .map(|(_, name)| name.as_str())
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Names the first row with `id`, adding it to the end if the param doesn't have it yet.
+    pub fn set(&mut self, param: &str, id: i32, name: &str) {
+        let key = param_key(param);
+        let key = self.params.keys().find(|existing| existing.eq_ignore_ascii_case(key)).cloned().unwrap_or_else(|| key.to_string());
+        let rows = self.params.entry(key).or_default();
+        match rows.iter_mut().find(|(row, _)| *row == id) {
+            Some((_, existing)) => *existing = name.to_string(),
+            None => rows.push((id, name.to_string())),
+        }
+    }
+}
+
+// "N:\GR\data\Param\param\GameParam\AtkParam_Npc.param" and "AtkParam_Npc" are both "AtkParam_Npc".
+pub(crate) fn param_key(param: &str) -> &str {
+    let name = param.rsplit(['/', '\\']).next().unwrap_or(param);
+    match name.len().checked_sub(6) {
+        Some(i) if name.is_char_boundary(i) && name[i..].eq_ignore_ascii_case(".param") => &name[..i],
+        _ => name,
+    }
+}
+
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children().find(|n| n.has_tag_name(name)).and_then(|n| n.text())
+}
+
+// Enum values are decimal, or hex with a 0x prefix for flags.
+fn parse_value(text: &str) -> Option<i64> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn invalid_meta(what: &str) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("Invalid param meta: {}", what)))
+}
+
+/// Applies edits to one PARAM in place. Every edit is checked before any is applied, so a bad edit leaves the param
+/// untouched. The `param` field of the edits isn't looked at.
+pub fn apply_row_edits(param: &mut [u8], edits: &[&RowEdit]) -> Result<(), DantelionFormatsError> {
+    let rows = ParamRows::from_bytes(param)?;
+    let mut writes = Vec::with_capacity(edits.len());
+    for edit in edits {
+        let row_offset = rows.find(edit.row)
+            .ok_or_else(|| DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, format!("No row {} in {}", edit.row, edit.param))))?;
+        if edit.offset + edit.data.len() > rows.row_size || row_offset + rows.row_size > param.len() {
+            return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidInput,
+                format!("Edit at 0x{:X} of row {} in {} is past the 0x{:X} byte row", edit.offset, edit.row, edit.param, rows.row_size))));
+        }
+        writes.push((row_offset + edit.offset, &edit.data));
+    }
+
+    for (start, data) in writes {
+        param[start..start + data.len()].copy_from_slice(data);
+    }
+
+    Ok(())
+}
+
+/// Applies row edits to an encrypted regulation.bin and returns it re-encrypted, with every param that isn't edited
+/// left as it was. The BND4 keeps its DCX compression.
+#[cfg(feature = "crypto")]
+pub fn patch_regulation(regulation: &[u8], key: &[u8], edits: &[RowEdit]) -> Result<Vec<u8>, DantelionFormatsError> {
+    let dcx = crypto_util::decrypt_regulation(regulation, key)?;
+    let mut bnd4 = parsed_file::open_bytes(&dcx)?.into_bnd4()?;
+
+    let mut by_param: HashMap<&str, Vec<&RowEdit>> = HashMap::new();
+    for edit in edits {
+        by_param.entry(edit.param.as_str()).or_default().push(edit);
+    }
+    for (name, edits) in by_param {
+        let file = bnd4.get_mut(name)
+            .ok_or_else(|| DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, format!("No param named {} in the regulation", name))))?;
+        let data = file.data.as_mut()
+            .ok_or_else(|| DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, format!("{} has no data", name))))?;
+        apply_row_edits(data, &edits)?;
+    }
+
+    crypto_util::encrypt_regulation(&bnd4.to_bytes(&BND4WriteOptions::default())?, key)
+}
@@ -0,0 +1,343 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use crate::error::DantelionFormatsError;
+
+/// A param row layout from a Paramdex paramdef, e.g. `Defs/AtkParam.xml`, for generating typed row structs with
+/// `to_rust`. Fields marked with a `RemovedRegVersion` are left out, so the layout is the newest one.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Paramdef {
+    // e.g. "ATK_PARAM_ST"
+    pub param_type: String,
+    pub fields: Vec<ParamdefField>,
+    // Bytes per row, bitfield storage included
+    pub row_size: usize,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct ParamdefField {
+    pub name: String,
+    // The paramdef type, e.g. "u8", "angle32", "fixstr" or "dummy8"
+    pub def_type: String,
+    pub offset: usize,
+    // Some for `name[n]` arrays and strings
+    pub array_len: Option<usize>,
+    // Some((bit, width)) for `name:width` bitfields, bit counted from the storage unit's lowest bit
+    pub bits: Option<(u32, u32)>,
+}
+
+impl Paramdef {
+    pub fn from_xml(xml: &str) -> Result<Paramdef, DantelionFormatsError> {
+        let doc = roxmltree::Document::parse(xml)?;
+        let root = doc.root_element();
+        let param_type = root.children().find(|n| n.has_tag_name("ParamType")).and_then(|n| n.text())
+            .ok_or_else(|| invalid_paramdef("ParamType"))?;
+
+        let mut fields = vec![];
+        let mut offset = 0;
+        // (storage type, offset, bits used) of the open bitfield unit
+        let mut unit: Option<(&str, usize, u32)> = None;
+        for fields_node in root.children().filter(|n| n.has_tag_name("Fields")) {
+            for field in fields_node.children().filter(|n| n.has_tag_name("Field") && n.attribute("RemovedRegVersion").is_none()) {
+                let def = field.attribute("Def").ok_or_else(|| invalid_paramdef("field without a Def"))?;
+                let FieldDef { def_type, name, array_len, bit_width } = parse_def(def)?;
+                let size = type_size(def_type).ok_or_else(|| invalid_paramdef(def))?;
+
+                let (field_offset, bits) = match bit_width {
+                    Some(width) => {
+                        let storage = if def_type == "dummy8" { "u8" } else { def_type };
+                        match unit {
+                            Some((unit_type, unit_offset, used)) if unit_type == storage && used + width <= size as u32 * 8 => {
+                                unit = Some((unit_type, unit_offset, used + width));
+                                (unit_offset, Some((used, width)))
+                            }
+                            _ => {
+                                unit = Some((storage, offset, width));
+                                offset += size;
+                                (offset - size, Some((0, width)))
+                            }
+                        }
+                    }
+                    None => {
+                        unit = None;
+                        offset += size * array_len.unwrap_or(1);
+                        (offset - size * array_len.unwrap_or(1), None)
+                    }
+                };
+
+                fields.push(ParamdefField {
+                    name: name.to_string(),
+                    def_type: def_type.to_string(),
+                    offset: field_offset,
+                    array_len,
+                    bits,
+                });
+            }
+        }
+
+        Ok(Paramdef {
+            param_type: param_type.trim().to_string(),
+            fields,
+            row_size: offset,
+        })
+    }
+
+    /// The struct name for the param type, e.g. "AtkParamSt" for "ATK_PARAM_ST".
+    pub fn struct_name(&self) -> String {
+        self.param_type.split('_')
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                let lower = part.to_lowercase();
+                let mut chars = lower.chars();
+                chars.next().map(|first| first.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+            })
+            .collect::<String>()
+    }
+
+    /// Rust source for a struct with a field per paramdef field, snake cased, and `read`/`write` functions over a row's
+    /// bytes. Padding (`dummy8`) isn't a field and `write` leaves it as it was. Bitfields are their storage type, e.g.
+    /// `u8` for "u8 flag:1", strings are their raw `u8` or `u16` units. The generated code uses the `byteorder` crate.
+    pub fn to_rust(&self) -> String {
+        let struct_name = self.struct_name();
+        let mut names = HashSet::new();
+        let fields: Vec<(String, &ParamdefField)> = self.fields.iter()
+            .filter(|field| field.def_type != "dummy8")
+            .map(|field| (unique_name(&mut names, &rust_name(&field.name)), field))
+            .collect();
+
+        let mut source = String::new();
+        let _ = writeln!(source, "/// A {} row, generated from its paramdef.", self.param_type);
+        let _ = writeln!(source, "#[derive(Clone, Debug, PartialEq)]");
+        let _ = writeln!(source, "pub struct {} {{", struct_name);
+        for (name, field) in &fields {
+            let _ = writeln!(source, "    pub {}: {},", name, field_type(field));
+        }
+        let _ = writeln!(source, "}}\n");
+
+        let _ = writeln!(source, "impl {} {{", struct_name);
+        let _ = writeln!(source, "    pub const PARAM_TYPE: &'static str = {:?};", self.param_type);
+        let _ = writeln!(source, "    pub const SIZE: usize = 0x{:X};\n", self.row_size);
+        let _ = writeln!(source, "    pub fn read<T: byteorder::ByteOrder>(row: &[u8]) -> {} {{", struct_name);
+        let _ = writeln!(source, "        {} {{", struct_name);
+        for (name, field) in &fields {
+            let _ = writeln!(source, "            {}: {},", name, read_expr(field));
+        }
+        let _ = writeln!(source, "        }}\n    }}\n");
+        let _ = writeln!(source, "    pub fn write<T: byteorder::ByteOrder>(&self, row: &mut [u8]) {{");
+        for (name, field) in &fields {
+            let _ = writeln!(source, "        {}", write_stmt(name, field));
+        }
+        let _ = writeln!(source, "    }}\n}}");
+
+        source
+    }
+}
+
+/// Generates `to_rust` structs for every paramdef XML in `defs_dir`, sorted by file name, for `include!`ing from a
+/// build script's output, e.g. with a Paramdex `Defs` folder for the game being modded.
+pub fn generate_dir(defs_dir: &Path) -> Result<String, DantelionFormatsError> {
+    let mut paths: Vec<_> = fs::read_dir(defs_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    paths.retain(|path| path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("xml")));
+    paths.sort();
+
+    let mut source = String::new();
+    let mut generated = HashSet::new();
+    for path in paths {
+        let paramdef = Paramdef::from_xml(&fs::read_to_string(&path)?)?;
+        // Several params can share a type, e.g. every AtkParam_* uses ATK_PARAM_ST.
+        if generated.insert(paramdef.param_type.clone()) {
+            source.push_str(&paramdef.to_rust());
+            source.push('\n');
+        }
+    }
+
+    Ok(source)
+}
+
+struct FieldDef<'a> {
+    def_type: &'a str,
+    name: &'a str,
+    array_len: Option<usize>,
+    bit_width: Option<u32>,
+}
+
+// "u8 atkType:4 = 0" is a 4 bit "atkType", "fixstr name[32]" a 32 element "name".
+fn parse_def(def: &str) -> Result<FieldDef<'_>, DantelionFormatsError> {
+    let def = def.split('=').next().unwrap_or(def).trim();
+    let (def_type, rest) = def.split_once(char::is_whitespace).ok_or_else(|| invalid_paramdef(def))?;
+    let rest = rest.trim();
+
+    if let Some((name, width)) = rest.split_once(':') {
+        let width = width.trim().parse().map_err(|_| invalid_paramdef(def))?;
+        return Ok(FieldDef { def_type, name: name.trim(), array_len: None, bit_width: Some(width) });
+    }
+
+    if let Some((name, len)) = rest.split_once('[') {
+        let len = len.trim_end_matches(']').trim().parse().map_err(|_| invalid_paramdef(def))?;
+        return Ok(FieldDef { def_type, name: name.trim(), array_len: Some(len), bit_width: None });
+    }
+
+    Ok(FieldDef { def_type, name: rest, array_len: None, bit_width: None })
+}
+
+// Size of one element.
+fn type_size(def_type: &str) -> Option<usize> {
+    match def_type {
+        "s8" | "u8" | "dummy8" | "fixstr" => Some(1),
+        "s16" | "u16" | "fixstrW" => Some(2),
+        "s32" | "u32" | "b32" | "f32" | "angle32" => Some(4),
+        "f64" => Some(8),
+        _ => None,
+    }
+}
+
+fn scalar_type(def_type: &str) -> &'static str {
+    match def_type {
+        "s8" => "i8",
+        "s16" => "i16",
+        "s32" => "i32",
+        "u16" | "fixstrW" => "u16",
+        "u32" => "u32",
+        "b32" => "bool",
+        "f32" | "angle32" => "f32",
+        "f64" => "f64",
+        _ => "u8",
+    }
+}
+
+// Bitfields are stored in an unsigned unit the size of their type.
+fn storage_type(def_type: &str) -> &'static str {
+    match type_size(def_type) {
+        Some(2) => "u16",
+        Some(4) => "u32",
+        _ => "u8",
+    }
+}
+
+fn field_type(field: &ParamdefField) -> String {
+    match (field.bits, field.array_len) {
+        (Some(_), _) => storage_type(&field.def_type).to_string(),
+        (None, Some(len)) => format!("[{}; {}]", scalar_type(&field.def_type), len),
+        (None, None) => scalar_type(&field.def_type).to_string(),
+    }
+}
+
+fn read_scalar(def_type: &str, at: &str) -> String {
+    match scalar_type(def_type) {
+        "u8" => format!("row[{}]", at),
+        "i8" => format!("row[{}] as i8", at),
+        "bool" => format!("T::read_u32(&row[{}..]) != 0", at),
+        other => format!("T::read_{}(&row[{}..])", other, at),
+    }
+}
+
+fn write_scalar(def_type: &str, at: &str, value: &str) -> String {
+    match scalar_type(def_type) {
+        "u8" => format!("row[{}] = {};", at, value),
+        "i8" => format!("row[{}] = {} as u8;", at, value),
+        "bool" => format!("T::write_u32(&mut row[{}..], {} as u32);", at, value),
+        other => format!("T::write_{}(&mut row[{}..], {});", other, at, value),
+    }
+}
+
+fn read_expr(field: &ParamdefField) -> String {
+    let offset = format!("0x{:X}", field.offset);
+    match (field.bits, field.array_len) {
+        // A bitfield filling its whole unit is just the unit.
+        (Some((0, width)), _) if is_whole_unit(field, width) => read_scalar(storage_type(&field.def_type), &offset),
+        (Some((bit, width)), _) => {
+            let storage = storage_type(&field.def_type);
+            let unit = if storage == "u8" { format!("row[{}]", offset) } else { format!("T::read_{}(&row[{}..])", storage, offset) };
+            if bit == 0 { format!("{} & 0x{:X}", unit, mask(width)) } else { format!("({} >> {}) & 0x{:X}", unit, bit, mask(width)) }
+        }
+        (None, Some(_)) => format!("std::array::from_fn(|i| {})", read_scalar(&field.def_type, &element_at(field, &offset))),
+        (None, None) => read_scalar(&field.def_type, &offset),
+    }
+}
+
+fn write_stmt(name: &str, field: &ParamdefField) -> String {
+    let offset = format!("0x{:X}", field.offset);
+    match (field.bits, field.array_len) {
+        (Some((0, width)), _) if is_whole_unit(field, width) => write_scalar(storage_type(&field.def_type), &offset, &format!("self.{}", name)),
+        (Some((bit, width)), _) => {
+            let storage = storage_type(&field.def_type);
+            let shift = if bit == 0 { String::new() } else { format!(" << {}", bit) };
+            let keep = format!("!(0x{:X}{})", mask(width), shift);
+            let bits = format!("(self.{} & 0x{:X}){}", name, mask(width), shift);
+            if storage == "u8" {
+                format!("row[{0}] = (row[{0}] & {1}) | {2};", offset, keep, bits)
+            } else {
+                // Read first, the row can't be borrowed for the read while it's borrowed for the write.
+                format!("let unit = (T::read_{0}(&row[{1}..]) & {2}) | {3}; T::write_{0}(&mut row[{1}..], unit);", storage, offset, keep, bits)
+            }
+        }
+        (None, Some(_)) => {
+            format!("for (i, value) in self.{}.iter().enumerate() {{ {} }}", name, write_scalar(&field.def_type, &element_at(field, &offset), "*value"))
+        }
+        (None, None) => write_scalar(&field.def_type, &offset, &format!("self.{}", name)),
+    }
+}
+
+fn is_whole_unit(field: &ParamdefField, width: u32) -> bool {
+    type_size(&field.def_type).is_some_and(|size| width as usize == size * 8)
+}
+
+// The offset of element `i` of an array.
+fn element_at(field: &ParamdefField, offset: &str) -> String {
+    match type_size(&field.def_type).unwrap_or(1) {
+        1 => format!("{} + i", offset),
+        size => format!("{} + i * {}", offset, size),
+    }
+}
+
+fn mask(width: u32) -> u64 {
+    (1u64 << width) - 1
+}
+
+// "hit0_Radius" is "hit0_radius", "atkPhys" is "atk_phys" and "ATK_TYPE" is "atk_type".
+fn rust_name(name: &str) -> String {
+    let chars: Vec<char> = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    let mut snake = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|next| next.is_ascii_lowercase());
+            if previous.is_ascii_lowercase() || previous.is_ascii_digit() || (previous.is_ascii_uppercase() && next_lower) {
+                snake.push('_');
+            }
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+
+    let mut snake = snake.split('_').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("_");
+    if snake.is_empty() || snake.starts_with(|c: char| c.is_ascii_digit()) {
+        snake.insert(0, '_');
+    }
+    match snake.as_str() {
+        "self" | "crate" | "super" => format!("{}_", snake),
+        "as" | "break" | "const" | "continue" | "else" | "enum" | "extern" | "false" | "fn" | "for" | "if" | "impl" | "in"
+        | "let" | "loop" | "match" | "mod" | "move" | "mut" | "pub" | "ref" | "return" | "static" | "struct" | "trait"
+        | "true" | "type" | "unsafe" | "use" | "where" | "while" | "async" | "await" | "dyn" | "abstract" | "become"
+        | "box" | "do" | "final" | "macro" | "override" | "priv" | "typeof" | "unsized" | "virtual" | "yield" | "try" => format!("r#{}", snake),
+        _ => snake,
+    }
+}
+
+fn unique_name(names: &mut HashSet<String>, name: &str) -> String {
+    let mut unique = name.to_string();
+    let mut n = 2;
+    while !names.insert(unique.clone()) {
+        unique = format!("{}_{}", name, n);
+        n += 1;
+    }
+
+    unique
+}
+
+fn invalid_paramdef(what: &str) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("Invalid paramdef: {}", what)))
+}
@@ -0,0 +1,205 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io::{Error, ErrorKind};
+use crate::bink::BinkHeader;
+use crate::bnd3::BND3;
+use crate::bnd4::BND4;
+use crate::clm2::CLM2;
+use crate::dcx::DCX;
+use crate::error::DantelionFormatsError;
+use crate::flver::FLVER;
+use crate::msb::MSB;
+use crate::source::{DataSource, FileSource};
+use crate::mqb::MQB;
+use crate::sound::{BNK, FSB5};
+use crate::tpf::TPF;
+use crate::util::DataLen;
+
+pub enum ParsedFile {
+    BND3(BND3),
+    BND4(BND4),
+    TPF(TPF),
+    FLVER(FLVER),
+    MSB(MSB),
+    MQB(MQB),
+    CLM2(CLM2),
+    FSB5(FSB5),
+    BNK(BNK),
+    // Movies are only read for their metadata.
+    Bink(BinkHeader),
+    // Decompressed bytes of a file we don't have a parser for.
+    Unknown(Vec<u8>),
+}
+
+impl Debug for ParsedFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ParsedFile::BND3(bnd3) => f.debug_tuple("BND3").field(bnd3).finish(),
+            ParsedFile::BND4(bnd4) => f.debug_tuple("BND4").field(bnd4).finish(),
+            ParsedFile::TPF(tpf) => f.debug_tuple("TPF").field(tpf).finish(),
+            ParsedFile::FLVER(flver) => f.debug_tuple("FLVER").field(flver).finish(),
+            ParsedFile::MSB(msb) => f.debug_tuple("MSB").field(msb).finish(),
+            ParsedFile::MQB(mqb) => f.debug_tuple("MQB").field(mqb).finish(),
+            ParsedFile::CLM2(clm2) => f.debug_tuple("CLM2").field(clm2).finish(),
+            ParsedFile::FSB5(fsb5) => f.debug_tuple("FSB5").field(fsb5).finish(),
+            ParsedFile::BNK(bnk) => f.debug_tuple("BNK").field(bnk).finish(),
+            ParsedFile::Bink(bink) => f.debug_tuple("Bink").field(bink).finish(),
+            ParsedFile::Unknown(bytes) => f.debug_tuple("Unknown").field(&DataLen(bytes.len())).finish(),
+        }
+    }
+}
+
+impl ParsedFile {
+    pub fn into_bnd3(self) -> Result<BND3, DantelionFormatsError> {
+        match self { ParsedFile::BND3(bnd3) => Ok(bnd3), other => Err(other.not_a("BND3")) }
+    }
+
+    pub fn into_bnd4(self) -> Result<BND4, DantelionFormatsError> {
+        match self { ParsedFile::BND4(bnd4) => Ok(bnd4), other => Err(other.not_a("BND4")) }
+    }
+
+    pub fn into_tpf(self) -> Result<TPF, DantelionFormatsError> {
+        match self { ParsedFile::TPF(tpf) => Ok(tpf), other => Err(other.not_a("TPF")) }
+    }
+
+    pub fn into_flver(self) -> Result<FLVER, DantelionFormatsError> {
+        match self { ParsedFile::FLVER(flver) => Ok(flver), other => Err(other.not_a("FLVER")) }
+    }
+
+    pub fn into_msb(self) -> Result<MSB, DantelionFormatsError> {
+        match self { ParsedFile::MSB(msb) => Ok(msb), other => Err(other.not_a("MSB")) }
+    }
+
+    pub fn into_mqb(self) -> Result<MQB, DantelionFormatsError> {
+        match self { ParsedFile::MQB(mqb) => Ok(mqb), other => Err(other.not_a("MQB")) }
+    }
+
+    pub fn into_clm2(self) -> Result<CLM2, DantelionFormatsError> {
+        match self { ParsedFile::CLM2(clm2) => Ok(clm2), other => Err(other.not_a("CLM2")) }
+    }
+
+    pub fn into_fsb5(self) -> Result<FSB5, DantelionFormatsError> {
+        match self { ParsedFile::FSB5(fsb5) => Ok(fsb5), other => Err(other.not_a("FSB5")) }
+    }
+
+    pub fn into_bnk(self) -> Result<BNK, DantelionFormatsError> {
+        match self { ParsedFile::BNK(bnk) => Ok(bnk), other => Err(other.not_a("BNK")) }
+    }
+
+    fn not_a(&self, expected: &str) -> DantelionFormatsError {
+        let found = match self {
+            ParsedFile::BND3(_) => "BND3",
+            ParsedFile::BND4(_) => "BND4",
+            ParsedFile::TPF(_) => "TPF",
+            ParsedFile::FLVER(_) => "FLVER",
+            ParsedFile::MSB(_) => "MSB",
+            ParsedFile::MQB(_) => "MQB",
+            ParsedFile::CLM2(_) => "CLM2",
+            ParsedFile::FSB5(_) => "FSB5",
+            ParsedFile::BNK(_) => "BNK",
+            ParsedFile::Bink(_) => "Bink",
+            ParsedFile::Unknown(_) => "an unknown format",
+        };
+
+        DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("Expected a {}, found {}", expected, found)))
+    }
+}
+
+/// Parses a binder entry's data with `open_bytes`, for the `as_*` helpers on BND4 and BND3 files.
+pub(crate) fn open_entry(name: Option<&str>, data: Option<&[u8]>) -> Result<ParsedFile, DantelionFormatsError> {
+    let data = data.ok_or_else(|| DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, format!("{} has no data", name.unwrap_or("The file")))))?;
+
+    open_bytes(data)
+}
+
+/// The summary of whatever was parsed.
+impl Display for ParsedFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ParsedFile::BND3(bnd3) => Display::fmt(bnd3, f),
+            ParsedFile::BND4(bnd4) => Display::fmt(bnd4, f),
+            ParsedFile::TPF(tpf) => Display::fmt(tpf, f),
+            ParsedFile::FLVER(flver) => Display::fmt(flver, f),
+            ParsedFile::MSB(msb) => Display::fmt(msb, f),
+            ParsedFile::MQB(mqb) => Display::fmt(mqb, f),
+            ParsedFile::CLM2(clm2) => Display::fmt(clm2, f),
+            ParsedFile::FSB5(fsb5) => Display::fmt(fsb5, f),
+            ParsedFile::BNK(bnk) => Display::fmt(bnk, f),
+            ParsedFile::Bink(bink) => Display::fmt(bink, f),
+            ParsedFile::Unknown(bytes) => write!(f, "Unknown — {} bytes", bytes.len()),
+        }
+    }
+}
+
+/// Reads the file at `path`, strips every layer of DCX compression and parses it based on its magic.
+pub fn open(path: &str) -> Result<ParsedFile, DantelionFormatsError> {
+    open_source(&FileSource::open(path)?)
+}
+
+pub fn open_source(source: &(impl DataSource + ?Sized)) -> Result<ParsedFile, DantelionFormatsError> {
+    let file = source.read_all()?;
+
+    open_bytes(&file)
+}
+
+pub fn open_bytes(file: &[u8]) -> Result<ParsedFile, DantelionFormatsError> {
+    let mut decompressed = vec![];
+    let dcx = DCX::decompress_layers_into(file, &mut decompressed)?;
+    let bytes = if dcx.is_some() { decompressed } else { file.to_vec() };
+
+    // Binders keep their compression, so writing them back gives a DCX again.
+    if bytes.starts_with(b"BND3") {
+        let mut bnd3 = BND3::from_bytes(&bytes)?;
+        bnd3.dcx = dcx;
+        return Ok(ParsedFile::BND3(bnd3));
+    }
+
+    if bytes.starts_with(b"BND4") {
+        let mut bnd4 = BND4::from_bytes(&bytes)?;
+        bnd4.dcx = dcx;
+        return Ok(ParsedFile::BND4(bnd4));
+    }
+
+    if bytes.starts_with(b"TPF\0") {
+        return Ok(ParsedFile::TPF(TPF::from_bytes(&bytes)?));
+    }
+
+    if FLVER::is(&bytes) {
+        return Ok(ParsedFile::FLVER(FLVER::from_bytes(&bytes)?));
+    }
+
+    if MSB::is(&bytes) {
+        return Ok(ParsedFile::MSB(MSB::from_bytes(&bytes)?));
+    }
+
+    if MQB::is(&bytes) {
+        return Ok(ParsedFile::MQB(MQB::from_bytes(&bytes)?));
+    }
+
+    if CLM2::is(&bytes) {
+        return Ok(ParsedFile::CLM2(CLM2::from_bytes(&bytes)?));
+    }
+
+    if FSB5::is(&bytes) {
+        return Ok(ParsedFile::FSB5(FSB5::from_bytes(&bytes)?));
+    }
+
+    if BNK::is(&bytes) {
+        return Ok(ParsedFile::BNK(BNK::from_bytes(&bytes)?));
+    }
+
+    if BinkHeader::is(&bytes) {
+        return Ok(ParsedFile::Bink(BinkHeader::from_bytes(&bytes)?));
+    }
+
+    Ok(ParsedFile::Unknown(bytes))
+}
+
+/// Decompresses `file` until it's no longer DCX. Some files in the wild are compressed more than once.
+pub fn strip_dcx(file: &[u8]) -> Result<Vec<u8>, DantelionFormatsError> {
+    let mut bytes = file.to_vec();
+    while DCX::is(&bytes) {
+        bytes = DCX::decompress_bytes(&bytes)?;
+    }
+
+    Ok(bytes)
+}
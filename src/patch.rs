@@ -0,0 +1,252 @@
+use std::fmt::{self, Debug, Formatter};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use crate::bhd5::{BHD5, BHD5ArchiveBuilder, BHD5Format, FileHeader};
+use crate::crypto_util;
+#[cfg(feature = "steam-discovery")]
+use crate::discovery;
+use crate::error::DantelionFormatsError;
+use crate::source::DataSource;
+use crate::util;
+
+/// Stages file replacements for a BHD5/BDT pair and applies them all at once. Nothing on disk changes until
+/// `commit`, which writes new copies next to the originals and only swaps them in once both are fully written,
+/// so a crash can't leave a half written BDT behind. The originals are kept as `util::backup` backups unless
+/// `keep_backups(false)` is set. With the steam-discovery feature, committing is refused while a game using the
+/// archive's format is running.
+pub struct BHD5EditSession {
+    bhd_path: PathBuf,
+    bdt_path: PathBuf,
+    public_key: String,
+    private_key: String,
+    staged: Vec<(String, Vec<u8>)>,
+    format: BHD5Format,
+    keep_backups: bool,
+    allow_while_running: bool,
+}
+
+impl BHD5EditSession {
+    const TEMP_SUFFIX: &'static str = ".dantelion.tmp";
+    const OLD_SUFFIX: &'static str = ".dantelion.old";
+
+    /// Opens an archive for editing. The private key is needed to re-encrypt the BHD5, so this only works for
+    /// archives made with `BHD5ArchiveBuilder` or otherwise re-keyed.
+    pub fn open(bhd_path: &str, bdt_path: &str, public_key: &str, private_key: &str) -> Result<BHD5EditSession, DantelionFormatsError> {
+        // Fail early if the keys don't match the archive.
+        let bhd5 = BHD5::from_encrypted_bytes(&fs::read(bhd_path)?, public_key.as_bytes())?;
+
+        Ok(BHD5EditSession {
+            bhd_path: PathBuf::from(bhd_path),
+            bdt_path: PathBuf::from(bdt_path),
+            public_key: public_key.to_string(),
+            private_key: private_key.to_string(),
+            staged: vec![],
+            format: bhd5.format,
+            keep_backups: true,
+            allow_while_running: false,
+        })
+    }
+
+    /// Stages `data` for `path`, e.g. "/parts/am_m_1600.partsbnd.dcx". Files not already in the archive are added.
+    pub fn replace_file(&mut self, path: &str, data: Vec<u8>) -> &mut BHD5EditSession {
+        self.staged.retain(|(staged, _)| staged != path);
+        self.staged.push((path.to_string(), data));
+        self
+    }
+
+    pub fn keep_backups(&mut self, keep_backups: bool) -> &mut BHD5EditSession {
+        self.keep_backups = keep_backups;
+        self
+    }
+
+    /// Skips the running game check, for archives that aren't the installed game's.
+    pub fn allow_while_running(&mut self, allow_while_running: bool) -> &mut BHD5EditSession {
+        self.allow_while_running = allow_while_running;
+        self
+    }
+
+    pub fn staged_count(&self) -> usize {
+        self.staged.len()
+    }
+
+    /// Writes every staged file. Either all of them end up in the archive, or the archive is left as it was.
+    pub fn commit(self) -> Result<(), DantelionFormatsError> {
+        #[cfg(feature = "steam-discovery")]
+        if !self.allow_while_running {
+            discovery::ensure_not_running(self.format.games())?;
+        }
+
+        let bhd_temp = with_suffix(&self.bhd_path, BHD5EditSession::TEMP_SUFFIX);
+        let bdt_temp = with_suffix(&self.bdt_path, BHD5EditSession::TEMP_SUFFIX);
+
+        let result = self.write_temp_files(&bhd_temp, &bdt_temp)
+            .and_then(|_| self.swap_in(&bhd_temp, &bdt_temp));
+        if result.is_err() {
+            let _ = fs::remove_file(&bhd_temp);
+            let _ = fs::remove_file(&bdt_temp);
+        }
+
+        result
+    }
+
+    // New data is appended to a copy of the BDT, so the entries that aren't replaced keep their offsets.
+    fn write_temp_files(&self, bhd_temp: &Path, bdt_temp: &Path) -> Result<(), DantelionFormatsError> {
+        let bhd5 = BHD5::from_encrypted_bytes(&fs::read(&self.bhd_path)?, self.public_key.as_bytes())?;
+        fs::copy(&self.bdt_path, bdt_temp)?;
+
+        let mut bdt = OpenOptions::new().write(true).open(bdt_temp)?;
+        let mut end = bdt.seek(SeekFrom::End(0))?;
+        let mut new_headers = Vec::with_capacity(self.staged.len());
+        for (path, data) in &self.staged {
+            let padding = (BHD5ArchiveBuilder::BDT_ALIGNMENT as u64 - end % BHD5ArchiveBuilder::BDT_ALIGNMENT as u64) % BHD5ArchiveBuilder::BDT_ALIGNMENT as u64;
+            bdt.write_all(&vec![0; padding as usize])?;
+            end += padding;
+
+            let (file_header, encrypted) = BHD5::encrypt_file(path, data.clone(), bhd5.format, end)?;
+            bdt.write_all(&encrypted)?;
+            end += encrypted.len() as u64;
+            new_headers.push(file_header);
+        }
+        bdt.sync_all()?;
+
+        let mut file_headers: Vec<_> = bhd5.buckets.into_iter()
+            .flat_map(|bucket| bucket.file_headers)
+            .filter(|file_header| !new_headers.iter().any(|new| new.file_path_hash == file_header.file_path_hash))
+            .collect();
+        file_headers.extend(new_headers);
+
+        let salt = String::from_utf8(bhd5.bhd5_header.salt)?;
+        let bhd = BHD5::new(bhd5.format, salt, file_headers).to_bytes()?;
+        let mut bhd_file = fs::File::create(bhd_temp)?;
+        bhd_file.write_all(&crypto_util::encrypt_bhd5_file(&bhd, self.private_key.as_bytes())?)?;
+        bhd_file.sync_all()?;
+
+        Ok(())
+    }
+
+    // Moves the originals aside before moving the new files in, so every step can be undone.
+    fn swap_in(&self, bhd_temp: &Path, bdt_temp: &Path) -> Result<(), DantelionFormatsError> {
+        let steps = [
+            (self.bdt_path.clone(), with_suffix(&self.bdt_path, BHD5EditSession::OLD_SUFFIX)),
+            (bdt_temp.to_path_buf(), self.bdt_path.clone()),
+            (self.bhd_path.clone(), with_suffix(&self.bhd_path, BHD5EditSession::OLD_SUFFIX)),
+            (bhd_temp.to_path_buf(), self.bhd_path.clone()),
+        ];
+
+        for (done, (from, to)) in steps.iter().enumerate() {
+            if let Err(e) = fs::rename(from, to) {
+                for (from, to) in steps[..done].iter().rev() {
+                    let _ = fs::rename(to, from);
+                }
+                return Err(e.into());
+            }
+        }
+
+        // The moved aside originals are the backups, which saves copying the whole BDT.
+        for (original, old) in [(&self.bdt_path, &steps[0].1), (&self.bhd_path, &steps[2].1)] {
+            let original = original.to_string_lossy();
+            if self.keep_backups && !util::has_backup(&original) {
+                fs::rename(old, util::backup_path(&original))?;
+            } else {
+                let _ = fs::remove_file(old);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Staged data is summarized and the private key left out.
+impl Debug for BHD5EditSession {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let staged: Vec<_> = self.staged.iter().map(|(path, data)| (path, util::DataLen(data.len()))).collect();
+        f.debug_struct("BHD5EditSession")
+            .field("bhd_path", &self.bhd_path)
+            .field("bdt_path", &self.bdt_path)
+            .field("public_key", &self.public_key)
+            .field("staged", &staged)
+            .field("format", &self.format)
+            .field("keep_backups", &self.keep_backups)
+            .field("allow_while_running", &self.allow_while_running)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Streams the BDT in `source` to `dest` with the files in `replacements` swapped for new data, and returns `bhd5`
+/// with every entry's new offset. Entries are written in the order they're in the BDT, copied through in chunks or
+/// replaced by their freshly encrypted data, then files that weren't in the archive are added at the end. Unlike
+/// `BHD5EditSession`, which appends, old copies of replaced files don't stay behind, and only one chunk or
+/// replacement is in memory at a time. Bytes no entry points at are dropped, apart from the BDT's header.
+pub fn splice_bdt(source: &(impl DataSource + ?Sized), dest: &mut impl Write, bhd5: BHD5, replacements: &[(String, Vec<u8>)]) -> Result<BHD5, DantelionFormatsError> {
+    const CHUNK_SIZE: u64 = 0x100000;
+
+    let format = bhd5.format;
+    let salt = String::from_utf8(bhd5.bhd5_header.salt)?;
+    let mut file_headers: Vec<FileHeader> = bhd5.buckets.into_iter().flat_map(|bucket| bucket.file_headers).collect();
+    file_headers.sort_by_key(|file_header| file_header.file_offset);
+    // A path given twice gets its last data, same as `BHD5EditSession::replace_file`.
+    let mut staged: Vec<(u64, &str, &[u8])> = vec![];
+    for (path, data) in replacements {
+        let hash = BHD5::hash_path(path, format);
+        staged.retain(|(staged_hash, _, _)| *staged_hash != hash);
+        staged.push((hash, path, data));
+    }
+
+    let header = source.read_at(0, BHD5ArchiveBuilder::BDT_HEADER.len())?;
+    dest.write_all(&header)?;
+    let mut written = header.len() as u64;
+
+    let mut spliced = Vec::with_capacity(file_headers.len() + staged.len());
+    for mut file_header in file_headers {
+        pad_bdt(dest, &mut written)?;
+        match staged.iter().position(|(hash, _, _)| *hash == file_header.file_path_hash) {
+            Some(i) => {
+                let (_, path, data) = staged.remove(i);
+                let (new_header, encrypted) = BHD5::encrypt_file(path, data.to_vec(), format, written)?;
+                dest.write_all(&encrypted)?;
+                written += encrypted.len() as u64;
+                spliced.push(new_header);
+            }
+            None => {
+                let size = file_header.padded_file_size as u64;
+                let mut copied = 0;
+                while copied < size {
+                    let len = (size - copied).min(CHUNK_SIZE);
+                    dest.write_all(&source.read_at(file_header.file_offset + copied, len as usize)?)?;
+                    copied += len;
+                }
+                file_header.file_offset = written;
+                written += size;
+                spliced.push(file_header);
+            }
+        }
+    }
+
+    for (_, path, data) in staged {
+        pad_bdt(dest, &mut written)?;
+        let (file_header, encrypted) = BHD5::encrypt_file(path, data.to_vec(), format, written)?;
+        dest.write_all(&encrypted)?;
+        written += encrypted.len() as u64;
+        spliced.push(file_header);
+    }
+    dest.flush()?;
+
+    Ok(BHD5::new(format, salt, spliced))
+}
+
+fn pad_bdt(dest: &mut impl Write, written: &mut u64) -> Result<(), DantelionFormatsError> {
+    let alignment = BHD5ArchiveBuilder::BDT_ALIGNMENT as u64;
+    let padding = (alignment - *written % alignment) % alignment;
+    dest.write_all(&vec![0; padding as usize])?;
+    *written += padding;
+
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
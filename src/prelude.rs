@@ -0,0 +1,35 @@
+// The types most code needs, for `use dantelion_formats::prelude::*`. Everything here is also exported from its own
+// module. BND4's `File` is left out so it doesn't clash with `std::fs::File`.
+
+pub use crate::{open, open_bytes, open_source, strip_dcx, ParsedFile};
+pub use crate::error::DantelionFormatsError;
+pub use crate::bhd5::{BHD5, BHD5Format, FileHeader, GameType};
+#[cfg(feature = "crypto")]
+pub use crate::bhd5::{BHD5Archive, BHD5ArchiveBuilder};
+#[cfg(feature = "crypto")]
+pub use crate::patch::BHD5EditSession;
+#[cfg(feature = "crypto")]
+pub use crate::keys::KeyStore;
+pub use crate::dcx::DCX;
+pub use crate::bnd3::BND3;
+pub use crate::bnd4::{BND4, BND4Builder, BND4FileOrder, BND4Version, BND4WriteOptions};
+pub use crate::binder::{BinderType, BinderVersion};
+pub use crate::kind::FileKind;
+pub use crate::manifest::BND4Manifest;
+pub use crate::tpf::TPF;
+pub use crate::flver::FLVER;
+pub use crate::msb::MSB;
+pub use crate::mqb::MQB;
+pub use crate::navgraph::{MCG, MCP};
+pub use crate::btpb::BTPB;
+pub use crate::grass::{Decal, GRASS};
+pub use crate::clm2::CLM2;
+pub use crate::sound::{BNK, FSB5};
+pub use crate::bink::BinkHeader;
+pub use crate::lua::LuaHeader;
+pub use crate::config::Config;
+pub use crate::source::{DataSource, FileSource};
+#[cfg(feature = "steam-discovery")]
+pub use crate::discovery::GameLocator;
+#[cfg(feature = "oodle")]
+pub use crate::oodle::OodleContext;
@@ -0,0 +1,241 @@
+use std::fmt::Debug;
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestCaseError, TestRunner};
+use crate::bhd5::{AESKey, BHD5, BHD5Format, FileHeader, Range};
+use crate::bnd3::BND3;
+use crate::bnd4::{BND4, BND4Builder, BND4WriteOptions};
+use crate::dcx::DCX;
+use crate::error::DantelionFormatsError;
+use crate::mqb::{MQB, MQBHeader};
+
+/// A format with a writer. Implementing this and calling `check_round_trip` from a test is all a new writer needs
+/// to be property tested: random values are built from a spec, written, read back and compared, and writing what
+/// was read has to give the same bytes again.
+pub(crate) trait RoundTrip: Sized {
+    // The builder input. Generated by proptest, so it has to be Debug, which the formats themselves aren't.
+    type Spec: Debug + Clone;
+    // What has to survive a round trip. Only what the writer promises to keep, so not offsets and the like.
+    type Summary: Debug + PartialEq;
+
+    fn spec() -> BoxedStrategy<Self::Spec>;
+    fn build(spec: &Self::Spec) -> Result<Self, DantelionFormatsError>;
+    fn write(&self) -> Result<Vec<u8>, DantelionFormatsError>;
+    fn read(bytes: &[u8]) -> Result<Self, DantelionFormatsError>;
+    fn summary(&self) -> Self::Summary;
+}
+
+pub(crate) fn check_round_trip<T: RoundTrip>() {
+    let mut runner = TestRunner::new(Config { cases: 64, ..Config::default() });
+    let result = runner.run(&T::spec(), |spec| {
+        let built = T::build(&spec).map_err(fail)?;
+        let bytes = built.write().map_err(fail)?;
+        let read = T::read(&bytes).map_err(fail)?;
+        prop_assert_eq!(read.summary(), built.summary());
+        prop_assert_eq!(read.write().map_err(fail)?, bytes);
+        Ok(())
+    });
+
+    if let Err(e) = result {
+        panic!("{}", e);
+    }
+}
+
+fn fail(e: DantelionFormatsError) -> TestCaseError {
+    TestCaseError::fail(e.to_string())
+}
+
+fn file_name() -> impl Strategy<Value = String> {
+    "N:\\\\GR\\\\data\\\\[a-z0-9_]{1,12}\\.[a-z]{3}"
+}
+
+// Unique ids, so ids can be used to tell files apart.
+fn binder_files() -> impl Strategy<Value = Vec<(i32, String, Vec<u8>)>> {
+    prop::collection::btree_map(0..100_000i32, (file_name(), prop::collection::vec(any::<u8>(), 0..0x80)), 0..8)
+        .prop_map(|files| files.into_iter().map(|(id, (name, data))| (id, name, data)).collect())
+}
+
+type BinderSummary = Vec<(Option<i32>, Option<String>, Option<Vec<u8>>)>;
+
+#[derive(Debug, Clone)]
+pub(crate) struct BND4Spec {
+    big_endian: bool,
+    unicode: bool,
+    extended: bool,
+    files: Vec<(i32, String, Vec<u8>)>,
+}
+
+impl RoundTrip for BND4 {
+    type Spec = BND4Spec;
+    type Summary = BinderSummary;
+
+    fn spec() -> BoxedStrategy<BND4Spec> {
+        (any::<bool>(), any::<bool>(), any::<bool>(), binder_files())
+            .prop_map(|(big_endian, unicode, extended, files)| BND4Spec { big_endian, unicode, extended, files })
+            .boxed()
+    }
+
+    fn build(spec: &BND4Spec) -> Result<BND4, DantelionFormatsError> {
+        let builder = BND4Builder::new().big_endian(spec.big_endian).unicode(spec.unicode).extended(spec.extended);
+        Ok(spec.files.iter().fold(builder, |builder, (id, name, data)| builder.add_file(*id, name, data.clone())).build())
+    }
+
+    fn write(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        self.to_bytes(&BND4WriteOptions::default())
+    }
+
+    fn read(bytes: &[u8]) -> Result<BND4, DantelionFormatsError> {
+        BND4::from_bytes(bytes)
+    }
+
+    fn summary(&self) -> BinderSummary {
+        self.files.iter().map(|file| (file.id, file.name.clone(), file.data.clone())).collect()
+    }
+}
+
+impl RoundTrip for BND3 {
+    type Spec = Vec<(i32, String, Vec<u8>)>;
+    type Summary = BinderSummary;
+
+    fn spec() -> BoxedStrategy<Self::Spec> {
+        binder_files().boxed()
+    }
+
+    fn build(spec: &Self::Spec) -> Result<BND3, DantelionFormatsError> {
+        let bnd4 = spec.iter().fold(BND4Builder::new(), |builder, (id, name, data)| builder.add_file(*id, name, data.clone())).build();
+        BND3::from_bnd4(&bnd4)
+    }
+
+    fn write(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        self.to_bytes()
+    }
+
+    fn read(bytes: &[u8]) -> Result<BND3, DantelionFormatsError> {
+        BND3::from_bytes(bytes)
+    }
+
+    fn summary(&self) -> BinderSummary {
+        self.files.iter().map(|file| (file.id, file.name.clone(), file.data.clone())).collect()
+    }
+}
+
+impl RoundTrip for DCX {
+    // EDGE or DFLT, and the data to compress
+    type Spec = (bool, Vec<u8>);
+    type Summary = (String, Vec<u8>);
+
+    fn spec() -> BoxedStrategy<Self::Spec> {
+        (any::<bool>(), prop::collection::vec(any::<u8>(), 0..0x20000)).boxed()
+    }
+
+    fn build((edge, data): &Self::Spec) -> Result<DCX, DantelionFormatsError> {
+        Ok(if *edge { DCX::compress_edge(data) } else { DCX::compress_dflt(data) })
+    }
+
+    fn write(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        self.to_bytes()
+    }
+
+    fn read(bytes: &[u8]) -> Result<DCX, DantelionFormatsError> {
+        DCX::from_bytes(bytes)
+    }
+
+    fn summary(&self) -> Self::Summary {
+        (self.header.format.clone(), self.decompress().unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BHD5Spec {
+    format_index: usize,
+    salt: String,
+    // Path, offset, size and whether the file has an AES key
+    files: Vec<(String, u64, u32, bool)>,
+}
+
+impl RoundTrip for BHD5 {
+    type Spec = BHD5Spec;
+    type Summary = Vec<(u64, u64, u64, u32, Option<Vec<u8>>)>;
+
+    fn spec() -> BoxedStrategy<BHD5Spec> {
+        let file = ("/[a-z0-9_/]{1,24}\\.[a-z]{3}", 0..1u64 << 40, any::<u32>(), any::<bool>());
+        (0..3usize, "[a-z0-9]{4,16}", prop::collection::vec(file, 0..24))
+            .prop_map(|(format_index, salt, files)| BHD5Spec { format_index, salt, files })
+            .boxed()
+    }
+
+    fn build(spec: &BHD5Spec) -> Result<BHD5, DantelionFormatsError> {
+        let (format, prefix) = [(BHD5Format::DarkSoulsII, "DS2_"), (BHD5Format::DarkSoulsIII, "FDP_"), (BHD5Format::EldenRing, "GR_")][spec.format_index];
+        let file_headers = spec.files.iter().map(|(path, file_offset, file_size, encrypted)| FileHeader {
+            file_path_hash: BHD5::hash_path(path, format),
+            padded_file_size: file_size.saturating_add(0xF) & !0xF,
+            // DS2 doesn't store the unpadded size.
+            file_size: if format == BHD5Format::DarkSoulsII { 0 } else { *file_size as u64 },
+            file_offset: *file_offset,
+            salted_hash_offset: 0,
+            aes_key_offset: 0,
+            salted_hash: None,
+            aes_key: encrypted.then(|| AESKey { key: path.bytes().cycle().take(16).collect(), range_count: 1, ranges: vec![Range { begin: 0, end: *file_size as u64 }] }),
+        }).collect();
+
+        Ok(BHD5::new(format, format!("{}{}", prefix, spec.salt), file_headers))
+    }
+
+    fn write(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        self.to_bytes()
+    }
+
+    fn read(bytes: &[u8]) -> Result<BHD5, DantelionFormatsError> {
+        BHD5::from_bytes(bytes)
+    }
+
+    fn summary(&self) -> Self::Summary {
+        let mut file_headers: Self::Summary = self.buckets.iter()
+            .flat_map(|bucket| &bucket.file_headers)
+            .map(|file_header| (
+                file_header.file_path_hash,
+                file_header.file_offset,
+                file_header.file_size,
+                file_header.padded_file_size,
+                file_header.aes_key.as_ref().map(|aes_key| aes_key.key.clone()),
+            ))
+            .collect();
+        file_headers.sort();
+        file_headers
+    }
+}
+
+impl RoundTrip for MQB {
+    type Spec = (bool, bool, u32, Vec<u8>);
+    type Summary = (bool, bool, u32, u32, Vec<u8>);
+
+    fn spec() -> BoxedStrategy<Self::Spec> {
+        (any::<bool>(), any::<bool>(), any::<u32>(), prop::collection::vec(any::<u8>(), 0..0x100)).boxed()
+    }
+
+    fn build((big_endian, long_format, version, body): &Self::Spec) -> Result<MQB, DantelionFormatsError> {
+        Ok(MQB {
+            header: MQBHeader {
+                magic: "MQB ".to_string(),
+                big_endian: *big_endian,
+                unk05: 0,
+                long_format: *long_format,
+                unk07: 0,
+                version: *version,
+                header_size: 0x14,
+            },
+            body: body.clone(),
+        })
+    }
+
+    fn write(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        self.to_bytes()
+    }
+
+    fn read(bytes: &[u8]) -> Result<MQB, DantelionFormatsError> {
+        MQB::from_bytes(bytes)
+    }
+
+    fn summary(&self) -> Self::Summary {
+        (self.header.big_endian, self.header.long_format, self.header.version, self.header.header_size, self.body.clone())
+    }
+}
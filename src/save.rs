@@ -0,0 +1,289 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use byteorder::{ByteOrder, LE};
+use md5::{Digest, Md5};
+use crate::bhd5::GameType;
+use crate::bnd4::{BND4, BND4WriteOptions};
+#[cfg(feature = "crypto")]
+use crate::crypto_util;
+use crate::error::DantelionFormatsError;
+
+/// A PC save, e.g. `ER0000.sl2`. It's a BND4 of character slots, USER_DATA000 on, and USER_DATA010, the menu data
+/// holding the Steam id and a summary of each character. Every entry starts with an MD5 of the rest of it, which the
+/// game checks on load. DS3 entries are also AES encrypted, so reading and writing those needs the crypto feature.
+/// Entries are read and written decrypted and without their checksum, which `set_entry` redoes.
+pub struct SaveFile {
+    pub game: GameType,
+    pub bnd4: BND4,
+}
+
+/// What the load menu shows for a slot.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CharacterSummary {
+    pub slot: usize,
+    // Empty slots keep whatever character was deleted from them, so check this before trusting the rest
+    pub active: bool,
+    pub name: String,
+    pub level: u32,
+    pub playtime: Duration,
+}
+
+struct SaveLayout {
+    slot_count: usize,
+    encrypted: bool,
+    // None where the menu data hasn't been mapped yet, which leaves `steam_id` searching for the id
+    menu: Option<MenuLayout>,
+}
+
+// Offsets into the menu data, after its checksum, and into each summary in it.
+struct MenuLayout {
+    steam_id: usize,
+    active_slots: usize,
+    summaries: usize,
+    summary_size: usize,
+    // UTF-16, terminator included
+    name_size: usize,
+    level: usize,
+    playtime: usize,
+}
+
+const ELDEN_RING_MENU: MenuLayout = MenuLayout {
+    steam_id: 0x4,
+    active_slots: 0x1954,
+    summaries: 0x195E,
+    summary_size: 0x24C,
+    name_size: 0x22,
+    level: 0x22,
+    playtime: 0x26,
+};
+
+impl SaveFile {
+    const CHECKSUM_SIZE: usize = 0x10;
+    const MENU_DATA: &'static str = "USER_DATA010";
+    /// The upper half of every individual Steam account's 64-bit id: public universe, individual type, desktop
+    /// instance.
+    pub const STEAM_ID_HIGH: u64 = 0x0110_0001;
+
+    /// The save's file name in the game's save folder.
+    pub fn file_name(game: GameType) -> Option<&'static str> {
+        match game {
+            GameType::DarkSoulsIII => Some("DS30000.sl2"),
+            GameType::Sekiro => Some("S0000.sl2"),
+            GameType::EldenRing => Some("ER0000.sl2"),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(game: GameType, path: &str) -> Result<SaveFile, DantelionFormatsError> {
+        SaveFile::from_bytes(game, &fs::read(path)?)
+    }
+
+    pub fn from_bytes(game: GameType, file: &[u8]) -> Result<SaveFile, DantelionFormatsError> {
+        SaveFile::layout(game)?;
+        let save = SaveFile { game, bnd4: BND4::from_bytes(file)? };
+        if save.bnd4.get(SaveFile::MENU_DATA).is_none() {
+            return Err(invalid_save(format!("No {} in the save", SaveFile::MENU_DATA)));
+        }
+
+        Ok(save)
+    }
+
+    pub fn to_path(&self, path: &str) -> Result<(), DantelionFormatsError> {
+        Ok(fs::write(path, self.to_bytes()?)?)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        self.bnd4.to_bytes(&BND4WriteOptions::default())
+    }
+
+    pub fn slot_count(&self) -> usize {
+        SaveFile::layout(self.game).map_or(0, |layout| layout.slot_count)
+    }
+
+    /// An entry's data, decrypted and without its checksum, e.g. "USER_DATA010".
+    pub fn entry(&self, name: &str) -> Result<Vec<u8>, DantelionFormatsError> {
+        let data = self.bnd4.get(name).and_then(|file| file.data.as_deref())
+            .ok_or_else(|| invalid_save(format!("No {} in the save", name)))?;
+        let data = data.get(SaveFile::CHECKSUM_SIZE..).ok_or_else(|| invalid_save(format!("{} is shorter than its checksum", name)))?;
+
+        self.decode(data)
+    }
+
+    /// Replaces an entry's data, encrypting it for DS3 and updating its checksum.
+    pub fn set_entry(&mut self, name: &str, data: &[u8]) -> Result<(), DantelionFormatsError> {
+        let encoded = self.encode(data)?;
+        let file = self.bnd4.get_mut(name).ok_or_else(|| invalid_save(format!("No {} in the save", name)))?;
+        let mut data = vec![0; SaveFile::CHECKSUM_SIZE];
+        data.extend_from_slice(&encoded);
+        data[..SaveFile::CHECKSUM_SIZE].copy_from_slice(&Md5::digest(&encoded));
+        file.compressed_size = data.len() as u64;
+        file.data = Some(data);
+
+        Ok(())
+    }
+
+    pub fn slot(&self, slot: usize) -> Result<Vec<u8>, DantelionFormatsError> {
+        self.entry(&self.slot_name(slot)?)
+    }
+
+    pub fn set_slot(&mut self, slot: usize, data: &[u8]) -> Result<(), DantelionFormatsError> {
+        self.set_entry(&self.slot_name(slot)?, data)
+    }
+
+    /// The Steam id in the menu data. For games whose menu data isn't mapped, DS3 and Sekiro, it's the first value
+    /// shaped like an individual account's id, see `STEAM_ID_HIGH`.
+    pub fn steam_id(&self) -> Result<u64, DantelionFormatsError> {
+        let menu_data = self.entry(SaveFile::MENU_DATA)?;
+        let Some(menu) = SaveFile::layout(self.game)?.menu else {
+            return (0..menu_data.len().saturating_sub(7))
+                .map(|i| LE::read_u64(&menu_data[i..]))
+                .find(|id| id >> 32 == SaveFile::STEAM_ID_HIGH)
+                .ok_or_else(|| invalid_save(format!("No Steam id in {}", SaveFile::MENU_DATA)));
+        };
+
+        menu_data.get(menu.steam_id..menu.steam_id + 8)
+            .map(LE::read_u64)
+            .ok_or_else(|| invalid_save(format!("{} is too short for the Steam id", SaveFile::MENU_DATA)))
+    }
+
+    pub fn characters(&self) -> Result<Vec<CharacterSummary>, DantelionFormatsError> {
+        let menu = self.menu_layout()?;
+        let slot_count = self.slot_count();
+        let menu_data = self.entry(SaveFile::MENU_DATA)?;
+        if menu_data.len() < menu.summaries + slot_count * menu.summary_size {
+            return Err(invalid_save(format!("{} is 0x{:X} bytes, too short for the character summaries", SaveFile::MENU_DATA, menu_data.len())));
+        }
+
+        (0..slot_count).map(|slot| {
+            let summary = &menu_data[menu.summaries + slot * menu.summary_size..];
+            let name: Vec<u16> = summary[..menu.name_size].chunks_exact(2)
+                .map(LE::read_u16)
+                .take_while(|c| *c != 0)
+                .collect();
+
+            Ok(CharacterSummary {
+                slot,
+                active: menu_data[menu.active_slots + slot] != 0,
+                name: String::from_utf16(&name)?,
+                level: LE::read_u32(&summary[menu.level..]),
+                playtime: Duration::from_secs(LE::read_u32(&summary[menu.playtime..]) as u64),
+            })
+        }).collect()
+    }
+
+    /// Moves the save to another Steam account, replacing the id `steam_id` finds. See `replace_steam_id`.
+    pub fn set_steam_id(&mut self, steam_id: u64) -> Result<usize, DantelionFormatsError> {
+        self.replace_steam_id(self.steam_id()?, steam_id)
+    }
+
+    /// Replaces every copy of `old` with `new` in every USER_DATA entry, re-encrypting and fixing checksums. The id
+    /// is in the menu data and at places in each character slot that move between patches, and the game checks all
+    /// of them, so every copy is replaced. Returns how many were.
+    pub fn replace_steam_id(&mut self, old: u64, new: u64) -> Result<usize, DantelionFormatsError> {
+        let (old, new) = (old.to_le_bytes(), new.to_le_bytes());
+        let names: Vec<String> = self.bnd4.files.iter().filter_map(|file| file.name.clone()).filter(|name| is_user_data(name)).collect();
+
+        let mut replaced = 0;
+        for name in names {
+            let mut data = self.entry(&name)?;
+            let mut count = 0;
+            let mut i = 0;
+            while i + old.len() <= data.len() {
+                if data[i..i + old.len()] == old {
+                    data[i..i + old.len()].copy_from_slice(&new);
+                    count += 1;
+                    i += old.len();
+                } else {
+                    i += 1;
+                }
+            }
+            if count > 0 {
+                self.set_entry(&name, &data)?;
+                replaced += count;
+            }
+        }
+
+        Ok(replaced)
+    }
+
+    /// Recomputes the MD5 at the start of every USER_DATA entry, after editing `bnd4` directly.
+    pub fn fix_checksums(&mut self) {
+        for file in self.bnd4.files.iter_mut().filter(|file| file.name.as_deref().is_some_and(is_user_data)) {
+            let Some(data) = file.data.as_mut() else { continue };
+            if data.len() >= SaveFile::CHECKSUM_SIZE {
+                let checksum = Md5::digest(&data[SaveFile::CHECKSUM_SIZE..]);
+                data[..SaveFile::CHECKSUM_SIZE].copy_from_slice(&checksum);
+            }
+        }
+    }
+
+    /// The names of the entries whose checksum doesn't match, which the game would refuse to load.
+    pub fn bad_checksums(&self) -> Vec<&str> {
+        self.bnd4.files.iter()
+            .filter(|file| file.data.as_deref().is_some_and(|data| {
+                data.len() < SaveFile::CHECKSUM_SIZE || Md5::digest(&data[SaveFile::CHECKSUM_SIZE..])[..] != data[..SaveFile::CHECKSUM_SIZE]
+            }))
+            .filter_map(|file| file.name.as_deref())
+            .filter(|name| is_user_data(name))
+            .collect()
+    }
+
+    fn layout(game: GameType) -> Result<SaveLayout, DantelionFormatsError> {
+        match game {
+            GameType::EldenRing => Ok(SaveLayout { slot_count: 10, encrypted: false, menu: Some(ELDEN_RING_MENU) }),
+            GameType::DarkSoulsIII => Ok(SaveLayout { slot_count: 10, encrypted: true, menu: None }),
+            GameType::Sekiro => Ok(SaveLayout { slot_count: 10, encrypted: false, menu: None }),
+            _ => Err(DantelionFormatsError::IoError(Error::new(ErrorKind::Unsupported, format!("{:?} saves are not supported", game)))),
+        }
+    }
+
+    fn menu_layout(&self) -> Result<MenuLayout, DantelionFormatsError> {
+        SaveFile::layout(self.game)?.menu.ok_or_else(|| {
+            DantelionFormatsError::IoError(Error::new(ErrorKind::Unsupported, format!("The {:?} menu data layout is not known", self.game)))
+        })
+    }
+
+    fn slot_name(&self, slot: usize) -> Result<String, DantelionFormatsError> {
+        if slot >= self.slot_count() {
+            return Err(invalid_save(format!("{:?} saves have {} slots, there is no slot {}", self.game, self.slot_count(), slot)));
+        }
+
+        Ok(format!("USER_DATA{:03}", slot))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>, DantelionFormatsError> {
+        if !SaveFile::layout(self.game)?.encrypted {
+            return Ok(data.to_vec());
+        }
+
+        #[cfg(feature = "crypto")]
+        return crypto_util::decrypt_save_entry(data, &crypto_util::DS3_SAVE_KEY);
+        #[cfg(not(feature = "crypto"))]
+        Err(needs_crypto(self.game))
+    }
+
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>, DantelionFormatsError> {
+        if !SaveFile::layout(self.game)?.encrypted {
+            return Ok(data.to_vec());
+        }
+
+        #[cfg(feature = "crypto")]
+        return crypto_util::encrypt_save_entry(data, &crypto_util::DS3_SAVE_KEY);
+        #[cfg(not(feature = "crypto"))]
+        Err(needs_crypto(self.game))
+    }
+}
+
+fn is_user_data(name: &str) -> bool {
+    name.starts_with("USER_DATA")
+}
+
+fn invalid_save(message: String) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, message))
+}
+
+#[cfg(not(feature = "crypto"))]
+fn needs_crypto(game: GameType) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::Unsupported, format!("{:?} saves are encrypted, which needs the crypto feature", game)))
+}
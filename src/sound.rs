@@ -0,0 +1,330 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io::{Cursor, Error, ErrorKind};
+use binary_interpreter::binary_reader::{BinaryPeeker, BinaryReader};
+use byteorder::{BE, LE, ByteOrder, ReadBytesExt};
+use crate::error::DantelionFormatsError;
+use crate::source::{DataSource, FileSource};
+use crate::util;
+use crate::util::{DataLen, ensure, Validate};
+
+/// An FMOD sound bank, used for sound from DS3 back. Samples are kept in whatever codec they were stored with.
+#[derive(Debug)]
+#[repr(C)]
+pub struct FSB5 {
+    pub header: FSB5Header,
+    pub samples: Vec<FSB5Sample>,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+#[non_exhaustive]
+pub struct FSB5Header {
+    pub magic: String,
+    pub version: u32,
+    pub sample_count: u32,
+    pub sample_headers_size: u32,
+    pub name_table_size: u32,
+    pub data_size: u32,
+    // 1-5 PCM, 6 GCADPCM, 7 IMAADPCM, 8 VAG, 9 HEVAG, 10 XMA, 11 MPEG, 12 CELT, 13 AT9, 14 XWMA, 15 Vorbis, 16 FADPCM
+    pub codec: u32,
+    // 0x3C, or 0x40 in version 0
+    pub header_size: u32,
+}
+
+#[repr(C)]
+pub struct FSB5Sample {
+    pub name: Option<String>,
+    pub frequency: u32,
+    pub channels: u32,
+    // From the start of the file
+    pub data_offset: u64,
+    pub sample_count: u32,
+    pub data: Vec<u8>,
+}
+
+/// A Wwise sound bank, used for sound in Elden Ring. Only the embedded files are read, the HIRC objects are not.
+#[derive(Debug)]
+#[repr(C)]
+pub struct BNK {
+    pub big_endian: bool,
+    pub version: u32,
+    pub bank_id: u32,
+    pub sections: Vec<BNKSection>,
+    pub files: Vec<BNKFile>,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct BNKSection {
+    pub tag: String,
+    // Of the section's data, after the tag and size
+    pub offset: u64,
+    pub size: u32,
+}
+
+/// A .wem embedded in a bank.
+#[repr(C)]
+pub struct BNKFile {
+    pub id: u32,
+    pub data: Vec<u8>,
+}
+
+impl FSB5 {
+    const MAGIC_SIZE: usize = 4;
+    // Zeroes, a hash and a dummy field
+    const HEADER_PADDING: u32 = 0x20;
+    const FREQUENCIES: [u32; 10] = [0, 8000, 11000, 11025, 16000, 22050, 24000, 32000, 44100, 48000];
+    const CHANNELS_CHUNK: u32 = 1;
+    const FREQUENCY_CHUNK: u32 = 2;
+
+    pub(crate) fn is(bytes: &[u8]) -> bool {
+        bytes.starts_with(b"FSB5")
+    }
+
+    pub fn from_path(path: &str) -> Result<FSB5, DantelionFormatsError> {
+        FSB5::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<FSB5, DantelionFormatsError> {
+        let file = source.read_all()?;
+
+        FSB5::from_bytes(&file)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<FSB5, DantelionFormatsError> {
+        let mut c = Cursor::new(file);
+        let header = FSB5::read_fsb5_header(&mut c)?;
+        let samples = FSB5::read_fsb5_samples(&mut c, &header)?;
+
+        Ok(FSB5 {
+            header,
+            samples,
+        })
+    }
+
+    fn read_fsb5_header(c: &mut Cursor<&[u8]>) -> Result<FSB5Header, DantelionFormatsError> {
+        let magic = c.read_fixed_cstr(FSB5::MAGIC_SIZE)?;
+        let version = c.read_u32::<LE>()?;
+        let sample_count = c.read_u32::<LE>()?;
+        let sample_headers_size = c.read_u32::<LE>()?;
+        let name_table_size = c.read_u32::<LE>()?;
+        let data_size = c.read_u32::<LE>()?;
+        let codec = c.read_u32::<LE>()?;
+        if version == 0 {
+            c.read_u32::<LE>()?;
+        }
+        let header_size = c.position() as u32 + FSB5::HEADER_PADDING;
+        c.set_position(header_size as u64);
+
+        let header = FSB5Header {
+            magic,
+            version,
+            sample_count,
+            sample_headers_size,
+            name_table_size,
+            data_size,
+            codec,
+            header_size,
+        };
+
+        header.validate()?;
+
+        Ok(header)
+    }
+
+    // Each sample header is a packed u64, optionally followed by chunks that override its fields.
+    fn read_fsb5_samples(c: &mut Cursor<&[u8]>, header: &FSB5Header) -> Result<Vec<FSB5Sample>, DantelionFormatsError> {
+        let name_table_offset = (header.header_size + header.sample_headers_size) as u64;
+        let data_start = name_table_offset + header.name_table_size as u64;
+
+        let mut samples = Vec::with_capacity(util::capacity_for(c, header.sample_count as u64));
+        for i in 0..header.sample_count {
+            let packed = c.read_u64::<LE>()?;
+            let mut has_chunk = packed & 1 != 0;
+            let mut frequency = FSB5::FREQUENCIES.get(((packed >> 1) & 0xF) as usize).copied().unwrap_or(0);
+            let mut channels = ((packed >> 5) & 1) as u32 + 1;
+            let data_offset = data_start + ((packed >> 6) & 0x0FFFFFFF) * 16;
+            let sample_count = ((packed >> 34) & 0x3FFFFFFF) as u32;
+
+            while has_chunk {
+                let chunk = c.read_u32::<LE>()?;
+                has_chunk = chunk & 1 != 0;
+                let size = (chunk >> 1) & 0xFFFFFF;
+                let end = c.position() + size as u64;
+                match chunk >> 25 {
+                    FSB5::CHANNELS_CHUNK => channels = c.read_u8()? as u32,
+                    FSB5::FREQUENCY_CHUNK => frequency = c.read_u32::<LE>()?,
+                    _ => {}
+                }
+                c.set_position(end);
+            }
+
+            let name = if header.name_table_size == 0 {
+                None
+            } else {
+                let start = c.position();
+                c.set_position(name_table_offset + i as u64 * 4);
+                let name_offset = c.read_u32::<LE>()?;
+                c.set_position(start);
+                Some(c.peek_cstr(name_table_offset + name_offset as u64)?)
+            };
+
+            samples.push(FSB5Sample {
+                name,
+                frequency,
+                channels,
+                data_offset,
+                sample_count,
+                data: vec![],
+            });
+        }
+
+        // Samples are stored back to back, so each one runs until the next.
+        let data_end = data_start + header.data_size as u64;
+        let file = c.get_ref();
+        for i in 0..samples.len() {
+            let end = samples.get(i + 1).map_or(data_end, |next| next.data_offset);
+            let start = samples[i].data_offset;
+            samples[i].data = file.get(start as usize..end as usize).ok_or_else(|| out_of_bounds("FSB5 sample"))?.to_vec();
+        }
+
+        Ok(samples)
+    }
+}
+
+impl BNK {
+    const TAG_SIZE: usize = 4;
+    const DIDX_ENTRY_SIZE: u32 = 12;
+
+    pub(crate) fn is(bytes: &[u8]) -> bool {
+        bytes.starts_with(b"BKHD")
+    }
+
+    pub fn from_path(path: &str) -> Result<BNK, DantelionFormatsError> {
+        BNK::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<BNK, DantelionFormatsError> {
+        let file = source.read_all()?;
+
+        BNK::from_bytes(&file)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<BNK, DantelionFormatsError> {
+        let mut c = Cursor::new(file);
+        // Console banks are big endian, which shows up as a BKHD size bigger than the file.
+        let bkhd_size = file.get(BNK::TAG_SIZE..BNK::TAG_SIZE + 4).ok_or_else(|| out_of_bounds("BKHD size"))?;
+        let big_endian = LE::read_u32(bkhd_size) as usize > file.len();
+        if big_endian { BNK::read_bnk::<BE>(&mut c, big_endian) } else { BNK::read_bnk::<LE>(&mut c, big_endian) }
+    }
+
+    /// The section with the given tag, e.g. "HIRC".
+    pub fn section(&self, tag: &str) -> Option<&BNKSection> {
+        self.sections.iter().find(|section| section.tag == tag)
+    }
+
+    fn read_bnk<T: ByteOrder>(c: &mut Cursor<&[u8]>, big_endian: bool) -> Result<BNK, DantelionFormatsError> {
+        let mut sections = vec![];
+        while (c.position() as usize) < c.get_ref().len() {
+            let tag = c.read_fixed_cstr(BNK::TAG_SIZE)?;
+            let size = c.read_u32::<T>()?;
+            let offset = c.position();
+            sections.push(BNKSection { tag, offset, size });
+            c.set_position(offset + size as u64);
+        }
+
+        let bkhd = sections.first().filter(|section| section.tag == "BKHD")
+            .ok_or_else(|| DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, "BNK does not start with BKHD")))?;
+        c.set_position(bkhd.offset);
+        let version = c.read_u32::<T>()?;
+        let bank_id = c.read_u32::<T>()?;
+
+        let mut files = vec![];
+        let didx = sections.iter().find(|section| section.tag == "DIDX");
+        let data = sections.iter().find(|section| section.tag == "DATA");
+        if let (Some(didx), Some(data)) = (didx, data) {
+            c.set_position(didx.offset);
+            for _ in 0..didx.size / BNK::DIDX_ENTRY_SIZE {
+                let id = c.read_u32::<T>()?;
+                let offset = c.read_u32::<T>()?;
+                let size = c.read_u32::<T>()?;
+                let start = data.offset as usize + offset as usize;
+                let bytes = c.get_ref().get(start..start + size as usize).ok_or_else(|| out_of_bounds("BNK file"))?;
+                files.push(BNKFile { id, data: bytes.to_vec() });
+            }
+        }
+
+        Ok(BNK {
+            big_endian,
+            version,
+            bank_id,
+            sections,
+            files,
+        })
+    }
+}
+
+/// A one line summary, e.g. "FSB5 v1 — 24 samples, codec 15".
+impl Display for FSB5 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "FSB5 v{} — {} samples, codec {}", self.header.version, self.samples.len(), self.header.codec)
+    }
+}
+
+impl Debug for FSB5Sample {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FSB5Sample")
+            .field("name", &self.name)
+            .field("frequency", &self.frequency)
+            .field("channels", &self.channels)
+            .field("data_offset", &self.data_offset)
+            .field("sample_count", &self.sample_count)
+            .field("data", &DataLen(self.data.len()))
+            .finish()
+    }
+}
+
+/// A one line summary, e.g. "BNK 1355168291 v141 — 3 files, 4 sections".
+impl Display for BNK {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "BNK {} v{} — {} files, {} sections", self.bank_id, self.version, self.files.len(), self.sections.len())
+    }
+}
+
+impl Debug for BNKFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BNKFile")
+            .field("id", &self.id)
+            .field("data", &DataLen(self.data.len()))
+            .finish()
+    }
+}
+
+fn out_of_bounds(what: &str) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::UnexpectedEof, format!("{} out of bounds", what)))
+}
+
+impl FSB5Header {
+    // Stable accessors, see `BND4Header`.
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn codec(&self) -> u32 {
+        self.codec
+    }
+}
+
+impl Validate for FSB5Header {
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.magic == "FSB5", "Magic was {}", self.magic);
+        ensure!(self.version == 0 || self.version == 1, "version was {}", self.version);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use crate::error::DantelionFormatsError;
+
+/// Random access to the bytes of a file, wherever they are: memory, disk, a memory map or a server. Every parser can
+/// read from one with `from_source`. Only the ranges that are asked for get read, so BDTs can be browsed without
+/// loading or downloading them whole.
+pub trait DataSource {
+    /// Reads exactly `len` bytes at `offset`.
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>, DantelionFormatsError>;
+
+    fn size(&self) -> Result<u64, DantelionFormatsError>;
+
+    /// A hint that `len` bytes at `offset` are about to be read, so the OS can start reading them in while the
+    /// current read is being worked on. Sources that have nothing to gain from it ignore it.
+    fn prefetch(&self, _offset: u64, _len: usize) {}
+
+    /// The whole thing, for parsers that need all of it.
+    fn read_all(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        let size = usize::try_from(self.size()?).map_err(|_| Error::new(ErrorKind::OutOfMemory, "Source too big for this platform"))?;
+        self.read_at(0, size)
+    }
+}
+
+impl DataSource for [u8] {
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>, DantelionFormatsError> {
+        let start = usize::try_from(offset).map_err(|_| out_of_bounds(offset, len))?;
+        let end = start.checked_add(len).ok_or_else(|| out_of_bounds(offset, len))?;
+        Ok(self.get(start..end).ok_or_else(|| out_of_bounds(offset, len))?.to_vec())
+    }
+
+    fn size(&self) -> Result<u64, DantelionFormatsError> {
+        Ok(self.len() as u64)
+    }
+}
+
+impl DataSource for Vec<u8> {
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>, DantelionFormatsError> {
+        self.as_slice().read_at(offset, len)
+    }
+
+    fn size(&self) -> Result<u64, DantelionFormatsError> {
+        Ok(self.len() as u64)
+    }
+}
+
+/// A file on disk. Reads seek and read under a lock, so one source can be shared between threads. Small reads, like
+/// a parser's header fields, are served from a window of the file read ahead of them, so scanning the headers of
+/// thousands of files doesn't cost a syscall per field. The window isn't refreshed if the file changes underneath it.
+#[derive(Debug)]
+pub struct FileSource {
+    file: Mutex<OpenFile>,
+}
+
+#[derive(Debug)]
+struct OpenFile {
+    file: File,
+    window: Vec<u8>,
+    window_offset: u64,
+}
+
+impl OpenFile {
+    fn new(file: File) -> OpenFile {
+        OpenFile {
+            file,
+            window: vec![],
+            window_offset: 0,
+        }
+    }
+
+    fn window_slice(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        let start = usize::try_from(offset.checked_sub(self.window_offset)?).ok()?;
+        self.window.get(start..start.checked_add(len)?)
+    }
+
+    // Short at the end of the file.
+    fn fill_window(&mut self, offset: u64) -> Result<(), DantelionFormatsError> {
+        self.window.clear();
+        self.window_offset = offset;
+        self.file.seek(SeekFrom::Start(offset))?;
+        (&mut self.file).take(FileSource::WINDOW_SIZE as u64).read_to_end(&mut self.window)?;
+
+        Ok(())
+    }
+}
+
+impl FileSource {
+    const WINDOW_SIZE: usize = 0x10000;
+    // Bigger reads go straight to the file, so they don't evict the window
+    const SMALL_READ: usize = 0x1000;
+
+    pub fn open(path: &str) -> Result<FileSource, DantelionFormatsError> {
+        Ok(FileSource {
+            file: Mutex::new(OpenFile::new(File::open(path)?)),
+        })
+    }
+
+    /// Same as `open`, telling the OS the file will mostly be read front to back, so it reads further ahead. That's
+    /// `POSIX_FADV_SEQUENTIAL` on Linux and `FILE_FLAG_SEQUENTIAL_SCAN` on Windows, elsewhere it's the same as `open`.
+    /// Worth it for extracting whole BDTs, especially from hard drives.
+    pub fn open_sequential(path: &str) -> Result<FileSource, DantelionFormatsError> {
+        #[cfg(windows)]
+        let file = {
+            use std::os::windows::fs::OpenOptionsExt;
+            const FILE_FLAG_SEQUENTIAL_SCAN: u32 = 0x08000000;
+            std::fs::OpenOptions::new().read(true).custom_flags(FILE_FLAG_SEQUENTIAL_SCAN).open(path)?
+        };
+        #[cfg(not(windows))]
+        let file = File::open(path)?;
+        #[cfg(target_os = "linux")]
+        fadvise(&file, 0, 0, libc::POSIX_FADV_SEQUENTIAL);
+
+        Ok(FileSource {
+            file: Mutex::new(OpenFile::new(file)),
+        })
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, OpenFile>, DantelionFormatsError> {
+        Ok(self.file.lock().map_err(|_| Error::other("File lock poisoned"))?)
+    }
+}
+
+impl DataSource for FileSource {
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>, DantelionFormatsError> {
+        let mut open = self.lock()?;
+        if len <= FileSource::SMALL_READ {
+            if open.window_slice(offset, len).is_none() {
+                open.fill_window(offset)?;
+            }
+            return open.window_slice(offset, len).map(<[u8]>::to_vec).ok_or_else(|| out_of_bounds(offset, len));
+        }
+
+        let mut data = vec![0; len];
+        open.file.seek(SeekFrom::Start(offset))?;
+        open.file.read_exact(&mut data)?;
+
+        Ok(data)
+    }
+
+    fn size(&self) -> Result<u64, DantelionFormatsError> {
+        Ok(self.lock()?.file.metadata()?.len())
+    }
+
+    // Only Linux has a way to ask for a range of a file ahead of time, Windows' closest is the sequential flag.
+    #[cfg(target_os = "linux")]
+    fn prefetch(&self, offset: u64, len: usize) {
+        if let Ok(open) = self.file.lock() {
+            fadvise(&open.file, offset, len, libc::POSIX_FADV_WILLNEED);
+        }
+    }
+}
+
+// Hints are best effort, so failures are ignored.
+#[cfg(target_os = "linux")]
+fn fadvise(file: &File, offset: u64, len: usize, advice: libc::c_int) {
+    use std::os::fd::AsRawFd;
+
+    if let (Ok(offset), Ok(len)) = (libc::off_t::try_from(offset), libc::off_t::try_from(len)) {
+        unsafe { libc::posix_fadvise(file.as_raw_fd(), offset, len, advice) };
+    }
+}
+
+/// A memory mapped file. Reads are copies out of the map, the OS only pages in what's read.
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub struct MmapSource {
+    map: memmap2::Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl MmapSource {
+    pub fn open(path: &str) -> Result<MmapSource, DantelionFormatsError> {
+        let file = File::open(path)?;
+        // Safety: the map is read only, but another process truncating or writing the file while it's mapped is
+        // undefined behavior. Game files aren't written while they're being read, see `discovery::ensure_not_running`.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+
+        Ok(MmapSource { map })
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl DataSource for MmapSource {
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>, DantelionFormatsError> {
+        self.map[..].read_at(offset, len)
+    }
+
+    fn size(&self) -> Result<u64, DantelionFormatsError> {
+        Ok(self.map.len() as u64)
+    }
+}
+
+/// A file on a web server, read with HTTP range requests. The server has to support them, a server that sends the
+/// whole file back instead is an error rather than a silent full download.
+#[cfg(feature = "http")]
+#[derive(Debug)]
+pub struct HttpSource {
+    pub url: String,
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "http")]
+impl HttpSource {
+    const PARTIAL_CONTENT: u16 = 206;
+
+    pub fn new(url: &str) -> HttpSource {
+        HttpSource {
+            url: url.to_string(),
+            agent: ureq::Agent::new(),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl DataSource for HttpSource {
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>, DantelionFormatsError> {
+        if len == 0 {
+            return Ok(vec![]);
+        }
+
+        let range = format!("bytes={}-{}", offset, offset + len as u64 - 1);
+        let response = self.agent.get(&self.url).set("Range", &range).call().map_err(Box::new)?;
+        if response.status() != HttpSource::PARTIAL_CONTENT {
+            return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::Unsupported, format!("{} does not support range requests", self.url))));
+        }
+
+        let mut data = Vec::with_capacity(len);
+        response.into_reader().take(len as u64).read_to_end(&mut data)?;
+        if data.len() != len {
+            return Err(out_of_bounds(offset, len));
+        }
+
+        Ok(data)
+    }
+
+    fn size(&self) -> Result<u64, DantelionFormatsError> {
+        let response = self.agent.head(&self.url).call().map_err(Box::new)?;
+        response.header("Content-Length")
+            .and_then(|length| length.parse().ok())
+            .ok_or_else(|| DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("{} has no Content-Length", self.url))))
+    }
+}
+
+fn out_of_bounds(offset: u64, len: usize) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::UnexpectedEof, format!("Read of {:#x} bytes at {:#x} out of bounds", len, offset)))
+}
@@ -0,0 +1,187 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::error::DantelionFormatsError;
+use crate::source::{DataSource, FileSource};
+use crate::util::DataLen;
+
+pub(crate) static SPILL_COUNTER: AtomicU64 = AtomicU64::new(0);
+// Names tried before giving up on creating a spill file.
+const SPILL_ATTEMPTS: usize = 100;
+
+/// Bytes that are either in memory or, past a size threshold, in a temporary file. Read it through `DataSource`,
+/// or `into_vec` when it's known to fit. The temporary file is deleted when the payload is dropped.
+#[derive(Debug)]
+pub enum Payload {
+    Memory(Vec<u8>),
+    File(SpillFile),
+}
+
+#[derive(Debug)]
+pub struct SpillFile {
+    pub path: PathBuf,
+    len: u64,
+}
+
+impl SpillFile {
+    // Opened per read rather than held, so nothing has the file open when it's deleted.
+    fn source(&self) -> Result<FileSource, DantelionFormatsError> {
+        FileSource::open(&self.path.to_string_lossy())
+    }
+}
+
+impl Payload {
+    /// Keeps `data` in memory if it's at most `threshold` bytes, otherwise writes it out and frees it.
+    pub fn from_vec(data: Vec<u8>, threshold: usize) -> Result<Payload, DantelionFormatsError> {
+        if data.len() <= threshold {
+            return Ok(Payload::Memory(data));
+        }
+
+        let mut writer = SpillWriter::new(threshold);
+        writer.write_all(&data)?;
+        writer.finish()
+    }
+
+    pub fn len(&self) -> u64 {
+        match self {
+            Payload::Memory(data) => data.len() as u64,
+            Payload::File(file) => file.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_spilled(&self) -> bool {
+        matches!(self, Payload::File(_))
+    }
+
+    /// Reads a spilled payload back into memory.
+    pub fn into_vec(self) -> Result<Vec<u8>, DantelionFormatsError> {
+        match self {
+            Payload::Memory(data) => Ok(data),
+            Payload::File(file) => file.source()?.read_all(),
+        }
+    }
+}
+
+impl DataSource for Payload {
+    fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>, DantelionFormatsError> {
+        match self {
+            Payload::Memory(data) => data.read_at(offset, len),
+            Payload::File(file) => file.source()?.read_at(offset, len),
+        }
+    }
+
+    fn size(&self) -> Result<u64, DantelionFormatsError> {
+        Ok(self.len())
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Collects written bytes in memory until there are more than `threshold`, then moves them to a temporary file and
+/// writes the rest there.
+pub struct SpillWriter {
+    threshold: usize,
+    memory: Vec<u8>,
+    file: Option<(PathBuf, BufWriter<File>)>,
+    len: u64,
+}
+
+impl SpillWriter {
+    pub fn new(threshold: usize) -> SpillWriter {
+        SpillWriter {
+            threshold,
+            memory: vec![],
+            file: None,
+            len: 0,
+        }
+    }
+
+    pub fn finish(mut self) -> Result<Payload, DantelionFormatsError> {
+        let Some((path, file)) = self.file.take() else { return Ok(Payload::Memory(std::mem::take(&mut self.memory))) };
+        // Closed here, so the file can be deleted on Windows once the payload is dropped.
+        let spill_file = SpillFile { path, len: self.len };
+        file.into_inner().map_err(|e| e.into_error())?;
+
+        Ok(Payload::File(spill_file))
+    }
+
+    fn spill(&mut self) -> std::io::Result<()> {
+        let (path, file) = SpillWriter::create_spill_file()?;
+        let mut file = BufWriter::new(file);
+        file.write_all(&self.memory)?;
+        self.memory = vec![];
+        self.file = Some((path, file));
+
+        Ok(())
+    }
+
+    // The names are predictable, so the file is only ever created new. Anything already at a name, left over or put
+    // there by someone else, is skipped rather than written through.
+    fn create_spill_file() -> std::io::Result<(PathBuf, File)> {
+        let mut last_error = None;
+        for _ in 0..SPILL_ATTEMPTS {
+            let name = format!("dantelion-spill-{}-{}", std::process::id(), SPILL_COUNTER.fetch_add(1, Ordering::Relaxed));
+            let path = std::env::temp_dir().join(name);
+            match File::options().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((path, file)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => last_error = Some(e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.expect("There is always at least one attempt"))
+    }
+}
+
+impl Write for SpillWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.file.is_none() && self.memory.len() + buf.len() > self.threshold {
+            self.spill()?;
+        }
+        match &mut self.file {
+            Some((_, file)) => file.write_all(buf)?,
+            None => self.memory.extend_from_slice(buf),
+        }
+        self.len += buf.len() as u64;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.file {
+            Some((_, file)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Debug for SpillWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SpillWriter")
+            .field("threshold", &self.threshold)
+            .field("memory", &DataLen(self.memory.len()))
+            .field("file", &self.file.as_ref().map(|(path, _)| path))
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+// A writer dropped without `finish` still cleans up after itself.
+impl Drop for SpillWriter {
+    fn drop(&mut self) {
+        if let Some((path, file)) = self.file.take() {
+            drop(file);
+            let _ = fs::remove_file(path);
+        }
+    }
+}
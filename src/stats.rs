@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// What a bulk operation did, for frontends to show a summary. Returned by `Unpacker`, kept by `Vfs` for opening
+/// its archives, and returned by `crypto_util::read_regulation`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub files: usize,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub decrypt_time: Duration,
+    pub decompress_time: Duration,
+    // Files per format, e.g. "tpf.dcx" for extracted files or "BND4" for parsed ones
+    pub formats: BTreeMap<String, usize>,
+}
+
+impl Stats {
+    /// Adds another operation's numbers to these, e.g. to total up several unpacks.
+    pub fn merge(&mut self, other: &Stats) {
+        self.files += other.files;
+        self.bytes_read += other.bytes_read;
+        self.bytes_written += other.bytes_written;
+        self.decrypt_time += other.decrypt_time;
+        self.decompress_time += other.decompress_time;
+        for (format, count) in &other.formats {
+            *self.formats.entry(format.clone()).or_default() += count;
+        }
+    }
+
+    pub(crate) fn count_format(&mut self, format: &str) {
+        *self.formats.entry(format.to_string()).or_default() += 1;
+    }
+
+    /// Runs `f`, adding how long it took to `duration`.
+    pub(crate) fn time<R>(duration: &mut Duration, f: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let result = f();
+        *duration += start.elapsed();
+        result
+    }
+}
+
+/// A one line summary, e.g. "214 files, 10485760 bytes read, 10485760 bytes written, 1.20s decrypting, 0ns decompressing".
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} files, {} bytes read, {} bytes written, {:.2?} decrypting, {:.2?} decompressing",
+            self.files, self.bytes_read, self.bytes_written, self.decrypt_time, self.decompress_time)
+    }
+}
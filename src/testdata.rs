@@ -0,0 +1,81 @@
+use std::fs;
+use std::path::Path;
+use crate::bhd5::{AESKey, BHD5, BHD5Format, FileHeader, Range, SaltedHash};
+use crate::bnd3::BND3;
+use crate::bnd4::BND4;
+use crate::error::DantelionFormatsError;
+use crate::fixtures;
+
+// Tiny samples, embedded so every parser can be tested without a game install. The binder and DCX are made by
+// `fixtures` with the sizes below, and the tests check that the writers still produce the same bytes. Regenerate them
+// with `write_testdata` when a writer change is intended.
+
+/// `fixtures::bnd4` with `BND4_FILE_COUNT` files of `BND4_FILE_SIZE` bytes.
+pub const BND4_BYTES: &[u8] = include_bytes!("../testdata/sample.bnd");
+/// An unencrypted ER BHD5 with three files, one with an AES key and one with a salted hash.
+pub const BHD5_BYTES: &[u8] = include_bytes!("../testdata/sample.bhd5");
+/// `fixtures::sample_data(DCX_CONTENT_SIZE)` in a DFLT DCX.
+pub const DFLT_DCX_BYTES: &[u8] = include_bytes!("../testdata/sample.dcx");
+
+pub const BND4_FILE_COUNT: usize = 2;
+pub const BND4_FILE_SIZE: usize = 0x10;
+pub const DCX_CONTENT_SIZE: usize = 0x40;
+pub const BHD5_SALT: &str = "GR_sample_salt";
+
+pub fn bhd5() -> BHD5 {
+    let file_header = |path: &str, file_offset: u64, file_size: u64| FileHeader {
+        file_path_hash: BHD5::hash_path(path, BHD5Format::EldenRing),
+        padded_file_size: ((file_size + 0xF) & !0xF) as u32,
+        file_size,
+        file_offset,
+        salted_hash_offset: 0,
+        aes_key_offset: 0,
+        salted_hash: None,
+        aes_key: None,
+    };
+
+    let mut encrypted = file_header("/sample/a.bin", 0x10, 0x20);
+    encrypted.aes_key = Some(AESKey { key: vec![0x11; 16], range_count: 1, ranges: vec![Range { begin: 0, end: 0x20 }] });
+    let mut hashed = file_header("/sample/b.bin", 0x30, 0x18);
+    hashed.salted_hash = Some(SaltedHash { hash: vec![0x22; 32], range_count: 1, ranges: vec![Range { begin: 0, end: 0x18 }] });
+    let plain = file_header("/sample/c.bin", 0x50, 0x8);
+
+    BHD5::new(BHD5Format::EldenRing, BHD5_SALT.to_string(), vec![encrypted, hashed, plain])
+}
+
+pub fn bhd5_bytes() -> Result<Vec<u8>, DantelionFormatsError> {
+    bhd5().to_bytes()
+}
+
+/// Rewrites the samples in `dir`, which is `testdata` at the root of the repo.
+pub fn write_testdata(dir: &str) -> Result<(), DantelionFormatsError> {
+    fs::create_dir_all(dir)?;
+    fs::write(Path::new(dir).join("sample.bnd"), fixtures::bnd4_bytes(BND4_FILE_COUNT, BND4_FILE_SIZE)?)?;
+    fs::write(Path::new(dir).join("sample.bhd5"), bhd5_bytes()?)?;
+    fs::write(Path::new(dir).join("sample.dcx"), fixtures::dflt_dcx_bytes(DCX_CONTENT_SIZE)?)?;
+
+    Ok(())
+}
+
+/// Writes seed corpora for the fuzz targets in `fuzz`, as `<dir>/<target>/seed_<n>`. `dir` is `fuzz/corpus`.
+pub fn write_fuzz_seeds(dir: &str) -> Result<(), DantelionFormatsError> {
+    let bnd3_bytes = BND3::from_bnd4(&BND4::from_bytes(BND4_BYTES)?)?.to_bytes()?;
+    let seeds: [(&str, Vec<Vec<u8>>); 6] = [
+        ("bnd4", vec![BND4_BYTES.to_vec(), fixtures::bnd4_bytes(3, 0x40)?]),
+        ("bnd3", vec![bnd3_bytes.clone()]),
+        ("dcx", vec![DFLT_DCX_BYTES.to_vec(), fixtures::edge_dcx_bytes(0x100)?]),
+        ("bhd5", vec![bhd5_bytes()?]),
+        ("tpf", vec![fixtures::tpf_bytes(2, 16)?]),
+        ("open_bytes", vec![BND4_BYTES.to_vec(), bnd3_bytes, fixtures::dflt_bnd4_bytes(2, 0x20)?, fixtures::tpf_bytes(2, 16)?]),
+    ];
+
+    for (target, files) in seeds {
+        let target_dir = Path::new(dir).join(target);
+        fs::create_dir_all(&target_dir)?;
+        for (i, file) in files.iter().enumerate() {
+            fs::write(target_dir.join(format!("seed_{}", i)), file)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,876 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use binary_interpreter::binary_reader::{BinaryPeeker, BinaryReader};
+use byteorder::{BE, LE, ByteOrder, ReadBytesExt, WriteBytesExt};
+use crate::bhd5::GameType;
+use crate::dcx::DCX;
+use crate::error::DantelionFormatsError;
+use crate::source::{DataSource, FileSource};
+use crate::util;
+use crate::util::{DataLen, ensure, Validate};
+
+#[cfg(feature = "texture-decode")]
+pub mod decode;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[repr(u8)]
+pub enum TPFPlatform {
+    PC = 0,
+    Xbox360 = 1,
+    PS3 = 2,
+    PS4 = 4,
+    XboxOne = 5,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[repr(u8)]
+pub enum TexType {
+    Texture = 0,
+    Cubemap = 1,
+    Volume = 2,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct TPF {
+    pub header: TPFHeader,
+    pub textures: Vec<Texture>,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+#[non_exhaustive]
+pub struct TPFHeader {
+    pub magic: String,
+    pub data_size: u32,
+    pub file_count: u32,
+    pub platform: TPFPlatform,
+    pub flag2: u8,
+    pub encoding: u8,
+    pub unk0f: u8,
+}
+
+#[repr(C)]
+#[non_exhaustive]
+pub struct Texture {
+    pub data_offset: u32,
+    pub data_size: u32,
+    pub format: u8,
+    pub tex_type: TexType,
+    pub mipmaps: u8,
+    pub flags1: u8,
+    // Only present on console platforms
+    pub tex_header: Option<TexHeader>,
+    pub name_offset: u32,
+    pub float_struct: Option<FloatStruct>,
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+#[non_exhaustive]
+pub struct TexHeader {
+    pub width: u16,
+    pub height: u16,
+    pub unk1: u32,
+    pub unk2: u32,
+    // PS4 and Xbox One only
+    pub texture_count: u32,
+    pub dxgi_format: u32,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct FloatStruct {
+    pub unk00: i32,
+    pub values: Vec<f32>,
+}
+
+impl TPF {
+    const MAGIC_SIZE: usize = 4;
+    const PLATFORM_OFFSET: u64 = 0xC;
+    const DATA_SIZE_OFFSET: usize = 4;
+    const DATA_ALIGNMENT: usize = 0x10;
+
+    /// An empty PC TPF with UTF-16 names, the layout the PC games use. Fill it with `import_dds`.
+    pub fn new() -> TPF {
+        TPF {
+            header: TPFHeader {
+                magic: "TPF\0".to_string(),
+                data_size: 0,
+                file_count: 0,
+                platform: TPFPlatform::PC,
+                flag2: 3,
+                encoding: 1,
+                unk0f: 0,
+            },
+            textures: vec![],
+        }
+    }
+
+    pub fn from_path(path: &str) -> Result<TPF, DantelionFormatsError> {
+        TPF::from_source(&FileSource::open(path)?)
+    }
+
+    pub fn from_source(source: &(impl DataSource + ?Sized)) -> Result<TPF, DantelionFormatsError> {
+        let file = source.read_all()?;
+
+        TPF::from_bytes(&file)
+    }
+
+    pub fn from_bytes(file: &[u8]) -> Result<TPF, DantelionFormatsError> {
+        let bytes = if DCX::is(file) {
+            let dcx = DCX::from_bytes(file)?;
+            dcx.decompress()?
+        } else {
+            file.to_vec()
+        };
+        let mut c = Cursor::new(&bytes[..]);
+
+        let platform = TPF::get_platform(c.peek_u8(TPF::PLATFORM_OFFSET)?)?;
+        let be = platform == TPFPlatform::Xbox360 || platform == TPFPlatform::PS3;
+        let header = if be { TPF::read_tpf_header::<BE>(&mut c, platform)? } else { TPF::read_tpf_header::<LE>(&mut c, platform)? };
+        let textures = if be { TPF::read_textures::<BE>(&mut c, &header)? } else { TPF::read_textures::<LE>(&mut c, &header)? };
+
+        Ok(TPF {
+            header,
+            textures,
+        })
+    }
+
+    /// Writes every texture to `out_dir` as a standalone DDS named after it, e.g. "c1000_a.dds", and returns the
+    /// paths. See `Texture::to_dds`.
+    pub fn export_dds(&self, out_dir: &Path, deswizzle: bool) -> Result<Vec<PathBuf>, DantelionFormatsError> {
+        self.export(out_dir, "dds", |texture| texture.to_dds(self.header.platform, deswizzle))
+    }
+
+    /// Same as `export_dds`, as PNGs of each texture's top mip.
+    #[cfg(feature = "texture-decode")]
+    pub fn export_png(&self, out_dir: &Path) -> Result<Vec<PathBuf>, DantelionFormatsError> {
+        self.export(out_dir, "png", |texture| texture.to_png(self.header.platform))
+    }
+
+    fn export(&self, out_dir: &Path, extension: &str, convert: impl Fn(&Texture) -> Result<Vec<u8>, DantelionFormatsError>) -> Result<Vec<PathBuf>, DantelionFormatsError> {
+        fs::create_dir_all(out_dir)?;
+        let mut paths = vec![];
+        for (index, texture) in self.textures.iter().enumerate() {
+            // Names are plain, but only the last component is used so one can't point outside `out_dir`.
+            let name = texture.name.rsplit(['/', '\\']).next()
+                .filter(|name| !name.is_empty() && *name != "." && *name != "..")
+                .map(str::to_string)
+                .unwrap_or_else(|| index.to_string());
+            let path = out_dir.join(format!("{}.{}", name, extension));
+            fs::write(&path, convert(texture)?)?;
+            paths.push(path);
+        }
+
+        Ok(paths)
+    }
+
+    /// Wraps `dds` into a texture named `name` with `Texture::from_dds`, replacing the texture of that name if
+    /// there is one. Only PC TPFs store DDS files as they are.
+    pub fn import_dds(&mut self, name: &str, dds: &[u8], game: GameType) -> Result<(), DantelionFormatsError> {
+        if self.header.platform != TPFPlatform::PC {
+            return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::Unsupported, format!("Can't import DDS files into a {:?} TPF", self.header.platform))));
+        }
+        let texture = Texture::from_dds(name, dds, game)?;
+        match self.textures.iter_mut().find(|texture| texture.name == name) {
+            Some(existing) => *existing = texture,
+            None => self.textures.push(texture),
+        }
+        self.header.file_count = self.textures.len() as u32;
+
+        Ok(())
+    }
+
+    pub fn to_path(&self, path: &str) -> Result<(), DantelionFormatsError> {
+        Ok(fs::write(path, self.to_bytes()?)?)
+    }
+
+    /// Serializes the TPF. Counts, sizes and offsets are recalculated from `textures`, and data with `flags1` 2 or 3
+    /// is compressed again as a DFLT DCX. Console textures need their `tex_header`.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        match self.header.platform {
+            TPFPlatform::Xbox360 | TPFPlatform::PS3 => self.write_tpf::<BE>(),
+            _ => self.write_tpf::<LE>(),
+        }
+    }
+
+    fn write_tpf<T: ByteOrder>(&self) -> Result<Vec<u8>, DantelionFormatsError> {
+        let header = &self.header;
+        let platform = header.platform;
+        let mut bytes = vec![];
+        util::write_fixed_str(&mut bytes, &header.magic, TPF::MAGIC_SIZE)?;
+        bytes.write_u32::<T>(0)?; // data_size
+        bytes.write_u32::<T>(self.textures.len() as u32)?;
+        bytes.write_u8(platform as u8)?;
+        bytes.write_u8(header.flag2)?;
+        bytes.write_u8(header.encoding)?;
+        bytes.write_u8(header.unk0f)?;
+
+        let mut data = Vec::with_capacity(self.textures.len());
+        for texture in &self.textures {
+            data.push(if texture.flags1 == 2 || texture.flags1 == 3 {
+                DCX::compress_dflt(&texture.data).to_bytes()?
+            } else {
+                texture.data.clone()
+            });
+        }
+
+        let mut data_offset_positions = Vec::with_capacity(self.textures.len());
+        let mut name_offset_positions = Vec::with_capacity(self.textures.len());
+        for (texture, data) in self.textures.iter().zip(&data) {
+            data_offset_positions.push(bytes.len());
+            bytes.write_u32::<T>(0)?;
+            bytes.write_u32::<T>(data.len() as u32)?;
+            bytes.write_u8(texture.format)?;
+            bytes.write_u8(texture.tex_type as u8)?;
+            bytes.write_u8(texture.mipmaps)?;
+            bytes.write_u8(texture.flags1)?;
+
+            if platform != TPFPlatform::PC {
+                let tex_header = texture.tex_header.as_ref().ok_or_else(|| DantelionFormatsError::IoError(
+                    Error::new(ErrorKind::InvalidData, format!("{} has no texture header, which {:?} TPFs need", texture.name, platform))
+                ))?;
+                bytes.write_u16::<T>(tex_header.width)?;
+                bytes.write_u16::<T>(tex_header.height)?;
+                match platform {
+                    TPFPlatform::Xbox360 => bytes.write_u32::<T>(0)?,
+                    TPFPlatform::PS3 => {
+                        bytes.write_u32::<T>(tex_header.unk1)?;
+                        if header.flag2 != 0 {
+                            bytes.write_u32::<T>(tex_header.unk2)?;
+                        }
+                    }
+                    _ => {
+                        bytes.write_u32::<T>(tex_header.texture_count)?;
+                        bytes.write_u32::<T>(tex_header.unk2)?;
+                    }
+                }
+            }
+
+            name_offset_positions.push(bytes.len());
+            bytes.write_u32::<T>(0)?;
+            bytes.write_u32::<T>(texture.float_struct.is_some() as u32)?;
+            if platform == TPFPlatform::PS4 || platform == TPFPlatform::XboxOne {
+                bytes.write_u32::<T>(texture.tex_header.as_ref().map_or(0, |tex_header| tex_header.dxgi_format))?;
+            }
+            if let Some(float_struct) = &texture.float_struct {
+                bytes.write_i32::<T>(float_struct.unk00)?;
+                bytes.write_i32::<T>(float_struct.values.len() as i32 * 4)?;
+                for &value in &float_struct.values {
+                    bytes.write_f32::<T>(value)?;
+                }
+            }
+        }
+
+        for (texture, position) in self.textures.iter().zip(name_offset_positions) {
+            let offset = bytes.len() as u32;
+            T::write_u32(&mut bytes[position..position + 4], offset);
+            if header.encoding == 1 {
+                for c in texture.name.encode_utf16() {
+                    bytes.write_u16::<T>(c)?;
+                }
+                bytes.write_u16::<T>(0)?;
+            } else {
+                bytes.extend_from_slice(texture.name.as_bytes());
+                bytes.write_u8(0)?;
+            }
+        }
+
+        let data_start = bytes.len();
+        for (data, position) in data.iter().zip(data_offset_positions) {
+            if !data.is_empty() {
+                util::pad_to(&mut bytes, TPF::DATA_ALIGNMENT);
+            }
+            let offset = bytes.len() as u32;
+            T::write_u32(&mut bytes[position..position + 4], offset);
+            bytes.extend_from_slice(data);
+        }
+        let data_size = (bytes.len() - data_start) as u32;
+        T::write_u32(&mut bytes[TPF::DATA_SIZE_OFFSET..TPF::DATA_SIZE_OFFSET + 4], data_size);
+
+        Ok(bytes)
+    }
+
+    fn get_platform(raw: u8) -> Result<TPFPlatform, DantelionFormatsError> {
+        match raw {
+            0 => Ok(TPFPlatform::PC),
+            1 => Ok(TPFPlatform::Xbox360),
+            2 => Ok(TPFPlatform::PS3),
+            4 => Ok(TPFPlatform::PS4),
+            5 => Ok(TPFPlatform::XboxOne),
+            _ => Err(DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("Unknown TPF platform {}", raw))))
+        }
+    }
+
+    fn read_tpf_header<T: ByteOrder>(c: &mut Cursor<&[u8]>, platform: TPFPlatform) -> Result<TPFHeader, DantelionFormatsError> {
+        let magic = c.read_fixed_cstr(TPF::MAGIC_SIZE)?;
+        let data_size = c.read_u32::<T>()?;
+        let file_count = c.read_u32::<T>()?;
+        let _platform = c.read_u8()?;
+        let flag2 = c.read_u8()?;
+        let encoding = c.read_u8()?;
+        let unk0f = c.read_u8()?;
+
+        let header = TPFHeader {
+            magic,
+            data_size,
+            file_count,
+            platform,
+            flag2,
+            encoding,
+            unk0f,
+        };
+
+        header.validate()?;
+
+        Ok(header)
+    }
+
+    fn read_textures<T: ByteOrder>(c: &mut Cursor<&[u8]>, header: &TPFHeader) -> Result<Vec<Texture>, DantelionFormatsError> {
+        let mut textures = Vec::with_capacity(util::capacity_for(c, header.file_count as u64));
+        for _ in 0..header.file_count {
+            textures.push(TPF::read_texture::<T>(c, header)?);
+        }
+
+        Ok(textures)
+    }
+
+    fn read_texture<T: ByteOrder>(c: &mut Cursor<&[u8]>, header: &TPFHeader) -> Result<Texture, DantelionFormatsError> {
+        let platform = header.platform;
+        let data_offset = c.read_u32::<T>()?;
+        let data_size = c.read_u32::<T>()?;
+        let format = c.read_u8()?;
+        let tex_type = match c.read_u8()? {
+            1 => TexType::Cubemap,
+            2 => TexType::Volume,
+            _ => TexType::Texture,
+        };
+        let mipmaps = c.read_u8()?;
+        let flags1 = c.read_u8()?;
+
+        let mut tex_header = if platform != TPFPlatform::PC {
+            let mut tex_header = TexHeader {
+                width: c.read_u16::<T>()?,
+                height: c.read_u16::<T>()?,
+                unk1: 0,
+                unk2: 0,
+                texture_count: 1,
+                dxgi_format: 0,
+            };
+            match platform {
+                TPFPlatform::Xbox360 => { c.read_u32::<T>()?; }
+                TPFPlatform::PS3 => {
+                    tex_header.unk1 = c.read_u32::<T>()?;
+                    if header.flag2 != 0 {
+                        tex_header.unk2 = c.read_u32::<T>()?;
+                    }
+                }
+                _ => {
+                    tex_header.texture_count = c.read_u32::<T>()?;
+                    tex_header.unk2 = c.read_u32::<T>()?;
+                }
+            }
+            Some(tex_header)
+        } else {
+            None
+        };
+
+        let name_offset = c.read_u32::<T>()?;
+        let has_float_struct = c.read_u32::<T>()? == 1;
+        if platform == TPFPlatform::PS4 || platform == TPFPlatform::XboxOne {
+            let dxgi_format = c.read_u32::<T>()?;
+            if let Some(tex_header) = tex_header.as_mut() {
+                tex_header.dxgi_format = dxgi_format;
+            }
+        }
+        let float_struct = if has_float_struct { Some(TPF::read_float_struct::<T>(c)?) } else { None };
+
+        let start = c.position();
+        c.set_position(data_offset as u64);
+        let mut data = util::read_len(c, data_size as u64)?;
+        c.set_position(start);
+        if flags1 == 2 || flags1 == 3 {
+            data = DCX::decompress_bytes(&data)?;
+        }
+
+        let name = if header.encoding == 1 {
+            c.peek_wcstr(name_offset as u64)?
+        } else {
+            c.peek_cstr(name_offset as u64)?
+        };
+
+        Ok(Texture {
+            data_offset,
+            data_size,
+            format,
+            tex_type,
+            mipmaps,
+            flags1,
+            tex_header,
+            name_offset,
+            float_struct,
+            name,
+            data,
+        })
+    }
+
+    fn read_float_struct<T: ByteOrder>(c: &mut Cursor<&[u8]>) -> Result<FloatStruct, DantelionFormatsError> {
+        let unk00 = c.read_i32::<T>()?;
+        let length = c.read_i32::<T>()?;
+        if length < 0 || length % 4 != 0 {
+            return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("Invalid float struct length {}", length))));
+        }
+        let mut values = Vec::with_capacity(util::capacity_for(c, length as u64 / 4));
+        for _ in 0..length / 4 {
+            values.push(c.read_f32::<T>()?);
+        }
+
+        Ok(FloatStruct {
+            unk00,
+            values,
+        })
+    }
+}
+
+impl Texture {
+    /// Wraps a DDS file into a PC texture for `game`, taking the format byte, mipmap count and type from its header.
+    /// Fails if `game` can't load the format: DX9 games (Dark Souls and Dark Souls II before SotFS) have no BC4-BC7
+    /// and can't read a DX10 header, and Demon's Souls only has PS3 TPFs. Offsets are filled in by `TPF::to_bytes`.
+    pub fn from_dds(name: &str, dds: &[u8], game: GameType) -> Result<Texture, DantelionFormatsError> {
+        let unsupported = |message: String| DantelionFormatsError::IoError(Error::new(ErrorKind::Unsupported, message));
+        let invalid = |message: String| DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, message));
+
+        let info = dds::parse_header(dds).ok_or_else(|| invalid(format!("{} is not a DDS in a format TPFs can hold", name)))?;
+        let format = dds::tpf_format_from_dxgi_format(info.dxgi_format)
+            .ok_or_else(|| unsupported(format!("{} is DXGI format {}, which TPFs can't hold", name, info.dxgi_format)))?;
+        let dx9 = match game {
+            GameType::DemonSouls => return Err(unsupported("Demon's Souls only has PS3 TPFs".to_string())),
+            GameType::DarkSouls | GameType::DarkSoulsII => true,
+            _ => false,
+        };
+        if dx9 && format >= 100 {
+            return Err(unsupported(format!("{} is DXGI format {}, which {:?} can't load", name, info.dxgi_format, game)));
+        }
+        if dx9 && info.data_offset != dds::LEGACY_DATA_OFFSET {
+            return Err(unsupported(format!("{} has a DX10 header, which {:?} can't load", name, game)));
+        }
+        if info.volume {
+            return Err(unsupported(format!("{} is a volume texture", name)));
+        }
+
+        // A full chain ends at 1x1, so more mips than that is a broken header.
+        let max_mipmaps = 32 - info.width.max(info.height).leading_zeros();
+        if info.width == 0 || info.height == 0 || info.mipmaps > max_mipmaps {
+            return Err(invalid(format!("{} is {}x{} with {} mipmaps", name, info.width, info.height, info.mipmaps)));
+        }
+        let faces = if info.cubemap { 6 } else { 1 };
+        let expected = dds::data_len(info.width, info.height, info.mipmaps, faces, info.dxgi_format);
+        if dds.len() - info.data_offset < expected {
+            return Err(invalid(format!("{} has {} bytes of data, expected {}", name, dds.len() - info.data_offset, expected)));
+        }
+
+        Ok(Texture {
+            data_offset: 0,
+            data_size: dds.len() as u32,
+            format,
+            tex_type: if info.cubemap { TexType::Cubemap } else { TexType::Texture },
+            mipmaps: info.mipmaps as u8,
+            flags1: 0,
+            tex_header: None,
+            name_offset: 0,
+            float_struct: None,
+            name: name.to_string(),
+            data: dds.to_vec(),
+        })
+    }
+
+    /// Returns the texture as a standalone DDS file. PC textures already carry their DDS header, console
+    /// textures have one rebuilt from the TPF metadata. PS4 data is optionally deswizzled.
+    pub fn to_dds(&self, platform: TPFPlatform, deswizzle: bool) -> Result<Vec<u8>, DantelionFormatsError> {
+        let tex_header = match &self.tex_header {
+            None => return Ok(self.data.clone()),
+            Some(tex_header) => tex_header,
+        };
+
+        let dxgi_format = if tex_header.dxgi_format != 0 {
+            tex_header.dxgi_format
+        } else {
+            dds::dxgi_format_from_tpf_format(self.format).ok_or_else(|| DantelionFormatsError::IoError(
+                Error::new(ErrorKind::InvalidData, format!("Unknown TPF texture format {}", self.format))
+            ))?
+        };
+
+        let face_count = if self.tex_type == TexType::Cubemap { 6 } else { tex_header.texture_count.max(1) };
+        let width = tex_header.width as u32;
+        let height = tex_header.height as u32;
+        let mipmaps = (self.mipmaps as u32).max(1);
+
+        let mut out = dds::build_header(width, height, mipmaps, dxgi_format, self.tex_type == TexType::Cubemap);
+        if platform == TPFPlatform::PS4 && deswizzle {
+            out.extend(dds::deswizzle_ps4(&self.data, width, height, mipmaps, face_count, dxgi_format));
+        } else {
+            out.extend_from_slice(&self.data);
+        }
+
+        Ok(out)
+    }
+
+    /// The top mip decoded to RGBA, from `to_dds` with PS4 data deswizzled.
+    #[cfg(feature = "texture-decode")]
+    pub fn to_rgba(&self, platform: TPFPlatform) -> Result<decode::RgbaImage, DantelionFormatsError> {
+        decode::decode_dds(&self.to_dds(platform, true)?)
+    }
+
+    /// The top mip as a PNG.
+    #[cfg(feature = "texture-decode")]
+    pub fn to_png(&self, platform: TPFPlatform) -> Result<Vec<u8>, DantelionFormatsError> {
+        Ok(self.to_rgba(platform)?.to_png())
+    }
+}
+
+/// Helpers for rebuilding the DDS headers console TPFs leave out.
+pub mod dds {
+    use super::*;
+
+    pub const DXGI_FORMAT_R16G16B16A16_FLOAT: u32 = 10;
+    pub const DXGI_FORMAT_R8G8B8A8_UNORM: u32 = 28;
+    pub const DXGI_FORMAT_A8_UNORM: u32 = 65;
+    pub const DXGI_FORMAT_BC1_UNORM: u32 = 71;
+    pub const DXGI_FORMAT_BC1_UNORM_SRGB: u32 = 72;
+    pub const DXGI_FORMAT_BC2_UNORM: u32 = 74;
+    pub const DXGI_FORMAT_BC2_UNORM_SRGB: u32 = 75;
+    pub const DXGI_FORMAT_BC3_UNORM: u32 = 77;
+    pub const DXGI_FORMAT_BC3_UNORM_SRGB: u32 = 78;
+    pub const DXGI_FORMAT_BC4_UNORM: u32 = 80;
+    pub const DXGI_FORMAT_BC5_UNORM: u32 = 83;
+    pub const DXGI_FORMAT_B5G5R5A1_UNORM: u32 = 86;
+    pub const DXGI_FORMAT_B8G8R8A8_UNORM: u32 = 87;
+    pub const DXGI_FORMAT_B8G8R8X8_UNORM: u32 = 88;
+    pub const DXGI_FORMAT_BC6H_UF16: u32 = 95;
+    pub const DXGI_FORMAT_BC7_UNORM: u32 = 98;
+    pub const DXGI_FORMAT_BC7_UNORM_SRGB: u32 = 99;
+
+    const DDS_HEADER_SIZE: u32 = 124;
+    const DDS_PIXELFORMAT_SIZE: u32 = 32;
+    const DDSD_CAPS: u32 = 0x1;
+    const DDSD_HEIGHT: u32 = 0x2;
+    const DDSD_WIDTH: u32 = 0x4;
+    const DDSD_PIXELFORMAT: u32 = 0x1000;
+    const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+    const DDSD_LINEARSIZE: u32 = 0x80000;
+    const DDPF_ALPHAPIXELS: u32 = 0x1;
+    const DDPF_ALPHA: u32 = 0x2;
+    const DDPF_FOURCC: u32 = 0x4;
+    // D3DFMT_A16B16G16R16F, which old writers store in the FourCC
+    const D3DFMT_A16B16G16R16F: &[u8] = &[113, 0, 0, 0];
+    const DDSCAPS_COMPLEX: u32 = 0x8;
+    const DDSCAPS_TEXTURE: u32 = 0x1000;
+    const DDSCAPS_MIPMAP: u32 = 0x400000;
+    const DDSCAPS2_CUBEMAP: u32 = 0x200;
+    const DDSCAPS2_CUBEMAP_ALLFACES: u32 = 0xFE00;
+    const DDSCAPS2_VOLUME: u32 = 0x200000;
+    const D3D10_RESOURCE_DIMENSION_TEXTURE2D: u32 = 3;
+    const D3D10_RESOURCE_DIMENSION_TEXTURE3D: u32 = 4;
+    const D3D10_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+    /// Where the data starts in a DDS without a DX10 header, which is the only kind DX9 can load.
+    pub const LEGACY_DATA_OFFSET: usize = 4 + DDS_HEADER_SIZE as usize;
+
+    /// Maps the TPF format byte to a DXGI format, for platforms that don't store one.
+    pub fn dxgi_format_from_tpf_format(format: u8) -> Option<u32> {
+        match format {
+            0 | 1 | 24 | 25 | 108 | 109 => Some(DXGI_FORMAT_BC1_UNORM),
+            3 => Some(DXGI_FORMAT_BC2_UNORM),
+            5 | 23 | 33 | 110 => Some(DXGI_FORMAT_BC3_UNORM),
+            6 => Some(DXGI_FORMAT_B5G5R5A1_UNORM),
+            9 | 105 => Some(DXGI_FORMAT_B8G8R8A8_UNORM),
+            10 => Some(DXGI_FORMAT_B8G8R8X8_UNORM),
+            16 => Some(DXGI_FORMAT_A8_UNORM),
+            22 => Some(DXGI_FORMAT_R16G16B16A16_FLOAT),
+            100 | 113 => Some(DXGI_FORMAT_BC6H_UF16),
+            102 | 106 | 107 => Some(DXGI_FORMAT_BC7_UNORM),
+            103 => Some(DXGI_FORMAT_BC4_UNORM),
+            104 => Some(DXGI_FORMAT_BC5_UNORM),
+            112 => Some(DXGI_FORMAT_BC7_UNORM_SRGB),
+            _ => None,
+        }
+    }
+
+    /// The format byte a TPF stores for a DXGI format, the inverse of `dxgi_format_from_tpf_format`. Formats
+    /// several bytes map to get the one the games use most. The byte has no sRGB flag for BC1-BC3.
+    pub fn tpf_format_from_dxgi_format(dxgi_format: u32) -> Option<u8> {
+        match dxgi_format {
+            DXGI_FORMAT_BC1_UNORM | DXGI_FORMAT_BC1_UNORM_SRGB => Some(0),
+            DXGI_FORMAT_BC2_UNORM | DXGI_FORMAT_BC2_UNORM_SRGB => Some(3),
+            DXGI_FORMAT_BC3_UNORM | DXGI_FORMAT_BC3_UNORM_SRGB => Some(5),
+            DXGI_FORMAT_B5G5R5A1_UNORM => Some(6),
+            DXGI_FORMAT_B8G8R8A8_UNORM => Some(9),
+            DXGI_FORMAT_B8G8R8X8_UNORM => Some(10),
+            DXGI_FORMAT_A8_UNORM => Some(16),
+            DXGI_FORMAT_R16G16B16A16_FLOAT => Some(22),
+            DXGI_FORMAT_BC6H_UF16 => Some(100),
+            DXGI_FORMAT_BC7_UNORM => Some(102),
+            DXGI_FORMAT_BC4_UNORM => Some(103),
+            DXGI_FORMAT_BC5_UNORM => Some(104),
+            DXGI_FORMAT_BC7_UNORM_SRGB => Some(112),
+            _ => None,
+        }
+    }
+
+    /// Returns (block dimension in pixels, bytes per block) for a DXGI format.
+    pub fn block_info(dxgi_format: u32) -> (u32, u32) {
+        match dxgi_format {
+            DXGI_FORMAT_BC1_UNORM | DXGI_FORMAT_BC1_UNORM_SRGB | DXGI_FORMAT_BC4_UNORM => (4, 8),
+            DXGI_FORMAT_BC2_UNORM | DXGI_FORMAT_BC2_UNORM_SRGB | DXGI_FORMAT_BC3_UNORM | DXGI_FORMAT_BC3_UNORM_SRGB
+            | DXGI_FORMAT_BC5_UNORM | DXGI_FORMAT_BC6H_UF16 | DXGI_FORMAT_BC7_UNORM | DXGI_FORMAT_BC7_UNORM_SRGB => (4, 16),
+            DXGI_FORMAT_R16G16B16A16_FLOAT => (1, 8),
+            DXGI_FORMAT_A8_UNORM => (1, 1),
+            DXGI_FORMAT_B5G5R5A1_UNORM => (1, 2),
+            _ => (1, 4),
+        }
+    }
+
+    /// The number of bytes `faces` faces of `mipmaps` mips take, starting at `width` x `height`.
+    pub fn data_len(width: u32, height: u32, mipmaps: u32, faces: u32, dxgi_format: u32) -> usize {
+        let (block_dim, block_size) = block_info(dxgi_format);
+        let face: usize = (0..mipmaps).map(|mip| {
+            let width_blocks = (width >> mip).max(1).div_ceil(block_dim);
+            let height_blocks = (height >> mip).max(1).div_ceil(block_dim);
+            width_blocks as usize * height_blocks as usize * block_size as usize
+        }).sum();
+
+        face * faces as usize
+    }
+
+    /// Builds a "DDS " magic, header and DX10 extension header.
+    pub fn build_header(width: u32, height: u32, mipmaps: u32, dxgi_format: u32, cubemap: bool) -> Vec<u8> {
+        let (block_dim, block_size) = block_info(dxgi_format);
+        let linear_size = width.div_ceil(block_dim).max(1) * height.div_ceil(block_dim).max(1) * block_size;
+
+        let mut caps = DDSCAPS_TEXTURE;
+        if mipmaps > 1 {
+            caps |= DDSCAPS_COMPLEX | DDSCAPS_MIPMAP;
+        }
+        if cubemap {
+            caps |= DDSCAPS_COMPLEX;
+        }
+
+        let mut out = Vec::with_capacity(4 + DDS_HEADER_SIZE as usize + 20);
+        out.extend_from_slice(b"DDS ");
+        // Writes into a Vec can't fail.
+        out.write_u32::<LE>(DDS_HEADER_SIZE).unwrap();
+        out.write_u32::<LE>(DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT | DDSD_MIPMAPCOUNT | DDSD_LINEARSIZE).unwrap();
+        out.write_u32::<LE>(height).unwrap();
+        out.write_u32::<LE>(width).unwrap();
+        out.write_u32::<LE>(linear_size).unwrap();
+        out.write_u32::<LE>(0).unwrap(); // depth
+        out.write_u32::<LE>(mipmaps).unwrap();
+        out.extend_from_slice(&[0; 11 * 4]);
+        out.write_u32::<LE>(DDS_PIXELFORMAT_SIZE).unwrap();
+        out.write_u32::<LE>(DDPF_FOURCC).unwrap();
+        out.extend_from_slice(b"DX10");
+        out.extend_from_slice(&[0; 5 * 4]);
+        out.write_u32::<LE>(caps).unwrap();
+        out.write_u32::<LE>(if cubemap { DDSCAPS2_CUBEMAP_ALLFACES } else { 0 }).unwrap();
+        out.extend_from_slice(&[0; 3 * 4]);
+
+        out.write_u32::<LE>(dxgi_format).unwrap();
+        out.write_u32::<LE>(D3D10_RESOURCE_DIMENSION_TEXTURE2D).unwrap();
+        out.write_u32::<LE>(if cubemap { D3D10_RESOURCE_MISC_TEXTURECUBE } else { 0 }).unwrap();
+        out.write_u32::<LE>(1).unwrap(); // array size
+        out.write_u32::<LE>(0).unwrap();
+
+        out
+    }
+
+    /// The size and format `parse_header` reads from a DDS file. The texture data starts at `data_offset`.
+    #[derive(PartialEq, Eq, Clone, Copy, Debug)]
+    pub struct DdsInfo {
+        pub width: u32,
+        pub height: u32,
+        pub mipmaps: u32,
+        pub dxgi_format: u32,
+        pub data_offset: usize,
+        pub cubemap: bool,
+        pub volume: bool,
+    }
+
+    /// Reads the size and format of a DDS file, from its DX10 header or its legacy pixel format. None if it isn't
+    /// one, or its legacy format isn't one of the DXGI formats above.
+    pub fn parse_header(dds: &[u8]) -> Option<DdsInfo> {
+        if dds.len() < 4 + DDS_HEADER_SIZE as usize || &dds[..4] != b"DDS " {
+            return None;
+        }
+        let u32_at = |offset: usize| LE::read_u32(&dds[offset..]);
+        let flags = u32_at(0x50);
+        let legacy_offset = LEGACY_DATA_OFFSET;
+        let caps2 = u32_at(0x70);
+        let mut cubemap = caps2 & DDSCAPS2_CUBEMAP != 0;
+        let mut volume = caps2 & DDSCAPS2_VOLUME != 0;
+        let (dxgi_format, data_offset) = if flags & DDPF_FOURCC != 0 {
+            match &dds[0x54..0x58] {
+                b"DX10" => {
+                    let dx10 = dds.get(0x80..0x94)?;
+                    volume |= LE::read_u32(&dx10[4..]) == D3D10_RESOURCE_DIMENSION_TEXTURE3D;
+                    cubemap |= LE::read_u32(&dx10[8..]) & D3D10_RESOURCE_MISC_TEXTURECUBE != 0;
+                    (LE::read_u32(dx10), legacy_offset + 20)
+                }
+                b"DXT1" => (DXGI_FORMAT_BC1_UNORM, legacy_offset),
+                b"DXT2" | b"DXT3" => (DXGI_FORMAT_BC2_UNORM, legacy_offset),
+                b"DXT4" | b"DXT5" => (DXGI_FORMAT_BC3_UNORM, legacy_offset),
+                b"ATI1" | b"BC4U" => (DXGI_FORMAT_BC4_UNORM, legacy_offset),
+                b"ATI2" | b"BC5U" => (DXGI_FORMAT_BC5_UNORM, legacy_offset),
+                D3DFMT_A16B16G16R16F => (DXGI_FORMAT_R16G16B16A16_FLOAT, legacy_offset),
+                _ => return None,
+            }
+        } else {
+            let (bit_count, red_mask, alpha_mask) = (u32_at(0x58), u32_at(0x5C), u32_at(0x68));
+            let dxgi_format = match (bit_count, red_mask) {
+                (32, 0xFF0000) if flags & DDPF_ALPHAPIXELS != 0 && alpha_mask != 0 => DXGI_FORMAT_B8G8R8A8_UNORM,
+                (32, 0xFF0000) => DXGI_FORMAT_B8G8R8X8_UNORM,
+                (32, 0xFF) => DXGI_FORMAT_R8G8B8A8_UNORM,
+                (16, 0x7C00) => DXGI_FORMAT_B5G5R5A1_UNORM,
+                (8, 0) if flags & DDPF_ALPHA != 0 => DXGI_FORMAT_A8_UNORM,
+                _ => return None,
+            };
+            (dxgi_format, legacy_offset)
+        };
+
+        Some(DdsInfo {
+            width: u32_at(0x10),
+            height: u32_at(0xC),
+            mipmaps: u32_at(0x1C).max(1),
+            dxgi_format,
+            data_offset,
+            cubemap,
+            volume,
+        })
+    }
+
+    /// Undoes the PS4 tiling, which stores blocks in 8x8 tiles with morton ordering inside each tile. Every
+    /// face and mip level is tiled separately and padded out to whole tiles.
+    pub fn deswizzle_ps4(data: &[u8], width: u32, height: u32, mipmaps: u32, faces: u32, dxgi_format: u32) -> Vec<u8> {
+        let (block_dim, block_size) = block_info(dxgi_format);
+        let block_size = block_size as usize;
+        let mut out = Vec::with_capacity(data.len());
+        let mut src = 0;
+
+        for _ in 0..faces {
+            for mip in 0..mipmaps {
+                let mip_width = (width >> mip).max(1);
+                let mip_height = (height >> mip).max(1);
+                let width_blocks = mip_width.div_ceil(block_dim) as usize;
+                let height_blocks = mip_height.div_ceil(block_dim) as usize;
+                let tiles_x = width_blocks.div_ceil(8);
+                let tiles_y = height_blocks.div_ceil(8);
+
+                let mut level = vec![0; width_blocks * height_blocks * block_size];
+                for tile_y in 0..tiles_y {
+                    for tile_x in 0..tiles_x {
+                        for t in 0..64 {
+                            if src + block_size > data.len() {
+                                break;
+                            }
+                            let index = morton(t, 8, 8);
+                            let x = tile_x * 8 + index % 8;
+                            let y = tile_y * 8 + index / 8;
+                            if x < width_blocks && y < height_blocks {
+                                let dst = (y * width_blocks + x) * block_size;
+                                level[dst..dst + block_size].copy_from_slice(&data[src..src + block_size]);
+                            }
+                            src += block_size;
+                        }
+                    }
+                }
+                out.extend(level);
+            }
+        }
+
+        out
+    }
+
+    fn morton(mut t: usize, mut sx: usize, mut sy: usize) -> usize {
+        let mut x_bit = 1;
+        let mut y_bit = 1;
+        let mut x = 0;
+        let mut y = 0;
+        let width = sx;
+        while sx > 1 || sy > 1 {
+            if sx > 1 {
+                x += x_bit * (t & 1);
+                t >>= 1;
+                x_bit *= 2;
+                sx >>= 1;
+            }
+            if sy > 1 {
+                y += y_bit * (t & 1);
+                t >>= 1;
+                y_bit *= 2;
+                sy >>= 1;
+            }
+        }
+
+        y * width + x
+    }
+}
+
+impl Default for TPF {
+    fn default() -> Self {
+        TPF::new()
+    }
+}
+
+/// A one line summary, e.g. "TPF PC — 12 textures".
+impl Display for TPF {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "TPF {:?} — {} textures", self.header.platform, self.textures.len())
+    }
+}
+
+impl Debug for Texture {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Texture")
+            .field("data_offset", &self.data_offset)
+            .field("data_size", &self.data_size)
+            .field("format", &self.format)
+            .field("tex_type", &self.tex_type)
+            .field("mipmaps", &self.mipmaps)
+            .field("flags1", &self.flags1)
+            .field("tex_header", &self.tex_header)
+            .field("name_offset", &self.name_offset)
+            .field("float_struct", &self.float_struct)
+            .field("name", &self.name)
+            .field("data", &DataLen(self.data.len()))
+            .finish()
+    }
+}
+
+impl TPFHeader {
+    // Stable accessors, see `BND4Header`.
+
+    pub fn platform(&self) -> TPFPlatform {
+        self.platform
+    }
+
+    pub fn file_count(&self) -> u32 {
+        self.file_count
+    }
+
+    pub fn encoding(&self) -> u8 {
+        self.encoding
+    }
+}
+
+impl Validate for TPFHeader {
+    fn validate(&self) -> Result<(), DantelionFormatsError> {
+        ensure!(self.magic == "TPF\0", "Magic was {}", self.magic);
+        ensure!(self.encoding <= 2, "encoding was {}", self.encoding);
+        ensure!(self.unk0f == 0, "unk0f was {}", self.unk0f);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,629 @@
+//! Decodes DDS textures to RGBA, and writes that as PNG, so texture browsers don't need a second image library for
+//! what TPFs hold. Covers BC1-BC7 and the uncompressed formats in `dds`. Only the top mip of the first face is decoded.
+
+use std::io::{Error, ErrorKind};
+use byteorder::{BE, ByteOrder, LE, WriteBytesExt};
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use crate::error::DantelionFormatsError;
+use super::dds::{self, *};
+
+/// 8 bit RGBA pixels, rows top to bottom.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// A PNG of the image, compressed with the same deflate the DCX code uses.
+    pub fn to_png(&self) -> Vec<u8> {
+        let row_len = self.width as usize * 4;
+        // Each row is prefixed with filter type 0, no filtering.
+        let mut rows = Vec::with_capacity((row_len + 1) * self.height as usize);
+        for row in self.pixels.chunks_exact(row_len.max(1)).take(self.height as usize) {
+            rows.push(0);
+            rows.extend_from_slice(row);
+        }
+
+        let mut ihdr = vec![];
+        // Writes into a Vec can't fail.
+        ihdr.write_u32::<BE>(self.width).unwrap();
+        ihdr.write_u32::<BE>(self.height).unwrap();
+        // 8 bits per channel, RGBA, deflate, no filtering, not interlaced
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        write_chunk(&mut png, b"IHDR", &ihdr);
+        write_chunk(&mut png, b"IDAT", &compress_to_vec_zlib(&rows, 6));
+        write_chunk(&mut png, b"IEND", &[]);
+
+        png
+    }
+}
+
+/// Decodes the top mip of a DDS file, such as `Texture::to_dds` returns.
+pub fn decode_dds(dds: &[u8]) -> Result<RgbaImage, DantelionFormatsError> {
+    let info = dds::parse_header(dds).ok_or_else(|| invalid("Not a DDS file, or one with an unknown format".to_string()))?;
+    decode(&dds[info.data_offset..], info.width, info.height, info.dxgi_format)
+}
+
+/// Decodes `width` by `height` pixels of `dxgi_format` data, e.g. a texture's top mip.
+pub fn decode(data: &[u8], width: u32, height: u32, dxgi_format: u32) -> Result<RgbaImage, DantelionFormatsError> {
+    let decode_block: fn(&[u8], &mut [[u8; 4]]) = match dxgi_format {
+        DXGI_FORMAT_BC1_UNORM | DXGI_FORMAT_BC1_UNORM_SRGB => bc1,
+        DXGI_FORMAT_BC2_UNORM | DXGI_FORMAT_BC2_UNORM_SRGB => bc2,
+        DXGI_FORMAT_BC3_UNORM | DXGI_FORMAT_BC3_UNORM_SRGB => bc3,
+        DXGI_FORMAT_BC4_UNORM => bc4,
+        DXGI_FORMAT_BC5_UNORM => bc5,
+        DXGI_FORMAT_BC6H_UF16 => bc6h,
+        DXGI_FORMAT_BC7_UNORM | DXGI_FORMAT_BC7_UNORM_SRGB => bc7,
+        DXGI_FORMAT_R8G8B8A8_UNORM => |p, out| out[0] = [p[0], p[1], p[2], p[3]],
+        DXGI_FORMAT_B8G8R8A8_UNORM => |p, out| out[0] = [p[2], p[1], p[0], p[3]],
+        DXGI_FORMAT_B8G8R8X8_UNORM => |p, out| out[0] = [p[2], p[1], p[0], 255],
+        DXGI_FORMAT_A8_UNORM => |p, out| out[0] = [0, 0, 0, p[0]],
+        DXGI_FORMAT_B5G5R5A1_UNORM => b5g5r5a1,
+        DXGI_FORMAT_R16G16B16A16_FLOAT => r16g16b16a16_float,
+        _ => return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::Unsupported, format!("Can't decode DXGI format {}", dxgi_format)))),
+    };
+
+    let (block_dim, block_size) = block_info(dxgi_format);
+    let (block_dim, block_size) = (block_dim as usize, block_size as usize);
+    let (width, height) = (width as usize, height as usize);
+    let blocks_x = width.div_ceil(block_dim);
+    let blocks_y = height.div_ceil(block_dim);
+    let len = blocks_x.checked_mul(blocks_y).and_then(|blocks| blocks.checked_mul(block_size));
+    if len.is_none_or(|len| len > data.len()) {
+        return Err(invalid(format!("{}x{} texture needs more than the {} bytes there are", width, height, data.len())));
+    }
+
+    let mut pixels = vec![0; width * height * 4];
+    let mut block = [[0; 4]; 16];
+    let block = &mut block[..block_dim * block_dim];
+    for (index, bytes) in data.chunks_exact(block_size).take(blocks_x * blocks_y).enumerate() {
+        decode_block(bytes, block);
+        let (block_x, block_y) = (index % blocks_x * block_dim, index / blocks_x * block_dim);
+        for (i, pixel) in block.iter().enumerate() {
+            let (x, y) = (block_x + i % block_dim, block_y + i / block_dim);
+            if x < width && y < height {
+                let offset = (y * width + x) * 4;
+                pixels[offset..offset + 4].copy_from_slice(pixel);
+            }
+        }
+    }
+
+    Ok(RgbaImage {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    })
+}
+
+fn invalid(message: String) -> DantelionFormatsError {
+    DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, message))
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.write_u32::<BE>(data.len() as u32).unwrap();
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.write_u32::<BE>(crc).unwrap();
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+fn b5g5r5a1(p: &[u8], out: &mut [[u8; 4]]) {
+    let value = LE::read_u16(p);
+    let channel = |shift: u16| {
+        let c = (value >> shift & 0x1F) as u8;
+        c << 3 | c >> 2
+    };
+    out[0] = [channel(10), channel(5), channel(0), if value & 0x8000 != 0 { 255 } else { 0 }];
+}
+
+fn r16g16b16a16_float(p: &[u8], out: &mut [[u8; 4]]) {
+    let channel = |i: usize| unit_to_u8(half_to_f32(LE::read_u16(&p[i * 2..])));
+    out[0] = [channel(0), channel(1), channel(2), channel(3)];
+}
+
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (half >> 10 & 0x1F) as i32;
+    let mantissa = (half & 0x3FF) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1F if mantissa == 0.0 => f32::INFINITY,
+        0x1F => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+// HDR values are clipped to 0-1. NaN ends up 0.
+fn unit_to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+}
+
+fn bc1(block: &[u8], out: &mut [[u8; 4]]) {
+    color_block(block, out, true);
+}
+
+fn bc2(block: &[u8], out: &mut [[u8; 4]]) {
+    color_block(&block[8..], out, false);
+    let alpha = LE::read_u64(block);
+    for (i, pixel) in out.iter_mut().enumerate() {
+        pixel[3] = (alpha >> (i * 4) & 0xF) as u8 * 17;
+    }
+}
+
+fn bc3(block: &[u8], out: &mut [[u8; 4]]) {
+    color_block(&block[8..], out, false);
+    for (pixel, alpha) in out.iter_mut().zip(channel_block(block)) {
+        pixel[3] = alpha;
+    }
+}
+
+// Single channel, shown as gray.
+fn bc4(block: &[u8], out: &mut [[u8; 4]]) {
+    for (pixel, value) in out.iter_mut().zip(channel_block(block)) {
+        *pixel = [value, value, value, 255];
+    }
+}
+
+// Red and green, usually a normal map's X and Y. Blue is left 0 rather than reconstructed.
+fn bc5(block: &[u8], out: &mut [[u8; 4]]) {
+    let (red, green) = (channel_block(block), channel_block(&block[8..]));
+    for (i, pixel) in out.iter_mut().enumerate() {
+        *pixel = [red[i], green[i], 0, 255];
+    }
+}
+
+// The BC1 color half. BC2 and BC3 always use four colors, BC1 switches to three and transparent black when the
+// endpoints are in order.
+fn color_block(block: &[u8], out: &mut [[u8; 4]], bc1: bool) {
+    let (c0, c1) = (LE::read_u16(block), LE::read_u16(&block[2..]));
+    let (e0, e1) = (rgb565(c0), rgb565(c1));
+    let mix = |w0: u32, w1: u32| {
+        let channel = |i: usize| ((e0[i] as u32 * w0 + e1[i] as u32 * w1) / (w0 + w1)) as u8;
+        [channel(0), channel(1), channel(2), 255]
+    };
+    let palette = if c0 > c1 || !bc1 {
+        [e0, e1, mix(2, 1), mix(1, 2)]
+    } else {
+        [e0, e1, mix(1, 1), [0, 0, 0, 0]]
+    };
+
+    let indices = LE::read_u32(&block[4..]);
+    for (i, pixel) in out.iter_mut().enumerate() {
+        *pixel = palette[(indices >> (i * 2) & 3) as usize];
+    }
+}
+
+fn rgb565(color: u16) -> [u8; 4] {
+    let (r, g, b) = ((color >> 11) as u8, (color >> 5 & 0x3F) as u8, (color & 0x1F) as u8);
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2, 255]
+}
+
+// A BC3 alpha, BC4 or BC5 channel: two endpoints and 3 bit indices.
+fn channel_block(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let mut palette = [a0, a1, 0, 0, 0, 0, 0, 255];
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i as usize + 1] = ((7 - i) * a0 + i * a1) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i as usize + 1] = ((5 - i) * a0 + i * a1) / 5;
+        }
+    }
+
+    let indices = LE::read_u48(&block[2..]);
+    std::array::from_fn(|i| palette[(indices >> (i * 3) & 7) as usize] as u8)
+}
+
+// Reads a BC6H or BC7 block, lowest bit first.
+struct Bits {
+    bits: u128,
+    position: u32,
+}
+
+impl Bits {
+    fn new(block: &[u8]) -> Bits {
+        Bits {
+            bits: LE::read_u128(block),
+            position: 0,
+        }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let value = self.bits.checked_shr(self.position).unwrap_or(0) as u32 & ((1u64 << count) - 1) as u32;
+        self.position += count;
+        value
+    }
+}
+
+const WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn weights(index_bits: u32) -> &'static [u32] {
+    match index_bits {
+        2 => &WEIGHTS_2,
+        3 => &WEIGHTS_3,
+        _ => &WEIGHTS_4,
+    }
+}
+
+fn interpolate(e0: u32, e1: u32, weight: u32) -> u32 {
+    ((64 - weight) * e0 + weight * e1 + 32) >> 6
+}
+
+// Which subset each pixel is in, bit i for pixel i, for the 64 two subset partitions. BC6H uses the first 32.
+const PARTITIONS_2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80, 0xC800, 0xFFEC, 0xFE80, 0xE800, 0xFFE8, 0xFF00, 0xFFF0, 0xF000,
+    0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE, 0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C,
+    0xAAAA, 0xF0F0, 0x5A5A, 0x33CC, 0x3C3C, 0x55AA, 0x9696, 0xA55A, 0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C, 0x9336, 0x9CC6, 0x817E, 0xE718, 0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+// Two bits per pixel for the three subset partitions.
+const PARTITIONS_3: [u32; 64] = [
+    0xAA685050, 0x6A5A5040, 0x5A5A4200, 0x5450A0A8, 0xA5A50000, 0xA0A05050, 0x5555A0A0, 0x5A5A5050,
+    0xAA550000, 0xAA555500, 0xAAAA5500, 0x90909090, 0x94949494, 0xA4A4A4A4, 0xA9A59450, 0x2A0A4250,
+    0xA5945040, 0x0A425054, 0xA5A5A500, 0x55A0A0A0, 0xA8A85454, 0x6A6A4040, 0xA4A45000, 0x1A1A0500,
+    0x0050A4A4, 0xAAA59090, 0x14696914, 0x69691400, 0xA08585A0, 0xAA821414, 0x50A4A450, 0x6A5A0200,
+    0xA9A58000, 0x5090A0A8, 0xA8A09050, 0x24242424, 0x00AA5500, 0x24924924, 0x24499224, 0x50A50A50,
+    0x500AA550, 0xAAAA4444, 0x66660000, 0xA5A0A5A0, 0x50A050A0, 0x69286928, 0x44AAAA44, 0x66666600,
+    0xAA444444, 0x54A854A8, 0x95809580, 0x96969600, 0xA85454A8, 0x80959580, 0xAA141414, 0x96960000,
+    0xAAAA1414, 0xA05050A0, 0xA0A5A5A0, 0x96000000, 0x40804080, 0xA9A8A9A8, 0xAAAAAA44, 0x2A4A5254,
+];
+
+// The pixel whose index is stored a bit short, for the second subset of each two subset partition, and the second
+// and third subsets of each three subset one. The first subset's is always pixel 0.
+const ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8, 2, 2, 8, 8, 2, 2,
+    15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2, 2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+const ANCHORS_3_SECOND: [u8; 64] = [
+    3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5, 15, 15,
+    8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8, 5, 10, 5, 10, 8, 13, 15, 12, 3, 3,
+];
+const ANCHORS_3_THIRD: [u8; 64] = [
+    15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6, 10, 15, 15, 10, 8,
+    15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+];
+
+// The subset of `pixel`, and whether its index is stored a bit short.
+fn subset(subsets: u32, partition: usize, pixel: usize) -> (usize, bool) {
+    match subsets {
+        2 => (
+            (PARTITIONS_2[partition] >> pixel & 1) as usize,
+            pixel == 0 || pixel == ANCHORS_2[partition] as usize,
+        ),
+        3 => (
+            (PARTITIONS_3[partition] >> (pixel * 2) & 3) as usize,
+            pixel == 0 || pixel == ANCHORS_3_SECOND[partition] as usize || pixel == ANCHORS_3_THIRD[partition] as usize,
+        ),
+        _ => (0, pixel == 0),
+    }
+}
+
+struct Bc7Mode {
+    subsets: u32,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_p_bits: bool,
+    shared_p_bits: bool,
+    index_bits: u32,
+    secondary_index_bits: u32,
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_p_bits: true, shared_p_bits: false, index_bits: 3, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: true, index_bits: 3, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_p_bits: true, shared_p_bits: false, index_bits: 2, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, secondary_index_bits: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, secondary_index_bits: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_p_bits: true, shared_p_bits: false, index_bits: 4, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_p_bits: true, shared_p_bits: false, index_bits: 2, secondary_index_bits: 0 },
+];
+
+fn bc7(block: &[u8], out: &mut [[u8; 4]]) {
+    // The mode is the number of zero bits before the first set one. A block without one is reserved, and decodes
+    // to transparent black.
+    if block[0] == 0 {
+        out.fill([0; 4]);
+        return;
+    }
+    let mode_index = block[0].trailing_zeros();
+    let mode = &BC7_MODES[mode_index as usize];
+    let mut bits = Bits::new(block);
+    bits.read(mode_index + 1);
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    // endpoints[subset * 2 + end][channel]
+    let endpoint_count = mode.subsets as usize * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in &mut endpoints[..endpoint_count] {
+            endpoint[channel] = bits.read(mode.color_bits);
+        }
+    }
+    for endpoint in &mut endpoints[..endpoint_count] {
+        endpoint[3] = if mode.alpha_bits > 0 { bits.read(mode.alpha_bits) } else { 255 };
+    }
+
+    let (mut color_bits, mut alpha_bits) = (mode.color_bits, mode.alpha_bits);
+    if mode.endpoint_p_bits || mode.shared_p_bits {
+        let p_bits: Vec<u32> = if mode.endpoint_p_bits {
+            (0..endpoint_count).map(|_| bits.read(1)).collect()
+        } else {
+            (0..mode.subsets).flat_map(|_| {
+                let p_bit = bits.read(1);
+                [p_bit, p_bit]
+            }).collect()
+        };
+        for (endpoint, p_bit) in endpoints.iter_mut().zip(p_bits) {
+            for value in &mut endpoint[..3] {
+                *value = *value << 1 | p_bit;
+            }
+            if mode.alpha_bits > 0 {
+                endpoint[3] = endpoint[3] << 1 | p_bit;
+            }
+        }
+        color_bits += 1;
+        if alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+    for endpoint in &mut endpoints[..endpoint_count] {
+        for value in &mut endpoint[..3] {
+            *value = expand(*value, color_bits);
+        }
+        if alpha_bits > 0 {
+            endpoint[3] = expand(endpoint[3], alpha_bits);
+        }
+    }
+
+    let mut indices = [0u32; 16];
+    for (pixel, index) in indices.iter_mut().enumerate() {
+        let (_, anchor) = subset(mode.subsets, partition, pixel);
+        *index = bits.read(mode.index_bits - anchor as u32);
+    }
+    let mut secondary = [0u32; 16];
+    if mode.secondary_index_bits > 0 {
+        for (pixel, index) in secondary.iter_mut().enumerate() {
+            *index = bits.read(mode.secondary_index_bits - (pixel == 0) as u32);
+        }
+    }
+
+    for (pixel, out) in out.iter_mut().enumerate() {
+        let (subset, _) = subset(mode.subsets, partition, pixel);
+        let (e0, e1) = (endpoints[subset * 2], endpoints[subset * 2 + 1]);
+        // Modes 4 and 5 have a second set of indices for alpha, swapped with the first when the selection bit is set.
+        let (color_weight, alpha_weight) = if mode.secondary_index_bits == 0 {
+            let weight = weights(mode.index_bits)[indices[pixel] as usize];
+            (weight, weight)
+        } else if index_selection == 0 {
+            (weights(mode.index_bits)[indices[pixel] as usize], weights(mode.secondary_index_bits)[secondary[pixel] as usize])
+        } else {
+            (weights(mode.secondary_index_bits)[secondary[pixel] as usize], weights(mode.index_bits)[indices[pixel] as usize])
+        };
+
+        let mut color: [u8; 4] = std::array::from_fn(|channel| {
+            let weight = if channel == 3 { alpha_weight } else { color_weight };
+            interpolate(e0[channel], e1[channel], weight) as u8
+        });
+        if rotation > 0 {
+            color.swap(3, rotation as usize - 1);
+        }
+        *out = color;
+    }
+}
+
+// Widens an n bit value to 8 bits by repeating its top bits.
+fn expand(value: u32, bits: u32) -> u32 {
+    let value = value << (8 - bits);
+    value | value >> bits
+}
+
+// Endpoint fields in the BC6H layouts, endpoint * 3 + channel. Endpoints 0 and 1 are the first subset's.
+const R0: u8 = 0;
+const G0: u8 = 1;
+const B0: u8 = 2;
+const R1: u8 = 3;
+const G1: u8 = 4;
+const B1: u8 = 5;
+const R2: u8 = 6;
+const G2: u8 = 7;
+const B2: u8 = 8;
+const R3: u8 = 9;
+const G3: u8 = 10;
+const B3: u8 = 11;
+
+struct Bc6Mode {
+    value: u32,
+    mode_bits: u32,
+    // Whether the other endpoints are signed deltas from the first
+    transformed: bool,
+    endpoint_bits: u32,
+    delta_bits: [u32; 3],
+    regions: u32,
+    // (field, first bit, last bit), read in order. A bit range going down is stored reversed.
+    layout: &'static [(u8, u32, u32)],
+}
+
+// The 14 BC6H modes and where each scatters its endpoint bits, as in the format's spec.
+const BC6_MODES: [Bc6Mode; 14] = [
+    Bc6Mode {
+        value: 0x00, mode_bits: 2, transformed: true, endpoint_bits: 10, delta_bits: [5, 5, 5], regions: 2,
+        layout: &[(G2, 4, 4), (B2, 4, 4), (B3, 4, 4), (R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 4), (G3, 4, 4),
+            (G2, 0, 3), (G1, 0, 4), (B3, 0, 0), (G3, 0, 3), (B1, 0, 4), (B3, 1, 1), (B2, 0, 3), (R2, 0, 4),
+            (B3, 2, 2), (R3, 0, 4), (B3, 3, 3)],
+    },
+    Bc6Mode {
+        value: 0x01, mode_bits: 2, transformed: true, endpoint_bits: 7, delta_bits: [6, 6, 6], regions: 2,
+        layout: &[(G2, 5, 5), (G3, 4, 4), (G3, 5, 5), (R0, 0, 6), (B3, 0, 0), (B3, 1, 1), (B2, 4, 4), (G0, 0, 6),
+            (B2, 5, 5), (B3, 2, 2), (G2, 4, 4), (B0, 0, 6), (B3, 3, 3), (B3, 5, 5), (B3, 4, 4), (R1, 0, 5),
+            (G2, 0, 3), (G1, 0, 5), (G3, 0, 3), (B1, 0, 5), (B2, 0, 3), (R2, 0, 5), (R3, 0, 5)],
+    },
+    Bc6Mode {
+        value: 0x02, mode_bits: 5, transformed: true, endpoint_bits: 11, delta_bits: [5, 4, 4], regions: 2,
+        layout: &[(R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 4), (R0, 10, 10), (G2, 0, 3), (G1, 0, 3), (G0, 10, 10),
+            (B3, 0, 0), (G3, 0, 3), (B1, 0, 3), (B0, 10, 10), (B3, 1, 1), (B2, 0, 3), (R2, 0, 4), (B3, 2, 2),
+            (R3, 0, 4), (B3, 3, 3)],
+    },
+    Bc6Mode {
+        value: 0x06, mode_bits: 5, transformed: true, endpoint_bits: 11, delta_bits: [4, 5, 4], regions: 2,
+        layout: &[(R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 3), (R0, 10, 10), (G3, 4, 4), (G2, 0, 3), (G1, 0, 4),
+            (G0, 10, 10), (G3, 0, 3), (B1, 0, 3), (B0, 10, 10), (B3, 1, 1), (B2, 0, 3), (R2, 0, 3), (B3, 0, 0),
+            (B3, 2, 2), (R3, 0, 3), (G2, 4, 4), (B3, 3, 3)],
+    },
+    Bc6Mode {
+        value: 0x0A, mode_bits: 5, transformed: true, endpoint_bits: 11, delta_bits: [4, 4, 5], regions: 2,
+        layout: &[(R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 3), (R0, 10, 10), (B2, 4, 4), (G2, 0, 3), (G1, 0, 3),
+            (G0, 10, 10), (B3, 0, 0), (G3, 0, 3), (B1, 0, 4), (B0, 10, 10), (B2, 0, 3), (R2, 0, 3), (B3, 1, 1),
+            (B3, 2, 2), (R3, 0, 3), (B3, 4, 4), (B3, 3, 3)],
+    },
+    Bc6Mode {
+        value: 0x0E, mode_bits: 5, transformed: true, endpoint_bits: 9, delta_bits: [5, 5, 5], regions: 2,
+        layout: &[(R0, 0, 8), (B2, 4, 4), (G0, 0, 8), (G2, 4, 4), (B0, 0, 8), (B3, 4, 4), (R1, 0, 4), (G3, 4, 4),
+            (G2, 0, 3), (G1, 0, 4), (B3, 0, 0), (G3, 0, 3), (B1, 0, 4), (B3, 1, 1), (B2, 0, 3), (R2, 0, 4),
+            (B3, 2, 2), (R3, 0, 4), (B3, 3, 3)],
+    },
+    Bc6Mode {
+        value: 0x12, mode_bits: 5, transformed: true, endpoint_bits: 8, delta_bits: [6, 5, 5], regions: 2,
+        layout: &[(R0, 0, 7), (G3, 4, 4), (B2, 4, 4), (G0, 0, 7), (B3, 2, 2), (G2, 4, 4), (B0, 0, 7), (B3, 3, 3),
+            (B3, 4, 4), (R1, 0, 5), (G2, 0, 3), (G1, 0, 4), (B3, 0, 0), (G3, 0, 3), (B1, 0, 4), (B3, 1, 1),
+            (B2, 0, 3), (R2, 0, 5), (R3, 0, 5)],
+    },
+    Bc6Mode {
+        value: 0x16, mode_bits: 5, transformed: true, endpoint_bits: 8, delta_bits: [5, 6, 5], regions: 2,
+        layout: &[(R0, 0, 7), (B3, 0, 0), (B2, 4, 4), (G0, 0, 7), (G2, 5, 5), (G2, 4, 4), (B0, 0, 7), (G3, 5, 5),
+            (B3, 4, 4), (R1, 0, 4), (G3, 4, 4), (G2, 0, 3), (G1, 0, 5), (G3, 0, 3), (B1, 0, 4), (B3, 1, 1),
+            (B2, 0, 3), (R2, 0, 4), (B3, 2, 2), (R3, 0, 4), (B3, 3, 3)],
+    },
+    Bc6Mode {
+        value: 0x1A, mode_bits: 5, transformed: true, endpoint_bits: 8, delta_bits: [5, 5, 6], regions: 2,
+        layout: &[(R0, 0, 7), (B3, 1, 1), (B2, 4, 4), (G0, 0, 7), (B2, 5, 5), (G2, 4, 4), (B0, 0, 7), (B3, 5, 5),
+            (B3, 4, 4), (R1, 0, 4), (G3, 4, 4), (G2, 0, 3), (G1, 0, 4), (B3, 0, 0), (G3, 0, 3), (B1, 0, 5),
+            (B2, 0, 3), (R2, 0, 4), (B3, 2, 2), (R3, 0, 4), (B3, 3, 3)],
+    },
+    Bc6Mode {
+        value: 0x1E, mode_bits: 5, transformed: false, endpoint_bits: 6, delta_bits: [6, 6, 6], regions: 2,
+        layout: &[(R0, 0, 5), (G3, 4, 4), (B3, 0, 0), (B3, 1, 1), (B2, 4, 4), (G0, 0, 5), (G2, 5, 5), (B2, 5, 5),
+            (B3, 2, 2), (G2, 4, 4), (B0, 0, 5), (G3, 5, 5), (B3, 3, 3), (B3, 5, 5), (B3, 4, 4), (R1, 0, 5),
+            (G2, 0, 3), (G1, 0, 5), (G3, 0, 3), (B1, 0, 5), (B2, 0, 3), (R2, 0, 5), (R3, 0, 5)],
+    },
+    Bc6Mode {
+        value: 0x03, mode_bits: 5, transformed: false, endpoint_bits: 10, delta_bits: [10, 10, 10], regions: 1,
+        layout: &[(R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 9), (G1, 0, 9), (B1, 0, 9)],
+    },
+    Bc6Mode {
+        value: 0x07, mode_bits: 5, transformed: true, endpoint_bits: 11, delta_bits: [9, 9, 9], regions: 1,
+        layout: &[(R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 8), (R0, 10, 10), (G1, 0, 8), (G0, 10, 10), (B1, 0, 8),
+            (B0, 10, 10)],
+    },
+    Bc6Mode {
+        value: 0x0B, mode_bits: 5, transformed: true, endpoint_bits: 12, delta_bits: [8, 8, 8], regions: 1,
+        layout: &[(R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 7), (R0, 11, 10), (G1, 0, 7), (G0, 11, 10), (B1, 0, 7),
+            (B0, 11, 10)],
+    },
+    Bc6Mode {
+        value: 0x0F, mode_bits: 5, transformed: true, endpoint_bits: 16, delta_bits: [4, 4, 4], regions: 1,
+        layout: &[(R0, 0, 9), (G0, 0, 9), (B0, 0, 9), (R1, 0, 3), (R0, 15, 10), (G1, 0, 3), (G0, 15, 10), (B1, 0, 3),
+            (B0, 15, 10)],
+    },
+];
+
+// Unsigned only, which is all `dxgi_format_from_tpf_format` maps to.
+fn bc6h(block: &[u8], out: &mut [[u8; 4]]) {
+    let mut bits = Bits::new(block);
+    let two_bit_mode = bits.bits as u32 & 3;
+    let mode = if two_bit_mode < 2 {
+        BC6_MODES.iter().find(|mode| mode.mode_bits == 2 && mode.value == two_bit_mode)
+    } else {
+        BC6_MODES.iter().find(|mode| mode.mode_bits == 5 && mode.value == bits.bits as u32 & 0x1F)
+    };
+    // The 4 reserved modes decode to black.
+    let Some(mode) = mode else {
+        out.fill([0, 0, 0, 255]);
+        return;
+    };
+    bits.read(mode.mode_bits);
+
+    let mut fields = [0u32; 12];
+    for &(field, first, last) in mode.layout {
+        if first <= last {
+            for bit in first..=last {
+                fields[field as usize] |= bits.read(1) << bit;
+            }
+        } else {
+            for bit in (last..=first).rev() {
+                fields[field as usize] |= bits.read(1) << bit;
+            }
+        }
+    }
+    let partition = if mode.regions == 2 { bits.read(5) as usize } else { 0 };
+
+    let endpoint_count = mode.regions as usize * 2;
+    let mask = (1u32 << mode.endpoint_bits) - 1;
+    let mut endpoints = [[0u32; 3]; 4];
+    for (endpoint, values) in endpoints.iter_mut().enumerate().take(endpoint_count) {
+        for channel in 0..3 {
+            let mut value = fields[endpoint * 3 + channel];
+            if mode.transformed && endpoint > 0 {
+                let delta = sign_extend(value, mode.delta_bits[channel]);
+                value = (fields[channel] as i32).wrapping_add(delta) as u32 & mask;
+            }
+            values[channel] = unquantize(value, mode.endpoint_bits);
+        }
+    }
+
+    let index_bits = if mode.regions == 2 { 3 } else { 4 };
+    for (pixel, out) in out.iter_mut().enumerate() {
+        let (subset, anchor) = subset(mode.regions, partition, pixel);
+        let weight = weights(index_bits)[bits.read(index_bits - anchor as u32) as usize];
+        let (e0, e1) = (endpoints[subset * 2], endpoints[subset * 2 + 1]);
+        let channel = |channel: usize| {
+            // Back to half float bits, then clipped to 8 bits
+            let half = (interpolate(e0[channel], e1[channel], weight) * 31) >> 6;
+            unit_to_u8(half_to_f32(half as u16))
+        };
+        *out = [channel(0), channel(1), channel(2), 255];
+    }
+}
+
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    ((value << shift) as i32) >> shift
+}
+
+// Spreads an n bit endpoint over 16 bits, keeping 0 and the maximum exact.
+fn unquantize(value: u32, bits: u32) -> u32 {
+    if bits >= 15 || value == 0 {
+        value
+    } else if value == (1 << bits) - 1 {
+        0xFFFF
+    } else {
+        ((value << 16) + 0x8000) >> bits
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::bhd5::{BHD5Format, FileHeader};
+use crate::dcx::DCX;
+use crate::error::DantelionFormatsError;
+use crate::kind::FileKind;
+use crate::source::{DataSource, FileSource};
+use crate::stats::Stats;
+use crate::util;
+use crate::vfs::{ChangeKind, Vfs, VfsArchive};
+
+pub const EXTRACTION_MANIFEST_NAME: &str = "_dantelion-extraction.json";
+/// Where entries without a dictionary name go, see `UnnamedPolicy`.
+pub const UNNAMED_DIR: &str = "/_unknown";
+
+/// What an `Unpacker` does with entries the dictionary has no name for.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum UnnamedPolicy {
+    // Leave them in the archive.
+    #[default]
+    Skip,
+    // Extract them as "/_unknown/<hash>".
+    Hash,
+    // Extract them as "/_unknown/<hash>.<ext>", with the extension from their sniffed `FileKind`. DCX entries are
+    // decompressed to sniff what's inside, giving e.g. "tpf.dcx".
+    HashWithExtension,
+}
+
+/// Everything an `Unpacker` extracted, sorted by path so the same extraction always gives the same manifest.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ExtractionManifest {
+    pub files: Vec<ExtractedFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct ExtractedFile {
+    // Virtual path, e.g. "/msg/engus/item.msgbnd.dcx"
+    pub path: String,
+    pub size: u64,
+    // Lowercase hex
+    pub sha256: String,
+    // The BHD the entry came from
+    pub archive: String,
+    // In the BDT
+    pub offset: u64,
+    // Of the data as stored, so usually `DCX`. Manifests written before this was added read as `Unknown`.
+    #[serde(default)]
+    pub kind: FileKind,
+}
+
+impl ExtractionManifest {
+    pub fn from_path(path: &str) -> Result<ExtractionManifest, DantelionFormatsError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn to_json(&self) -> Result<String, DantelionFormatsError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Checks an extracted folder against its manifest and returns the paths that are missing or don't match their hash.
+pub fn verify_extraction(dir: &str, manifest: &ExtractionManifest) -> Result<Vec<String>, DantelionFormatsError> {
+    let mut mismatched = vec![];
+    for file in &manifest.files {
+        let matches = match fs::read(out_path(Path::new(dir), &file.path)) {
+            Ok(data) => data.len() as u64 == file.size && sha256_hex(&data) == file.sha256,
+            Err(_) => false,
+        };
+        if !matches {
+            mismatched.push(file.path.clone());
+        }
+    }
+
+    Ok(mismatched)
+}
+
+/// Extracts the resolved entries of a `Vfs` to a folder, laid out by virtual path, e.g.
+/// "/msg/engus/item.msgbnd.dcx" goes to "<out_dir>/msg/engus/item.msgbnd.dcx". Entries are written as stored in the
+/// BDT, still DCX compressed. Each archive's BDT is expected next to its BHD.
+#[derive(Debug)]
+pub struct Unpacker {
+    pub out_dir: PathBuf,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    extensions: Vec<String>,
+    max_size: Option<u64>,
+    write_manifest: bool,
+    previous: Option<ExtractionManifest>,
+    unnamed: UnnamedPolicy,
+}
+
+impl Unpacker {
+    pub fn new(out_dir: &str) -> Unpacker {
+        Unpacker {
+            out_dir: PathBuf::from(out_dir),
+            include: vec![],
+            exclude: vec![],
+            extensions: vec![],
+            max_size: None,
+            write_manifest: false,
+            previous: None,
+            unnamed: UnnamedPolicy::Skip,
+        }
+    }
+
+    /// Only extracts entries matching one of the included globs, e.g. "/msg/**". Globs match the whole virtual
+    /// path, ignoring case, with `*` matching within a folder, `**` across folders and `?` any one character.
+    /// Everything is included if none are given.
+    pub fn include(mut self, pattern: &str) -> Unpacker {
+        self.include.push(pattern.to_string());
+        self
+    }
+
+    /// Skips entries matching the glob, even if they're included.
+    pub fn exclude(mut self, pattern: &str) -> Unpacker {
+        self.exclude.push(pattern.to_string());
+        self
+    }
+
+    /// Only extracts entries with one of the given extensions, e.g. "tpf.dcx". Ignores case.
+    pub fn extension(mut self, extension: &str) -> Unpacker {
+        self.extensions.push(format!(".{}", extension.trim_start_matches('.').to_lowercase()));
+        self
+    }
+
+    /// Skips entries bigger than `max_size` bytes.
+    pub fn max_size(mut self, max_size: u64) -> Unpacker {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Writes an `ExtractionManifest` to `EXTRACTION_MANIFEST_NAME` in the output folder.
+    pub fn write_manifest(mut self, write_manifest: bool) -> Unpacker {
+        self.write_manifest = write_manifest;
+        self
+    }
+
+    /// The manifest of an earlier extraction to the same folder. Entries still at the same offset and size in the
+    /// same archive whose file is still there are skipped, so re-extracting after a patch only writes what moved.
+    pub fn previous(mut self, previous: ExtractionManifest) -> Unpacker {
+        self.previous = Some(previous);
+        self
+    }
+
+    /// Extracts entries without a name under `UNNAMED_DIR` instead of skipping them. Filters see the path they're
+    /// given, so `extension` works on inferred extensions too.
+    pub fn unnamed(mut self, unnamed: UnnamedPolicy) -> Unpacker {
+        self.unnamed = unnamed;
+        self
+    }
+
+    /// Whether the entry at `path` passes the filters. `size` is the entry's size in the BDT.
+    pub fn matches(&self, path: &str, size: u64) -> bool {
+        let lowercase = path.to_lowercase();
+        (self.include.is_empty() || self.include.iter().any(|pattern| util::glob_matches(pattern, path)))
+            && !self.exclude.iter().any(|pattern| util::glob_matches(pattern, path))
+            && (self.extensions.is_empty() || self.extensions.iter().any(|extension| lowercase.ends_with(extension)))
+            && self.max_size.is_none_or(|max_size| size <= max_size)
+    }
+
+    /// Extracts every entry that passes the filters. `files` in the returned stats is how many were written, entries
+    /// skipped as unchanged aren't counted.
+    pub fn unpack(&self, vfs: &Vfs) -> Result<Stats, DantelionFormatsError> {
+        self.unpack_where(vfs, |_, _| true)
+    }
+
+    /// Extracts only the entries added or changed from `old` to `new`, see `Vfs::diff`. Filters still apply.
+    pub fn unpack_changes(&self, old: &Vfs, new: &Vfs) -> Result<Stats, DantelionFormatsError> {
+        let changed: HashSet<(String, u64)> = old.diff(new).into_iter()
+            .filter(|change| change.kind != ChangeKind::Removed)
+            .map(|change| (change.archive, change.hash))
+            .collect();
+
+        self.unpack_where(new, |archive, file_header| changed.contains(&(archive.file_name(), file_header.file_path_hash)))
+    }
+
+    fn unpack_where(&self, vfs: &Vfs, keep: impl Fn(&VfsArchive, &FileHeader) -> bool) -> Result<Stats, DantelionFormatsError> {
+        // Unnamed entries are also looked up by hash, since their extension isn't known until they're read.
+        let previous: HashMap<&str, &ExtractedFile> = self.previous.iter()
+            .flat_map(|manifest| &manifest.files)
+            .flat_map(|file| [Some(file.path.as_str()), unnamed_key(&file.path)].into_iter().flatten().map(move |key| (key, file)))
+            .collect();
+
+        let mut stats = Stats::default();
+        let mut manifest = ExtractionManifest::default();
+        for archive in &vfs.archives {
+            // Unnamed entries only get a path once they're read, so only their size is filtered up front.
+            let mut entries: Vec<_> = archive.bhd5.iter()
+                .map(|file_header| (archive.names.get(&file_header.file_path_hash), file_header))
+                .filter(|(path, file_header)| match path {
+                    Some(path) => self.matches(path, entry_size(file_header)),
+                    None => self.unnamed != UnnamedPolicy::Skip && self.max_size.is_none_or(|max_size| entry_size(file_header) <= max_size),
+                })
+                .filter(|(_, file_header)| keep(archive, file_header))
+                .collect();
+            if entries.is_empty() {
+                continue;
+            }
+            // Buckets are in hash order, so reading in BDT order instead turns a whole unpack into one pass over the
+            // file, with the next entry prefetched while the current one is decrypted and written.
+            entries.sort_by_key(|(_, file_header)| file_header.file_offset);
+
+            let archive_path = archive.bhd_path.to_string_lossy();
+            let bdt = FileSource::open_sequential(&archive.bhd_path.with_extension("bdt").to_string_lossy())?;
+            for (i, &(path, file_header)) in entries.iter().enumerate() {
+                if let Some((_, next)) = entries.get(i + 1) {
+                    bdt.prefetch(next.file_offset, next.padded_file_size as usize);
+                }
+                let key = match path {
+                    Some(path) => path.clone(),
+                    None => format!("{}/{}", UNNAMED_DIR, hash_name(file_header.file_path_hash, archive.bhd5.format)),
+                };
+                let unchanged = previous.get(key.as_str()).filter(|file| {
+                    file.archive == archive_path && file.offset == file_header.file_offset && file.size == entry_size(file_header) && out_path(&self.out_dir, &file.path).is_file()
+                });
+                if let Some(&file) = unchanged {
+                    manifest.files.push(file.clone());
+                    continue;
+                }
+
+                let encrypted = file_header.read_encrypted_from(&bdt)?;
+                stats.bytes_read += encrypted.len() as u64;
+                let data = Stats::time(&mut stats.decrypt_time, || file_header.decrypt_data(encrypted))?;
+                let path = match path {
+                    Some(_) => key,
+                    None => {
+                        let path = self.unnamed_path(key, &data, &mut stats);
+                        if !self.matches(&path, entry_size(file_header)) {
+                            continue;
+                        }
+                        path
+                    },
+                };
+                let out_path = out_path(&self.out_dir, &path);
+                if let Some(parent) = out_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&out_path, &data)?;
+                stats.files += 1;
+                stats.bytes_written += data.len() as u64;
+                stats.count_format(&format_of(&path));
+
+                manifest.files.push(ExtractedFile {
+                    path,
+                    size: data.len() as u64,
+                    sha256: sha256_hex(&data),
+                    archive: archive_path.to_string(),
+                    offset: file_header.file_offset,
+                    kind: FileKind::sniff(&data),
+                });
+            }
+        }
+
+        if self.write_manifest {
+            manifest.files.sort_by(|a, b| a.path.cmp(&b.path));
+            fs::create_dir_all(&self.out_dir)?;
+            fs::write(self.out_dir.join(EXTRACTION_MANIFEST_NAME), manifest.to_json()?)?;
+        }
+
+        Ok(stats)
+    }
+
+    // `path` is the entry's "/_unknown/<hash>" path, which gets the inferred extension if the policy asks for it.
+    fn unnamed_path(&self, path: String, data: &[u8], stats: &mut Stats) -> String {
+        if self.unnamed != UnnamedPolicy::HashWithExtension {
+            return path;
+        }
+
+        let extension = match FileKind::sniff(data) {
+            // Entries that don't decompress, e.g. KRAK without Oodle, are still extracted, just as plain "dcx".
+            FileKind::DCX => match Stats::time(&mut stats.decompress_time, || DCX::decompress_bytes(data)) {
+                Ok(inner) if FileKind::sniff(&inner) != FileKind::Unknown => format!("{}.dcx", FileKind::sniff(&inner).extension()),
+                _ => "dcx".to_string(),
+            },
+            kind => kind.extension().to_string(),
+        };
+        if extension.is_empty() { path } else { format!("{}.{}", path, extension) }
+    }
+}
+
+// "/_unknown/<hash>" for an unnamed entry's path, with or without an extension.
+fn unnamed_key(path: &str) -> Option<&str> {
+    let file_name = path.strip_prefix(UNNAMED_DIR)?.strip_prefix('/')?;
+    let hash_len = file_name.find('.').unwrap_or(file_name.len());
+    Some(&path[..UNNAMED_DIR.len() + 1 + hash_len])
+}
+
+// Elden Ring's hashes are 64-bit, the older formats' 32-bit.
+fn hash_name(hash: u64, format: BHD5Format) -> String {
+    match format {
+        BHD5Format::EldenRing => format!("{:016x}", hash),
+        _ => format!("{:08x}", hash),
+    }
+}
+
+fn out_path(dir: &Path, path: &str) -> PathBuf {
+    dir.join(path.trim_start_matches(['/', '\\']))
+}
+
+// Everything after the first dot of the file name, e.g. "tpf.dcx".
+fn format_of(path: &str) -> String {
+    let file_name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    file_name.split_once('.').map_or("", |(_, extension)| extension).to_lowercase()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Older archives leave `file_size` as 0 and only store the padded size.
+pub(crate) fn entry_size(file_header: &FileHeader) -> u64 {
+    if file_header.file_size != 0 { file_header.file_size } else { file_header.padded_file_size as u64 }
+}
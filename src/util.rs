@@ -1,79 +1,247 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::fmt::{self, Debug, Formatter};
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind, Write};
 use std::path::Path;
-use winreg;
-use winreg::enums::*;
-use winreg::{RegKey};
+use byteorder::ByteOrder;
+use encoding_rs::SHIFT_JIS;
+use crate::error::DantelionFormatsError;
 
+/// Checks the fields of a header or record that are constant in every known file, so a corrupt or unsupported file is
+/// an `InvalidData` error instead of garbage further in.
 pub trait Validate {
-    fn validate(&self);
+    fn validate(&self) -> Result<(), DantelionFormatsError>;
 }
 
-pub(crate) static STEAM_REGISTRY_LOCATIONS: [(&str, &str, &str); 4] = [
-    ("HKCU", r"SOFTWARE\Valve\Steam", "SteamPath"),
-    ("HKLM", r"SOFTWARE\Wow6432Node\Valve\Steam", "InstallPath"),
-    ("HKLM", r"SOFTWARE\Valve\Steam", "InstallPath"),
-    ("HKCU", r"SOFTWARE\Wow6432Node\Valve\Steam", "SteamPath"),
-];
+/// Returns an `InvalidData` error with the formatted message unless `condition` holds. The fallible `assert!` for
+/// `Validate` impls.
+macro_rules! ensure {
+    ($condition:expr, $($message:tt)+) => {
+        if !($condition) {
+            return Err($crate::error::DantelionFormatsError::IoError(std::io::Error::new(std::io::ErrorKind::InvalidData, format!($($message)+))));
+        }
+    };
+}
+pub(crate) use ensure;
 
-// Works, for now...
-pub fn get_oodle_path() -> Option<String> {
-    if Path::new("oo2core_6_win64.dll").exists() {
-        return Some("oo2core_6_win64.dll".to_string());
+pub(crate) const OODLE_DLL_NAME: &str = "oo2core_6_win64.dll";
+
+/// Writes `s` as exactly `size` bytes, truncating or padding with zeros. Counterpart to `read_fixed_cstr`.
+pub(crate) fn write_fixed_str(w: &mut impl Write, s: &str, size: usize) -> std::io::Result<()> {
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.resize(size, 0);
+    w.write_all(&bytes)
+}
+
+/// Converts between integer types, with an error naming `what` instead of silently truncating. Offsets and sizes
+/// from files are u32 or u64, so anything used as a usize goes through here for 32-bit targets and corrupt files.
+pub(crate) fn checked_cast<T: TryFrom<U>, U: Copy + std::fmt::Display>(value: U, what: &str) -> Result<T, DantelionFormatsError> {
+    T::try_from(value).map_err(|_| {
+        DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("{} {} does not fit in a {}", what, value, std::any::type_name::<T>())))
+    })
+}
+
+/// How many records to reserve room for when `count` comes from the file `c` is reading. Capped by the bytes left, so a
+/// corrupt count fails on the first short read instead of in the allocator.
+pub(crate) fn capacity_for(c: &Cursor<&[u8]>, count: u64) -> usize {
+    let remaining = (c.get_ref().len() as u64).saturating_sub(c.position());
+    count.min(remaining) as usize
+}
+
+/// `count` as a usize, or an `InvalidData` error naming `what` when that many records of `record_size` bytes don't fit
+/// in what's left of `c`. Checked casts alone don't stop a corrupt count from reaching the allocator on 64-bit targets.
+pub(crate) fn checked_count(c: &Cursor<&[u8]>, count: u64, record_size: u64, what: &str) -> Result<usize, DantelionFormatsError> {
+    let remaining = (c.get_ref().len() as u64).saturating_sub(c.position());
+    if count.saturating_mul(record_size) > remaining {
+        return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::InvalidData, format!("{} {} does not fit in the {:#X} bytes left", what, count, remaining))));
     }
 
-    let steam_path = get_steam_install_path();
-    match steam_path {
-        None => return None,
-        Some(path) => {
-            return Some(search_steam_for_oodle(path)?);
-        }
+    checked_cast(count, what)
+}
+
+/// Reads `len` bytes where `len` comes from the file, failing before anything is allocated if there aren't that many.
+pub(crate) fn read_len(c: &mut Cursor<&[u8]>, len: u64) -> Result<Vec<u8>, DantelionFormatsError> {
+    let start = c.position();
+    let bytes = start.checked_add(len)
+        .and_then(|end| c.get_ref().get(usize::try_from(start).ok()?..usize::try_from(end).ok()?))
+        .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, format!("{:#X} bytes at {:#X} run past the end of the file", len, start)))?
+        .to_vec();
+    c.set_position(start + len);
+
+    Ok(bytes)
+}
+
+/// Stands in for file data in `Debug` output, which would otherwise print every byte.
+pub(crate) struct DataLen(pub(crate) usize);
+
+impl Debug for DataLen {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "[{} bytes]", self.0)
     }
+}
 
+pub(crate) fn pad_to(bytes: &mut Vec<u8>, alignment: usize) {
+    let len = bytes.len().div_ceil(alignment) * alignment;
+    bytes.resize(len, 0);
 }
 
-fn search_steam_for_oodle(steam_path: String) -> Option<String> {
-    let vdf = match File::open(format!(r"{steam_path}/SteamApps/libraryfolders.vdf")) {
-        Ok(vdf) => vdf,
-        Err(_) => return None
-    };
+/// Reads a null terminated UTF-16 string at `offset` in byte order `T`, which `peek_wcstr` doesn't know about.
+pub(crate) fn peek_utf16<T: ByteOrder>(c: &Cursor<&[u8]>, offset: u64) -> Result<String, DantelionFormatsError> {
+    let bytes = c.get_ref().get(offset as usize..).ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Name offset out of bounds"))?;
+    let units: Vec<u16> = bytes.chunks_exact(2).map(T::read_u16).take_while(|&unit| unit != 0).collect();
+    if units.len() == bytes.len() / 2 {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Unterminated name").into());
+    }
 
-    let library_folders = BufReader::new(vdf);
+    Ok(String::from_utf16(&units)?)
+}
 
-    for line in library_folders.lines().map(|x| x.unwrap()).skip_while(|p| p.contains("\"path\"")) {
-        let split: Vec<&str> = line.split("\t").skip_while(|&x| !x.to_lowercase().contains("steam")).collect();
-        if split.len() < 1 { continue; }
+/// Reads a null terminated Shift-JIS string at `offset`, without moving the cursor.
+pub(crate) fn peek_shift_jis(c: &Cursor<&[u8]>, offset: u64) -> Result<String, DantelionFormatsError> {
+    let bytes = c.get_ref().get(offset as usize..).ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "String offset out of bounds"))?;
+    let end = bytes.iter().position(|&b| b == 0).ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Unterminated string"))?;
+    let (s, _, had_errors) = SHIFT_JIS.decode(&bytes[..end]);
+    if had_errors {
+        return Err(Error::new(ErrorKind::InvalidData, "Invalid Shift-JIS string").into());
+    }
+
+    Ok(s.into_owned())
+}
+
+/// Writes `s` as a null terminated Shift-JIS string.
+pub(crate) fn write_shift_jis(bytes: &mut Vec<u8>, s: &str) -> Result<(), DantelionFormatsError> {
+    let (encoded, _, had_errors) = SHIFT_JIS.encode(s);
+    if had_errors {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{} can't be encoded as Shift-JIS", s)).into());
+    }
+    bytes.extend_from_slice(&encoded);
+    bytes.push(0);
+
+    Ok(())
+}
+
+/// The hash used by binder hash tables and the 32-bit BHD5 formats. Lowercase, forward slashes and a leading slash,
+/// then `hash * 37 + c` over the characters.
+pub fn path_hash(path: &str) -> u32 {
+    let mut hashable = path.to_lowercase().replace('\\', "/");
+    if !hashable.starts_with('/') {
+        hashable.insert(0, '/');
+    }
+
+    hashable.chars().fold(0u32, |hash, c| hash.wrapping_mul(37).wrapping_add(c as u32))
+}
+
+/// The 64-bit path hash Elden Ring uses in its BHD5s. Same normalization as `path_hash`.
+pub fn path_hash_64(path: &str) -> u64 {
+    let mut hashable = path.to_lowercase().replace('\\', "/");
+    if !hashable.starts_with('/') {
+        hashable.insert(0, '/');
+    }
 
-        let steam_path = split[0].replace("\"", "");
-        let elden_path = format!("{}\\steamapps\\common\\ELDEN RING\\Game\\oo2core_6_win64.dll", steam_path);
-        if Path::new(&elden_path).exists() {
-            return Some(elden_path.replace("\\\\", "\\"));
+    hashable.chars().fold(0u64, |hash, c| hash.wrapping_mul(0x85).wrapping_add(c as u64))
+}
+
+/// Matches `text` against a glob, ignoring case. `*` matches any run of characters within a path segment and `**` any
+/// run, slashes included, so "/chr/*.dcx" only finds files directly in chr and "/chr/**.dcx" finds them in subfolders
+/// too. `**/` also matches no folders at all. `?` matches any single character but a slash.
+pub(crate) fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    // matched[t]: whether the pattern so far matches text[..t].
+    let mut matched = vec![false; text.len() + 1];
+    matched[0] = true;
+    let mut p = 0;
+    while p < pattern.len() {
+        let mut next = vec![false; text.len() + 1];
+        if pattern[p] == '*' && pattern.get(p + 1) == Some(&'*') {
+            let folders = pattern.get(p + 2) == Some(&'/');
+            // Whether an earlier prefix matched, so `**` can take everything from there to here. `**/` has to end on
+            // a slash.
+            let mut reached = false;
+            for t in 0..=text.len() {
+                next[t] = matched[t] || (reached && (!folders || text[t - 1] == '/'));
+                reached |= matched[t];
+            }
+            p += if folders { 3 } else { 2 };
+        } else if pattern[p] == '*' {
+            for t in 0..=text.len() {
+                next[t] = matched[t] || (t > 0 && text[t - 1] != '/' && next[t - 1]);
+            }
+            p += 1;
+        } else {
+            for t in 1..=text.len() {
+                next[t] = matched[t - 1] && (pattern[p] == text[t - 1] || (pattern[p] == '?' && text[t - 1] != '/'));
+            }
+            p += 1;
         }
+        matched = next;
+    }
+
+    matched[text.len()]
+}
+
+/// How names are compared everywhere a file is looked up by name: binder entries, VFS paths and manifest files. Case
+/// is ignored, Unicode aware, and `\` and `/` are the same. With `ignore_roots`, the "N:\" style drive the developers'
+/// paths start with and any leading slashes are dropped too, so "N:\GR\data\a.bin" and "/GR/data/a.bin" match.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct NameMatcher {
+    pub ignore_roots: bool,
+}
+
+impl NameMatcher {
+    pub fn new() -> NameMatcher {
+        NameMatcher::default()
+    }
 
-        let sekiro_path = format!("{}\\steamapps\\common\\Sekiro\\Game\\oo2core_6_win64.dll", steam_path);
-        if Path::new(&sekiro_path).exists() {
-            return Some(sekiro_path.replace("\\\\", "\\"));
+    pub fn ignore_roots(mut self, ignore_roots: bool) -> NameMatcher {
+        self.ignore_roots = ignore_roots;
+        self
+    }
+
+    /// The form names are compared in: lowercase with forward slashes, and without the root when it's ignored.
+    pub fn normalize(&self, name: &str) -> String {
+        let name = name.to_lowercase().replace('\\', "/");
+        if !self.ignore_roots {
+            return name;
         }
+
+        strip_root(&name).trim_start_matches('/').to_string()
+    }
+
+    /// Whether two names are the same file.
+    pub fn eq(&self, a: &str, b: &str) -> bool {
+        self.normalize(a) == self.normalize(b)
     }
 
-    None
+    /// Whether `entry_name` is the file `name` asks for, either as the whole path or just the file name, so
+    /// "c2010.anibnd" finds "N:\GR\data\INTERROOT_win64\chr\c2010\c2010.anibnd".
+    pub fn matches(&self, entry_name: &str, name: &str) -> bool {
+        let (entry_name, name) = (self.normalize(entry_name), self.normalize(name));
+        entry_name == name || entry_name.rsplit('/').next() == Some(&name[..])
+    }
 }
 
-fn get_steam_install_path() -> Option<String> {
-    for location in STEAM_REGISTRY_LOCATIONS {
-        let hkey = if location.0 == "HKCU" { HKEY_CURRENT_USER } //I hate this :(
-        else if location.0 == "HKLM" { HKEY_LOCAL_MACHINE } else { return None; };
+/// `name` without its "N:\" or "N:/" style drive, if it has one.
+pub(crate) fn strip_root(name: &str) -> &str {
+    match name.find(":\\").or_else(|| name.find(":/")) {
+        Some(i) if !name[..i].contains(['/', '\\']) => &name[i + 2..],
+        _ => name,
+    }
+}
 
-        let reg_key = RegKey::predef(hkey)
-            .open_subkey(location.1);
+pub(crate) fn is_prime(n: u32) -> bool {
+    if n < 2 {
+        return false;
+    }
 
-        match reg_key {
-            Ok(key) => return Some(key.get_value(location.2).unwrap()),
-            Err(_) => {}
+    let mut i = 2u32;
+    while (i as u64) * (i as u64) <= n as u64 {
+        if n.is_multiple_of(i) {
+            return false;
         }
+        i += 1;
     }
 
-    None
+    true
 }
 
 pub fn reverse_bits(byte: u8) -> u8 {
@@ -110,3 +278,39 @@ pub fn reverse_bits(byte: u8) -> u8 {
 //     Ok(result)
 // }
 
+
+pub const BACKUP_SUFFIX: &str = ".dantelion.bak";
+
+pub fn backup_path(path: &str) -> String {
+    format!("{}{}", path, BACKUP_SUFFIX)
+}
+
+pub fn has_backup(path: &str) -> bool {
+    Path::new(&backup_path(path)).exists()
+}
+
+/// Copies `path` to `path.dantelion.bak`. An existing backup is never overwritten, so the backup is always the file
+/// as it was before the first edit. Returns false if there already was one.
+pub fn backup(path: &str) -> Result<bool, DantelionFormatsError> {
+    if has_backup(path) {
+        return Ok(false);
+    }
+
+    // Copy under a temp name first so a partial copy is never mistaken for a backup.
+    let temp = format!("{}.tmp", backup_path(path));
+    fs::copy(path, &temp)?;
+    fs::rename(&temp, backup_path(path))?;
+
+    Ok(true)
+}
+
+/// Copies the backup made by `backup` over `path`. The backup is kept.
+pub fn restore(path: &str) -> Result<(), DantelionFormatsError> {
+    if !has_backup(path) {
+        return Err(DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, format!("No backup for {}", path))));
+    }
+
+    fs::copy(backup_path(path), path)?;
+
+    Ok(())
+}
@@ -0,0 +1,273 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Error, ErrorKind};
+#[cfg(feature = "crypto")]
+use std::path::Path;
+use std::path::PathBuf;
+use regex::RegexBuilder;
+use crate::bhd5::{BHD5, FileHeader};
+#[cfg(feature = "crypto")]
+use crate::bhd5::GameType;
+#[cfg(feature = "crypto")]
+use crate::config::Config;
+use crate::error::DantelionFormatsError;
+use crate::kind::FileKind;
+use crate::source::FileSource;
+use crate::stats::Stats;
+use crate::util::{self, NameMatcher};
+
+/// The archives of a game install, with entry names resolved through a dictionary. Only the BHD5 headers are kept,
+/// data is read from the BDTs as needed.
+#[derive(Debug)]
+pub struct Vfs {
+    pub archives: Vec<VfsArchive>,
+    // What reading and decrypting the BHDs took, for `open_install`
+    pub stats: Stats,
+    dictionary: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct VfsArchive {
+    pub bhd_path: PathBuf,
+    pub bhd5: BHD5,
+    // Dictionary paths whose hash is in this archive
+    pub names: HashMap<u64, String>,
+}
+
+/// An entry found by `Vfs::find`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VfsMatch {
+    pub bhd_path: PathBuf,
+    pub path: String,
+    pub hash: u64,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ChangeKind {
+    Added,
+    Changed,
+    Removed,
+}
+
+/// An entry that differs between two versions of an install, see `Vfs::diff`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VfsChange {
+    pub kind: ChangeKind,
+    // The BHD's file name, lowercased, e.g. "data0.bhd", since the two installs are usually in different folders
+    pub archive: String,
+    pub hash: u64,
+    // None if the dictionary doesn't have the entry's name
+    pub path: Option<String>,
+}
+
+impl Vfs {
+    /// `dictionary` is every known archive path, e.g. "/parts/am_m_1600.partsbnd.dcx".
+    pub fn new(dictionary: Vec<String>) -> Vfs {
+        Vfs {
+            archives: vec![],
+            stats: Stats::default(),
+            dictionary,
+        }
+    }
+
+    /// Reads a dictionary in UXM's format, one path per line, with `#` lines naming the archive they're for.
+    pub fn read_dictionary(path: &str) -> Result<Vec<String>, DantelionFormatsError> {
+        Ok(fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Opens every ER archive under `game_dir`, e.g. ".../ELDEN RING/Game". Keys come from `Config::key_store`, a BHD
+    /// with neither a built in nor a configured key is an error rather than being left out.
+    #[cfg(feature = "crypto")]
+    pub fn open_install(game_dir: &str, dictionary: Vec<String>) -> Result<Vfs, DantelionFormatsError> {
+        let mut bhd_paths = vec![];
+        find_bhds(Path::new(game_dir), &mut bhd_paths)?;
+        bhd_paths.sort();
+
+        let key_store = Config::load()?.key_store();
+        let mut buffer = vec![];
+        let mut vfs = Vfs::new(dictionary);
+        for bhd_path in bhd_paths {
+            let bhd_path = bhd_path.to_string_lossy();
+            let file = fs::read(&*bhd_path)?;
+            let bhd5 = Stats::time(&mut vfs.stats.decrypt_time, || {
+                if BHD5::is_decrypted(&file) {
+                    return BHD5::from_bytes(&file);
+                }
+
+                key_store.decrypt_into(GameType::EldenRing, &bhd_path, &file, None, &mut buffer)?;
+                BHD5::from_bytes(&buffer)
+            })?;
+            vfs.stats.files += 1;
+            vfs.stats.bytes_read += file.len() as u64;
+            vfs.stats.count_format("bhd");
+            vfs.add_archive(&bhd_path, bhd5);
+        }
+
+        Ok(vfs)
+    }
+
+    /// Adds an archive and resolves its entry names from the dictionary.
+    pub fn add_archive(&mut self, bhd_path: &str, bhd5: BHD5) {
+        let hashes: HashSet<u64> = bhd5.iter().map(|file_header| file_header.file_path_hash).collect();
+        let names = self.dictionary.iter()
+            .map(|path| (BHD5::hash_path(path, bhd5.format), path.clone()))
+            .filter(|(hash, _)| hashes.contains(hash))
+            .collect();
+
+        self.archives.push(VfsArchive {
+            bhd_path: PathBuf::from(bhd_path),
+            bhd5,
+            names,
+        });
+    }
+
+    /// What changed from this install to `new`, e.g. the game before and after a patch. Archives are paired up by file
+    /// name. Entries count as changed when their size, salted hash or AES key differs, since patches usually rebuild
+    /// the whole BDT and move even the entries that didn't change.
+    pub fn diff(&self, new: &Vfs) -> Vec<VfsChange> {
+        let mut changes = vec![];
+        for new_archive in &new.archives {
+            let name = new_archive.file_name();
+            let old_archive = self.archives.iter().find(|archive| archive.file_name() == name);
+            let old_headers: HashMap<u64, &FileHeader> = old_archive.iter()
+                .flat_map(|archive| &archive.bhd5)
+                .map(|file_header| (file_header.file_path_hash, file_header))
+                .collect();
+
+            for file_header in &new_archive.bhd5 {
+                let kind = match old_headers.get(&file_header.file_path_hash) {
+                    None => ChangeKind::Added,
+                    Some(old) if !same_contents(old, file_header) => ChangeKind::Changed,
+                    Some(_) => continue,
+                };
+                changes.push(new_archive.change(kind, file_header.file_path_hash));
+            }
+
+            if let Some(old_archive) = old_archive {
+                let new_hashes: HashSet<u64> = new_archive.bhd5.iter().map(|file_header| file_header.file_path_hash).collect();
+                changes.extend(old_archive.bhd5.iter()
+                    .filter(|file_header| !new_hashes.contains(&file_header.file_path_hash))
+                    .map(|file_header| old_archive.change(ChangeKind::Removed, file_header.file_path_hash)));
+            }
+        }
+
+        for old_archive in &self.archives {
+            let name = old_archive.file_name();
+            if !new.archives.iter().any(|archive| archive.file_name() == name) {
+                changes.extend(old_archive.bhd5.iter().map(|file_header| old_archive.change(ChangeKind::Removed, file_header.file_path_hash)));
+            }
+        }
+
+        changes
+    }
+
+    /// Sniffs what a found entry is from the first `FileKind::SNIFF_SIZE` bytes, read from the archive's BDT.
+    pub fn sniff(&self, entry: &VfsMatch) -> Result<FileKind, DantelionFormatsError> {
+        let file_header = self.archives.iter()
+            .filter(|archive| archive.bhd_path == entry.bhd_path)
+            .flat_map(|archive| &archive.bhd5)
+            .find(|file_header| file_header.file_path_hash == entry.hash)
+            .ok_or_else(|| DantelionFormatsError::IoError(Error::new(ErrorKind::NotFound, format!("{} is not in the vfs", entry.path))))?;
+        let bdt = FileSource::open(&entry.bhd_path.with_extension("bdt").to_string_lossy())?;
+
+        Ok(FileKind::sniff(&file_header.read_prefix_from(&bdt, FileKind::SNIFF_SIZE)?))
+    }
+
+    /// Every resolved entry whose path matches the glob `pattern`, in archive order. `*` matches within a folder, `**`
+    /// across folders and `?` any single character but a slash. Matching ignores case and slash direction, and
+    /// a pattern without wildcards matches anywhere in the path, so "c2010" finds every file with it in its path.
+    pub fn find(&self, pattern: &str) -> Vec<VfsMatch> {
+        let matcher = NameMatcher::new();
+        let pattern = matcher.normalize(pattern);
+        let pattern = if pattern.contains(['*', '?']) { pattern } else { format!("**{}**", pattern) };
+
+        self.find_by(|path| util::glob_matches(&pattern, path))
+    }
+
+    /// Every resolved entry whose path contains a match for the regex `pattern`, in archive order. Paths are matched in
+    /// `NameMatcher` form, lowercase with forward slashes, and the regex ignores case, so r"/c\d{4}\.chrbnd" finds
+    /// every character binder. Fails if `pattern` isn't a valid regex.
+    pub fn find_regex(&self, pattern: &str) -> Result<Vec<VfsMatch>, DantelionFormatsError> {
+        let regex = RegexBuilder::new(pattern).case_insensitive(true).build()?;
+
+        Ok(self.find_by(|path| regex.is_match(path)))
+    }
+
+    // Matches are sorted by path within each archive. `is_match` is given normalized paths.
+    fn find_by(&self, is_match: impl Fn(&str) -> bool) -> Vec<VfsMatch> {
+        let matcher = NameMatcher::new();
+        let mut matches = vec![];
+        for archive in &self.archives {
+            let mut names: Vec<_> = archive.names.iter()
+                .filter(|(_, path)| is_match(&matcher.normalize(path)))
+                .collect();
+            names.sort_by(|a, b| a.1.cmp(b.1));
+            matches.extend(names.into_iter().map(|(hash, path)| VfsMatch {
+                bhd_path: archive.bhd_path.clone(),
+                path: path.clone(),
+                hash: *hash,
+            }));
+        }
+
+        matches
+    }
+
+    /// The entry named `name`, compared like binder entries are, see `NameMatcher`, with roots ignored. So
+    /// "/chr/c2010.anibnd.dcx", "chr\c2010.anibnd.dcx" and just "c2010.anibnd.dcx" all find the same entry. The first
+    /// match in archive order wins when a file name is in more than one folder.
+    pub fn get(&self, name: &str) -> Option<VfsMatch> {
+        let matcher = NameMatcher::new().ignore_roots(true);
+        self.archives.iter()
+            .flat_map(|archive| {
+                let mut names: Vec<_> = archive.names.iter().filter(|(_, path)| matcher.matches(path, name)).collect();
+                names.sort_by(|a, b| a.1.cmp(b.1));
+                names.into_iter().map(move |(hash, path)| VfsMatch {
+                    bhd_path: archive.bhd_path.clone(),
+                    path: path.clone(),
+                    hash: *hash,
+                })
+            })
+            .next()
+    }
+}
+
+#[cfg(feature = "crypto")]
+fn find_bhds(dir: &Path, bhd_paths: &mut Vec<PathBuf>) -> Result<(), DantelionFormatsError> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_bhds(&path, bhd_paths)?;
+        } else if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("bhd")) {
+            bhd_paths.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+impl VfsArchive {
+    pub fn file_name(&self) -> String {
+        self.bhd_path.file_name().map_or_else(String::new, |name| name.to_string_lossy().to_lowercase())
+    }
+
+    fn change(&self, kind: ChangeKind, hash: u64) -> VfsChange {
+        VfsChange {
+            kind,
+            archive: self.file_name(),
+            hash,
+            path: self.names.get(&hash).cloned(),
+        }
+    }
+}
+
+fn same_contents(old: &FileHeader, new: &FileHeader) -> bool {
+    old.file_size == new.file_size
+        && old.padded_file_size == new.padded_file_size
+        && old.salted_hash == new.salted_hash
+        && old.aes_key == new.aes_key
+}
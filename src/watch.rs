@@ -0,0 +1,84 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use crate::error::DantelionFormatsError;
+
+/// A change to a file in a mod folder. Paths are virtual paths, the same as archive paths, e.g. "/parts/am_m_1600.partsbnd.dcx".
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ModChange {
+    // Created or written to
+    Changed(String),
+    Removed(String),
+}
+
+/// Watches a mod folder, so tools serving assets from it can reload them when they change.
+#[derive(Debug)]
+pub struct ModWatcher {
+    pub mod_dir: PathBuf,
+    changes: Receiver<ModChange>,
+    // Stops watching when dropped.
+    _watcher: RecommendedWatcher,
+}
+
+impl ModWatcher {
+    pub fn new(mod_dir: &str) -> Result<ModWatcher, DantelionFormatsError> {
+        // Events come with absolute paths, which may have symlinks resolved.
+        let mod_dir = Path::new(mod_dir).canonicalize()?;
+        let (sender, changes) = channel();
+
+        let root = mod_dir.clone();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let Ok(event) = event else { return };
+            for change in ModWatcher::changes(&root, &event) {
+                let _ = sender.send(change);
+            }
+        })?;
+        watcher.watch(&mod_dir, RecursiveMode::Recursive)?;
+
+        Ok(ModWatcher {
+            mod_dir,
+            changes,
+            _watcher: watcher,
+        })
+    }
+
+    /// Every change since the last call, without blocking.
+    pub fn poll(&self) -> Vec<ModChange> {
+        self.changes.try_iter().collect()
+    }
+
+    /// Blocks until there is a change or `timeout` passes.
+    pub fn wait(&self, timeout: Duration) -> Option<ModChange> {
+        self.changes.recv_timeout(timeout).ok()
+    }
+
+    fn changes(root: &Path, event: &Event) -> Vec<ModChange> {
+        let virtual_paths = event.paths.iter().filter_map(|path| virtual_path(root, path));
+        match event.kind {
+            EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => virtual_paths.map(ModChange::Removed).collect(),
+            // Renames within the folder come as one event with the old path first.
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => virtual_paths.enumerate()
+                .map(|(i, path)| if i == 0 { ModChange::Removed(path) } else { ModChange::Changed(path) })
+                .collect(),
+            EventKind::Create(_) | EventKind::Modify(_) => event.paths.iter()
+                .filter(|path| path.is_file())
+                .filter_map(|path| virtual_path(root, path))
+                .map(ModChange::Changed)
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// The virtual path for a file in the mod folder, or None if it's outside it.
+pub fn virtual_path(mod_dir: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(mod_dir).ok()?;
+    let parts: Vec<String> = relative.components().map(|component| component.as_os_str().to_string_lossy().to_string()).collect();
+    if parts.is_empty() {
+        return None;
+    }
+
+    Some(format!("/{}", parts.join("/")))
+}